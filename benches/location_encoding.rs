@@ -0,0 +1,144 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Compares the cost of encoding a task's spawn location as a probe argument.
+//!
+//! The task probes pass the file in which a task was spawned as a `&str`,
+//! borrowing the `&'static str` returned by [`Location::file`] unless the path
+//! is mapped or truncated. This benchmark measures that against two
+//! alternatives:
+//!
+//! - `String`: copying the file into a new `String` each time an enabled probe
+//!   fires, as the task probes originally did.
+//! - interned `u32`: passing an index into a table of previously seen
+//!   locations in place of the file, line, and column. The index must be
+//!   looked up on every call, and inserted the first time a location is seen.
+//!
+//! For each encoding, the benchmark reports the mean time and the number of
+//! heap allocations per spawn (which must also register a new location with
+//! the interning table) and per poll (which only encodes a location that has
+//! already been seen).
+//!
+//! Run with `cargo bench --bench location_encoding`.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::Instant;
+
+/// Counts heap allocations, so that they can be reported per call.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: forwarded directly to the system allocator.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: forwarded directly to the system allocator.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: u64 = 1_000_000;
+
+/// Interned locations, keyed by their address.
+static INTERNED: LazyLock<RwLock<HashMap<usize, u32>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+#[inline(never)]
+fn encode_string(location: &'static Location<'static>) -> (String, u32, u32) {
+    (
+        location.file().to_string(),
+        location.line(),
+        location.column(),
+    )
+}
+
+#[inline(never)]
+fn encode_str(location: &'static Location<'static>) -> (&'static str, u32, u32) {
+    (location.file(), location.line(), location.column())
+}
+
+#[inline(never)]
+fn encode_interned(location: &'static Location<'static>) -> u32 {
+    let key = interning_key(location);
+    let index = INTERNED.read().unwrap().get(&key).copied();
+    index.unwrap_or_else(|| {
+        let mut table = INTERNED.write().unwrap();
+        let next = table.len() as u32;
+        *table.entry(key).or_insert(next)
+    })
+}
+
+fn interning_key(location: &'static Location<'static>) -> usize {
+    std::ptr::from_ref(location) as usize
+}
+
+/// Runs `f` [`ITERATIONS`] times, returning the mean time and number of
+/// allocations per call.
+fn measure<T>(mut f: impl FnMut() -> T) -> (f64, f64) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    (
+        elapsed.as_nanos() as f64 / ITERATIONS as f64,
+        allocations as f64 / ITERATIONS as f64,
+    )
+}
+
+fn report(encoding: &str, event: &str, (nanos, allocations): (f64, f64)) {
+    println!(
+        "{encoding:>12} {event:>6}: {nanos:>8.1} ns/call, {allocations:>5.2} allocations/call",
+    );
+}
+
+fn main() {
+    // Distinct spawn locations, as would be seen by a program spawning tasks
+    // from many call sites.
+    let locations: Vec<&'static Location<'static>> = vec![
+        Location::caller(),
+        Location::caller(),
+        Location::caller(),
+        Location::caller(),
+    ];
+    let mut next = {
+        let mut i = 0;
+        move || {
+            i = (i + 1) % locations.len();
+            locations[i]
+        }
+    };
+
+    for (event, clear_table) in [("spawn", true), ("poll", false)] {
+        report("String", event, measure(|| encode_string(next())));
+        report("&str", event, measure(|| encode_str(next())));
+        report(
+            "interned u32",
+            event,
+            measure(|| {
+                let location = next();
+                if clear_table {
+                    // Each spawn is assumed to come from a location that has not
+                    // been seen before, which is the worst case for interning.
+                    // Note that this includes the cost of removing the
+                    // location from the table again.
+                    INTERNED.write().unwrap().remove(&interning_key(location));
+                }
+                encode_interned(location)
+            }),
+        );
+    }
+}
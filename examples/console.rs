@@ -0,0 +1,34 @@
+// Copyright 2025 Oxide Computer Company
+
+//! A program that can be observed with both `tokio-console` and DTrace.
+//!
+//! Run this program with the `console` feature enabled, then connect to it
+//! using `tokio-console`, and run `examples/print-all.d` with its PID. Task IDs
+//! shown by the console match the first argument of the task probes.
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // The console's subscriber must be installed before the runtime spawns any
+    // tasks, so that it observes their spans.
+    console_subscriber::init();
+
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_time().enable_io();
+    let rt = tokio_dtrace::register_hooks(&mut builder)?.build()?;
+
+    rt.block_on(async {
+        tokio::spawn(async {
+            loop {
+                for secs in 0..10 {
+                    tokio::spawn(async move {
+                        tokio::time::sleep(tokio::time::Duration::from_secs(secs)).await;
+                    });
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+            }
+        });
+
+        tokio::signal::ctrl_c().await.unwrap();
+    });
+
+    Ok(())
+}
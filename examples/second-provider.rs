@@ -0,0 +1,48 @@
+// Copyright 2025 Oxide Computer Company
+
+//! A program that defines its own USDT provider alongside `tokio-dtrace`'s,
+//! and registers the probes for both providers with a single call to
+//! `usdt::register_probes()`.
+//!
+//! Try running this program and then running the following with its PID:
+//!
+//! ```console
+//! $ pfexec dtrace -n 'tokio$target:::task-spawn,myapp$target:::work-done' -p <PID>
+//! ```
+
+#[usdt::provider(provider = "myapp")]
+#[allow(non_snake_case)]
+mod myapp {
+    fn work__done(iteration: u64) {}
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_time().enable_io();
+
+    // Register `tokio-dtrace`'s runtime hooks, but don't register any USDT
+    // probes with DTrace yet.
+    tokio_dtrace::register_hooks_without_probe_registration(&mut builder)?;
+
+    // Now that every provider in the program has been defined, register all of
+    // their probes, including `tokio-dtrace`'s, in one call. If this call is
+    // omitted, none of the probes will fire.
+    usdt::register_probes()?;
+
+    let rt = builder.build()?;
+    rt.block_on(async {
+        tokio::spawn(async {
+            for iteration in 0.. {
+                tokio::spawn(async move {
+                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                    myapp::work__done!(|| iteration);
+                });
+                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            }
+        });
+
+        tokio::signal::ctrl_c().await.unwrap();
+    });
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+// Copyright 2025 Oxide Computer Company
+
+//! The `#[tokio_dtrace::main]` attribute macro.
+//!
+//! This crate is an implementation detail of `tokio-dtrace`. Enable its
+//! `macros` feature and use the macro as `tokio_dtrace::main`, rather than
+//! depending on this crate directly.
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{Expr, ExprLit, ItemFn, Lit, LitBool, LitInt, MetaNameValue, Path, Token};
+
+/// Runs an `async fn` on a Tokio runtime with `tokio-dtrace`'s hooks
+/// registered.
+///
+/// See the documentation of `tokio_dtrace::main` for details.
+#[proc_macro_attribute]
+pub fn main(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let item = TokenStream::from(item);
+    let expanded = syn::parse::Parser::parse2(
+        Punctuated::<MetaNameValue, Token![,]>::parse_terminated,
+        args.into(),
+    )
+    .and_then(Options::from_args)
+    .and_then(|options| expand(options, item.clone()));
+    match expanded {
+        Ok(tokens) => tokens.into(),
+        // Emit the function unchanged alongside the error, so that it is not
+        // also reported as missing wherever it is used.
+        Err(e) => {
+            let error = e.to_compile_error();
+            quote!(#error #item).into()
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Flavor {
+    CurrentThread,
+    MultiThread,
+}
+
+struct Options {
+    flavor: Flavor,
+    worker_threads: Option<LitInt>,
+    require_hooks: bool,
+    krate: Path,
+}
+
+impl Options {
+    fn from_args(args: Punctuated<MetaNameValue, Token![,]>) -> syn::Result<Self> {
+        let mut flavor = None;
+        let mut worker_threads = None;
+        let mut require_hooks = false;
+        let mut krate = None;
+        for arg in args {
+            let Some(name) = arg.path.get_ident().map(ToString::to_string) else {
+                return Err(syn::Error::new_spanned(&arg.path, "unknown option"));
+            };
+            match name.as_str() {
+                "flavor" => {
+                    let value = lit_str(&arg.value)?;
+                    flavor = Some(match value.value().as_str() {
+                        "current_thread" => Flavor::CurrentThread,
+                        "multi_thread" => Flavor::MultiThread,
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                value,
+                                "`flavor` must be \"current_thread\" or \"multi_thread\"",
+                            ));
+                        }
+                    });
+                }
+                "worker_threads" => match &arg.value {
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Int(n), ..
+                    }) => {
+                        if n.base10_parse::<usize>()? == 0 {
+                            return Err(syn::Error::new_spanned(
+                                n,
+                                "`worker_threads` may not be 0",
+                            ));
+                        }
+                        worker_threads = Some(n.clone());
+                    }
+                    value => {
+                        return Err(syn::Error::new_spanned(
+                            value,
+                            "`worker_threads` must be an integer",
+                        ));
+                    }
+                },
+                "require_hooks" => match &arg.value {
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Bool(LitBool { value, .. }),
+                        ..
+                    }) => require_hooks = *value,
+                    value => {
+                        return Err(syn::Error::new_spanned(
+                            value,
+                            "`require_hooks` must be `true` or `false`",
+                        ));
+                    }
+                },
+                "crate" => krate = Some(lit_str(&arg.value)?.parse::<Path>()?),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &arg.path,
+                        format!(
+                            "unknown option `{name}`; expected one of `flavor`, \
+                             `worker_threads`, `require_hooks`, or `crate`"
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let flavor = flavor.unwrap_or(Flavor::MultiThread);
+        if let (Flavor::CurrentThread, Some(n)) = (flavor, &worker_threads) {
+            return Err(syn::Error::new_spanned(
+                n,
+                "`worker_threads` may only be set for the \"multi_thread\" flavor",
+            ));
+        }
+        Ok(Self {
+            flavor,
+            worker_threads,
+            require_hooks,
+            krate: krate.unwrap_or_else(|| syn::parse_quote!(::tokio_dtrace)),
+        })
+    }
+}
+
+fn lit_str(value: &Expr) -> syn::Result<&syn::LitStr> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(s), ..
+        }) => Ok(s),
+        value => Err(syn::Error::new_spanned(value, "expected a string literal")),
+    }
+}
+
+fn expand(options: Options, item: TokenStream) -> syn::Result<TokenStream> {
+    let mut item: ItemFn = syn::parse2(item)?;
+    if item.sig.asyncness.take().is_none() {
+        return Err(syn::Error::new_spanned(
+            item.sig.fn_token,
+            "the `async` keyword is missing from the function declaration",
+        ));
+    }
+
+    let Options {
+        flavor,
+        worker_threads,
+        require_hooks,
+        krate,
+    } = options;
+    let flavor = match flavor {
+        Flavor::CurrentThread => quote!(#krate::Flavor::CurrentThread),
+        Flavor::MultiThread => quote!(#krate::Flavor::MultiThread),
+    };
+    let configure = match worker_threads {
+        Some(n) => quote!(|builder| {
+            builder.worker_threads(#n);
+        }),
+        None => quote!(|_| {}),
+    };
+
+    let body = &item.block;
+    item.block = syn::parse_quote! {
+        {
+            let body = async move #body;
+            #krate::__private::main_runtime(#flavor, #require_hooks, #configure).block_on(body)
+        }
+    };
+    Ok(quote!(#item))
+}
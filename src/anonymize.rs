@@ -0,0 +1,65 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Built-in mappers for anonymizing probe arguments.
+//!
+//! These functions may be passed to [`Config::id_mapper`] and
+//! [`Config::path_mapper`] to replace task IDs and source file paths with
+//! hashes before they are passed to the probes, so that traces shipped off
+//! the host do not reveal them.
+//!
+//! Values are hashed using 64-bit [FNV-1a], seeded with a value chosen
+//! randomly when the process first uses a mapper. Within a process, the same
+//! input is always mapped to the same output, so probes for the same task (or
+//! the same spawn location) can still be correlated with each other. Across
+//! processes, the outputs are unrelated, so hashes of well-known paths cannot
+//! be precomputed. Note that FNV is not a cryptographic hash: these mappers
+//! prevent values from appearing in traces verbatim, but are not intended to
+//! resist a determined attacker with access to many hashes.
+//!
+//! [`Config::id_mapper`]: crate::Config::id_mapper
+//! [`Config::path_mapper`]: crate::Config::path_mapper
+//! [FNV-1a]: https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::LazyLock;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The per-process seed mixed into the FNV offset basis.
+static SEED: LazyLock<u64> = LazyLock::new(|| RandomState::new().hash_one(FNV_OFFSET_BASIS));
+
+/// Maps a task ID to its FNV-1a hash.
+pub fn fnv_id(id: u64) -> u64 {
+    fnv(*SEED, &id.to_le_bytes())
+}
+
+/// Maps a source file path to its FNV-1a hash, formatted as 16 hexadecimal
+/// digits.
+pub fn fnv_path(path: &str) -> Cow<'_, str> {
+    Cow::Owned(format!("{:016x}", fnv(*SEED, path.as_bytes())))
+}
+
+fn fnv(seed: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS ^ seed, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv_is_consistent() {
+        // The unseeded FNV-1a hash of "a".
+        assert_eq!(fnv(0, b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        assert_eq!(fnv_id(42), fnv_id(42));
+        assert_ne!(fnv_id(42), fnv_id(43));
+        assert_eq!(fnv_path("src/main.rs"), fnv_path("src/main.rs"));
+        assert_ne!(fnv_path("src/main.rs"), fnv_path("src/lib.rs"));
+        assert_eq!(fnv_path("src/main.rs").len(), 16);
+    }
+}
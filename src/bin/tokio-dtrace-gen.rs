@@ -0,0 +1,56 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Prints one of `tokio-dtrace`'s ready-made D scripts, bound to a process.
+//!
+//! ```console
+//! $ tokio-dtrace-gen poll-latency 1234 > poll-latency.d
+//! $ pfexec dtrace -s poll-latency.d
+//! ```
+//!
+//! The scripts are those in `tokio_dtrace::scripts`, and use the probe names
+//! and argument layouts of the version of `tokio-dtrace` this program was
+//! built with. Run it without arguments to list them.
+use std::process::ExitCode;
+use tokio_dtrace::scripts;
+
+const USAGE: &str = "usage: tokio-dtrace-gen <SCRIPT> <PID> [--provider <NAME>]";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(script) => {
+            print!("{script}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}\n\nscripts:");
+            for (name, _) in scripts::ALL {
+                eprintln!("    {name}");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns the script selected by `args`, or a message describing what was
+/// wrong with them.
+fn run(mut args: impl Iterator<Item = String>) -> Result<String, String> {
+    let name = args.next().ok_or("no script given")?;
+    let script = scripts::ALL
+        .iter()
+        .find(|(script, _)| *script == name)
+        .map(|(_, script)| *script)
+        .ok_or_else(|| format!("unknown script `{name}`"))?;
+    let pid = args.next().ok_or("no PID given")?;
+    let pid = pid
+        .parse::<u32>()
+        .map_err(|e| format!("invalid PID `{pid}`: {e}"))?;
+    let provider = match args.next().as_deref() {
+        None => "tokio".to_string(),
+        Some("--provider") => args.next().ok_or("no provider name given")?,
+        Some(arg) => return Err(format!("unexpected argument `{arg}`")),
+    };
+    if let Some(arg) = args.next() {
+        return Err(format!("unexpected argument `{arg}`"));
+    }
+    Ok(scripts::instantiate(script, &provider, pid))
+}
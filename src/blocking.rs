@@ -0,0 +1,145 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Instrumented blocking tasks.
+use tokio::task::JoinHandle;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+use {
+    crate::{clock, config, worker},
+    std::sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The identifier of the next blocking task spawned by [`spawn_blocking`].
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Runs `f` on Tokio's blocking thread pool, as [`tokio::task::spawn_blocking`]
+/// does, firing probes as it is spawned, starts, and ends.
+///
+/// Tokio's runtime hooks do not fire when a blocking task is spawned or run,
+/// so work on the blocking pool is invisible to the task probes: only
+/// `task-terminate` fires for a blocking task, with no spawn to match it.
+/// Spawning blocking work using this function instead fires the following
+/// probes, whose `arg0` is an identifier assigned by `tokio-dtrace` to each
+/// blocking task, starting from 1. These identifiers are distinct from
+/// [task `Id`s](tokio::task::Id).
+///
+/// - `tokio*:::blocking-task-spawn`, when the task is spawned. `arg1`,
+///   `arg2`, and `arg3` are the file, line, and column of the call to
+///   `spawn_blocking`.
+/// - `tokio*:::blocking-task-start`, when a thread of the blocking pool
+///   begins running `f`. `arg1` is the thread's worker index, and `arg2` is
+///   the time the task spent waiting for a thread, in nanoseconds.
+/// - `tokio*:::blocking-task-end`, when `f` returns or panics. `arg1` is the
+///   time spent running `f`, in nanoseconds.
+///
+/// It also fires `tokio*:::task-kind` for the task once it has been spawned,
+/// reporting it as [`Blocking`](crate::TaskKind::Blocking), which ties its
+/// [task `Id`](tokio::task::Id) in `task-terminate` to a blocking task.
+///
+/// `blocking-task-spawn` and `task-kind` belong to the [`Minimal`](crate::Tier::Minimal)
+/// tier, and the others to the [`Normal`](crate::Tier::Normal) tier. The
+/// probes only fire when the `probes-task` feature is enabled and the hooks
+/// are registered; otherwise, this behaves exactly like
+/// `tokio::task::spawn_blocking`.
+///
+/// # Panics
+///
+/// Panics if called from outside a Tokio runtime, as
+/// [`tokio::task::spawn_blocking`] does.
+///
+/// # Examples
+///
+/// ```rust
+/// # async fn example() {
+/// let sum = tokio_dtrace::spawn_blocking(|| (0..1000u64).sum::<u64>())
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[track_caller]
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    {
+        if !crate::ENABLED.load(Ordering::Relaxed) {
+            return tokio::task::spawn_blocking(f);
+        }
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let location = std::panic::Location::caller();
+        crate::probes::blocking__task__spawn!(|| {
+            (
+                id,
+                config::probe_path(location.file()),
+                location.line(),
+                location.column(),
+            )
+        });
+        let spawned_at = clock::now();
+        let handle = tokio::task::spawn_blocking(move || {
+            let _end = End::start(id, spawned_at);
+            f()
+        });
+        crate::task_kind::spawned(handle.id(), crate::TaskKind::Blocking);
+        handle
+    }
+    #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+    {
+        tokio::task::spawn_blocking(f)
+    }
+}
+
+/// Fires `blocking-task-start` when created, and `blocking-task-end` when
+/// dropped, so that the end of a task which panics is still reported.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+struct End {
+    id: u64,
+    started_at: std::time::Instant,
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl End {
+    fn start(id: u64, spawned_at: std::time::Instant) -> Self {
+        let started_at = clock::now();
+        if config::tier_enabled(config::Tier::Normal) {
+            crate::probes::blocking__task__start!(|| {
+                let queue_nanos = started_at.saturating_duration_since(spawned_at);
+                (id, worker::current(), config::nanos(queue_nanos))
+            });
+        }
+        Self { id, started_at }
+    }
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl Drop for End {
+    fn drop(&mut self) {
+        if config::tier_enabled(config::Tier::Normal) {
+            crate::probes::blocking__task__end!(|| {
+                let run_nanos = clock::now().saturating_duration_since(self.started_at);
+                (self.id, config::nanos(run_nanos))
+            });
+        }
+    }
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    #[test]
+    fn blocking_tasks_run() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let sum = rt.block_on(async {
+            super::spawn_blocking(|| (1..=10u64).sum::<u64>())
+                .await
+                .unwrap()
+        });
+        assert_eq!(sum, 55);
+    }
+}
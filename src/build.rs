@@ -0,0 +1,137 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Building instrumented runtimes in a single call.
+use crate::{Config, RegistrationError};
+use tokio::runtime::{Builder, Runtime};
+
+/// The kind of runtime built by [`runtime`] and [`runtime_with`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Flavor {
+    /// A runtime which runs all tasks on the current thread, as built by
+    /// [`Builder::new_current_thread`].
+    CurrentThread,
+    /// A runtime with a pool of worker threads, as built by
+    /// [`Builder::new_multi_thread`]. Only available when the
+    /// `rt-multi-thread` feature is enabled.
+    #[cfg(feature = "rt-multi-thread")]
+    MultiThread,
+}
+
+/// Errors returned by [`runtime`] and [`runtime_with`].
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError {
+    /// `tokio-dtrace`'s hooks could not be registered.
+    #[error(transparent)]
+    Registration(#[from] RegistrationError),
+
+    /// The runtime could not be built.
+    #[error("failed to build the Tokio runtime: {0}")]
+    Runtime(#[from] std::io::Error),
+}
+
+/// Builds a Tokio runtime of the given `flavor`, with `tokio-dtrace`'s hooks
+/// registered.
+///
+/// This is a shorthand for creating a [`Builder`], enabling all of its I/O
+/// and time drivers (as `#[tokio::main]` does), calling [`register_hooks`],
+/// and then building the runtime. `configure` is called with the builder
+/// after the hooks are registered and before the runtime is built, so it may
+/// change any of the builder's other settings, or override some of the hooks
+/// as described in [the `hooks` module](crate::hooks).
+///
+/// ## Errors
+///
+/// Returns [`BuildError::Registration`] if the hooks could not be registered,
+/// for the same reasons as [`register_hooks`], and [`BuildError::Runtime`] if
+/// the runtime could not be built. To run without probes when registration
+/// fails, call [`register_hooks`] directly instead.
+///
+/// ## Examples
+///
+/// ```
+/// use tokio_dtrace::Flavor;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let rt = tokio_dtrace::runtime(Flavor::CurrentThread, |builder| {
+///         builder.thread_name("my-app");
+///     })?;
+///     rt.block_on(async {
+///         // Your application code here
+///     });
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// [`register_hooks`]: crate::register_hooks
+pub fn runtime(
+    flavor: Flavor,
+    configure: impl FnOnce(&mut Builder),
+) -> Result<Runtime, BuildError> {
+    runtime_with(flavor, Config::default(), configure)
+}
+
+/// Builds a Tokio runtime of the given `flavor`, with `tokio-dtrace`'s hooks
+/// registered using the settings in `config`.
+///
+/// This behaves identically to [`runtime`], except that the hooks are
+/// registered using [`register_hooks_with`](crate::register_hooks_with).
+/// Note that configuration is process-global; see the [`Config`]
+/// documentation for details.
+///
+/// ## Errors
+///
+/// This function returns the same errors as [`runtime`].
+pub fn runtime_with(
+    flavor: Flavor,
+    config: Config,
+    configure: impl FnOnce(&mut Builder),
+) -> Result<Runtime, BuildError> {
+    let mut builder = builder(flavor);
+    crate::register_hooks_with(&mut builder, config)?;
+    configure(&mut builder);
+    Ok(builder.build()?)
+}
+
+/// Builds the runtime of a function annotated with `#[tokio_dtrace::main]`.
+///
+/// If the hooks cannot be registered, this prints a warning and builds the
+/// runtime without them, unless `require_hooks` is set, in which case it
+/// panics. As `#[tokio::main]` does, it panics if the runtime cannot be
+/// built.
+#[doc(hidden)]
+pub fn main_runtime(
+    flavor: Flavor,
+    require_hooks: bool,
+    configure: impl FnOnce(&mut Builder),
+) -> Runtime {
+    let mut builder = builder(flavor);
+    // Registration fails before changing the builder, so it is left ready to
+    // build a runtime without the hooks.
+    if let Err(error) = crate::register_hooks(&mut builder) {
+        if require_hooks {
+            panic!("failed to register tokio-dtrace's hooks: {error}");
+        }
+        eprintln!(
+            "warning: failed to register tokio-dtrace's hooks, so its probes will not fire: \
+             {error}"
+        );
+    }
+    configure(&mut builder);
+    builder
+        .build()
+        .unwrap_or_else(|error| panic!("failed to build the Tokio runtime: {error}"))
+}
+
+/// Returns a builder for a runtime of the given `flavor`, with all of its I/O
+/// and time drivers enabled.
+fn builder(flavor: Flavor) -> Builder {
+    let mut builder = match flavor {
+        Flavor::CurrentThread => Builder::new_current_thread(),
+        #[cfg(feature = "rt-multi-thread")]
+        Flavor::MultiThread => Builder::new_multi_thread(),
+    };
+    builder.enable_all();
+    builder
+}
@@ -0,0 +1,95 @@
+// Copyright 2025 Oxide Computer Company
+
+//! The source of the timestamps used to measure tasks.
+//!
+//! The hooks read the time using [`now`], rather than calling
+//! [`Instant::now`] directly, so that a [`Clock`] set using
+//! [`Config::clock`](crate::Config::clock) can replace it.
+use std::fmt;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// A source of the current time, which may be set using
+/// [`Config::clock`](crate::Config::clock) to replace [`Instant::now`] as the
+/// source of the timestamps from which the task probes measure durations.
+///
+/// This is intended for deterministic tests, and for replaying traces: a clock
+/// which advances by a known step each time it is read makes every measured
+/// duration an exact multiple of the step, so that tests can assert the exact
+/// durations reported by the probes. It applies to every duration measured
+/// from a task's spawn, polls, wakes, and terminate, and from the instrumented
+/// [`sync`](crate::sync) primitives. It does not apply to the samplers, which
+/// report rates per real second, nor to the hooks' measurement of their own
+/// overhead.
+///
+/// # Thread Safety
+///
+/// The clock is read concurrently from every worker thread, on the hot path
+/// of every poll, so it should be fast. The times it returns should not go
+/// backwards; durations which would be negative are reported as zero.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicU32, Ordering};
+/// use std::time::{Duration, Instant};
+///
+/// /// A clock which advances by a millisecond each time it is read.
+/// #[derive(Debug)]
+/// struct StepClock {
+///     start: Instant,
+///     reads: AtomicU32,
+/// }
+///
+/// impl tokio_dtrace::Clock for StepClock {
+///     fn now(&self) -> Instant {
+///         let reads = self.reads.fetch_add(1, Ordering::Relaxed);
+///         self.start + Duration::from_millis(1) * reads
+///     }
+/// }
+///
+/// let clock = Box::leak(Box::new(StepClock {
+///     start: Instant::now(),
+///     reads: AtomicU32::new(0),
+/// }));
+/// let config = tokio_dtrace::Config::new().clock(clock);
+/// ```
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// The clock of the most recently applied [`Config`](crate::Config), if it set
+/// one.
+static CLOCK: RwLock<Option<&'static dyn Clock>> = RwLock::new(None);
+
+/// Whether [`CLOCK`] is set, so that reading the real clock does not take its
+/// lock.
+static CUSTOM: AtomicBool = AtomicBool::new(false);
+
+/// Returns the current time, from the configured [`Clock`] if one is set.
+#[inline]
+pub(crate) fn now() -> Instant {
+    if CUSTOM.load(Ordering::Relaxed) {
+        custom_now()
+    } else {
+        Instant::now()
+    }
+}
+
+#[inline(never)]
+fn custom_now() -> Instant {
+    match *CLOCK.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(clock) => clock.now(),
+        None => Instant::now(),
+    }
+}
+
+/// Makes `clock` the clock read by [`now`], or restores the real clock if it
+/// is `None`.
+pub(crate) fn set(clock: Option<&'static dyn Clock>) {
+    let mut current = CLOCK.write().unwrap_or_else(|e| e.into_inner());
+    *current = clock;
+    CUSTOM.store(clock.is_some(), Ordering::Relaxed);
+}
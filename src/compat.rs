@@ -0,0 +1,76 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Probes with the argument layouts of `tokio-dtrace` 0.1.0, for migrating
+//! DTrace scripts between probe schemas.
+//!
+//! Some of `tokio-dtrace`'s probes have gained arguments since they were
+//! introduced, and future releases may change their layouts in ways which are
+//! not backwards compatible. When the `compat-v1` feature is enabled, each of
+//! the original task and worker thread probes is accompanied by a variant with
+//! its original argument layout, so that scripts written against that layout
+//! can continue to work while they are migrated. These probes are provided by a
+//! separate `tokio_v1${PID}` provider, and have a `-v1` suffix:
+//!
+//! | Probe                                 | Arguments                  |
+//! |---------------------------------------|----------------------------|
+//! | `tokio_v1*:::task-spawn-v1`           | `task_id, file, line, col` |
+//! | `tokio_v1*:::task-poll-start-v1`      | `task_id, file, line, col` |
+//! | `tokio_v1*:::task-poll-end-v1`        | `task_id, file, line, col` |
+//! | `tokio_v1*:::task-terminate-v1`       | `task_id, file, line, col` |
+//! | `tokio_v1*:::worker-thread-start-v1`  | none                       |
+//! | `tokio_v1*:::worker-thread-stop-v1`   | none                       |
+//! | `tokio_v1*:::worker-thread-park-v1`   | none                       |
+//! | `tokio_v1*:::worker-thread-unpark-v1` | none                       |
+//!
+//! Each fires immediately after the corresponding probe of the `tokio`
+//! provider. The suffix ensures that a script which enables
+//! `tokio*:::task-spawn` does not also match the compatibility probes.
+//! Migrating a script to the current layout then only requires removing the
+//! provider and name suffixes, and any changes needed to use the new
+//! arguments.
+//!
+//! ## Performance
+//!
+//! While a consumer has both variants of a probe enabled, each event fires two
+//! probes, and gathers their arguments twice, which roughly doubles the cost
+//! of tracing on the hot path. When only one variant is enabled, the other
+//! costs no more than any disabled probe.
+//!
+//! This feature is intended to be enabled temporarily, for the duration of a
+//! migration. It will be removed in a future release.
+
+#[cfg(any(unix, windows))]
+#[usdt::provider(provider = "tokio_v1")]
+#[allow(non_snake_case)]
+pub(crate) mod probes {
+    fn task__spawn__v1(task_id: u64, file: &str, line: u32, col: u32) {}
+    fn task__poll__start__v1(task_id: u64, file: &str, line: u32, col: u32) {}
+    fn task__poll__end__v1(task_id: u64, file: &str, line: u32, col: u32) {}
+    fn task__terminate__v1(task_id: u64, file: &str, line: u32, col: u32) {}
+
+    fn worker__thread__start__v1() {}
+    fn worker__thread__stop__v1() {}
+    fn worker__thread__park__v1() {}
+    fn worker__thread__unpark__v1() {}
+}
+
+/// Stands in for the provider on targets which `usdt` does not support.
+#[cfg(not(any(unix, windows)))]
+#[allow(unused_imports)]
+pub(crate) mod probes {
+    macro_rules! disabled {
+        ($args:expr) => {{
+            let _ = $args;
+        }};
+    }
+
+    pub(crate) use disabled as task__spawn__v1;
+    pub(crate) use disabled as task__poll__start__v1;
+    pub(crate) use disabled as task__poll__end__v1;
+    pub(crate) use disabled as task__terminate__v1;
+
+    pub(crate) use disabled as worker__thread__start__v1;
+    pub(crate) use disabled as worker__thread__stop__v1;
+    pub(crate) use disabled as worker__thread__park__v1;
+    pub(crate) use disabled as worker__thread__unpark__v1;
+}
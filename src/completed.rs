@@ -0,0 +1,67 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Detection of tasks which are polled after they have terminated.
+//!
+//! When a task terminates, its ID is recorded in a table of recently completed
+//! tasks, which is separate from the table of live tasks. If a poll of one of
+//! those IDs then begins within [`GRACE_PERIOD`], the
+//! `tokio*:::task-poll-after-complete` probe fires. Entries older than the
+//! grace period are evicted periodically, as further tasks terminate, so the
+//! table only holds the tasks which terminated recently.
+use crate::task_table::TaskTable;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long a task's ID is remembered after it terminates.
+pub(crate) const GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// How many tasks terminate between each sweep of expired entries.
+const SWEEP_INTERVAL: u64 = 1024;
+
+static COMPLETED: LazyLock<TaskTable<Instant>> =
+    LazyLock::new(|| TaskTable::new(crate::config::task_table_shards()));
+
+static TERMINATED: AtomicU64 = AtomicU64::new(0);
+
+/// Records that the task with the given ID terminated at `now`.
+pub(crate) fn terminated(id: u64, now: Instant) {
+    COMPLETED.insert(id, now);
+    if TERMINATED.fetch_add(1, Ordering::Relaxed) % SWEEP_INTERVAL == SWEEP_INTERVAL - 1 {
+        COMPLETED
+            .retain(|_, completed_at| now.saturating_duration_since(*completed_at) < GRACE_PERIOD);
+    }
+}
+
+/// Returns whether the task with the given ID terminated within the grace
+/// period before `now`.
+pub(crate) fn is_completed(id: u64, now: Instant) -> bool {
+    COMPLETED
+        .with(id, |completed_at| {
+            now.saturating_duration_since(*completed_at) < GRACE_PERIOD
+        })
+        .unwrap_or(false)
+}
+
+/// Forgets every completed task.
+pub(crate) fn reset() {
+    COMPLETED.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_expires() {
+        // Task IDs are never reused, so an ID from `u64::MAX` downwards will
+        // not collide with a task spawned by another test.
+        let id = u64::MAX - 58;
+        let now = Instant::now();
+        assert!(!is_completed(id, now));
+        terminated(id, now);
+        assert!(is_completed(id, now));
+        assert!(is_completed(id, now + GRACE_PERIOD / 2));
+        assert!(!is_completed(id, now + GRACE_PERIOD));
+    }
+}
@@ -0,0 +1,1032 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Configuration for `tokio-dtrace`'s runtime hooks.
+use crate::{ProbeMask, TaskInfo};
+use std::borrow::Cow;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The blocking threshold of the most recently applied [`Config`], in
+/// nanoseconds.
+static BLOCKING_THRESHOLD_NANOS: AtomicU64 =
+    AtomicU64::new(nanos(Config::DEFAULT_BLOCKING_THRESHOLD));
+
+/// The slow poll threshold of the most recently applied [`Config`], in
+/// nanoseconds.
+static SLOW_POLL_THRESHOLD_NANOS: AtomicU64 =
+    AtomicU64::new(nanos(Config::DEFAULT_SLOW_POLL_THRESHOLD));
+
+/// The maximum path length of the most recently applied [`Config`], in bytes.
+static MAX_PATH_LEN: AtomicUsize = AtomicUsize::new(Config::DEFAULT_MAX_PATH_LEN);
+
+/// The contention threshold of the most recently applied [`Config`], in
+/// nanoseconds.
+static CONTENTION_THRESHOLD_NANOS: AtomicU64 =
+    AtomicU64::new(nanos(Config::DEFAULT_CONTENTION_THRESHOLD));
+
+/// The minimum poll count for per-poll probes of the most recently applied
+/// [`Config`].
+static MIN_POLL_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the most recently applied [`Config`] enables per-location poll
+/// statistics.
+static LOCATION_STATS: AtomicBool = AtomicBool::new(false);
+
+/// Whether the most recently applied [`Config`] enables the
+/// `task-spawn-queue-depth` probe.
+static QUEUE_DEPTH_AT_SPAWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether the most recently applied [`Config`] enables changepoint mode.
+static CHANGEPOINT_MODE: AtomicBool = AtomicBool::new(false);
+
+/// The number of task table shards of the most recently applied [`Config`].
+static TASK_TABLE_SHARDS: AtomicUsize = AtomicUsize::new(Config::DEFAULT_TASK_TABLE_SHARDS);
+
+/// Whether the most recently applied [`Config`] uses
+/// [`WorkerIndexSource::Native`].
+static NATIVE_WORKER_INDICES: AtomicBool = AtomicBool::new(false);
+
+/// The bits of the [`HookGroups`] of the most recently applied [`Config`].
+static HOOK_GROUPS: AtomicU8 = AtomicU8::new(HookGroups::ALL.0);
+
+/// The current [`Tier`], set by [`Config::tier`] or [`set_tier`].
+static TIER: AtomicU8 = AtomicU8::new(Tier::Verbose as u8);
+
+/// Maps a task ID before it is passed to a probe.
+pub type IdMapper = fn(u64) -> u64;
+
+/// Maps a source file path before it is passed to a probe.
+pub type PathMapper = fn(&str) -> Cow<'_, str>;
+
+/// The ID mapper of the most recently applied [`Config`].
+static ID_MAPPER: RwLock<Option<IdMapper>> = RwLock::new(None);
+
+/// Called by [`teardown`](crate::teardown) with the tasks which were still
+/// alive.
+pub type LeakHandler = fn(&[TaskInfo]);
+
+/// The shutdown leak callback of the most recently applied [`Config`].
+static ON_SHUTDOWN_LEAKS: RwLock<Option<LeakHandler>> = RwLock::new(None);
+
+/// The path mapper of the most recently applied [`Config`].
+static PATH_MAPPER: RwLock<Option<PathMapper>> = RwLock::new(None);
+
+/// Prepended to a source file path which has been truncated to fit within
+/// [`Config::max_path_len`].
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+const TRUNCATION_MARKER: &str = "...";
+
+/// Configures the behavior of `tokio-dtrace`'s runtime hooks.
+///
+/// A `Config` is applied when it is passed to [`register_hooks_with`]. The
+/// other registration functions apply [`Config::default()`].
+///
+/// Because `tokio-dtrace`'s hooks are plain functions, rather than closures
+/// capturing per-runtime state, configuration is **process-global**: if
+/// multiple runtimes are built with `tokio-dtrace`'s hooks, the configuration
+/// applied by the most recent registration is used by all of them.
+///
+/// ## Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut builder = tokio::runtime::Builder::new_multi_thread();
+///     let config = tokio_dtrace::Config::new()
+///         .blocking_threshold(Duration::from_millis(50));
+///     tokio_dtrace::register_hooks_with(&mut builder, config)?;
+///
+///     let rt = builder.enable_all().build()?;
+///     rt.block_on(async {
+///         // Your application code here
+///     });
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// [`register_hooks_with`]: crate::register_hooks_with
+#[derive(Clone, Debug)]
+pub struct Config {
+    blocking_threshold: Duration,
+    slow_poll_threshold: Duration,
+    max_path_len: usize,
+    on_consumer_change: Option<fn(bool)>,
+    contention_threshold: Duration,
+    task_table_shards: usize,
+    panic_hook: bool,
+    worker_index_source: WorkerIndexSource,
+    id_mapper: Option<IdMapper>,
+    path_mapper: Option<PathMapper>,
+    tier: Tier,
+    min_poll_count: u64,
+    location_stats: bool,
+    on_shutdown_leaks: Option<LeakHandler>,
+    rate_limits: [u32; ProbeMask::COUNT],
+    mpsc_sink: usize,
+    queue_depth_at_spawn: bool,
+    changepoint_mode: bool,
+    hook_groups: HookGroups,
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    task_store: Option<&'static dyn crate::TaskStore>,
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    clock: Option<&'static dyn crate::Clock>,
+}
+
+impl Config {
+    /// The default value of [`Config::blocking_threshold`].
+    pub const DEFAULT_BLOCKING_THRESHOLD: Duration = Duration::from_millis(100);
+
+    /// The default value of [`Config::slow_poll_threshold`].
+    pub const DEFAULT_SLOW_POLL_THRESHOLD: Duration = Duration::from_millis(10);
+
+    /// The default value of [`Config::max_path_len`].
+    pub const DEFAULT_MAX_PATH_LEN: usize = 256;
+
+    /// The default value of [`Config::contention_threshold`].
+    pub const DEFAULT_CONTENTION_THRESHOLD: Duration = Duration::from_micros(10);
+
+    /// The default value of [`Config::task_table_shards`].
+    pub const DEFAULT_TASK_TABLE_SHARDS: usize = 64;
+
+    /// Returns a new `Config` with the default settings.
+    pub const fn new() -> Self {
+        Self {
+            blocking_threshold: Self::DEFAULT_BLOCKING_THRESHOLD,
+            slow_poll_threshold: Self::DEFAULT_SLOW_POLL_THRESHOLD,
+            max_path_len: Self::DEFAULT_MAX_PATH_LEN,
+            on_consumer_change: None,
+            contention_threshold: Self::DEFAULT_CONTENTION_THRESHOLD,
+            task_table_shards: Self::DEFAULT_TASK_TABLE_SHARDS,
+            panic_hook: false,
+            worker_index_source: WorkerIndexSource::Synthetic,
+            id_mapper: None,
+            path_mapper: None,
+            tier: Tier::Verbose,
+            min_poll_count: 0,
+            location_stats: false,
+            on_shutdown_leaks: None,
+            rate_limits: [0; ProbeMask::COUNT],
+            mpsc_sink: 0,
+            queue_depth_at_spawn: false,
+            changepoint_mode: false,
+            hook_groups: HookGroups::ALL,
+            #[cfg(all(tokio_unstable, feature = "probes-task"))]
+            task_store: None,
+            #[cfg(all(tokio_unstable, feature = "probes-task"))]
+            clock: None,
+        }
+    }
+
+    /// Sets the poll duration above which the `tokio*:::task-blocking-suspected`
+    /// probe fires. By default, this is
+    /// [`DEFAULT_BLOCKING_THRESHOLD`](Self::DEFAULT_BLOCKING_THRESHOLD) (100
+    /// milliseconds).
+    ///
+    /// A task which blocks its worker thread, such as by performing synchronous
+    /// I/O or waiting on a blocking lock, shows up as a single very long poll.
+    /// Note that this is only a heuristic: a poll which legitimately performs a
+    /// lot of CPU-bound work will also exceed the threshold, and a task which
+    /// blocks for less than the threshold will not be detected.
+    pub const fn blocking_threshold(mut self, threshold: Duration) -> Self {
+        self.blocking_threshold = threshold;
+        self
+    }
+
+    /// Sets the poll duration above which the `tokio*:::task-poll-slow` probe
+    /// fires. By default, this is
+    /// [`DEFAULT_SLOW_POLL_THRESHOLD`](Self::DEFAULT_SLOW_POLL_THRESHOLD) (10
+    /// milliseconds).
+    ///
+    /// Unlike the [blocking threshold](Self::blocking_threshold), which is
+    /// meant to catch tasks blocking their worker thread, this selects the
+    /// polls which are slow enough to be worth attributing to their spawn
+    /// location, without enabling the per-poll probes.
+    pub const fn slow_poll_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_poll_threshold = threshold;
+        self
+    }
+
+    /// Sets the maximum length, in bytes, of the source file paths passed to
+    /// the task probes. By default, this is
+    /// [`DEFAULT_MAX_PATH_LEN`](Self::DEFAULT_MAX_PATH_LEN) (256 bytes), which
+    /// matches DTrace's default `strsize`.
+    ///
+    /// DTrace truncates strings longer than its `strsize` option, keeping the
+    /// *beginning* of the string. For source files in deeply nested workspaces,
+    /// or in dependencies within Cargo's registry, this can lose the file name,
+    /// which is the most informative part of the path. Instead, paths longer
+    /// than this limit are truncated by `tokio-dtrace` before being passed to
+    /// the probes, keeping the *end* of the path, and prefixed with `...` to
+    /// indicate that they were truncated. The truncated path, including the
+    /// `...` prefix, is at most `len` bytes long, unless `len` is less than 3.
+    ///
+    /// This only applies to the `file` argument of the task probes. The
+    /// in-process [event stream](crate::events) and the
+    /// [serialized probes](crate::serialized) always report the full path.
+    pub const fn max_path_len(mut self, len: usize) -> Self {
+        self.max_path_len = len;
+        self
+    }
+
+    /// Sets a function to be called when a DTrace consumer attaches to, or
+    /// detaches from, `tokio-dtrace`'s probes. The function is passed `true`
+    /// when a consumer attaches, and `false` when it detaches.
+    ///
+    /// This allows an application to perform expensive bookkeeping of its own
+    /// only while the process is being traced.
+    ///
+    /// Consumers are detected by the [metrics sampler](crate::metrics), which
+    /// must be running (and requires the `probes-metrics` feature). Detection
+    /// has the granularity of the sampler's interval: a consumer is considered
+    /// attached if any of the task or worker thread probes fired while enabled
+    /// during the previous interval. This means that a consumer which only
+    /// enables other probes is not detected, and that an attached consumer may
+    /// be reported as detached if the runtime was entirely idle for an
+    /// interval. The function is called on the runtime's worker threads, and
+    /// should not block.
+    pub const fn on_consumer_change(mut self, f: fn(bool)) -> Self {
+        self.on_consumer_change = Some(f);
+        self
+    }
+
+    /// Sets the time spent waiting for a lock on the task table above which
+    /// the `tokio*:::task-table-contended` probe fires. By default, this is
+    /// [`DEFAULT_CONTENTION_THRESHOLD`](Self::DEFAULT_CONTENTION_THRESHOLD) (10
+    /// microseconds).
+    ///
+    /// The task table holds the per-task state tracked by `tokio-dtrace`'s
+    /// hooks, and is divided into [shards](Self::task_table_shards), each
+    /// protected by a lock. Lock wait times are only measured when the
+    /// `debug-contention` feature is enabled; otherwise, this setting has no
+    /// effect.
+    pub const fn contention_threshold(mut self, threshold: Duration) -> Self {
+        self.contention_threshold = threshold;
+        self
+    }
+
+    /// Sets the number of independently-locked shards in the task table. By
+    /// default, this is
+    /// [`DEFAULT_TASK_TABLE_SHARDS`](Self::DEFAULT_TASK_TABLE_SHARDS).
+    ///
+    /// Increasing the number of shards reduces contention between worker
+    /// threads which spawn and poll tasks concurrently, at the cost of a
+    /// small amount of memory per shard. Values less than 1 are treated as 1.
+    ///
+    /// The task table is created when the first task is spawned on a runtime
+    /// with `tokio-dtrace`'s hooks, so this setting only takes effect if it is
+    /// applied before then.
+    pub const fn task_table_shards(mut self, shards: usize) -> Self {
+        self.task_table_shards = shards;
+        self
+    }
+
+    /// Sets the [`TaskStore`](crate::TaskStore) in which the runtime hooks
+    /// keep their per-task state. By default, this is a map sharded into
+    /// [`task_table_shards`](Self::task_table_shards) independently-locked
+    /// parts.
+    ///
+    /// The store is called concurrently from every worker thread; see
+    /// [`TaskStore`](crate::TaskStore#thread-safety) for the requirements this
+    /// places on its implementation. The store cannot be changed once it has
+    /// been chosen: registering the hooks with this configuration fails with
+    /// [`RegistrationError::TaskStoreAlreadyChosen`] if a different store was
+    /// configured earlier, or if a task has already been spawned on a runtime
+    /// with `tokio-dtrace`'s hooks using the default store. This is only
+    /// available when the `probes-task` feature is enabled and
+    /// `tokio_unstable` is set.
+    ///
+    /// [`RegistrationError::TaskStoreAlreadyChosen`]: crate::RegistrationError::TaskStoreAlreadyChosen
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    pub const fn task_store(mut self, store: &'static dyn crate::TaskStore) -> Self {
+        self.task_store = Some(store);
+        self
+    }
+
+    /// Sets the [`Clock`](crate::Clock) from which the task probes measure
+    /// durations. By default, this is [`Instant::now`](std::time::Instant::now).
+    ///
+    /// Unlike the task store, the clock is replaced each time a configuration
+    /// is applied, so applying another configuration without a clock, such as
+    /// by calling [`register_hooks`](crate::register_hooks), restores the real
+    /// clock. This is only available when the `probes-task` feature is enabled
+    /// and `tokio_unstable` is set.
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    pub const fn clock(mut self, clock: &'static dyn crate::Clock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Sets whether `tokio-dtrace` installs a panic hook which fires the
+    /// `tokio*:::task-panic` probe when a task panics. By default, this is
+    /// `false`, as replacing the process's panic hook is left to the
+    /// application.
+    ///
+    /// The panic hook is installed using [`std::panic::set_hook`] when this
+    /// configuration is applied, and calls the panic hook that was previously
+    /// installed after firing the probe. It is only installed once per
+    /// process, and is never removed: disabling it in a later `Config` does not
+    /// uninstall it. Note that if the application sets its own panic hook
+    /// *after* registering `tokio-dtrace`'s hooks, without calling the
+    /// previous hook, the probe will not fire.
+    pub const fn panic_hook(mut self, enabled: bool) -> Self {
+        self.panic_hook = enabled;
+        self
+    }
+
+    /// Sets how the worker indices passed to the probes are assigned. By
+    /// default, this is [`WorkerIndexSource::Synthetic`].
+    ///
+    /// See [`WorkerIndexSource`] for the differences between the sources.
+    pub const fn worker_index_source(mut self, source: WorkerIndexSource) -> Self {
+        self.worker_index_source = source;
+        self
+    }
+
+    /// Sets a function which maps each task ID before it is passed to the
+    /// probes. By default, task IDs are passed unchanged.
+    ///
+    /// This allows task IDs to be anonymized, in environments where they are
+    /// considered sensitive, using a function such as
+    /// [`anonymize::fnv_id`](crate::anonymize::fnv_id). Probes for the same
+    /// task can only be correlated with each other if the function is
+    /// deterministic, mapping each ID to the same value every time it is
+    /// called.
+    ///
+    /// The function is called each time a task probe fires while enabled, on
+    /// the thread which fired it, so it adds to the cost of every traced event,
+    /// and should be fast. It applies to the probes of the `tokio` and
+    /// `tokio_v1` providers. It does *not* apply to the
+    /// [serialized probes](crate::serialized), the in-process
+    /// [event stream](crate::events), or OpenTelemetry spans, which always
+    /// report the original ID.
+    pub const fn id_mapper(mut self, f: IdMapper) -> Self {
+        self.id_mapper = Some(f);
+        self
+    }
+
+    /// Sets a function which maps each source file path before it is passed
+    /// to the probes. By default, paths are passed unchanged.
+    ///
+    /// This allows paths to be anonymized, in environments where they are
+    /// considered sensitive, using a function such as
+    /// [`anonymize::fnv_path`](crate::anonymize::fnv_path). Mapped paths are
+    /// then truncated to [`max_path_len`](Self::max_path_len). The function is
+    /// subject to the same costs and limitations as the
+    /// [ID mapper](Self::id_mapper).
+    pub const fn path_mapper(mut self, f: PathMapper) -> Self {
+        self.path_mapper = Some(f);
+        self
+    }
+
+    /// Sets which [`Tier`] of probes fire. By default, this is
+    /// [`Tier::Verbose`], so every probe fires when enabled by DTrace.
+    ///
+    /// The tier may also be changed while the runtime is running, using
+    /// [`set_tier`](crate::set_tier).
+    pub const fn tier(mut self, tier: Tier) -> Self {
+        self.tier = tier;
+        self
+    }
+
+    /// Sets the number of times a task must have been polled before the
+    /// per-poll probes fire for it. By default, this is 0, so the probes fire
+    /// for every poll.
+    ///
+    /// Tasks which are polled many times are often the interesting ones: they
+    /// may be busy, contended, or repeatedly woken without making progress.
+    /// Setting a minimum poll count keeps short-lived tasks, which are polled
+    /// only a few times, off the hot probe path entirely, focusing a trace on
+    /// the tasks which are actually busy. The per-poll probes are
+    /// `task-poll-start`, `task-poll-end`, and `task-poll-span` (including
+    /// their [compatibility](crate::compat) and
+    /// [serialized](crate::serialized) variants). They fire for a task's
+    /// polls after its first `count` polls, so the first `count` polls of even
+    /// a busy task are never reported; this is the intended tradeoff.
+    ///
+    /// Other probes, including `task-spawn`, `task-terminate`, and
+    /// `task-blocking-suspected`, and `task-poll-slow`, fire for all tasks
+    /// regardless of this
+    /// setting.
+    pub const fn min_poll_count_for_probe(mut self, count: u64) -> Self {
+        self.min_poll_count = count;
+        self
+    }
+
+    /// Sets whether cumulative poll time is tracked for each spawn location,
+    /// and reported by the [metrics sampler](crate::metrics) using the
+    /// `tokio*:::location-cumulative` probe. By default, this is `false`.
+    ///
+    /// When enabled, each spawn location is looked up in a table of the
+    /// locations seen so far each time a task is spawned, and each completed
+    /// poll adds to its location's totals. This answers "where is the runtime
+    /// spending its time?" without a consumer having to process a probe for
+    /// every poll. It requires the `probes-task` and `probes-metrics`
+    /// features; otherwise, this setting has no effect.
+    pub const fn location_stats(mut self, enabled: bool) -> Self {
+        self.location_stats = enabled;
+        self
+    }
+
+    /// Sets a function to be called by [`teardown`](crate::teardown) with the
+    /// tasks which were still alive, and are about to be discarded.
+    ///
+    /// `teardown` discards `tokio-dtrace`'s state for every task which has been
+    /// spawned but has not terminated. This callback is passed a [`TaskInfo`]
+    /// describing each of those tasks, including where it was spawned, when,
+    /// and how often it was polled, so that an application can log exactly
+    /// which spawn sites are leaking tasks. It is called once per call to
+    /// `teardown` (including when a [`HooksGuard`](crate::HooksGuard) is
+    /// dropped), on the calling thread, after the hooks have been disabled. It
+    /// is not called if there are no such tasks.
+    ///
+    /// Note that "leaked" tasks include any task which was still alive at
+    /// teardown, which may include legitimately long-running tasks, such as
+    /// background tasks which run for the lifetime of a runtime that has not
+    /// yet been dropped. Tasks which are cancelled when their runtime is
+    /// dropped terminate normally, so they are only reported if `teardown` is
+    /// called before the runtime is dropped. Tasks are only tracked when the
+    /// `probes-task` feature is enabled; otherwise, the callback is never
+    /// called.
+    pub const fn on_shutdown_leaks(mut self, f: LeakHandler) -> Self {
+        self.on_shutdown_leaks = Some(f);
+        self
+    }
+
+    /// Limits each of the given probes to firing at most `per_sec` times per
+    /// second. By default, probes are not rate limited. A limit of 0 removes
+    /// any limit previously set for the given probes.
+    ///
+    /// Sometimes a single probe, such as `worker-thread-park` on a runtime
+    /// which parks constantly, dominates a trace while the others are fine.
+    /// Rate limiting throttles only the chosen probes, so that the others
+    /// still fire for every event. Each probe is limited independently, using
+    /// a token bucket which allows bursts of up to `per_sec` fires, and then
+    /// refills at `per_sec` tokens per second; fires in excess of this are
+    /// dropped. This trades completeness for bounded overhead: scripts which
+    /// count or pair events (such as matching `task-poll-start` with
+    /// `task-poll-end`) will see gaps when the limit is hit.
+    ///
+    /// Dropped fires are counted, and reported periodically by the
+    /// [metrics sampler](crate::metrics), which fires
+    /// `tokio*:::probe-dropped` with the probe's bit (as in [`ProbeMask`]) as
+    /// `arg0` and the number of fires dropped since the previous sample as
+    /// `arg1`, so that consumers know that data was throttled. Only fires
+    /// dropped while the probe is [enabled](crate::enabled_probes) are
+    /// counted. Note that checking the limit costs an atomic operation each
+    /// time a limited probe would fire, even when DTrace has not enabled it.
+    ///
+    /// Only the probes which have a bit in [`ProbeMask`] can be rate limited.
+    pub const fn rate_limit(mut self, probes: ProbeMask, per_sec: u32) -> Self {
+        let mut bit = 0;
+        while bit < ProbeMask::COUNT {
+            if probes.bits() & (1 << bit) != 0 {
+                self.rate_limits[bit] = per_sec;
+            }
+            bit += 1;
+        }
+        self
+    }
+
+    /// Configures a lock-free ring buffer, holding at least `capacity` events,
+    /// which the runtime hooks push every event to, for a single consumer to
+    /// drain using [`events::drainer`](crate::events::drainer). By default,
+    /// there is no ring, and a `capacity` of 0 does not create one.
+    ///
+    /// Pushing an event never blocks the hook: when the ring is full, the
+    /// event is dropped, and counted in
+    /// [`Drainer::dropped`](crate::events::Drainer::dropped). The ring is
+    /// lock-free, but not wait-free: a hook claims a slot with a
+    /// compare-and-swap, which it retries if another thread claims the same
+    /// slot first, so under heavy contention a push may take several attempts.
+    /// Some hook always makes progress, however. There is one ring
+    /// per process, created the first time a configuration with a non-zero
+    /// capacity is applied; the capacities of configurations applied later are
+    /// ignored. See [the `events` module](crate::events#draining-events-from-a-ring)
+    /// for details. This requires the `event-stream` feature; otherwise, this
+    /// setting has no effect.
+    pub const fn mpsc_sink(mut self, capacity: usize) -> Self {
+        self.mpsc_sink = capacity;
+        self
+    }
+
+    /// Sets whether the `tokio*:::task-spawn-queue-depth` probe fires, reporting
+    /// the depth of the runtime's global queue as each task is spawned. By
+    /// default, this is `false`.
+    ///
+    /// The [metrics sampler](crate::metrics) can only show how congested the
+    /// runtime was on average over each interval; this correlates each
+    /// individual spawn with the runtime's congestion at that instant. The
+    /// depth is read using [`RuntimeMetrics::global_queue_depth`] on the
+    /// runtime of the current context, so it is only reported for tasks
+    /// spawned from within a runtime, and reflects the *spawning* runtime
+    /// (which is almost always also the runtime the task is spawned on).
+    ///
+    /// Reading the depth requires looking up the current runtime's handle,
+    /// which costs a thread-local access and a pair of atomic reference count
+    /// updates, in addition to an atomic load. This is only done when DTrace
+    /// has enabled the probe, but spawn-heavy programs may notice the overhead
+    /// while it is being traced. This requires the `probes-task` feature;
+    /// otherwise, this setting has no effect.
+    ///
+    /// [`RuntimeMetrics::global_queue_depth`]: tokio::runtime::RuntimeMetrics::global_queue_depth
+    pub const fn queue_depth_at_spawn(mut self, enabled: bool) -> Self {
+        self.queue_depth_at_spawn = enabled;
+        self
+    }
+
+    /// Sets whether the per-poll probes are replaced by the
+    /// `tokio*:::task-changepoint` probe, which only fires when a task's
+    /// behavior changes. By default, this is `false`.
+    ///
+    /// A long-lived task, such as a server's connection handler, may be
+    /// polled millions of times while doing nothing of interest. In
+    /// changepoint mode, the per-poll probes (as listed for
+    /// [`min_poll_count_for_probe`](Self::min_poll_count_for_probe)) do not
+    /// fire at all. Instead, `task-changepoint` fires when a poll ends, if it
+    /// marks one of the following transitions:
+    ///
+    /// - the task completed its first poll;
+    /// - the task's poll was *slow*, after a fast poll (or its first poll); or
+    /// - the task's poll was fast, after a slow poll.
+    ///
+    /// A poll is slow if it took longer than the
+    /// [blocking threshold](Self::blocking_threshold). Together with
+    /// `task-spawn` and `task-terminate`, which fire as usual, this records a
+    /// summary of each task's behavior: when it started running, and when it
+    /// began and stopped polling slowly. This trades per-poll granularity for
+    /// a trace whose size depends on how often tasks change, rather than on
+    /// how often they are polled. Note that a task which completes during its
+    /// first poll terminates before that poll ends, and so reports only
+    /// `task-terminate`.
+    ///
+    /// Whether each task's previous poll was slow is kept in `tokio-dtrace`'s
+    /// per-task state, so this mode requires the `probes-task` feature;
+    /// otherwise, this setting has no effect.
+    pub const fn changepoint_mode(mut self, enabled: bool) -> Self {
+        self.changepoint_mode = enabled;
+        self
+    }
+
+    /// Sets which groups of runtime hooks are installed on the
+    /// [`Builder`](tokio::runtime::Builder). By default, this is
+    /// [`HookGroups::ALL`].
+    ///
+    /// The poll hooks run on every poll of every task, so even when no
+    /// consumer has enabled their probes, a busy runtime pays for calling them
+    /// millions of times per second. Hooks in a group which is not selected are
+    /// never set on the builder at all, rather than being set to a function
+    /// which does nothing, so they cost nothing. For example, an application
+    /// may keep tracing spawns and terminations in production, while leaving
+    /// out the poll hooks. See [`HookGroups`] for which probes each group
+    /// fires, and what is lost when a group is left out.
+    ///
+    /// Unlike the other settings, which are read by the hooks as they run,
+    /// this only affects the builders that hooks are installed on after the
+    /// configuration is applied. Hooks for [probe families](crate#probe-families)
+    /// which are disabled by feature flags are not installed regardless of
+    /// this setting.
+    pub const fn hook_groups(mut self, groups: HookGroups) -> Self {
+        self.hook_groups = groups;
+        self
+    }
+
+    /// Makes this configuration the one used by the runtime hooks, failing
+    /// without changing any of the settings if its task store cannot be used.
+    #[cfg_attr(not(tokio_unstable), allow(dead_code))]
+    pub(crate) fn apply(&self) -> Result<(), crate::RegistrationError> {
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        if let Some(store) = self.task_store {
+            crate::task_table::set_store(store)?;
+        }
+        BLOCKING_THRESHOLD_NANOS.store(nanos(self.blocking_threshold), Ordering::Relaxed);
+        SLOW_POLL_THRESHOLD_NANOS.store(nanos(self.slow_poll_threshold), Ordering::Relaxed);
+        MAX_PATH_LEN.store(self.max_path_len, Ordering::Relaxed);
+        CONTENTION_THRESHOLD_NANOS.store(nanos(self.contention_threshold), Ordering::Relaxed);
+        TASK_TABLE_SHARDS.store(self.task_table_shards, Ordering::Relaxed);
+        MIN_POLL_COUNT.store(self.min_poll_count, Ordering::Relaxed);
+        LOCATION_STATS.store(self.location_stats, Ordering::Relaxed);
+        QUEUE_DEPTH_AT_SPAWN.store(self.queue_depth_at_spawn, Ordering::Relaxed);
+        CHANGEPOINT_MODE.store(self.changepoint_mode, Ordering::Relaxed);
+        HOOK_GROUPS.store(self.hook_groups.0, Ordering::Relaxed);
+        #[cfg(tokio_unstable)]
+        crate::rate_limit::configure(&self.rate_limits);
+        NATIVE_WORKER_INDICES.store(
+            matches!(self.worker_index_source, WorkerIndexSource::Native),
+            Ordering::Relaxed,
+        );
+        set_tier(self.tier);
+        *ID_MAPPER.write().unwrap_or_else(|e| e.into_inner()) = self.id_mapper;
+        *PATH_MAPPER.write().unwrap_or_else(|e| e.into_inner()) = self.path_mapper;
+        *ON_SHUTDOWN_LEAKS.write().unwrap_or_else(|e| e.into_inner()) = self.on_shutdown_leaks;
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        crate::clock::set(self.clock);
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        if self.panic_hook {
+            crate::panic_hook::install();
+        }
+        #[cfg(tokio_unstable)]
+        crate::consumer::set_on_change(self.on_consumer_change);
+        #[cfg(feature = "event-stream")]
+        if self.mpsc_sink > 0 {
+            crate::events::install_sink(self.mpsc_sink);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A level of tracing detail, selecting which of `tokio-dtrace`'s probes fire.
+///
+/// Each tier includes the probes of the tiers below it, so that operators can
+/// turn the amount of tracing up or down, such as during an incident, with a
+/// single setting. The tier is set by [`Config::tier`], and may be changed at
+/// any time using [`set_tier`](crate::set_tier).
+///
+/// Tiers filter the probes within the process, in addition to DTrace's own
+/// enabling: a probe outside the current tier does not fire even if a
+/// consumer has enabled it, and does not gather its arguments. The hooks'
+/// bookkeeping continues regardless of the tier, so probes which depend on it
+/// (such as `task-pickup`) are accurate as soon as the tier is raised. Probes
+/// which belong to a disabled [probe family](crate#probe-families) do not
+/// fire at any tier.
+///
+/// | Tier        | Probes |
+/// |-------------|--------|
+/// | [`Minimal`] | `task-spawn`, `task-spawn-runtime`, `task-spawn-parent`, `task-kind`, `task-spawn-scope`, `task-spawn-queue-depth`, `task-terminate`, `task-deadline-missed`, `task-name`, `task-panic`, `task-poll-after-complete`, `scope-enter`, `blocking-task-spawn`, `worker-thread-start`, `worker-thread-stop`, `worker-thread-abnormal-stop`, `worker-poll-utilization`, `runtime-heartbeat`, `runtime-shutdown`, `runtime-rates`, `runtime-alive-delta`, `runtime-metrics`, `runtime-workers-changed`, `location-cumulative`, and `probe-dropped` |
+/// | [`Normal`]  | `task-poll-start`, `task-poll-end`, `task-wake`, `task-sched-latency`, `task-changepoint`, `task-finalize-latency`, `blocking-task-start`, `blocking-task-end`, `mutex-acquire-start`, `mutex-acquire-end`, `mutex-release`, `span-enter`, `span-exit`, `event`, `task-blocking-suspected`, `task-poll-slow`, `worker-thread-park`, `worker-thread-unpark`, `runtime-idle`, and `runtime-active` |
+/// | [`Verbose`] | `task-pickup`, `task-spawn-source`, `task-poll-span`, `task-table-contended`, `worker-steal-event`, and `hook-overhead` |
+///
+/// The [serialized](crate::serialized) and [compatibility](crate::compat)
+/// variants of each probe belong to the same tier as the probe itself.
+///
+/// [`Minimal`]: Tier::Minimal
+/// [`Normal`]: Tier::Normal
+/// [`Verbose`]: Tier::Verbose
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Tier {
+    /// Probes for rare lifecycle events, which are cheap enough to leave on
+    /// at all times.
+    Minimal = 0,
+    /// Also fire the probes for each poll of a task, and each time a worker
+    /// thread parks.
+    Normal = 1,
+    /// Fire every probe.
+    Verbose = 2,
+}
+
+impl Tier {
+    const fn from_u8(tier: u8) -> Self {
+        match tier {
+            0 => Self::Minimal,
+            1 => Self::Normal,
+            _ => Self::Verbose,
+        }
+    }
+}
+
+/// Sets which [`Tier`] of probes fire, taking effect immediately on every
+/// runtime with `tokio-dtrace`'s hooks registered.
+pub fn set_tier(tier: Tier) {
+    TIER.store(tier as u8, Ordering::Relaxed);
+}
+
+/// Returns the [`Tier`] of probes which currently fire.
+pub fn tier() -> Tier {
+    Tier::from_u8(TIER.load(Ordering::Relaxed))
+}
+
+/// Returns `true` if the probes in `tier` currently fire.
+#[cfg_attr(
+    not(all(
+        tokio_unstable,
+        any(
+            feature = "probes-task",
+            feature = "probes-worker",
+            feature = "probes-metrics"
+        )
+    )),
+    allow(dead_code)
+)]
+#[inline]
+pub(crate) fn tier_enabled(tier: Tier) -> bool {
+    TIER.load(Ordering::Relaxed) >= tier as u8
+}
+
+/// Where the worker indices passed to `tokio-dtrace`'s probes (such as the
+/// `arg2` and `arg3` of `tokio*:::task-pickup`) come from.
+///
+/// Tokio does not pass the identity of the current worker to its runtime
+/// hooks, so `tokio-dtrace` must determine it separately.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WorkerIndexSource {
+    /// Worker indices are assigned by `tokio-dtrace`, in the order in which
+    /// the runtime's threads start.
+    ///
+    /// Synthetic indices are contiguous and cheap to determine, and are
+    /// assigned to every runtime thread, including the threads of Tokio's
+    /// blocking pool. However, they do not necessarily match the indices used
+    /// by Tokio itself, and are shared by all runtimes in the process (until
+    /// [`teardown`](crate::teardown) is called).
+    #[default]
+    Synthetic,
+    /// Worker indices are the indices Tokio itself uses to identify each
+    /// worker, as accepted by the `worker_*` methods of [`RuntimeMetrics`].
+    ///
+    /// Native indices correlate directly with Tokio's metrics, and with the
+    /// `tokio*:::worker-steal-event` probe. They are determined by finding the
+    /// current thread in [`RuntimeMetrics::worker_thread_id`], which is cached
+    /// once found, so they are only available on a worker thread that has
+    /// begun running its worker. Elsewhere, including on the threads of
+    /// Tokio's blocking pool, the worker index is reported as
+    /// `0xffffffffffffffff` (`u64::MAX`). As a result, native indices may not
+    /// be contiguous, and each runtime's workers are numbered from 0.
+    ///
+    /// [`RuntimeMetrics`]: tokio::runtime::RuntimeMetrics
+    /// [`RuntimeMetrics::worker_thread_id`]: https://docs.rs/tokio/latest/tokio/runtime/struct.RuntimeMetrics.html#method.worker_thread_id
+    Native,
+}
+
+/// A set of groups of `tokio-dtrace`'s runtime hooks, selecting which hooks
+/// are installed on a [`Builder`](tokio::runtime::Builder). Set by
+/// [`Config::hook_groups`].
+///
+/// | Group              | Hooks                                                                 |
+/// |--------------------|-----------------------------------------------------------------------|
+/// | [`TASK_LIFECYCLE`] | `on_task_spawn` and `on_task_terminate`                               |
+/// | [`TASK_POLL`]      | `on_before_task_poll` and `on_after_task_poll`                        |
+/// | [`WORKER`]         | `on_thread_start`, `on_thread_stop`, `on_thread_park`, and `on_thread_unpark` |
+///
+/// Each group's hooks fire the corresponding probes: `task-spawn` and
+/// `task-terminate` (and the probes which accompany them), the per-poll task
+/// probes, and the worker thread probes, respectively. Leaving out a group
+/// also leaves out the bookkeeping its hooks perform for the other groups:
+///
+/// - Without [`TASK_POLL`], tasks are never observed being polled, so their
+///   poll counts and poll times are not tracked, and the flags and
+///   `reschedules` argument of `task-terminate` are reported as if each task
+///   was never polled.
+/// - Without [`TASK_LIFECYCLE`], the poll hooks do not know when a task was
+///   spawned or that it has terminated, and treat each task as one which was
+///   spawned before the hooks were registered. In particular, `task-pickup`
+///   does not fire, and [trace IDs](crate::set_trace_id) are not inherited.
+/// - Without [`WORKER`], runtime threads are never assigned
+///   [synthetic](WorkerIndexSource::Synthetic) worker indices, so the task
+///   probes report each worker index as `0xffffffffffffffff`
+///   (`u64::MAX`).
+///
+/// [`TASK_LIFECYCLE`]: HookGroups::TASK_LIFECYCLE
+/// [`TASK_POLL`]: HookGroups::TASK_POLL
+/// [`WORKER`]: HookGroups::WORKER
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HookGroups(u8);
+
+impl HookGroups {
+    /// The task spawn and terminate hooks.
+    pub const TASK_LIFECYCLE: Self = Self(1 << 0);
+    /// The hooks called before and after each task poll.
+    pub const TASK_POLL: Self = Self(1 << 1);
+    /// The worker thread hooks.
+    pub const WORKER: Self = Self(1 << 2);
+    /// Every group of hooks.
+    pub const ALL: Self = Self(Self::TASK_LIFECYCLE.0 | Self::TASK_POLL.0 | Self::WORKER.0);
+
+    /// Returns a set containing no groups.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns `true` if this set contains every group in `other`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns a set containing the groups in both `self` and `other`.
+    ///
+    /// This is a `const` equivalent of the `|` operator.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl Default for HookGroups {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl std::ops::BitOr for HookGroups {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+/// Returns the currently configured [`HookGroups`].
+#[cfg(tokio_unstable)]
+#[inline]
+pub(crate) fn hook_groups() -> HookGroups {
+    HookGroups(HOOK_GROUPS.load(Ordering::Relaxed))
+}
+
+/// Returns the currently configured blocking threshold, in nanoseconds.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[inline]
+pub(crate) fn blocking_threshold_nanos() -> u64 {
+    BLOCKING_THRESHOLD_NANOS.load(Ordering::Relaxed)
+}
+
+/// Returns the currently configured slow poll threshold, in nanoseconds.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[inline]
+pub(crate) fn slow_poll_threshold_nanos() -> u64 {
+    SLOW_POLL_THRESHOLD_NANOS.load(Ordering::Relaxed)
+}
+
+/// Returns whether the per-poll probes should fire for a task's poll, given
+/// the number of times it has been polled, including that poll.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[inline]
+pub(crate) fn poll_count_traced(poll_count: u64) -> bool {
+    poll_count > MIN_POLL_COUNT.load(Ordering::Relaxed) && !changepoint_mode()
+}
+
+/// Returns whether changepoint mode is currently enabled.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[inline]
+pub(crate) fn changepoint_mode() -> bool {
+    CHANGEPOINT_MODE.load(Ordering::Relaxed)
+}
+
+/// Returns whether the `task-spawn-queue-depth` probe is currently enabled.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[inline]
+pub(crate) fn queue_depth_at_spawn() -> bool {
+    QUEUE_DEPTH_AT_SPAWN.load(Ordering::Relaxed)
+}
+
+/// Returns whether per-location poll statistics are currently enabled.
+#[cfg(all(tokio_unstable, feature = "probes-task", feature = "probes-metrics"))]
+#[inline]
+pub(crate) fn location_stats() -> bool {
+    LOCATION_STATS.load(Ordering::Relaxed)
+}
+
+/// Returns the currently configured shutdown leak callback.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn on_shutdown_leaks() -> Option<LeakHandler> {
+    *ON_SHUTDOWN_LEAKS.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the currently configured contention threshold, in nanoseconds.
+#[cfg(all(tokio_unstable, feature = "debug-contention"))]
+#[inline]
+pub(crate) fn contention_threshold_nanos() -> u64 {
+    CONTENTION_THRESHOLD_NANOS.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if the currently configured [`WorkerIndexSource`] is
+/// [`WorkerIndexSource::Native`].
+#[cfg(all(
+    tokio_unstable,
+    any(feature = "probes-task", feature = "debug-overhead")
+))]
+#[inline]
+pub(crate) fn native_worker_indices() -> bool {
+    NATIVE_WORKER_INDICES.load(Ordering::Relaxed)
+}
+
+/// Returns the currently configured number of task table shards.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn task_table_shards() -> usize {
+    TASK_TABLE_SHARDS.load(Ordering::Relaxed).max(1)
+}
+
+/// Returns `id` mapped by the currently configured ID mapper.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[inline]
+pub(crate) fn probe_id(id: u64) -> u64 {
+    match *ID_MAPPER.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(map) => map(id),
+        None => id,
+    }
+}
+
+/// Returns `path` mapped by the currently configured path mapper, and truncated
+/// to the currently configured maximum path length.
+///
+/// Spawn locations are `'static`, so a path which is neither mapped nor
+/// truncated is borrowed rather than copied, and passing it to a probe does not
+/// allocate.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn probe_path(path: &'static str) -> Cow<'static, str> {
+    let mapper = *PATH_MAPPER.read().unwrap_or_else(|e| e.into_inner());
+    let path = match mapper {
+        Some(map) => map(path),
+        None => Cow::Borrowed(path),
+    };
+    let max_len = MAX_PATH_LEN.load(Ordering::Relaxed);
+    match path {
+        Cow::Borrowed(path) => truncate_path_to(path, max_len),
+        Cow::Owned(path) if path.len() <= max_len => Cow::Owned(path),
+        Cow::Owned(path) => Cow::Owned(truncate_path_to(&path, max_len).into_owned()),
+    }
+}
+
+/// Returns `path`, truncated to at most `max_len` bytes by removing its
+/// beginning and prefixing it with [`TRUNCATION_MARKER`].
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+fn truncate_path_to(path: &str, max_len: usize) -> Cow<'_, str> {
+    if path.len() <= max_len {
+        return Cow::Borrowed(path);
+    }
+    let mut start = path.len() - max_len.saturating_sub(TRUNCATION_MARKER.len());
+    while !path.is_char_boundary(start) {
+        start += 1;
+    }
+    let mut truncated = String::with_capacity(TRUNCATION_MARKER.len() + path.len() - start);
+    truncated.push_str(TRUNCATION_MARKER);
+    truncated.push_str(&path[start..]);
+    Cow::Owned(truncated)
+}
+
+/// Converts `duration` to nanoseconds, saturating at `u64::MAX`.
+pub(crate) const fn nanos(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos();
+    if nanos > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        nanos as u64
+    }
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiers_round_trip() {
+        for tier in [Tier::Minimal, Tier::Normal, Tier::Verbose] {
+            assert_eq!(Tier::from_u8(tier as u8), tier);
+        }
+        assert!(Tier::Minimal < Tier::Normal && Tier::Normal < Tier::Verbose);
+    }
+
+    #[test]
+    fn every_probe_has_a_tier() {
+        // The probes are the functions of the provider declared in `lib.rs`.
+        let lib = include_str!("lib.rs");
+        let start = lib.find("mod probes {").unwrap();
+        let end = start + lib[start..].find("\n}\n").unwrap();
+        let config = include_str!("config.rs");
+        let table = config
+            .lines()
+            .filter(|line| line.starts_with("/// | [`"))
+            .collect::<String>();
+        let probes = lib[start..end]
+            .split("fn ")
+            .skip(1)
+            .map(|probe| probe[..probe.find('(').unwrap()].replace("__", "-"))
+            .collect::<Vec<_>>();
+        assert!(probes.len() > 40);
+        for probe in probes {
+            assert!(
+                table.contains(&format!("`{probe}`")),
+                "`{probe}` is missing from the table of tiers"
+            );
+        }
+    }
+
+    #[test]
+    fn paths_are_truncated_from_the_start() {
+        assert_eq!(truncate_path_to("src/lib.rs", 10), "src/lib.rs");
+        assert_eq!(truncate_path_to("src/lib.rs", 9), "...lib.rs");
+        assert_eq!(
+            truncate_path_to("a/very/long/path/main.rs", 10),
+            "...main.rs"
+        );
+        assert_eq!(truncate_path_to("src/lib.rs", 0), "...");
+        // Truncation never splits a multi-byte character.
+        assert_eq!(truncate_path_to("ünïcödé.rs", 8), "...é.rs");
+        assert_eq!(truncate_path_to("ünïcödé.rs", 7), "....rs");
+    }
+
+    #[test]
+    fn short_paths_are_borrowed() {
+        assert!(matches!(
+            truncate_path_to("src/lib.rs", 10),
+            Cow::Borrowed("src/lib.rs")
+        ));
+        assert!(matches!(probe_path(file!()), Cow::Borrowed(_)));
+    }
+}
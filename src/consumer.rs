@@ -0,0 +1,131 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Detection of whether a DTrace consumer is attached.
+//!
+//! USDT probes do not provide a way to ask whether a consumer has enabled
+//! them. However, the closure that constructs a probe's arguments is only
+//! called when the probe is enabled, so `tokio-dtrace`'s hooks call
+//! [`observe`] from within those closures, recording which probe fired.
+//! Periodically calling [`poll`] then reveals which probes fired while enabled
+//! since the previous call.
+use crate::ProbeMask;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static DETECTOR: Detector = Detector::new();
+
+/// The callback set by [`Config::on_consumer_change`](crate::Config::on_consumer_change).
+static ON_CHANGE: RwLock<Option<fn(bool)>> = RwLock::new(None);
+
+/// Records that an enabled probe has fired.
+///
+/// This should only be called from within a probe's argument closure.
+#[cfg_attr(
+    not(any(feature = "probes-task", feature = "probes-worker")),
+    allow(dead_code)
+)]
+#[inline]
+pub(crate) fn observe(probe: ProbeMask) {
+    DETECTOR.observe(probe);
+}
+
+/// Checks whether a consumer has attached or detached since the previous call,
+/// and if so, calls the callback set by the current [`Config`](crate::Config).
+#[cfg_attr(not(feature = "probes-metrics"), allow(dead_code))]
+pub(crate) fn poll() {
+    if let Some(attached) = DETECTOR.poll() {
+        let on_change = *ON_CHANGE.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(on_change) = on_change {
+            on_change(attached);
+        }
+    }
+}
+
+/// Returns the probes which fired while enabled since the previous call to
+/// [`poll`], or during the interval before it.
+pub(crate) fn enabled() -> ProbeMask {
+    DETECTOR.enabled()
+}
+
+/// Sets the callback called by [`poll`].
+pub(crate) fn set_on_change(on_change: Option<fn(bool)>) {
+    *ON_CHANGE.write().unwrap_or_else(|e| e.into_inner()) = on_change;
+}
+
+struct Detector {
+    /// The probes which fired while enabled since the last call to
+    /// [`Detector::poll`].
+    observed: AtomicU32,
+    /// The probes which fired while enabled between the previous two calls to
+    /// [`Detector::poll`].
+    previous: AtomicU32,
+}
+
+impl Detector {
+    const fn new() -> Self {
+        Self {
+            observed: AtomicU32::new(0),
+            previous: AtomicU32::new(0),
+        }
+    }
+
+    #[inline]
+    fn observe(&self, probe: ProbeMask) {
+        // Avoid writing to the shared cache line in the common case that the
+        // probe has already been observed.
+        if self.observed.load(Ordering::Relaxed) & probe.bits() != probe.bits() {
+            self.observed.fetch_or(probe.bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns whether a consumer is attached, if that has changed since the
+    /// previous call.
+    fn poll(&self) -> Option<bool> {
+        let observed = self.observed.swap(0, Ordering::Relaxed);
+        let previous = self.previous.swap(observed, Ordering::Relaxed);
+        let attached = observed != 0;
+        ((previous != 0) != attached).then_some(attached)
+    }
+
+    fn enabled(&self) -> ProbeMask {
+        let observed = self.observed.load(Ordering::Relaxed);
+        ProbeMask::from_bits(observed | self.previous.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_transitions() {
+        let detector = Detector::new();
+        assert_eq!(detector.poll(), None);
+
+        detector.observe(ProbeMask::TASK_SPAWN);
+        assert_eq!(detector.poll(), Some(true));
+        detector.observe(ProbeMask::TASK_POLL_START);
+        assert_eq!(detector.poll(), None);
+        assert_eq!(detector.poll(), Some(false));
+        assert_eq!(detector.poll(), None);
+    }
+
+    #[test]
+    fn enabled_probes() {
+        let detector = Detector::new();
+        detector.observe(ProbeMask::TASK_SPAWN);
+        assert_eq!(detector.enabled(), ProbeMask::TASK_SPAWN);
+
+        detector.poll();
+        detector.observe(ProbeMask::WORKER_THREAD_PARK);
+        assert_eq!(
+            detector.enabled(),
+            ProbeMask::TASK_SPAWN | ProbeMask::WORKER_THREAD_PARK
+        );
+
+        detector.poll();
+        assert_eq!(detector.enabled(), ProbeMask::WORKER_THREAD_PARK);
+        detector.poll();
+        assert!(detector.enabled().is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Process-wide event counters, read and reset by [`take_counters`].
+//!
+//! These counters are independent of those reported by the
+//! [metrics sampler](crate::metrics), so taking them does not disturb the
+//! sampler's probes, and vice versa. Like the sampler's counters, they are
+//! sharded, so that threads counting events concurrently rarely contend with
+//! each other: each thread is assigned a shard the first time it counts an
+//! event.
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// The number of independently-updated sets of counters.
+const SHARDS: usize = 8;
+
+#[repr(align(64))]
+struct Shard {
+    spawns: AtomicU64,
+    terminates: AtomicU64,
+    polls: AtomicU64,
+    parks: AtomicU64,
+    drops: AtomicU64,
+}
+
+static SHARDS_BY_INDEX: [Shard; SHARDS] = [const {
+    Shard {
+        spawns: AtomicU64::new(0),
+        terminates: AtomicU64::new(0),
+        polls: AtomicU64::new(0),
+        parks: AtomicU64::new(0),
+        drops: AtomicU64::new(0),
+    }
+}; SHARDS];
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: &'static Shard =
+        &SHARDS_BY_INDEX[NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS];
+}
+
+/// Counts of the events recorded by `tokio-dtrace`'s runtime hooks, as
+/// returned by [`take_counters`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Counters {
+    /// The number of tasks spawned.
+    pub spawns: u64,
+    /// The number of tasks which terminated.
+    pub terminates: u64,
+    /// The number of task polls started.
+    pub polls: u64,
+    /// The number of times a worker thread parked.
+    pub parks: u64,
+    /// The number of probe fires dropped by
+    /// [rate limits](crate::Config::rate_limit). As with the
+    /// `tokio*:::probe-dropped` probe, only fires dropped while the probe is
+    /// [enabled](crate::enabled_probes) are counted.
+    pub drops: u64,
+}
+
+/// Returns the number of events recorded by `tokio-dtrace`'s runtime hooks
+/// since the previous call (or since the process started), and resets the
+/// counts to zero.
+///
+/// This is a building block for exporters to pull-based metrics systems,
+/// which compute their own rates from the counts taken at each scrape, rather
+/// than having them pushed by the [metrics sampler](crate::metrics). The
+/// counts include events from every runtime with `tokio-dtrace`'s hooks
+/// registered, and are recorded whether or not DTrace is attached. They are
+/// independent of the sampler, which keeps its own counts, but like any
+/// reset, taking them from more than one place splits the counts between the
+/// callers.
+///
+/// Each count is read and reset using an atomic swap, so no event is ever
+/// lost or counted twice: an event recorded concurrently with a call to this
+/// function is included either in the counts it returns, or in those returned
+/// by the next call. However, the counts are not read at a single instant, so
+/// they may not be consistent with each other; for instance, a task which is
+/// spawned and terminates during the call may have its termination counted in
+/// one interval and its spawn in the next.
+///
+/// Task events are only counted when the `probes-task` feature is enabled,
+/// and parks when the `probes-worker` feature is enabled; otherwise, those
+/// counts are always zero.
+pub fn take_counters() -> Counters {
+    SHARDS_BY_INDEX
+        .iter()
+        .fold(Counters::default(), |counters, shard| Counters {
+            spawns: counters.spawns + shard.spawns.swap(0, Ordering::Relaxed),
+            terminates: counters.terminates + shard.terminates.swap(0, Ordering::Relaxed),
+            polls: counters.polls + shard.polls.swap(0, Ordering::Relaxed),
+            parks: counters.parks + shard.parks.swap(0, Ordering::Relaxed),
+            drops: counters.drops + shard.drops.swap(0, Ordering::Relaxed),
+        })
+}
+
+#[inline]
+fn count(counter: impl FnOnce(&Shard) -> &AtomicU64) {
+    // This fails only while the thread's thread-locals are being destroyed.
+    let _ = SHARD.try_with(|shard| counter(shard).fetch_add(1, Ordering::Relaxed));
+}
+
+/// Records that a task was spawned.
+#[cfg_attr(not(all(tokio_unstable, feature = "probes-task")), allow(dead_code))]
+#[inline]
+pub(crate) fn spawned() {
+    count(|shard| &shard.spawns);
+}
+
+/// Records that a task terminated.
+#[cfg_attr(not(all(tokio_unstable, feature = "probes-task")), allow(dead_code))]
+#[inline]
+pub(crate) fn terminated() {
+    count(|shard| &shard.terminates);
+}
+
+/// Records that a task poll started.
+#[cfg_attr(not(all(tokio_unstable, feature = "probes-task")), allow(dead_code))]
+#[inline]
+pub(crate) fn polled() {
+    count(|shard| &shard.polls);
+}
+
+/// Records that a worker thread parked.
+#[cfg_attr(not(all(tokio_unstable, feature = "probes-worker")), allow(dead_code))]
+#[inline]
+pub(crate) fn parked() {
+    count(|shard| &shard.parks);
+}
+
+/// Records that a probe fire was dropped by a rate limit.
+#[cfg_attr(
+    not(all(
+        tokio_unstable,
+        any(feature = "probes-task", feature = "probes-worker")
+    )),
+    allow(dead_code)
+)]
+#[inline]
+pub(crate) fn dropped() {
+    count(|shard| &shard.drops);
+}
@@ -0,0 +1,157 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tracks the task currently being polled on each thread.
+//!
+//! A task is never polled concurrently, and is polled entirely on one thread,
+//! so the state of the task being polled can be stored in a thread-local
+//! between the `on_before_task_poll` and `on_after_task_poll` hooks. Tasks
+//! spawned during the poll are spawned on the same thread, allowing them to
+//! inherit state from the task that spawned them.
+//!
+//! A task's poll may itself poll other tasks on the same thread, such as when
+//! it drives another runtime from within [`block_in_place`], so the task whose
+//! poll was interrupted becomes current again once the inner poll ends, along
+//! with when its poll began.
+//!
+//! [`block_in_place`]: tokio::task::block_in_place
+use std::cell::{Cell, RefCell};
+use std::panic::Location;
+use std::time::Instant;
+
+thread_local! {
+    static CURRENT: Cell<Option<CurrentTask>> = const { Cell::new(None) };
+    /// The tasks whose polls were interrupted by the poll of another task on
+    /// this thread, with the innermost last.
+    static OUTER: RefCell<Vec<CurrentTask>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Copy, Clone, Debug)]
+struct CurrentTask {
+    id: u64,
+    runtime: u64,
+    trace_id: u128,
+    scope: u32,
+    location: &'static Location<'static>,
+    /// Whether the per-poll probes fire for this poll, as set by
+    /// [`Config::min_poll_count_for_probe`].
+    ///
+    /// [`Config::min_poll_count_for_probe`]: crate::Config::min_poll_count_for_probe
+    traced: bool,
+    /// When this poll began, once the hooks have recorded it.
+    poll_start: Option<Instant>,
+}
+
+/// Records that the task with the given ID, spawned at `location` on the
+/// runtime with the given ID, is being polled on this thread, and whether the
+/// per-poll probes fire for the poll.
+pub(crate) fn enter(
+    id: u64,
+    runtime: u64,
+    trace_id: u128,
+    scope: u32,
+    location: &'static Location<'static>,
+    traced: bool,
+) {
+    let outer = CURRENT.replace(Some(CurrentTask {
+        id,
+        runtime,
+        trace_id,
+        scope,
+        location,
+        traced,
+        poll_start: None,
+    }));
+    if let Some(outer) = outer {
+        OUTER.with_borrow_mut(|tasks| tasks.push(outer));
+    }
+}
+
+/// Records when the poll of the task currently being polled on this thread
+/// began.
+pub(crate) fn set_poll_start(start: Instant) {
+    if let Some(task) = CURRENT.get() {
+        CURRENT.set(Some(CurrentTask {
+            poll_start: Some(start),
+            ..task
+        }));
+    }
+}
+
+/// Records that the task with the given ID has finished its poll on this
+/// thread, making the task whose poll it interrupted, if any, current again.
+///
+/// Returns when the poll began, if that was recorded, and whether the
+/// per-poll probes fire for it, or `None` if the task is not the one being
+/// polled, such as when the hooks were enabled during its poll.
+pub(crate) fn exit(id: u64) -> Option<(Option<Instant>, bool)> {
+    let task = CURRENT.get().filter(|task| task.id == id)?;
+    CURRENT.set(OUTER.with_borrow_mut(Vec::pop));
+    Some((task.poll_start, task.traced))
+}
+
+/// Returns when the poll of the task with the given ID began, if it is the
+/// task currently being polled on this thread.
+#[cfg(any(feature = "otel", feature = "probes-metrics", feature = "metrics"))]
+pub(crate) fn poll_start(id: u64) -> Option<Instant> {
+    CURRENT
+        .get()
+        .filter(|task| task.id == id)
+        .and_then(|task| task.poll_start)
+}
+
+/// Returns whether the per-poll probes fire for the poll in progress on this
+/// thread, which they do outside of a poll.
+pub(crate) fn traced() -> bool {
+    CURRENT.get().is_none_or(|task| task.traced)
+}
+
+/// Returns the ID of the task currently being polled on this thread.
+pub(crate) fn id() -> Option<u64> {
+    CURRENT.get().map(|task| task.id)
+}
+
+/// Returns the ID and spawn location of the task currently being polled on
+/// this thread.
+pub(crate) fn id_and_location() -> Option<(u64, &'static Location<'static>)> {
+    CURRENT.get().map(|task| (task.id, task.location))
+}
+
+/// Returns the ID of the runtime of the task currently being polled on this
+/// thread, as reported by the hooks polling it, or
+/// [`NO_RUNTIME`](crate::worker::NO_RUNTIME) if no task is being polled.
+pub(crate) fn runtime() -> u64 {
+    CURRENT
+        .get()
+        .map_or(crate::worker::NO_RUNTIME, |task| task.runtime)
+}
+
+/// Returns the trace ID of the task currently being polled on this thread, or
+/// 0 if no task is being polled or the task has no trace ID.
+pub(crate) fn trace_id() -> u128 {
+    CURRENT.get().map_or(0, |task| task.trace_id)
+}
+
+/// Sets the trace ID of the task currently being polled on this thread.
+pub(crate) fn set_trace_id(trace_id: u128) {
+    if let Some(task) = CURRENT.get() {
+        CURRENT.set(Some(CurrentTask { trace_id, ..task }));
+    }
+}
+
+/// Returns the scope of the task currently being polled on this thread, or 0
+/// if no task is being polled or the task is not in a scope.
+pub(crate) fn scope() -> u32 {
+    CURRENT.get().map_or(0, |task| task.scope)
+}
+
+/// Sets the scope of the task currently being polled on this thread, returning
+/// its previous scope.
+pub(crate) fn set_scope(scope: u32) -> u32 {
+    match CURRENT.get() {
+        Some(task) => {
+            CURRENT.set(Some(CurrentTask { scope, ..task }));
+            task.scope
+        }
+        None => 0,
+    }
+}
@@ -0,0 +1,489 @@
+// Copyright 2025 Oxide Computer Company
+
+//! An in-process stream of probe events.
+//!
+//! This module allows an application to consume the events recorded by
+//! `tokio-dtrace`'s runtime hooks from within the same process, without
+//! DTrace. This may be used to, for example, build a live debugging view of
+//! the application's own Tokio runtime.
+//!
+//! Each time one of the runtime hooks fires, a corresponding [`ProbeEvent`] is
+//! sent to every stream returned by [`event_stream`]. Events are sent
+//! regardless of whether DTrace has enabled the corresponding probe.
+//!
+//! ## Backpressure and Event Loss
+//!
+//! **The event stream is lossy.** Runtime hooks are called on the hot path of
+//! every task spawn and poll, and `tokio-dtrace` will never allow a slow
+//! consumer to block the runtime. Events are sent over a bounded
+//! [broadcast channel] with a capacity of [`EVENT_STREAM_CAPACITY`]. If a
+//! stream falls more than [`EVENT_STREAM_CAPACITY`] events behind the hooks,
+//! the *oldest* events it has not yet received are discarded, and the stream
+//! resumes with the oldest event still retained by the channel.
+//!
+//! The total number of events discarded across all streams is reported by
+//! [`dropped_events`]. Consumers that require a complete record of runtime
+//! events should use DTrace instead.
+//!
+//! When no streams exist, the hooks do not construct or send events at all.
+//! Once a stream has been created, however, *every* hook invocation pays the
+//! cost of sending an event to the channel, which involves acquiring a lock
+//! internal to the channel. This is why the event stream is only available
+//! when the `event-stream` feature flag is enabled.
+//!
+//! ## Draining Events From a Ring
+//!
+//! As an alternative to [`event_stream`], [`Config::mpsc_sink`] configures a
+//! bounded, lock-free ring buffer which the hooks push every event to, and
+//! which a single consumer drains using the [`Drainer`] returned by
+//! [`drainer`]. Unlike the broadcast channel, pushing to the ring never
+//! acquires a lock: a hook claims a slot in the ring using an atomic
+//! compare-and-swap, and never waits for the consumer, although it may retry
+//! if another hook claims the same slot first. This makes the ring
+//! better suited to forwarding every event to a dedicated thread which
+//! exports them elsewhere, such as to a file or over the network.
+//!
+//! **The ring has exactly one consumer.** There is one ring per process, and
+//! [`drainer`] returns its `Drainer` only once; the `Drainer` may be moved
+//! between threads, but not shared. Events are removed from the ring as they
+//! are received, so the ring cannot be used to fan events out to several
+//! consumers; use [`event_stream`] for that.
+//!
+//! **The ring is lossy, too.** If the consumer falls behind and the ring is
+//! full, the hooks discard the *newest* events (the ones they are trying to
+//! push), rather than the oldest. Each discarded event is counted, and the
+//! total is reported by [`Drainer::dropped`]; these drops are not included in
+//! [`dropped_events`], which only counts events discarded by streams.
+//!
+//! ## Flushing at Shutdown
+//!
+//! Events are delivered to their consumers asynchronously, so when the
+//! application shuts down, the last events recorded by the hooks may still be
+//! buffered in the ring or the broadcast channel. These are often the most
+//! interesting events, since they record the shutdown itself. To avoid losing
+//! them, shut down in this order:
+//!
+//! 1. Shut down the runtimes with hooks registered, for example by dropping
+//!    them or calling [`Runtime::shutdown_timeout`]. Tasks which are still
+//!    alive terminate as their runtime shuts down, so this records the final
+//!    events. The consumer must therefore not run on one of these runtimes:
+//!    drain the events from a dedicated thread, or from a runtime without
+//!    hooks.
+//! 2. Wait for the consumers to receive the buffered events, using
+//!    [`SinkGuard::blocking_flush`] (or [`SinkGuard::flush`] from an
+//!    asynchronous context).
+//! 3. Stop the consumers.
+//!
+//! [broadcast channel]: tokio::sync::broadcast
+//! [`Runtime::shutdown_timeout`]: tokio::runtime::Runtime::shutdown_timeout
+//! [`Config::mpsc_sink`]: crate::Config::mpsc_sink
+use crate::ring::Ring;
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
+use tokio_stream::{Stream, StreamExt};
+
+/// The maximum number of events buffered for each stream returned by
+/// [`event_stream`] before the oldest events are discarded.
+pub const EVENT_STREAM_CAPACITY: usize = 4096;
+
+static SENDER: OnceLock<broadcast::Sender<ProbeEvent>> = OnceLock::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static SINK: OnceLock<Sink> = OnceLock::new();
+static DRAINER_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The wakers of the [`SinkGuard`] flushes waiting for a consumer to receive
+/// events, and whether there are any, so that consumers only touch the wakers
+/// when a flush is in progress.
+static FLUSH_WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+static FLUSH_WAITING: AtomicBool = AtomicBool::new(false);
+
+/// The ring configured by [`Config::mpsc_sink`](crate::Config::mpsc_sink),
+/// along with the state used to wake its consumer.
+struct Sink {
+    ring: Ring<ProbeEvent>,
+    /// Set by the consumer when it is about to wait for an event, so that the
+    /// hooks only touch `waker` when the consumer is idle.
+    waiting: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+    /// Set when the [`Drainer`] is dropped, after which nothing will ever
+    /// receive the events in the ring.
+    drainer_dropped: AtomicBool,
+}
+
+/// An event recorded by one of `tokio-dtrace`'s runtime hooks.
+///
+/// Each variant corresponds to one of the DTrace probes provided by this
+/// crate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProbeEvent {
+    /// A task was spawned (`tokio*:::task-spawn`).
+    TaskSpawn(TaskEvent),
+    /// The runtime began polling a task (`tokio*:::task-poll-start`).
+    TaskPollStart(TaskEvent),
+    /// The runtime finished polling a task (`tokio*:::task-poll-end`).
+    TaskPollEnd(TaskEvent),
+    /// A task terminated (`tokio*:::task-terminate`).
+    TaskTerminate(TaskEvent),
+    /// A worker thread started (`tokio*:::worker-thread-start`).
+    WorkerThreadStart,
+    /// A worker thread is about to stop (`tokio*:::worker-thread-stop`).
+    WorkerThreadStop,
+    /// A worker thread is about to park (`tokio*:::worker-thread-park`).
+    WorkerThreadPark,
+    /// A worker thread unparked (`tokio*:::worker-thread-unpark`).
+    WorkerThreadUnpark,
+}
+
+/// Describes the task associated with a [`ProbeEvent`].
+///
+/// These fields are the same values passed as arguments to the task probes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TaskEvent {
+    /// The task's [`tokio::task::Id`], as an integer.
+    pub id: u64,
+    /// The source file in which the task was spawned.
+    pub file: &'static str,
+    /// The line number at which the task was spawned.
+    pub line: u32,
+    /// The column at which the task was spawned.
+    pub col: u32,
+}
+
+/// Returns a new [`Stream`] of events recorded by `tokio-dtrace`'s runtime
+/// hooks.
+///
+/// Each stream receives every event recorded after it was created. **Events
+/// are discarded if the stream is not consumed quickly enough**; see [the
+/// module documentation](self#backpressure-and-event-loss) for details.
+///
+/// Events are only recorded by runtimes whose hooks were registered using
+/// [`register_hooks`](crate::register_hooks) or the functions in the
+/// [`hooks`](crate::hooks) module.
+pub fn event_stream() -> impl Stream<Item = ProbeEvent> + Send + 'static {
+    let rx = SENDER
+        .get_or_init(|| broadcast::channel(EVENT_STREAM_CAPACITY).0)
+        .subscribe();
+    BroadcastStream::new(rx).filter_map(|result| {
+        notify_flushes();
+        match result {
+            Ok(event) => Some(event),
+            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                DROPPED.fetch_add(n, Ordering::Relaxed);
+                None
+            }
+        }
+    })
+}
+
+/// Returns the total number of events that have been discarded because a
+/// stream returned by [`event_stream`] fell too far behind.
+///
+/// A discarded event is counted once for each stream that did not receive it.
+pub fn dropped_events() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Returns the [`Drainer`] for the ring configured by
+/// [`Config::mpsc_sink`](crate::Config::mpsc_sink).
+///
+/// The ring has a single consumer, so this returns `Some` only the first time
+/// it is called after the ring has been configured, and `None` otherwise. See
+/// [the module documentation](self#draining-events-from-a-ring) for details.
+pub fn drainer() -> Option<Drainer> {
+    let sink = SINK.get()?;
+    (!DRAINER_TAKEN.swap(true, Ordering::AcqRel)).then_some(Drainer { sink })
+}
+
+/// The single consumer of the ring configured by
+/// [`Config::mpsc_sink`](crate::Config::mpsc_sink), returned by [`drainer`].
+#[derive(Debug)]
+pub struct Drainer {
+    sink: &'static Sink,
+}
+
+impl Drainer {
+    /// Removes the oldest event from the ring, if there is one, without
+    /// waiting.
+    pub fn try_recv(&mut self) -> Option<ProbeEvent> {
+        // SAFETY: Only one `Drainer` is ever returned by `drainer`, and it is
+        // exclusively borrowed here.
+        let event = unsafe { self.sink.ring.pop() };
+        if event.is_some() {
+            notify_flushes();
+        }
+        event
+    }
+
+    /// Removes the oldest event from the ring, blocking the current thread
+    /// until one is available.
+    ///
+    /// This must not be called from within an asynchronous context, such as
+    /// a Tokio task; use [`recv`](Self::recv) there instead.
+    pub fn blocking_recv(&mut self) -> ProbeEvent {
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match self.poll_recv(&mut cx) {
+                Poll::Ready(event) => return event,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    /// Removes the oldest event from the ring, waiting until one is
+    /// available.
+    pub async fn recv(&mut self) -> ProbeEvent {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Returns the total number of events which were discarded because the
+    /// ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.sink.ring.dropped()
+    }
+
+    /// Returns the number of events the ring can hold.
+    pub fn capacity(&self) -> usize {
+        self.sink.ring.capacity()
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<ProbeEvent> {
+        if let Some(event) = self.try_recv() {
+            return Poll::Ready(event);
+        }
+        *self.sink.waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(cx.waker().clone());
+        self.sink.waiting.store(true, Ordering::Relaxed);
+        std::sync::atomic::fence(Ordering::SeqCst);
+        // An event may have been pushed after the ring was found to be empty,
+        // but before `waiting` was set, in which case its producer will not
+        // have woken us, so check again.
+        match self.try_recv() {
+            Some(event) => {
+                self.sink.waiting.store(false, Ordering::Relaxed);
+                Poll::Ready(event)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Drainer {
+    fn drop(&mut self) {
+        self.sink.drainer_dropped.store(true, Ordering::Release);
+        notify_flushes();
+    }
+}
+
+/// Waits for the consumers of the ring and the event streams to receive the
+/// events buffered for them, returned by [`sink_guard`].
+///
+/// See [the module documentation](self#flushing-at-shutdown) for the order in
+/// which to shut down the runtime and the consumers.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SinkGuard {}
+
+/// Returns a [`SinkGuard`], which flushes the events buffered for the ring
+/// configured by [`Config::mpsc_sink`](crate::Config::mpsc_sink) and the
+/// streams returned by [`event_stream`].
+pub fn sink_guard() -> SinkGuard {
+    SinkGuard {}
+}
+
+impl SinkGuard {
+    /// Waits until every event recorded before this was called has been
+    /// received from the ring by its [`Drainer`], and every event buffered in
+    /// the broadcast channel has been received by every stream.
+    ///
+    /// Events which were discarded because a consumer fell behind are not
+    /// waited for. If the `Drainer` has been dropped, or was never taken,
+    /// the events in the ring are not waited for either, since nothing will
+    /// ever receive them. Similarly, dropping a stream discards the events
+    /// buffered for it. Otherwise, this waits for as long as the consumers
+    /// take to catch up, so it should be wrapped in a timeout if a consumer may
+    /// stop making progress, such as with [`tokio::time::timeout`].
+    pub async fn flush(&self) {
+        let ring_tail = SINK.get().map(|sink| sink.ring.tail());
+        poll_fn(|cx| poll_flush(ring_tail, cx)).await
+    }
+
+    /// Like [`flush`](Self::flush), but blocks the current thread until the
+    /// events have been received.
+    ///
+    /// This must not be called from within an asynchronous context, such as
+    /// a Tokio task; use [`flush`](Self::flush) there instead.
+    pub fn blocking_flush(&self) {
+        let ring_tail = SINK.get().map(|sink| sink.ring.tail());
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        while poll_flush(ring_tail, &mut cx).is_pending() {
+            std::thread::park();
+        }
+    }
+}
+
+/// Returns whether the ring has been drained up to `ring_tail`, and the
+/// broadcast channel has been drained, registering `cx`'s waker to be woken
+/// when a consumer receives an event if not.
+fn poll_flush(ring_tail: Option<usize>, cx: &mut Context<'_>) -> Poll<()> {
+    let flushed = || {
+        let ring_flushed = SINK.get().zip(ring_tail).is_none_or(|(sink, tail)| {
+            !DRAINER_TAKEN.load(Ordering::Acquire)
+                || sink.drainer_dropped.load(Ordering::Acquire)
+                || sink.ring.popped_to(tail)
+        });
+        ring_flushed && SENDER.get().is_none_or(|tx| tx.is_empty())
+    };
+    if flushed() {
+        return Poll::Ready(());
+    }
+    {
+        let mut wakers = FLUSH_WAKERS.lock().unwrap_or_else(|e| e.into_inner());
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+    }
+    FLUSH_WAITING.store(true, Ordering::Relaxed);
+    std::sync::atomic::fence(Ordering::SeqCst);
+    // A consumer may have received the last event after it was checked above,
+    // but before `FLUSH_WAITING` was set, in which case it will not have woken
+    // us, so check again.
+    if flushed() {
+        Poll::Ready(())
+    } else {
+        Poll::Pending
+    }
+}
+
+/// Wakes any [`SinkGuard`] flushes waiting for a consumer to receive events.
+fn notify_flushes() {
+    std::sync::atomic::fence(Ordering::SeqCst);
+    if FLUSH_WAITING.load(Ordering::Relaxed) && FLUSH_WAITING.swap(false, Ordering::AcqRel) {
+        let wakers = std::mem::take(&mut *FLUSH_WAKERS.lock().unwrap_or_else(|e| e.into_inner()));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl Sink {
+    /// Pushes `event` onto the ring, waking the consumer if it is waiting.
+    fn push(&self, event: ProbeEvent) {
+        if !self.ring.push(event) {
+            return;
+        }
+        std::sync::atomic::fence(Ordering::SeqCst);
+        if self.waiting.load(Ordering::Relaxed) && self.waiting.swap(false, Ordering::AcqRel) {
+            let waker = self.waker.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Sink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sink")
+            .field("capacity", &self.ring.capacity())
+            .field("dropped", &self.ring.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Creates the ring which [`emit`] pushes events to, if it does not already
+/// exist.
+#[cfg_attr(not(tokio_unstable), allow(dead_code))]
+pub(crate) fn install_sink(capacity: usize) {
+    SINK.get_or_init(|| Sink {
+        ring: Ring::new(capacity),
+        waiting: AtomicBool::new(false),
+        waker: Mutex::new(None),
+        drainer_dropped: AtomicBool::new(false),
+    });
+}
+
+/// Sends the event returned by `f` to any existing event streams, and pushes
+/// it to the ring configured by
+/// [`Config::mpsc_sink`](crate::Config::mpsc_sink).
+///
+/// `f` is only called if at least one stream or the ring exists.
+#[cfg_attr(
+    not(all(
+        tokio_unstable,
+        any(feature = "probes-task", feature = "probes-worker")
+    )),
+    allow(dead_code)
+)]
+#[inline]
+pub(crate) fn emit(f: impl FnOnce() -> ProbeEvent) {
+    let tx = SENDER.get().filter(|tx| tx.receiver_count() > 0);
+    let sink = SINK.get();
+    if tx.is_none() && sink.is_none() {
+        return;
+    }
+    let event = f();
+    if let Some(sink) = sink {
+        sink.push(event.clone());
+    }
+    if let Some(tx) = tx {
+        // An error here only indicates that all receivers were dropped since
+        // we checked, so the event can be ignored.
+        let _ = tx.send(event);
+    }
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_receives_task_events() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut events = event_stream();
+
+        rt.block_on(async {
+            let id = crate::hooks::id_to_u64(tokio::spawn(async {}).id());
+
+            let mut seen = Vec::new();
+            while let Some(event) = events.next().await {
+                let (kind, task) = match event {
+                    ProbeEvent::TaskSpawn(task) => ("spawn", task),
+                    ProbeEvent::TaskPollStart(task) => ("poll-start", task),
+                    ProbeEvent::TaskPollEnd(task) => ("poll-end", task),
+                    ProbeEvent::TaskTerminate(task) => ("terminate", task),
+                    _ => continue,
+                };
+                if task.id != id {
+                    continue;
+                }
+                assert_eq!(task.file, file!());
+                seen.push(kind);
+                if kind == "poll-end" {
+                    break;
+                }
+            }
+
+            assert_eq!(seen, ["spawn", "poll-start", "terminate", "poll-end"]);
+        });
+    }
+}
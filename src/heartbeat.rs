@@ -0,0 +1,172 @@
+// Copyright 2025 Oxide Computer Company
+
+//! A periodic liveness probe.
+//!
+//! The [`spawn_heartbeat`] function spawns a task which fires the
+//! `tokio*:::runtime-heartbeat` probe on a fixed schedule, regardless of what
+//! else the runtime is doing. Unlike the other probes, which only fire when
+//! the runtime does something, the heartbeat allows a DTrace consumer to
+//! notice when the runtime has stopped making progress: if the heartbeat
+//! stops firing, either the runtime has been dropped, or its worker threads
+//! are stalled (for example, because every worker is blocked in a task).
+//!
+//! ## Aligning Timelines
+//!
+//! DTrace's `timestamp` variable is monotonic, but has an arbitrary base which
+//! differs between hosts, so it cannot be used to align traces from multiple
+//! processes or hosts. To allow this, `tokio-dtrace` captures the wall-clock
+//! time once, when its hooks are first registered (or when the first heartbeat
+//! fires, if that is earlier), and every heartbeat reports it along with the
+//! monotonic time elapsed since then. The wall-clock time at which a heartbeat
+//! fired is:
+//!
+//! ```text
+//! wall = epoch_base + uptime
+//! ```
+//!
+//! where `epoch_base` is `arg1` of the `runtime-heartbeat` probe, and `uptime`
+//! is `arg2`. Any other `timestamp` recorded by the same DTrace consumer can
+//! then be converted to wall-clock time by adding its offset from the
+//! `timestamp` of a heartbeat. Because the uptime is monotonic, this
+//! conversion is unaffected by adjustments to the system clock after the base
+//! was captured; conversely, it does not reflect them.
+#[cfg(feature = "probes-heartbeat")]
+use std::sync::OnceLock;
+use std::time::Duration;
+#[cfg(feature = "probes-heartbeat")]
+use std::time::{Instant, SystemTime};
+use tokio::runtime::Handle;
+use tokio::task::AbortHandle;
+
+/// Spawns a task on the runtime referenced by `handle` which fires the
+/// `tokio*:::runtime-heartbeat` probe every `interval`.
+///
+/// `arg0` of the probe is a sequence number, which starts at 0 and increments
+/// by one with each heartbeat. `arg1` is the wall-clock time captured when
+/// `tokio-dtrace`'s hooks were registered, in nanoseconds since the Unix
+/// epoch, and `arg2` is the monotonic time elapsed since then, in nanoseconds;
+/// see [Aligning Timelines](self#aligning-timelines). If the runtime is too busy to run the heartbeat
+/// task on time, heartbeats are delayed rather than skipped, so the interval
+/// between heartbeats may be longer than `interval`.
+///
+/// The heartbeat task stops when the runtime shuts down, or when the returned
+/// [`HeartbeatGuard`] is dropped, whichever comes first. A DTrace consumer
+/// which stops seeing heartbeats should therefore assume that the runtime has
+/// either stalled or been dropped.
+///
+/// # Panics
+///
+/// The heartbeat uses [`tokio::time`], so the runtime must have been built
+/// with the time driver enabled (e.g. using
+/// [`Builder::enable_time`](tokio::runtime::Builder::enable_time)).
+/// Otherwise, the heartbeat task will panic.
+///
+/// If the `probes-heartbeat` feature is disabled, this function does nothing.
+pub fn spawn_heartbeat(handle: &Handle, interval: Duration) -> HeartbeatGuard {
+    #[cfg(feature = "probes-heartbeat")]
+    {
+        let task = handle.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            for seq in 0u64.. {
+                ticker.tick().await;
+                crate::probes::runtime__heartbeat!(|| {
+                    let epoch = epoch();
+                    let uptime = crate::config::nanos(epoch.instant.elapsed());
+                    (seq, epoch.wall_nanos, uptime)
+                });
+            }
+        });
+        HeartbeatGuard {
+            task: Some(task.abort_handle()),
+        }
+    }
+    #[cfg(not(feature = "probes-heartbeat"))]
+    {
+        let _ = (handle, interval);
+        HeartbeatGuard { task: None }
+    }
+}
+
+/// The wall-clock and monotonic times at which the epoch base was captured.
+#[cfg(feature = "probes-heartbeat")]
+#[derive(Debug)]
+struct Epoch {
+    /// Nanoseconds since the Unix epoch.
+    wall_nanos: u64,
+    instant: Instant,
+}
+
+#[cfg(feature = "probes-heartbeat")]
+static EPOCH: OnceLock<Epoch> = OnceLock::new();
+
+/// Captures the epoch base reported by the heartbeat, if it has not already
+/// been captured.
+#[cfg(feature = "probes-heartbeat")]
+#[cfg_attr(not(tokio_unstable), allow(dead_code))]
+pub(crate) fn capture_epoch() {
+    epoch();
+}
+
+#[cfg(feature = "probes-heartbeat")]
+fn epoch() -> &'static Epoch {
+    EPOCH.get_or_init(|| {
+        let wall = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        Epoch {
+            wall_nanos: crate::config::nanos(wall),
+            instant: Instant::now(),
+        }
+    })
+}
+
+/// Stops a heartbeat task spawned by [`spawn_heartbeat`] when dropped.
+#[derive(Debug)]
+#[must_use = "the heartbeat stops when the guard is dropped"]
+pub struct HeartbeatGuard {
+    task: Option<AbortHandle>,
+}
+
+impl Drop for HeartbeatGuard {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "probes-heartbeat"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch_is_captured_once() {
+        let before = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        capture_epoch();
+        let epoch = epoch();
+        assert!(std::ptr::eq(epoch, super::epoch()));
+        // Another test may have captured it first, but not before this test
+        // started by more than a few minutes.
+        assert!(epoch.wall_nanos > crate::config::nanos(before) - 600_000_000_000);
+    }
+
+    #[test]
+    fn heartbeat_stops_when_guard_dropped() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let guard = spawn_heartbeat(rt.handle(), Duration::from_millis(1));
+        let task = guard.task.clone().unwrap();
+
+        rt.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+        assert!(!task.is_finished());
+
+        drop(guard);
+        rt.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+        assert!(task.is_finished());
+    }
+}
@@ -0,0 +1,1012 @@
+// Copyright 2025 Oxide Computer Company
+
+#[cfg(all(
+    feature = "compat-v1",
+    any(feature = "probes-task", feature = "probes-worker")
+))]
+use crate::compat;
+#[cfg(feature = "probes-worker")]
+use crate::idle;
+#[cfg(feature = "otel")]
+use crate::otel;
+#[cfg(feature = "debug-overhead")]
+use crate::overhead::{self, Hook};
+use crate::{ENABLED, HookGroups, worker};
+#[cfg(any(feature = "probes-task", feature = "probes-worker"))]
+use crate::{ProbeMask, consumer};
+#[cfg(feature = "probes-task")]
+use std::borrow::Cow;
+#[cfg(feature = "probes-task")]
+use std::num::NonZeroU64;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::runtime::TaskMeta;
+
+#[cfg(any(feature = "probes-task", feature = "probes-worker"))]
+use crate::config::{self, Tier};
+#[cfg(feature = "probes-task")]
+use crate::outcome::{self, Reason};
+#[cfg(any(feature = "probes-task", feature = "probes-worker"))]
+use crate::probes;
+#[cfg(feature = "probes-task")]
+use crate::task_table::{TASKS, TERMINATE_UNTRACKED, TaskState};
+#[cfg(feature = "probes-task")]
+use crate::{SpawnSource, clock, current_task};
+
+#[cfg(all(feature = "event-stream", feature = "probes-task"))]
+use crate::events::TaskEvent;
+#[cfg(all(
+    feature = "event-stream",
+    any(feature = "probes-task", feature = "probes-worker")
+))]
+use crate::events::{self, ProbeEvent};
+#[cfg(feature = "serialized-probes")]
+use crate::serialized::{self, TaskRecord};
+
+/// Installs all of `tokio-dtrace`'s hooks on `builder`, without checking casts
+/// or registering probes.
+///
+/// This is the part of [`register_hooks`](crate::register_hooks) which wires
+/// each hook function in this module to the corresponding method of
+/// [`Builder`](tokio::runtime::Builder), for applications which call
+/// [`check_casts`](crate::check_casts) and register probes themselves, and
+/// then override some of the hooks. Hooks for probe families which are
+/// disabled by feature flags would do nothing, so they are not installed, and
+/// neither are hooks left out by [`Config::hook_groups`]. The
+/// [`Config`](crate::Config) most recently applied by a registration function
+/// (or the default configuration, if none has been) is used.
+///
+/// As with the builder's own methods, each hook installed here replaces any
+/// previously set for the same callback, so hooks which call this module's
+/// functions alongside other code should be set *after* calling this, or
+/// added to a [`HookSet`] instead.
+///
+/// # Panics
+///
+/// Panics if [`check_casts`](crate::check_casts) fails, as the hooks would
+/// otherwise be unsound. Call `check_casts` first to handle this as an error.
+///
+/// [`Config::hook_groups`]: crate::Config::hook_groups
+pub fn install_all(builder: &mut tokio::runtime::Builder) -> &mut tokio::runtime::Builder {
+    HookSet::new().install(builder)
+}
+
+/// Where a hook added to a [`HookSet`] runs, relative to `tokio-dtrace`'s own
+/// hook for the same callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// The hook runs before `tokio-dtrace`'s hook.
+    Before,
+    /// The hook runs after `tokio-dtrace`'s hook.
+    After,
+}
+
+/// A set of hooks to run alongside `tokio-dtrace`'s, installed on a
+/// [`Builder`](tokio::runtime::Builder) together.
+///
+/// Tokio only allows a single function to be set for each runtime hook, so
+/// an application which needs its own hooks as well as `tokio-dtrace`'s would
+/// otherwise have to write a wrapper function for each of them, as described
+/// in [the module documentation](self). Instead, the application's hooks can
+/// be added to a `HookSet`, each running either [before](Order::Before) or
+/// [after](Order::After) `tokio-dtrace`'s hook, in the order in which they
+/// were added. [`install`](Self::install) then installs a single function for
+/// each callback which calls them all.
+///
+/// `tokio-dtrace`'s hooks are installed as [`install_all`] installs them: so,
+/// for example, if [`Config::hook_groups`] leaves out the poll hooks, any
+/// poll hooks added here are still installed, but run alone.
+///
+/// # Examples
+///
+#[cfg_attr(all(feature = "usdt", any(unix, windows)), doc = "```")]
+#[cfg_attr(not(all(feature = "usdt", any(unix, windows))), doc = "```ignore")]
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use tokio_dtrace::hooks::{HookSet, Order};
+///
+/// static SPAWNED: AtomicU64 = AtomicU64::new(0);
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     tokio_dtrace::check_casts()?;
+///     usdt::register_probes()?;
+///
+///     let mut builder = tokio::runtime::Builder::new_multi_thread();
+///     HookSet::new()
+///         .on_task_spawn(Order::After, |_| {
+///             SPAWNED.fetch_add(1, Ordering::Relaxed);
+///         })
+///         .on_thread_start(Order::Before, || println!("starting a thread"))
+///         .install(&mut builder);
+///     let rt = builder.enable_all().build()?;
+///
+///     rt.block_on(async {
+///         // Your application code here
+///     });
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// [`Config::hook_groups`]: crate::Config::hook_groups
+#[derive(Default)]
+pub struct HookSet {
+    task_spawn: Hooks<TaskHook>,
+    before_task_poll: Hooks<TaskHook>,
+    after_task_poll: Hooks<TaskHook>,
+    task_terminate: Hooks<TaskHook>,
+    thread_start: Hooks<ThreadHook>,
+    thread_stop: Hooks<ThreadHook>,
+    thread_park: Hooks<ThreadHook>,
+    thread_unpark: Hooks<ThreadHook>,
+}
+
+type TaskHook = Box<dyn Fn(&TaskMeta<'_>) + Send + Sync>;
+type ThreadHook = Box<dyn Fn() + Send + Sync>;
+
+/// The hooks added to a [`HookSet`] for a single callback.
+struct Hooks<H> {
+    before: Vec<H>,
+    after: Vec<H>,
+}
+
+impl<H> Default for Hooks<H> {
+    fn default() -> Self {
+        Self {
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+}
+
+impl<H> Hooks<H> {
+    fn push(&mut self, order: Order, hook: H) {
+        match order {
+            Order::Before => self.before.push(hook),
+            Order::After => self.after.push(hook),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty()
+    }
+}
+
+/// Sets the builder's `$method` callback to call `$hooks` around `$dtrace`,
+/// which is `tokio-dtrace`'s hook, if it is installed. If there are no other
+/// hooks, `$dtrace` is installed directly, so that it is not called through
+/// another layer of indirection.
+macro_rules! install {
+    ($builder:ident.$method:ident($($arg:ident)?), $hooks:expr, $dtrace:expr) => {{
+        let hooks = $hooks;
+        let dtrace = $dtrace;
+        if hooks.is_empty() {
+            if let Some(dtrace) = dtrace {
+                $builder.$method(dtrace);
+            }
+        } else {
+            $builder.$method(move |$($arg)?| {
+                for hook in &hooks.before {
+                    hook($($arg)?);
+                }
+                if let Some(dtrace) = &dtrace {
+                    dtrace($($arg)?);
+                }
+                for hook in &hooks.after {
+                    hook($($arg)?);
+                }
+            });
+        }
+    }};
+}
+
+impl HookSet {
+    /// Returns a `HookSet` which installs only `tokio-dtrace`'s hooks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a hook to run when a task is spawned.
+    pub fn on_task_spawn(
+        mut self,
+        order: Order,
+        hook: impl Fn(&TaskMeta<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.task_spawn.push(order, Box::new(hook));
+        self
+    }
+
+    /// Adds a hook to run before a task is polled.
+    pub fn on_before_task_poll(
+        mut self,
+        order: Order,
+        hook: impl Fn(&TaskMeta<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.before_task_poll.push(order, Box::new(hook));
+        self
+    }
+
+    /// Adds a hook to run after a task is polled.
+    pub fn on_after_task_poll(
+        mut self,
+        order: Order,
+        hook: impl Fn(&TaskMeta<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.after_task_poll.push(order, Box::new(hook));
+        self
+    }
+
+    /// Adds a hook to run when a task terminates.
+    pub fn on_task_terminate(
+        mut self,
+        order: Order,
+        hook: impl Fn(&TaskMeta<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.task_terminate.push(order, Box::new(hook));
+        self
+    }
+
+    /// Adds a hook to run when a runtime thread starts.
+    pub fn on_thread_start(
+        mut self,
+        order: Order,
+        hook: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        self.thread_start.push(order, Box::new(hook));
+        self
+    }
+
+    /// Adds a hook to run when a runtime thread stops.
+    pub fn on_thread_stop(mut self, order: Order, hook: impl Fn() + Send + Sync + 'static) -> Self {
+        self.thread_stop.push(order, Box::new(hook));
+        self
+    }
+
+    /// Adds a hook to run when a worker thread parks.
+    pub fn on_thread_park(mut self, order: Order, hook: impl Fn() + Send + Sync + 'static) -> Self {
+        self.thread_park.push(order, Box::new(hook));
+        self
+    }
+
+    /// Adds a hook to run when a worker thread unparks.
+    pub fn on_thread_unpark(
+        mut self,
+        order: Order,
+        hook: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        self.thread_unpark.push(order, Box::new(hook));
+        self
+    }
+
+    /// Installs the hooks in this set, and `tokio-dtrace`'s, on `builder`,
+    /// without checking casts or registering probes, as [`install_all`] does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`check_casts`](crate::check_casts) fails, as the hooks would
+    /// otherwise be unsound.
+    pub fn install(self, builder: &mut tokio::runtime::Builder) -> &mut tokio::runtime::Builder {
+        if let Err(error) = crate::check_casts() {
+            panic!("{error}");
+        }
+        let groups = crate::config::hook_groups();
+        // Each builder's hooks report the ID of the runtime it builds, so that
+        // the probes of several runtimes in one process can be told apart.
+        let runtime = Arc::new(worker::RuntimeId::new());
+        let workers = groups.contains(HookGroups::WORKER);
+        let thread_probes = cfg!(feature = "probes-worker") && workers;
+        let lifecycle =
+            cfg!(feature = "probes-task") && groups.contains(HookGroups::TASK_LIFECYCLE);
+        let poll = cfg!(feature = "probes-task") && groups.contains(HookGroups::TASK_POLL);
+
+        // Worker indices are used by both probe families, so `on_thread_start`
+        // is installed even when the worker probes are disabled.
+        let thread_start = workers.then_some({
+            let runtime = runtime.clone();
+            move || thread_started(runtime.entered())
+        });
+        install!(builder.on_thread_start(), self.thread_start, thread_start);
+        let thread_stop = thread_probes.then_some(on_thread_stop as fn());
+        install!(builder.on_thread_stop(), self.thread_stop, thread_stop);
+        let thread_park = thread_probes.then_some(on_thread_park as fn());
+        install!(builder.on_thread_park(), self.thread_park, thread_park);
+        let thread_unpark = thread_probes.then_some(on_thread_unpark as fn());
+        install!(
+            builder.on_thread_unpark(),
+            self.thread_unpark,
+            thread_unpark
+        );
+
+        // The task hooks report the runtime they were installed on, rather
+        // than finding it from the thread calling them.
+        // A task may be spawned from outside of its runtime's context, but is
+        // only polled and terminated within it.
+        #[cfg(feature = "probes-task")]
+        let (task_spawn, task_terminate, before_poll, after_poll) = {
+            let hook = |f: fn(&TaskMeta<'_>, u64), entered: bool| {
+                let runtime = runtime.clone();
+                move |meta: &TaskMeta<'_>| {
+                    f(
+                        meta,
+                        if entered {
+                            runtime.entered()
+                        } else {
+                            runtime.get()
+                        },
+                    )
+                }
+            };
+            (
+                lifecycle.then(|| hook(task_spawned, false)),
+                lifecycle.then(|| hook(task_terminated, true)),
+                poll.then(|| hook(before_task_poll, true)),
+                poll.then(|| hook(after_task_poll, true)),
+            )
+        };
+        #[cfg(not(feature = "probes-task"))]
+        let (task_spawn, task_terminate, before_poll, after_poll) = (
+            lifecycle.then_some(on_task_spawn as fn(&TaskMeta<'_>)),
+            lifecycle.then_some(on_task_terminate as fn(&TaskMeta<'_>)),
+            poll.then_some(on_before_task_poll as fn(&TaskMeta<'_>)),
+            poll.then_some(on_after_task_poll as fn(&TaskMeta<'_>)),
+        );
+        install!(builder.on_task_spawn(meta), self.task_spawn, task_spawn);
+        install!(
+            builder.on_task_terminate(meta),
+            self.task_terminate,
+            task_terminate
+        );
+        install!(
+            builder.on_before_task_poll(meta),
+            self.before_task_poll,
+            before_poll
+        );
+        install!(
+            builder.on_after_task_poll(meta),
+            self.after_task_poll,
+            after_poll
+        );
+        builder
+    }
+}
+
+impl std::fmt::Debug for HookSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HookSet").finish_non_exhaustive()
+    }
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_task_spawn`].
+///
+/// Tokio does not tell its hooks which runtime they belong to, so the task
+/// probes fired by the individual hook functions report the runtime the
+/// calling thread belongs to: the runtime which started it, or whose context
+/// it has entered. A task spawned using a [`Handle`](tokio::runtime::Handle)
+/// from a thread outside of any runtime is reported as belonging to the only
+/// runtime in the process, or to runtime `0` if there are several.
+/// [`install_all`] and [`HookSet`] install hooks which report the runtime
+/// they were installed on, wherever they are called from; both report the same
+/// ID for a runtime on which they are mixed.
+#[cfg_attr(not(feature = "probes-task"), allow(unused_variables))]
+pub fn on_task_spawn(meta: &TaskMeta<'_>) {
+    #[cfg(feature = "probes-task")]
+    task_spawned(meta, worker::runtime());
+}
+
+/// Fires the probes for a task spawned on the runtime with the given ID.
+#[cfg(feature = "probes-task")]
+fn task_spawned(meta: &TaskMeta<'_>, runtime: u64) {
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "debug-overhead")]
+    let _timer = overhead::Timer::start(Hook::TaskSpawn);
+    let trace_id = current_task::trace_id();
+    let scope = current_task::scope();
+    let name = crate::task_name::take_pending();
+    let kind = crate::task_kind::take_pending();
+    crate::counters::spawned();
+    #[cfg(feature = "otel")]
+    otel::spawned();
+    #[cfg(feature = "probes-metrics")]
+    crate::rates::spawned(id_to_u64(meta.id()));
+    #[cfg(all(unix, feature = "shared-counters"))]
+    crate::shm::spawned();
+    TASKS.insert(
+        id_to_u64(meta.id()),
+        TaskState {
+            scope,
+            name: name.clone(),
+            #[cfg(feature = "metrics")]
+            location_metrics: Some(crate::metrics_rs::spawned(meta.spawned_at())),
+            // The task is counted as active until its state is dropped, which
+            // may be before it terminates if the store does not keep it.
+            #[cfg(feature = "otel")]
+            otel_active: otel::Active::new(),
+            ..TaskState::new(meta.spawned_at(), worker::current(), trace_id)
+        },
+    );
+    fire!(probes::task__spawn, limit: ProbeMask::TASK_SPAWN, || {
+        consumer::observe(ProbeMask::TASK_SPAWN);
+        with_trace_id(unpack_meta(meta), trace_id)
+    });
+    // The companions of `task-spawn` belong to its tier, and are checked
+    // against it explicitly, as `task-spawn-source` is against its own.
+    let companions = config::tier_enabled(Tier::Minimal);
+    if companions {
+        fire!(probes::task__spawn__runtime, || {
+            (runtime, config::probe_id(id_to_u64(meta.id())))
+        });
+        fire!(probes::task__spawn__parent, || {
+            let parent = current_task::id().map_or(0, config::probe_id);
+            (runtime, config::probe_id(id_to_u64(meta.id())), parent)
+        });
+        fire!(probes::task__kind, || {
+            (
+                runtime,
+                config::probe_id(id_to_u64(meta.id())),
+                u8::from(kind),
+            )
+        });
+    }
+    fire_name(meta, runtime, name.as_deref());
+    #[cfg(feature = "compat-v1")]
+    compat::probes::task__spawn__v1!(|| unpack_meta(meta));
+    if config::tier_enabled(Tier::Verbose) {
+        fire!(probes::task__spawn__source, || {
+            let source = SpawnSource::current();
+            (
+                runtime,
+                config::probe_id(id_to_u64(meta.id())),
+                u8::from(source),
+            )
+        });
+    }
+    if companions && scope != 0 {
+        fire!(probes::task__spawn__scope, || {
+            (runtime, config::probe_id(id_to_u64(meta.id())), scope)
+        });
+    }
+    if companions && config::queue_depth_at_spawn() {
+        fire!(probes::task__spawn__queue__depth, || {
+            let depth = tokio::runtime::Handle::try_current()
+                .map_or(0, |handle| handle.metrics().global_queue_depth());
+            (
+                runtime,
+                config::probe_id(id_to_u64(meta.id())),
+                depth as u64,
+            )
+        });
+    }
+    #[cfg(feature = "serialized-probes")]
+    serialized::probes::task__spawn__json!(|| TaskRecord::new(meta, trace_id));
+    #[cfg(feature = "event-stream")]
+    events::emit(|| ProbeEvent::TaskSpawn(task_event(meta)));
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_before_task_poll`].
+///
+/// The runtime reported by the task probes is found as [`on_task_spawn`]
+/// describes.
+#[cfg_attr(not(feature = "probes-task"), allow(unused_variables))]
+pub fn on_before_task_poll(meta: &TaskMeta<'_>) {
+    #[cfg(feature = "probes-task")]
+    before_task_poll(meta, worker::runtime());
+}
+
+/// Fires the probes for the start of a poll of a task on the runtime with the
+/// given ID.
+#[cfg(feature = "probes-task")]
+fn before_task_poll(meta: &TaskMeta<'_>, runtime: u64) {
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "debug-overhead")]
+    let _timer = overhead::Timer::start(Hook::BeforeTaskPoll);
+    crate::counters::polled();
+    let id = id_to_u64(meta.id());
+    #[cfg(feature = "debug-completion")]
+    if crate::completed::is_completed(id, clock::now()) {
+        fire!(probes::task__poll__after__complete, || {
+            (runtime, config::probe_id(id))
+        });
+    }
+    // The task's span is recorded in once the table's lock is released.
+    #[cfg(feature = "otel")]
+    let mut span = None;
+    let (first_poll, trace_id, scope, poll_count, name) = TASKS
+        .poll_start(id, |task| {
+            let first = task.poll_count == 0;
+            task.poll_count += 1;
+            let first_poll = first.then_some((task.spawner_worker, task.spawned_at));
+            #[cfg(feature = "otel")]
+            {
+                span = Some((task.span.clone(), task.location, task.spawned_at));
+            }
+            // The name is only needed if `task-name` fires for this poll.
+            let named =
+                config::poll_count_traced(task.poll_count) && config::tier_enabled(Tier::Normal);
+            let name = if named { task.name.clone() } else { None };
+            (first_poll, task.trace_id, task.scope, task.poll_count, name)
+        })
+        // A task which is missing from the table was spawned before the
+        // hooks were enabled, so its poll count is unknown.
+        .unwrap_or((None, 0, 0, u64::MAX, None));
+    #[cfg(feature = "otel")]
+    if let Some((span, location, spawned_at)) = span {
+        span.poll_start(id, location, spawned_at);
+    }
+    let traced = config::poll_count_traced(poll_count);
+    current_task::enter(id, runtime, trace_id, scope, meta.spawned_at(), traced);
+    if let Some((spawner_worker, spawned_at)) = first_poll
+        && config::tier_enabled(Tier::Verbose)
+    {
+        fire!(probes::task__pickup, || {
+            let queue_nanos = spawned_at.elapsed().as_nanos();
+            (
+                runtime,
+                config::probe_id(id),
+                spawner_worker,
+                worker::current(),
+                u64::try_from(queue_nanos).unwrap_or(u64::MAX),
+            )
+        });
+    }
+    if traced && config::tier_enabled(Tier::Normal) {
+        fire!(probes::task__poll__start, limit: ProbeMask::TASK_POLL_START, || {
+            consumer::observe(ProbeMask::TASK_POLL_START);
+            with_trace_id(unpack_meta(meta), trace_id)
+        });
+        fire_name(meta, runtime, name.as_deref());
+        #[cfg(feature = "compat-v1")]
+        compat::probes::task__poll__start__v1!(|| unpack_meta(meta));
+        #[cfg(feature = "serialized-probes")]
+        serialized::probes::task__poll__start__json!(|| TaskRecord::new(meta, trace_id));
+    }
+    #[cfg(feature = "tracing")]
+    if traced && config::tier_enabled(Tier::Verbose) {
+        fire!(probes::task__poll__span, || {
+            let span_id = tracing::Span::current().id().map_or(0, |id| id.into_u64());
+            (runtime, config::probe_id(id), span_id)
+        });
+    }
+    current_task::set_poll_start(clock::now());
+    #[cfg(feature = "event-stream")]
+    events::emit(|| ProbeEvent::TaskPollStart(task_event(meta)));
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_after_task_poll`].
+///
+/// The runtime reported by the task probes is found as [`on_task_spawn`]
+/// describes.
+#[cfg_attr(not(feature = "probes-task"), allow(unused_variables))]
+pub fn on_after_task_poll(meta: &TaskMeta<'_>) {
+    #[cfg(feature = "probes-task")]
+    after_task_poll(meta, worker::runtime());
+}
+
+/// Fires the probes for the end of a poll of a task on the runtime with the
+/// given ID.
+#[cfg(feature = "probes-task")]
+fn after_task_poll(meta: &TaskMeta<'_>, runtime: u64) {
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "debug-overhead")]
+    let _timer = overhead::Timer::start(Hook::AfterTaskPoll);
+    #[cfg(feature = "serialized-probes")]
+    let trace_id = current_task::trace_id();
+    // If `on_before_task_poll` did not run for this poll, the per-poll probes
+    // fire for it.
+    let (poll_start, traced) = current_task::exit(id_to_u64(meta.id())).unwrap_or((None, true));
+    // The terminate hook takes the reason of a task which terminated
+    // during this poll, so any reason left over was recorded by a wrapped
+    // future which finished within a task which did not terminate.
+    outcome::take(id_to_u64(meta.id()));
+    // If `on_before_task_poll` did not run for this poll, its duration is
+    // unknown.
+    let mut poll_duration = u64::MAX;
+    if let Some(start) = poll_start {
+        let end = clock::now();
+        let elapsed = end.saturating_duration_since(start);
+        #[cfg(feature = "poll-percentiles")]
+        crate::poll_latency::record(elapsed);
+        let id = id_to_u64(meta.id());
+        let poll_nanos = config::nanos(elapsed);
+        poll_duration = poll_nanos;
+        #[cfg(feature = "probes-metrics")]
+        crate::utilization::record(poll_nanos);
+        #[cfg(feature = "otel")]
+        otel::polled(poll_nanos);
+        let slow = poll_nanos > config::blocking_threshold_nanos();
+        // The poll is recorded in the task's span and metrics once the
+        // table's lock is released.
+        #[cfg(feature = "probes-metrics")]
+        let mut stats = None;
+        #[cfg(feature = "metrics")]
+        let mut metrics = None;
+        #[cfg(feature = "otel")]
+        let mut span = None;
+        let changepoint = TASKS.poll_end(id, |task| {
+            task.total_poll_nanos = task.total_poll_nanos.saturating_add(poll_nanos);
+            task.last_poll_end = Some(end);
+            #[cfg(feature = "probes-metrics")]
+            {
+                stats = task.location_stats;
+            }
+            #[cfg(feature = "metrics")]
+            {
+                metrics = task.location_metrics;
+            }
+            #[cfg(feature = "otel")]
+            {
+                span = Some(task.span.clone());
+            }
+            task.changepoint(slow)
+        });
+        #[cfg(feature = "probes-metrics")]
+        if let Some(stats) = stats {
+            stats.record(poll_nanos);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = metrics {
+            crate::metrics_rs::polled(metrics, poll_nanos);
+        }
+        #[cfg(feature = "otel")]
+        if let Some(span) = span {
+            span.poll_end(poll_nanos);
+        }
+        if let Some(changepoint) = changepoint.flatten()
+            && config::changepoint_mode()
+            && config::tier_enabled(Tier::Normal)
+        {
+            fire!(probes::task__changepoint, || (
+                runtime,
+                config::probe_id(id),
+                changepoint as u8,
+                poll_nanos
+            ));
+        }
+        if slow && config::tier_enabled(Tier::Normal) {
+            fire!(probes::task__blocking__suspected, || {
+                (runtime, config::probe_id(id), poll_nanos)
+            });
+        }
+        if poll_nanos > config::slow_poll_threshold_nanos() && config::tier_enabled(Tier::Normal) {
+            fire!(probes::task__poll__slow, || {
+                let (id, file, line, col) = unpack_meta(meta);
+                (runtime, id, poll_nanos, file, line, col)
+            });
+        }
+    }
+    if traced && config::tier_enabled(Tier::Normal) {
+        fire!(probes::task__poll__end, limit: ProbeMask::TASK_POLL_END, || {
+            consumer::observe(ProbeMask::TASK_POLL_END);
+            let (id, file, line, col) = unpack_meta(meta);
+            let budget_exhausted = !tokio::task::coop::has_budget_remaining();
+            (id, file, line, col, u8::from(budget_exhausted), poll_duration)
+        });
+        #[cfg(feature = "compat-v1")]
+        compat::probes::task__poll__end__v1!(|| unpack_meta(meta));
+        #[cfg(feature = "serialized-probes")]
+        serialized::probes::task__poll__end__json!(|| TaskRecord::new(meta, trace_id));
+    }
+    #[cfg(feature = "event-stream")]
+    events::emit(|| ProbeEvent::TaskPollEnd(task_event(meta)));
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_task_terminate`].
+///
+/// The runtime reported by the task probes is found as [`on_task_spawn`]
+/// describes.
+#[cfg_attr(not(feature = "probes-task"), allow(unused_variables))]
+pub fn on_task_terminate(meta: &TaskMeta<'_>) {
+    #[cfg(feature = "probes-task")]
+    task_terminated(meta, worker::runtime());
+}
+
+/// Fires the probes for a task terminating on the runtime with the given ID.
+#[cfg(feature = "probes-task")]
+fn task_terminated(meta: &TaskMeta<'_>, runtime: u64) {
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "debug-overhead")]
+    let _timer = overhead::Timer::start(Hook::TaskTerminate);
+    let task = TASKS.terminate(id_to_u64(meta.id()));
+    #[cfg(feature = "debug-completion")]
+    crate::completed::terminated(id_to_u64(meta.id()), clock::now());
+    crate::counters::terminated();
+    #[cfg(feature = "probes-metrics")]
+    crate::rates::terminated(id_to_u64(meta.id()));
+    #[cfg(all(unix, feature = "shared-counters"))]
+    crate::shm::terminated();
+    #[cfg(feature = "poll-percentiles")]
+    if let Some(task) = &task {
+        let lifetime = clock::now().saturating_duration_since(task.spawned_at);
+        crate::poll_latency::record_lifetime(lifetime);
+    }
+    // A task which completes terminates during its final poll, before
+    // `on_after_task_poll` is called, so that poll must end here.
+    #[cfg(any(feature = "otel", feature = "probes-metrics", feature = "metrics"))]
+    let final_poll_nanos = || {
+        current_task::poll_start(id_to_u64(meta.id()))
+            .map(|start| config::nanos(clock::now().saturating_duration_since(start)))
+    };
+    #[cfg(feature = "probes-metrics")]
+    if let Some(stats) = task.as_ref().and_then(|task| task.location_stats)
+        && let Some(poll_nanos) = final_poll_nanos()
+    {
+        stats.record(poll_nanos);
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(metrics) = task.as_ref().and_then(|task| task.location_metrics)
+        && let Some(poll_nanos) = final_poll_nanos()
+    {
+        crate::metrics_rs::polled(metrics, poll_nanos);
+    }
+    #[cfg(feature = "otel")]
+    if let Some(task) = &task {
+        task.span.end(final_poll_nanos());
+    }
+    // A task which completes during a poll is finalized within that poll,
+    // so only tasks which terminate between polls have a gap to report.
+    if let Some(task) = &task
+        && task.last_poll_end.is_some()
+        && current_task::id() != Some(id_to_u64(meta.id()))
+        && config::tier_enabled(Tier::Normal)
+    {
+        fire!(probes::task__finalize__latency, || {
+            let nanos = task.since_last_poll(clock::now()).map_or(0, config::nanos);
+            (runtime, config::probe_id(id_to_u64(meta.id())), nanos)
+        });
+    }
+    if let Some(over_by) = task
+        .as_ref()
+        .and_then(|task| task.deadline_missed_by(clock::now()))
+    {
+        fire!(probes::task__deadline__missed, || {
+            (
+                runtime,
+                config::probe_id(id_to_u64(meta.id())),
+                config::nanos(over_by),
+            )
+        });
+    }
+    let completed = current_task::id() == Some(id_to_u64(meta.id()));
+    let reason = outcome::take(id_to_u64(meta.id())).unwrap_or(
+        // Only cancellation terminates a task outside of its own poll.
+        // Tokio calls the terminate hook for blocking tasks from the
+        // blocking pool, outside of any poll, so only tasks which were
+        // seen being spawned can be assumed to have been cancelled.
+        if task.is_some() && !completed {
+            Reason::Cancelled
+        } else {
+            Reason::Unknown
+        },
+    );
+    fire!(probes::task__terminate, limit: ProbeMask::TASK_TERMINATE, || {
+        consumer::observe(ProbeMask::TASK_TERMINATE);
+        let (id, file, line, col) = unpack_meta(meta);
+        let reschedules = task.as_ref().map_or(0, TaskState::reschedules);
+        // A task which is missing from the table was spawned before the
+        // hooks were enabled, or is a blocking task, for which Tokio only
+        // calls the terminate hook, so how often it was polled is unknown.
+        let flags = task.as_ref().map_or(TERMINATE_UNTRACKED, |task| {
+            task.terminate_flags(completed)
+        });
+        (id, file, line, col, reschedules, flags | reason.flags())
+    });
+    fire_name(
+        meta,
+        runtime,
+        task.as_ref().and_then(|task| task.name.as_deref()),
+    );
+    #[cfg(feature = "compat-v1")]
+    compat::probes::task__terminate__v1!(|| unpack_meta(meta));
+    #[cfg(feature = "serialized-probes")]
+    serialized::probes::task__terminate__json!(|| {
+        let trace_id = task.as_ref().map_or(0, |task| task.trace_id);
+        TaskRecord::new(meta, trace_id)
+    });
+    #[cfg(feature = "event-stream")]
+    events::emit(|| ProbeEvent::TaskTerminate(task_event(meta)));
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_thread_start`].
+///
+/// The runtime reported by the thread probes is that whose context the new
+/// thread has entered, which Tokio does before calling the hook.
+pub fn on_thread_start() {
+    thread_started(worker::runtime());
+}
+
+/// Fires the probes for a thread started by the runtime with the given ID.
+fn thread_started(runtime: u64) {
+    worker::set_runtime(runtime);
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "debug-overhead")]
+    let _timer = overhead::Timer::start(Hook::ThreadStart);
+    // Worker indices are also used by the task probes, so they are assigned
+    // even when the worker probes are disabled.
+    worker::register_current(runtime);
+    #[cfg(feature = "probes-worker")]
+    {
+        worker::with_identity(|runtime, name, index| {
+            fire!(probes::worker__thread__start, limit: ProbeMask::WORKER_THREAD_START, || {
+                consumer::observe(ProbeMask::WORKER_THREAD_START);
+                (runtime, name, index, worker::pool(true))
+            });
+        });
+        #[cfg(feature = "compat-v1")]
+        compat::probes::worker__thread__start__v1!(|| ());
+        #[cfg(feature = "event-stream")]
+        events::emit(|| ProbeEvent::WorkerThreadStart);
+    }
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_thread_stop`].
+pub fn on_thread_stop() {
+    #[cfg(feature = "probes-worker")]
+    {
+        if !enabled() {
+            return;
+        }
+        #[cfg(feature = "debug-overhead")]
+        let _timer = overhead::Timer::start(Hook::ThreadStop);
+        worker::stopped();
+        worker::with_identity(|runtime, name, index| {
+            fire!(probes::worker__thread__stop, limit: ProbeMask::WORKER_THREAD_STOP, || {
+                consumer::observe(ProbeMask::WORKER_THREAD_STOP);
+                (runtime, name, index, worker::pool(false))
+            });
+        });
+        #[cfg(feature = "compat-v1")]
+        compat::probes::worker__thread__stop__v1!(|| ());
+        #[cfg(feature = "event-stream")]
+        events::emit(|| ProbeEvent::WorkerThreadStop);
+    }
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_thread_park`].
+pub fn on_thread_park() {
+    #[cfg(feature = "probes-worker")]
+    {
+        if !enabled() {
+            return;
+        }
+        #[cfg(feature = "debug-overhead")]
+        let _timer = overhead::Timer::start(Hook::ThreadPark);
+        crate::counters::parked();
+        #[cfg(feature = "metrics")]
+        crate::metrics_rs::parked();
+        let normal = config::tier_enabled(Tier::Normal);
+        if normal {
+            worker::with_identity(|runtime, name, index| {
+                fire!(probes::worker__thread__park, limit: ProbeMask::WORKER_THREAD_PARK, || {
+                    consumer::observe(ProbeMask::WORKER_THREAD_PARK);
+                    (runtime, name, index)
+                });
+            });
+            #[cfg(feature = "compat-v1")]
+            compat::probes::worker__thread__park__v1!(|| ());
+        }
+        // The idle tracker must be updated at every tier.
+        if idle::park() && normal {
+            fire!(probes::runtime__idle, || ());
+        }
+        #[cfg(feature = "event-stream")]
+        events::emit(|| ProbeEvent::WorkerThreadPark);
+    }
+}
+
+/// Hook function to be used in [`tokio::runtime::Builder::on_thread_unpark`].
+pub fn on_thread_unpark() {
+    #[cfg(feature = "probes-worker")]
+    {
+        if !enabled() {
+            return;
+        }
+        #[cfg(feature = "debug-overhead")]
+        let _timer = overhead::Timer::start(Hook::ThreadUnpark);
+        let normal = config::tier_enabled(Tier::Normal);
+        if normal {
+            worker::with_identity(|runtime, name, index| {
+                fire!(probes::worker__thread__unpark, limit: ProbeMask::WORKER_THREAD_UNPARK, || {
+                    consumer::observe(ProbeMask::WORKER_THREAD_UNPARK);
+                    (runtime, name, index)
+                });
+            });
+            #[cfg(feature = "compat-v1")]
+            compat::probes::worker__thread__unpark__v1!(|| ());
+        }
+        // The idle tracker must be updated at every tier.
+        if idle::unpark() && normal {
+            fire!(probes::runtime__active, || ());
+        }
+        #[cfg(feature = "event-stream")]
+        events::emit(|| ProbeEvent::WorkerThreadUnpark);
+    }
+}
+
+#[inline]
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns whether the per-poll probes fire for the poll in progress on this
+/// thread.
+#[cfg(feature = "probes-task")]
+#[inline]
+pub(crate) fn poll_traced() -> bool {
+    current_task::traced()
+}
+
+#[cfg(feature = "probes-task")]
+#[inline]
+fn unpack_meta(meta: &TaskMeta<'_>) -> (u64, Cow<'static, str>, u32, u32) {
+    let id = config::probe_id(id_to_u64(meta.id()));
+    let location = meta.spawned_at();
+    let file = config::probe_path(location.file());
+    let line = location.line();
+    let col = location.column();
+    (id, file, line, col)
+}
+
+#[cfg(feature = "probes-task")]
+#[inline]
+fn with_trace_id(
+    (id, file, line, col): (u64, Cow<'static, str>, u32, u32),
+    trace_id: u128,
+) -> (u64, Cow<'static, str>, u32, u32, u64, u64) {
+    (
+        id,
+        file,
+        line,
+        col,
+        (trace_id >> 64) as u64,
+        trace_id as u64,
+    )
+}
+
+/// Fires `task-name` for a task spawned by [`crate::spawn_named`].
+#[cfg(feature = "probes-task")]
+#[inline]
+fn fire_name(meta: &TaskMeta<'_>, runtime: u64, name: Option<&str>) {
+    if let Some(name) = name {
+        fire!(probes::task__name, || (
+            runtime,
+            config::probe_id(id_to_u64(meta.id())),
+            name
+        ));
+    }
+}
+
+#[cfg(all(feature = "event-stream", feature = "probes-task"))]
+#[inline]
+fn task_event(meta: &TaskMeta<'_>) -> TaskEvent {
+    let location = meta.spawned_at();
+    TaskEvent {
+        id: id_to_u64(meta.id()),
+        file: location.file(),
+        line: location.line(),
+        col: location.column(),
+    }
+}
+
+#[cfg(feature = "probes-task")]
+#[inline]
+pub(crate) fn id_to_u64(id: tokio::task::Id) -> u64 {
+    unsafe {
+        // SAFETY: Based on training and experience, I know that a
+        // `tokio::task::Id` is represented as a single `NonZeroU64`. The
+        // registration functions refuse to install the hooks if
+        // `check_casts` finds that its size or alignment has changed.
+        union TrustMeOnThis {
+            id: tokio::task::Id,
+            int: NonZeroU64,
+        }
+        TrustMeOnThis { id }.int.get()
+    }
+}
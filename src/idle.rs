@@ -0,0 +1,94 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tracking of whole-runtime idle and active transitions.
+//!
+//! The runtime is considered idle when every one of its worker threads is
+//! parked. `tokio-dtrace` counts the number of parked workers in the
+//! `on_thread_park` and `on_thread_unpark` hooks, and compares it against the
+//! number of workers reported by the runtime's [`RuntimeMetrics`].
+//!
+//! [`RuntimeMetrics`]: tokio::runtime::RuntimeMetrics
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TRACKER: IdleTracker = IdleTracker::new();
+
+thread_local! {
+    /// Whether the current thread was counted as parked by [`park`].
+    static PARKED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Records that the current worker thread is about to park, returning `true`
+/// if this made the runtime idle.
+pub(crate) fn park() -> bool {
+    if PARKED.replace(true) {
+        return false;
+    }
+    // Parking is much less frequent than polling, so looking up the worker
+    // count each time is cheap enough, and keeps it correct if a runtime is
+    // replaced by one with a different number of workers.
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        TRACKER.set_workers(handle.metrics().num_workers() as u64);
+    }
+    TRACKER.park()
+}
+
+/// Records that the current worker thread has unparked, returning `true` if
+/// this made the runtime active.
+pub(crate) fn unpark() -> bool {
+    // If hooks were registered while this thread was parked, its park was
+    // never counted.
+    PARKED.replace(false) && TRACKER.unpark()
+}
+
+/// Forgets all parked workers.
+pub(crate) fn reset() {
+    TRACKER.parked.store(0, Ordering::Relaxed);
+}
+
+struct IdleTracker {
+    parked: AtomicU64,
+    workers: AtomicU64,
+}
+
+impl IdleTracker {
+    const fn new() -> Self {
+        Self {
+            parked: AtomicU64::new(0),
+            workers: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    fn set_workers(&self, workers: u64) {
+        self.workers.store(workers, Ordering::Relaxed);
+    }
+
+    fn park(&self) -> bool {
+        let parked = self.parked.fetch_add(1, Ordering::AcqRel) + 1;
+        parked == self.workers.load(Ordering::Relaxed)
+    }
+
+    fn unpark(&self) -> bool {
+        let parked = self.parked.fetch_sub(1, Ordering::AcqRel);
+        parked == self.workers.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let tracker = IdleTracker::new();
+        tracker.set_workers(2);
+
+        assert!(!tracker.park());
+        assert!(tracker.park());
+        assert!(tracker.unpark());
+        assert!(!tracker.unpark());
+
+        assert!(!tracker.park());
+        assert!(!tracker.unpark());
+    }
+}
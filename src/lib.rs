@@ -62,9 +62,11 @@
 //!
 //! Note that, because `tokio-dtrace` requires the use of the
 //! [`tokio::runtime::Builder`] to add hooks to the runtime, it is not possible
-//! to use `tokio-dtrace` with the [`tokio::main`] attribute macro.
-//! Fortunately, code using `#[tokio::main]` can be transformed to code using
-//! the runtime builder fairly simply.
+//! to use `tokio-dtrace` with the [`tokio::main`] attribute macro. With the
+//! `macros` feature enabled, `#[tokio_dtrace::main]` may be used in its
+//! place; see the documentation of `main`. Otherwise, code using
+//! `#[tokio::main]` can be transformed to code using the runtime builder
+//! fairly simply.
 //!
 //! For example, this:
 //!
@@ -94,6 +96,17 @@
 //! }
 //! ```
 //!
+//! The [`runtime`] function performs all of these steps in a single call,
+//! creating a builder of the requested [`Flavor`] with all drivers enabled,
+//! registering the hooks, and building the runtime after passing the builder
+//! to a closure for any other configuration. Multi-threaded runtimes require
+//! the `rt-multi-thread` feature, which enables Tokio's feature of the same
+//! name.
+//!
+//! Alternatively, importing the [`RegisterDtrace`] extension trait allows the
+//! hooks to be registered using a method on the builder, which may read more
+//! naturally in code which configures the builder fluently.
+//!
 //! See the documentation for
 //! [`tokio_dtrace::register_hooks`](crate::register_hooks) for more
 //! information.
@@ -103,10 +116,482 @@
 //! called in one or more of these hooks, refer to the documentation for the
 //! [`hooks`] module for more complex uses.
 //!
+//! ### Consuming Events In-Process
+//!
+//! When the `event-stream` feature flag is enabled, the events recorded by
+//! `tokio-dtrace`'s hooks may also be consumed from within the instrumented
+//! process as a [`Stream`](tokio_stream::Stream), using the
+//! [`events::event_stream`] function. Note that this stream is *lossy*: events
+//! are discarded rather than blocking the runtime if the stream is not
+//! consumed quickly enough. Alternatively, [`Config::mpsc_sink`] configures a
+//! lock-free ring which a single consumer drains using an
+//! [`events::Drainer`]. See the [`events`] module for details.
+//!
+//! ### Using `tokio-dtrace` Without DTrace
+//!
+//! The DTrace probes are fired using the [`usdt`] crate, which is enabled by
+//! the default `usdt` feature flag. The rest of `tokio-dtrace` — the runtime
+//! hooks, the per-task bookkeeping and timing, and the in-process sinks — does
+//! not depend on it. Building with `default-features = false` and the `usdt`
+//! feature omitted gives a pure-Rust runtime instrumentation library, which
+//! works on any platform: [`register_hooks`] still installs the hooks, but no
+//! probes fire, and probes are not registered with DTrace.
+//!
+//! The features which remain useful without `usdt` are:
+//!
+//! | Feature            | Without `usdt`                                    |
+//! |--------------------|---------------------------------------------------|
+//! | `probes-task`      | Tracks tasks, for the features below.             |
+//! | `probes-worker`    | Tracks worker threads, for the features below.    |
+//! | `event-stream`     | In-process [`events`] stream and ring.            |
+//! | `poll-percentiles` | Poll latency and task lifetime histograms.        |
+//! | `otel`             | OpenTelemetry task spans and metrics.             |
+//! | `metrics`          | Task and worker metrics for the `metrics` crate.  |
+//! | `shared-counters`  | Task counters in shared memory.                   |
+//! | `probes-metrics`   | The sampler runs, but its probes do not fire.     |
+//!
+//! [`take_counters`] and [`Config::on_shutdown_leaks`] also work without
+//! `usdt`. The `compat-v1` and `serialized-probes` features only add probes,
+//! so they enable `usdt`. For example, to consume task events in-process
+//! without DTrace:
+//!
+//! ```toml
+//! [dependencies]
+//! tokio-dtrace = { version = "0.1", default-features = false, features = ["probes-task", "event-stream"] }
+//! ```
+//!
+//! ### Tracing on Linux
+//!
+//! The `usdt` crate does not support Linux, where the probes are instead
+//! consumed as SystemTap-style SDT probes, by tools such as `bpftrace` and
+//! `perf`. When the `sdt` feature flag is enabled on Linux, the `tokio`
+//! provider's probes are fired using the [`probe`] crate instead of `usdt`.
+//! SDT probes are recorded in the binary when it is built, so they need no
+//! registration, and are named after the provider and the probe as written in
+//! Rust, with double underscores: for example,
+//!
+//! ```console
+//! # bpftrace -e 'usdt:./my-app:tokio:task__spawn { @[str(arg1)] = count(); }'
+//! ```
+//!
+//! The probes take the same arguments as their DTrace equivalents. Strings
+//! are passed as pointers to NUL-terminated copies, which are only made when
+//! the probe is enabled, and signed integers as their two's complement bits.
+//! The `sdt` feature has no effect on other platforms, so it may be enabled
+//! alongside `usdt` to support both. The probes added by the `compat-v1` and
+//! `serialized-probes` features, and the [`named_spawn_sites!`] macro, always
+//! use `usdt`.
+//!
+//! `usdt` itself is only built for Unix and Windows targets. On other targets,
+//! such as WebAssembly, the `usdt` feature has no effect: the crate still
+//! compiles, [`register_hooks`] succeeds, and the hooks maintain
+//! `tokio-dtrace`'s in-process state, but the probes compile away, so the same
+//! code may be built for every target without conditional compilation. The
+//! `compat-v1` and `serialized-probes` features' probes compile away too.
+//!
+//! ### Poll Latency Percentiles
+//!
+//! When the `poll-percentiles` feature flag is enabled, the duration of every
+//! task poll, and the lifetime of every task, is also recorded in an
+//! in-process histogram, so that poll latency and task lifetime percentiles
+//! can be reported without DTrace. See [`poll_latency_percentiles`] and
+//! [`task_lifetime_percentiles`] for details. Spawn rates are available
+//! without DTrace from [`take_counters`].
+//!
+//! ### Runtime Metrics
+//!
+//! When the `probes-metrics` feature flag is enabled, the [`metrics`] module
+//! provides a sampler task which periodically reads Tokio's runtime metrics
+//! and fires probes describing information that is not observable from the
+//! runtime hooks alone, such as work stealing between worker threads.
+//!
+//! ### Heartbeat
+//!
+//! When the `probes-heartbeat` feature flag is enabled, the [`heartbeat`]
+//! module provides a task which fires a probe on a fixed schedule, so that a
+//! DTrace consumer can detect a stalled runtime even when no other probes are
+//! firing. Each heartbeat also reports the wall-clock time at which the hooks
+//! were registered, allowing traces from multiple hosts to be aligned.
+//!
+//! ### Probe Families
+//!
+//! `tokio-dtrace`'s probes are divided into families, each of which is
+//! controlled by a feature flag:
+//!
+//! - `probes-task` (enabled by default): the task probes (`task-spawn`,
+//!   `task-poll-start`, `task-poll-end`, `task-terminate`, and related
+//!   probes), and the per-task bookkeeping that supports them.
+//! - `probes-worker` (enabled by default): the worker thread probes
+//!   (`worker-thread-start`, `worker-thread-stop`, `worker-thread-park`, and
+//!   `worker-thread-unpark`).
+//! - `probes-metrics`: the probes fired by the [`metrics`] sampler.
+//! - `probes-heartbeat`: the `runtime-heartbeat` probe fired by the
+//!   [`heartbeat`] task.
+//!
+//! When a family's feature is disabled, its probes are compiled out entirely,
+//! rather than merely disabled at runtime: [`register_hooks`] does not register
+//! the corresponding runtime hooks with the [`tokio::runtime::Builder`], and
+//! the corresponding functions in the [`hooks`] module do nothing. The public
+//! API is the same regardless of which families are enabled, so code using
+//! `tokio-dtrace` compiles with any combination of them.
+//!
+//! The task and worker thread hooks can also be left out at runtime, without
+//! rebuilding, using [`Config::hook_groups`]. This is useful for keeping
+//! `task-spawn` and `task-terminate` enabled in production, while leaving out
+//! the hooks which run on every task poll.
+//!
+//! Each probe site adds a small amount of code and a record in the binary's
+//! DTrace probe metadata, and each hook adds the code to gather the probe's
+//! arguments. The task family has the largest impact on binary size, as it has
+//! the most probes and includes the bookkeeping used by the `task-pickup`
+//! probe and [trace IDs](set_trace_id); it is also by far the most frequently
+//! called, since its hooks run on every task poll. The worker family is small:
+//! its probes only take the thread's name. The metrics and heartbeat families
+//! each add a background task, and enable Tokio's `time` feature. Applications
+//! which are sensitive to binary size should measure the impact of each family
+//! on their own builds, as it depends on the compiler's inlining decisions.
+//!
+//! ### Serialized Probe Arguments
+//!
+//! When the `serialized-probes` feature flag is enabled, each task probe is
+//! accompanied by a variant which passes its arguments as a single JSON
+//! object, allowing the argument schema to grow without breaking scripts. See
+//! the [`serialized`] module for details.
+//!
+//! ### Probe Schema Migration
+//!
+//! When the `compat-v1` feature flag is enabled, the task and worker thread
+//! probes are also fired with their original argument layouts, by a separate
+//! provider, so that existing scripts keep working while they are migrated to
+//! the current layouts. See the [`compat`] module for details.
+//!
+//! ### `tracing` Span IDs
+//!
+//! When the `tracing` feature flag is enabled, the `task-poll-span` probe fires
+//! immediately after each `task-poll-start` probe, with the ID of the
+//! [`tracing`](::tracing) span that is current when the task is polled. This
+//! allows DTrace timing to be joined with structured logs. Note that the span
+//! is read *before* the task's future is polled: spans which a future enters
+//! itself, such as those attached using
+//! [`Instrument`](::tracing::Instrument), are not yet entered, and are not
+//! reported. Reading the current span requires a call into the active
+//! `tracing` subscriber on every poll, but only while the probe is enabled.
+//!
+//! The `tracing` feature also provides [`tracing::DtraceLayer`], a subscriber
+//! layer which fires probes when spans are entered and exited, and when events
+//! are recorded, reporting the ID of the task in which they occurred.
+//!
+//! ### OpenTelemetry Spans and Metrics
+//!
+//! When the `otel` feature flag is enabled, the task hooks also report each
+//! task's lifetime as an [OpenTelemetry] span, created using the tracer
+//! provider installed with [`opentelemetry::global::set_tracer_provider`].
+//! This does not require DTrace. The spans are mapped as follows:
+//!
+//! - A span named `tokio.task` is started when a task is first polled, with
+//!   its start time set to when the task was spawned. It is a root span, and
+//!   has the attributes `tokio.task.id`, `code.file.path`, `code.line.number`,
+//!   and `code.column.number`, describing the task and where it was spawned.
+//! - Each poll of the task adds a `poll-start` event to the span when it
+//!   begins, and a `poll-end` event when it ends. The `poll-end` event has a
+//!   `tokio.poll.nanos` attribute, with the duration of the poll.
+//! - The span ends when the task terminates. Tasks which are never polled do
+//!   not have a span.
+//!
+//! The hooks also record the following [metrics], using instruments created
+//! from the meter provider installed with
+//! [`opentelemetry::global::set_meter_provider`]. The instruments are created
+//! when the first task is spawned, so the meter provider must be installed
+//! before then.
+//!
+//! - `tokio.task.spawned`, a counter of the tasks spawned.
+//! - `tokio.task.active`, an up-down counter of the tasks which have been
+//!   spawned and have not yet terminated. Tasks spawned before the hooks were
+//!   registered, and blocking tasks, are not counted.
+//! - `tokio.task.poll.duration`, a histogram of the duration of each task
+//!   poll, in seconds.
+//!
+//! The metrics have no attributes, so that their cardinality does not grow
+//! with the number of spawn locations.
+//!
+//! Unlike the DTrace probes, which cost almost nothing until a consumer
+//! enables them, spans are created for every task whenever the hooks are
+//! registered. Even a span which the configured sampler drops costs a tracer
+//! lookup and an allocation per task, so sampling does not make this feature
+//! free. Also note that long-lived tasks may be polled many more times than
+//! the number of events a tracer records per span; most SDKs discard events
+//! beyond a configurable limit.
+//!
+//! ### The `metrics` Crate
+//!
+//! When the `metrics` feature flag is enabled, the hooks also update
+//! counters and histograms using the [`metrics`](::metrics) crate, so that
+//! exporters built on it, such as Prometheus exporters, report them alongside
+//! the application's own metrics. This does not require DTrace:
+//!
+//! - `tokio.task.spawned`, a counter of the tasks spawned.
+//! - `tokio.task.poll_duration`, a histogram of the duration of each task
+//!   poll, in seconds. Polls of tasks spawned before the hooks were
+//!   registered are not recorded.
+//! - `tokio.worker.parks`, a counter of the times a worker thread parked.
+//!
+//! The task metrics are labelled with the `file` and `line` at which the task
+//! was spawned, with the file's path rewritten by
+//! [`Config::path_mapper`] as it is for the probes. A program which spawns
+//! tasks from many locations creates a metric for each of them, which some
+//! exporters handle poorly. Each location's metrics are registered the first
+//! time a task is spawned there, so the recorder must be installed before
+//! the runtime is started.
+//!
+//! ### Named Spawn Sites
+//!
+//! The [`named_spawn_sites!`] macro declares a USDT provider with a
+//! zero-argument probe for each of a set of named spawn sites, and the
+//! [`spawn_at_site!`] macro spawns a task at one of those sites, firing its
+//! probe. This allows DTrace scripts to select the tasks spawned at a few hot
+//! spawn sites by probe name, rather than by matching file names and line
+//! numbers. The provider is defined in the crate using the macro, which must
+//! also depend on [`usdt`].
+//!
+//! ### Shared Memory Counters
+//!
+//! When the `shared-counters` feature flag is enabled on Unix platforms, the
+//! [`shm`] module can mirror counts of spawned, terminated, and leaked tasks
+//! into a file-backed shared memory mapping. The last values written survive
+//! the process exiting abnormally, so they can be inspected after a crash
+//! which no DTrace consumer was attached to observe.
+//!
+//! ### `tokio-console`
+//!
+//! The runtime hooks registered by this crate compose with [`tokio-console`]:
+//! `console-subscriber` collects its data from the `tracing` spans emitted by
+//! Tokio's instrumentation, and does not register any runtime hooks of its
+//! own, so both can be used with the same runtime. Tokio records each task's
+//! [`tokio::task::Id`] as the `task.id` field of its `runtime.spawn` span, and
+//! the console shows it alongside each task. This is the same ID that the
+//! task probes report as their first argument, so tasks in DTrace output can
+//! be found in the console by ID, and vice versa.
+//!
+//! Tokio only emits these spans when it is built with its `tracing` feature
+//! and with `tokio_unstable`. The `console` feature flag enables Tokio's
+//! `tracing` feature and pulls in `console-subscriber`; the recommended setup
+//! installs the console's subscriber before building a runtime with the hooks
+//! registered:
+//!
+//! ```rust,ignore
+//! console_subscriber::init();
+//!
+//! let mut builder = tokio::runtime::Builder::new_multi_thread();
+//! let rt = tokio_dtrace::register_hooks(&mut builder)?.build()?;
+//! ```
+//!
+//! See `examples/console.rs` for a complete program. Note that the `tracing`
+//! instrumentation has a cost on every spawn and poll whether or not a
+//! console is connected, unlike the DTrace probes.
+//!
+//! [OpenTelemetry]: https://opentelemetry.io
+//! [metrics]: https://opentelemetry.io/docs/concepts/signals/metrics/
+//! [`probe`]: https://docs.rs/probe
+//! [`tokio-console`]: https://github.com/tokio-rs/console
 //! [unstable features]: https://docs.rs/tokio/latest/tokio/#unstable-features
 //! [`tokio::main`]: https://docs.rs/tokio/latest/tokio/attr.main.html
 //!
 use std::num::NonZeroU64;
+#[cfg(tokio_unstable)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Fires a `tokio` provider probe, first constructing its arguments if probes
+/// are being [forced](crate::testing::force_enabled), so that the argument
+/// closure's bookkeeping runs as if a consumer had enabled the probe, and so
+/// that the probe can be [captured](crate::testing::capture).
+///
+/// Probes which have a bit in [`ProbeMask`] are passed it as `limit`, and are
+/// subject to the [rate limit](crate::Config::rate_limit) configured for them.
+///
+/// The arguments are taken as tokens, rather than as an expression, and passed
+/// on to the probe macro unchanged, as the USDT probe macros only accept a
+/// closure literal.
+#[cfg(any(
+    all(
+        tokio_unstable,
+        any(feature = "probes-task", feature = "probes-worker")
+    ),
+    feature = "tracing"
+))]
+macro_rules! fire {
+    ($($probe:ident)::+, limit: $mask:expr, $($args:tt)+) => {
+        if crate::rate_limit::allow($mask) {
+            fire!($($probe)::+, $($args)+);
+        }
+    };
+    ($($probe:ident)::+, $($args:tt)+) => {{
+        #[cfg(feature = "testing")]
+        if crate::testing::forced() {
+            crate::testing::record(stringify!($($probe)::+), ($($args)+)());
+        }
+        $($probe)::+!($($args)+);
+    }};
+}
+
+pub mod anonymize;
+mod blocking;
+mod build;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+mod clock;
+#[cfg(feature = "compat-v1")]
+pub mod compat;
+#[cfg(all(tokio_unstable, feature = "debug-completion"))]
+mod completed;
+mod config;
+#[cfg(tokio_unstable)]
+mod consumer;
+mod counters;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+mod current_task;
+#[cfg(feature = "event-stream")]
+pub mod events;
+pub mod heartbeat;
+#[cfg(all(tokio_unstable, feature = "probes-worker"))]
+mod idle;
+#[cfg(all(tokio_unstable, feature = "probes-task", feature = "probes-metrics"))]
+mod locations;
+#[cfg(all(tokio_unstable, target_has_atomic = "64"))]
+pub mod metrics;
+#[cfg(all(tokio_unstable, feature = "metrics"))]
+mod metrics_rs;
+#[cfg(all(tokio_unstable, feature = "otel"))]
+mod otel;
+mod outcome;
+#[cfg(all(tokio_unstable, feature = "debug-overhead"))]
+mod overhead;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+mod panic_hook;
+#[cfg(feature = "poll-percentiles")]
+mod poll_latency;
+mod probe_mask;
+#[cfg(tokio_unstable)]
+mod rate_limit;
+#[cfg(all(tokio_unstable, feature = "probes-task", feature = "probes-metrics"))]
+mod rates;
+#[cfg(feature = "event-stream")]
+mod ring;
+mod scope;
+pub mod scripts;
+#[cfg(all(feature = "sdt", target_os = "linux"))]
+mod sdt;
+#[cfg(feature = "serialized-probes")]
+pub mod serialized;
+#[cfg(all(unix, feature = "shared-counters"))]
+pub mod shm;
+mod sites;
+mod spawn_source;
+#[cfg(feature = "sync")]
+pub mod sync;
+mod task_info;
+mod task_kind;
+mod task_name;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+mod task_table;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+#[cfg(all(tokio_unstable, feature = "probes-task", feature = "probes-metrics"))]
+mod utilization;
+mod wake;
+#[cfg(tokio_unstable)]
+mod worker;
+
+pub use blocking::spawn_blocking;
+pub use build::{BuildError, Flavor, runtime, runtime_with};
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub use clock::Clock;
+pub use config::{
+    Config, HookGroups, IdMapper, LeakHandler, PathMapper, Tier, WorkerIndexSource, set_tier, tier,
+};
+pub use counters::{Counters, take_counters};
+pub use outcome::{TraceOutcome, trace_outcome};
+pub use probe_mask::ProbeMask;
+pub use scope::{ScopeGuard, scope};
+pub use spawn_source::SpawnSource;
+pub use task_info::TaskInfo;
+pub use task_kind::{TaskKind, spawn_local};
+pub use task_name::spawn_named;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub use task_table::{TaskState, TaskStore, TaskSummary};
+pub use wake::{TraceWakes, trace_wakes};
+
+/// Runs an `async fn` on a Tokio runtime with `tokio-dtrace`'s hooks
+/// registered, in place of [`tokio::main`].
+///
+/// The function is rewritten to build a runtime as [`runtime`] does, and then
+/// to run its body to completion on that runtime. As with [`tokio::main`],
+/// all of the runtime's I/O and time drivers are enabled, and the function
+/// panics if the runtime cannot be built. If the hooks cannot be registered,
+/// such as when Tokio's unstable features are not enabled, a warning is
+/// printed to standard error, and the runtime is built without them, so that
+/// the program still runs, without probes.
+///
+/// The following options may be passed to the attribute:
+///
+/// - `flavor = "multi_thread"` or `flavor = "current_thread"` selects the
+///   [`Flavor`] of runtime. Defaults to `"multi_thread"`.
+/// - `worker_threads = N` sets the number of worker threads of a
+///   `"multi_thread"` runtime, using [`Builder::worker_threads`].
+/// - `require_hooks = true` makes a failure to register the hooks fatal: the
+///   function panics with the [`RegistrationError`], rather than running
+///   without probes. Defaults to `false`.
+/// - `crate = "path"` gives the path to `tokio-dtrace`, if it has been
+///   renamed or is re-exported by another crate. Defaults to
+///   `::tokio_dtrace`.
+///
+/// Only available when the `macros` feature is enabled.
+///
+/// # Examples
+///
+/// ```rust
+/// # async fn do_stuff() {}
+/// #[tokio_dtrace::main(flavor = "multi_thread", worker_threads = 10)]
+/// async fn main() {
+///     do_stuff().await;
+/// }
+/// ```
+///
+/// is equivalent to
+///
+/// ```rust
+/// # async fn do_stuff() {}
+/// fn main() {
+///     let body = async move {
+///         do_stuff().await;
+///     };
+///     let mut builder = tokio::runtime::Builder::new_multi_thread();
+///     builder.enable_all();
+///     if let Err(error) = tokio_dtrace::register_hooks(&mut builder) {
+///         eprintln!("warning: failed to register tokio-dtrace's hooks: {error}");
+///     }
+///     builder.worker_threads(10);
+///     builder
+///         .build()
+///         .expect("failed to build the Tokio runtime")
+///         .block_on(body)
+/// }
+/// ```
+///
+/// To configure the runtime in ways which the attribute's options do not
+/// cover, call [`runtime`] directly.
+///
+/// [`Builder::worker_threads`]: tokio::runtime::Builder::worker_threads
+/// [`tokio::main`]: https://docs.rs/tokio/latest/tokio/attr.main.html
+#[cfg(feature = "macros")]
+pub use tokio_dtrace_macros::main;
+
+#[cfg(feature = "poll-percentiles")]
+pub use poll_latency::{
+    LIFETIME_HISTOGRAM_MAX_NANOS, POLL_HISTOGRAM_MAX_NANOS, POLL_HISTOGRAM_SIGFIG, Percentiles,
+    poll_latency_percentiles, task_lifetime_percentiles,
+};
 
 /// Registers `tokio-dtrace`s probe hooks with the provided
 /// [`tokio::runtime::Builder`].
@@ -191,28 +676,454 @@ use std::num::NonZeroU64;
 pub fn register_hooks(
     builder: &mut tokio::runtime::Builder,
 ) -> Result<&mut tokio::runtime::Builder, RegistrationError> {
+    register_hooks_with(builder, Config::default())
+}
+
+/// An extension trait for registering `tokio-dtrace`'s hooks using a method on
+/// [`tokio::runtime::Builder`].
+///
+/// This allows [`register_hooks`] to be called fluently, in code which
+/// configures a builder using a chain of method calls, or which receives a
+/// builder configured elsewhere.
+///
+/// ## Examples
+///
+/// ```
+/// use tokio_dtrace::RegisterDtrace;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let rt = tokio::runtime::Builder::new_multi_thread()
+///         .register_dtrace()?
+///         .enable_all()
+///         .build()?;
+///     rt.block_on(async {
+///         // Your application code here
+///     });
+///
+///     Ok(())
+/// }
+/// ```
+pub trait RegisterDtrace {
+    /// Registers `tokio-dtrace`'s probe hooks with this builder, as
+    /// [`register_hooks`] does.
+    ///
+    /// ## Errors
+    ///
+    /// This method returns the same errors as [`register_hooks`].
+    fn register_dtrace(&mut self) -> Result<&mut Self, RegistrationError>;
+}
+
+impl RegisterDtrace for tokio::runtime::Builder {
+    fn register_dtrace(&mut self) -> Result<&mut Self, RegistrationError> {
+        register_hooks(self)
+    }
+}
+
+/// Registers `tokio-dtrace`s probe hooks with the provided
+/// [`tokio::runtime::Builder`], using the provided [`Config`].
+///
+/// This function behaves identically to [`register_hooks`], except that the
+/// runtime hooks use the settings in `config`, rather than the defaults. Note
+/// that configuration is process-global; see the [`Config`] documentation for
+/// details.
+///
+/// ## Errors
+///
+/// This function returns the same errors as [`register_hooks`], and also
+/// returns `RegistrationError::TaskStoreAlreadyChosen` if `config` sets a task
+/// store, and the hooks have already chosen a different one.
+///
+/// ## Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut builder = tokio::runtime::Builder::new_multi_thread();
+///     let config = tokio_dtrace::Config::new()
+///         // Fire `task-blocking-suspected` for polls longer than 10ms.
+///         .blocking_threshold(Duration::from_millis(10));
+///     tokio_dtrace::register_hooks_with(&mut builder, config)?;
+///
+///     let rt = builder.enable_all().build()?;
+///     rt.block_on(async {
+///         // Your application code here
+///     });
+///
+///     Ok(())
+/// }
+/// ```
+pub fn register_hooks_with(
+    builder: &mut tokio::runtime::Builder,
+    config: Config,
+) -> Result<&mut tokio::runtime::Builder, RegistrationError> {
+    register_hooks_impl(builder, config, true, cfg!(tokio_unstable))
+}
+
+/// Registers `tokio-dtrace`s probe hooks with the provided
+/// [`tokio::runtime::Builder`], *without* registering the USDT probes provided
+/// by this crate with DTrace.
+///
+/// This function behaves identically to [`register_hooks`], except that it
+/// does not call [`usdt::register_probes`]. This is intended for applications
+/// which define their own USDT providers in addition to `tokio-dtrace`'s, and
+/// wish to call [`usdt::register_probes`] exactly once, after all providers
+/// have been set up.
+///
+/// <div class="warning">
+/// When using this function, the caller <strong>must</strong> call
+/// <code>usdt::register_probes()</code>. Otherwise, the runtime hooks will be
+/// registered, but <code>tokio-dtrace</code>'s probes will never fire.
+/// </div>
+///
+/// ## Errors
+///
+/// This function returns [an error](RegistrationError) in the following
+/// conditions:
+///
+/// - [`RegistrationError::UnstableFeaturesRequired`] if Tokio's
+///   [unstable features](crate#enabling-tokio_unstable-features) are not
+///   enabled at compile time.
+/// - [`RegistrationError::InvalidCasts`] if a call to [`check_casts`] fails,
+///   which would indicate that type layout in Tokio has changed in a way that
+///   would render unsafe casts used by `tokio-dtrace` unsound.
+///
+/// ## Examples
+///
+#[cfg_attr(all(feature = "usdt", any(unix, windows)), doc = "```")]
+#[cfg_attr(not(all(feature = "usdt", any(unix, windows))), doc = "```ignore")]
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut builder = tokio::runtime::Builder::new_multi_thread();
+///     tokio_dtrace::register_hooks_without_probe_registration(&mut builder)?;
+///
+///     // ... set up any other USDT providers used by the application ...
+///
+///     // Register the probes for all USDT providers, including
+///     // `tokio-dtrace`'s, exactly once.
+///     usdt::register_probes()?;
+///
+///     let rt = builder.enable_all().build()?;
+///     rt.block_on(async {
+///         // Your application code here
+///     });
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// See also the [`second-provider` example] in the `tokio-dtrace` repository.
+///
+/// [`second-provider` example]: https://github.com/oxidecomputer/tokio-dtrace/blob/main/examples/second-provider.rs
+pub fn register_hooks_without_probe_registration(
+    builder: &mut tokio::runtime::Builder,
+) -> Result<&mut tokio::runtime::Builder, RegistrationError> {
+    register_hooks_impl(builder, Config::default(), false, cfg!(tokio_unstable))
+}
+
+/// Registers the runtime hooks, applying `config`, and registering the USDT
+/// probes with DTrace if `register_probes` is `true`.
+///
+/// `unstable` must be `cfg!(tokio_unstable)`. It is passed in, rather than
+/// checked here, so that tests can exercise the error returned when Tokio's
+/// unstable features are not enabled.
+fn register_hooks_impl(
+    builder: &mut tokio::runtime::Builder,
+    config: Config,
+    register_probes: bool,
+    unstable: bool,
+) -> Result<&mut tokio::runtime::Builder, RegistrationError> {
+    if !unstable {
+        return Err(RegistrationError::UnstableFeaturesRequired);
+    }
     #[cfg(tokio_unstable)]
     {
         check_casts()?;
-        usdt::register_probes()?;
-        let builder = builder
-            .on_thread_start(hooks::on_thread_start)
-            .on_thread_park(hooks::on_thread_park)
-            .on_thread_unpark(hooks::on_thread_unpark)
-            .on_thread_stop(hooks::on_thread_stop)
-            .on_task_spawn(hooks::on_task_spawn)
-            .on_before_task_poll(hooks::on_before_task_poll)
-            .on_after_task_poll(hooks::on_after_task_poll)
-            .on_task_terminate(hooks::on_task_terminate);
-        Ok(builder)
+        #[cfg(all(feature = "usdt", any(unix, windows)))]
+        if register_probes {
+            usdt::register_probes()?;
+        }
+        #[cfg(not(all(feature = "usdt", any(unix, windows))))]
+        let _ = register_probes;
+        config.apply()?;
+        #[cfg(feature = "probes-heartbeat")]
+        heartbeat::capture_epoch();
+        ENABLED.store(true, Ordering::Relaxed);
+        Ok(hooks::install_all(builder))
     }
     #[cfg(not(tokio_unstable))]
     {
+        let _ = (builder, config, register_probes);
         Err(RegistrationError::UnstableFeaturesRequired)
     }
 }
 
-/// Errors returned by [`register_hooks`].
+/// Registers `tokio-dtrace`s probe hooks with the provided
+/// [`tokio::runtime::Builder`], returning a [`HooksGuard`] which tears down
+/// `tokio-dtrace`'s state when dropped.
+///
+/// This function behaves identically to [`register_hooks`], except that it
+/// also returns a guard which calls [`teardown`] when it is dropped. This is
+/// intended for programs that build and drop runtimes repeatedly, such as
+/// tests, so that the lifetime of `tokio-dtrace`'s instrumentation can be tied
+/// to a scope.
+///
+/// ## Errors
+///
+/// This function returns the same errors as [`register_hooks`].
+///
+/// ## Examples
+///
+/// ```
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     for _ in 0..3 {
+///         let mut builder = tokio::runtime::Builder::new_multi_thread();
+///         let (builder, _guard) = tokio_dtrace::register_hooks_with_guard(&mut builder)?;
+///         let rt = builder.enable_all().build()?;
+///         rt.block_on(async {
+///             // Your application code here
+///         });
+///         // The runtime is dropped before the guard.
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub fn register_hooks_with_guard(
+    builder: &mut tokio::runtime::Builder,
+) -> Result<(&mut tokio::runtime::Builder, HooksGuard), RegistrationError> {
+    let builder = register_hooks(builder)?;
+    Ok((builder, HooksGuard { _priv: () }))
+}
+
+/// Tears down `tokio-dtrace`'s runtime state when dropped.
+///
+/// Returned by [`register_hooks_with_guard`]. When this guard is dropped, it
+/// fires the `tokio*:::runtime-shutdown` probe, and then calls [`teardown`],
+/// which discards all per-task state and disables the runtime hooks until they
+/// are registered again.
+///
+/// The guard should be dropped *after* the runtime it was returned for, and
+/// may be sent to, and dropped on, any thread. As with [`teardown`], dropping
+/// it resets `tokio-dtrace`'s state for every runtime in the process, and does
+/// not unregister the USDT probes, which remain registered with DTrace until
+/// the process exits. See [the probe lifecycle
+/// documentation](teardown#probe-lifecycle) for details.
+#[derive(Debug)]
+#[must_use = "tokio-dtrace's state is torn down when the guard is dropped"]
+pub struct HooksGuard {
+    _priv: (),
+}
+
+impl Drop for HooksGuard {
+    fn drop(&mut self) {
+        probes::runtime__shutdown!(|| ());
+        teardown();
+    }
+}
+
+/// Re-exports used by `tokio-dtrace`'s macros. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::build::main_runtime;
+    pub use tokio;
+}
+
+/// Whether the runtime hooks should do anything when called.
+///
+/// This is cleared by [`teardown`] (including when a [`HooksGuard`] is
+/// dropped), and set again by the registration functions.
+#[cfg(tokio_unstable)]
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Resets `tokio-dtrace`'s global state, and disables its runtime hooks.
+///
+/// This is intended for programs which build and drop multiple Tokio runtimes
+/// over their lifetime, such as test harnesses, and want each runtime to start
+/// from a clean slate. It should only be called once every runtime with
+/// `tokio-dtrace`'s hooks registered has been dropped.
+///
+/// After this function is called:
+///
+/// - The runtime hooks in the [`hooks`] module return immediately without
+///   firing any probes or doing any bookkeeping, until [`register_hooks`] or
+///   [`register_hooks_without_probe_registration`] is called again. Note that
+///   this applies to *all* runtimes in the process, including any that are
+///   still running.
+/// - All per-task state tracked by the hooks (such as the information used by
+///   the `task-pickup` probe, and task [trace IDs](set_trace_id)) is
+///   discarded. This includes state for tasks that never terminated, such as
+///   tasks that were still running when their runtime was dropped, which are
+///   first passed to the callback set by [`Config::on_shutdown_leaks`], if
+///   any.
+/// - Worker thread indices are reset, so that the first worker thread started
+///   by the next runtime is assigned index 0.
+/// - The count of parked worker threads used by the `runtime-idle` and
+///   `runtime-active` probes is reset.
+///
+/// ## Probe Lifecycle
+///
+/// Registering USDT probes with DTrace (using [`register_hooks`] or
+/// [`usdt::register_probes`]) is process-global, and cannot be undone: probes
+/// remain registered, and visible to `dtrace -l`, until the process exits.
+/// This function does *not* unregister them. Similarly, dropping a Tokio
+/// runtime does not unregister probes; it only means that the dropped
+/// runtime's hooks will no longer be called. Registering probes again (for
+/// instance, when a subsequent runtime is built using [`register_hooks`]) is
+/// harmless.
+///
+/// While the probes remain registered, a DTrace consumer may still enable
+/// them, but they will not fire while the hooks are disabled.
+pub fn teardown() {
+    #[cfg(tokio_unstable)]
+    {
+        ENABLED.store(false, Ordering::Relaxed);
+        #[cfg(feature = "probes-task")]
+        #[cfg_attr(not(all(unix, feature = "shared-counters")), allow(unused_variables))]
+        let leaked = match config::on_shutdown_leaks() {
+            Some(on_shutdown_leaks) => {
+                let leaked: Vec<_> = task_table::TASKS
+                    .drain()
+                    .into_iter()
+                    .map(|(id, task)| TaskInfo::new(id, &task))
+                    .collect();
+                if !leaked.is_empty() {
+                    on_shutdown_leaks(&leaked);
+                }
+                leaked.len()
+            }
+            None => task_table::TASKS.clear(),
+        };
+        #[cfg(feature = "debug-completion")]
+        completed::reset();
+        #[cfg(all(unix, feature = "shared-counters"))]
+        shm::leaked(leaked);
+        worker::reset();
+        #[cfg(feature = "probes-worker")]
+        idle::reset();
+    }
+}
+
+/// Returns the set of `tokio-dtrace`'s probes which DTrace currently has
+/// enabled.
+///
+/// USDT probes cannot be asked directly whether they are enabled, so this is
+/// determined by recording which probes fire while enabled. A probe is
+/// included in the returned mask if it fired while enabled during the current
+/// or previous interval of the [metrics sampler](metrics::spawn_sampler). This
+/// means that a probe is not reported as enabled until it has fired at least
+/// once, and that a disabled probe is still reported for up to two sampler
+/// intervals. If no sampler is running, every probe which has fired while
+/// enabled since the process started is reported.
+///
+/// This is intended for status endpoints and dashboards, which may want to
+/// show whether a process is being traced at a glance; see [`ProbeMask`] for
+/// the bits assigned to each probe.
+pub fn enabled_probes() -> ProbeMask {
+    #[cfg(tokio_unstable)]
+    {
+        consumer::enabled()
+    }
+    #[cfg(not(tokio_unstable))]
+    {
+        ProbeMask::empty()
+    }
+}
+
+/// Returns a DTrace probe description matching `tokio-dtrace`'s probes in the
+/// current process.
+///
+/// USDT providers are named after the process which registers them, so the
+/// `tokio` provider in the process with ID 12345 is `tokio12345`. A script
+/// which is started with `dtrace -p` can use `tokio$target` instead, but
+/// applications which print instructions for attaching to themselves, or
+/// which start a DTrace consumer themselves, must substitute their own process
+/// ID, which is easy to get wrong. This returns a description in the form
+/// `tokio<pid>:::<name>`:
+///
+/// - With `None`, the description matches every probe: `tokio12345:::`.
+/// - With a [`ProbeMask`], it matches each of the probes in the mask, as a
+///   comma-separated list of descriptions, such as
+///   `tokio12345:::task-spawn,tokio12345:::task-terminate`. An empty mask
+///   matches every probe, as `None` does.
+///
+/// The result can be used directly as a probe description in a D program, or
+/// with `dtrace -n`. `tokio-dtrace`'s provider is always named `tokio`; probes
+/// without a bit in [`ProbeMask`] can be matched by appending their name to
+/// the description returned for `None`.
+pub fn dtrace_match(probes: Option<ProbeMask>) -> String {
+    dtrace_match_for(std::process::id(), probes)
+}
+
+fn dtrace_match_for(pid: u32, probes: Option<ProbeMask>) -> String {
+    let provider = format!("tokio{pid}:::");
+    match probes.filter(|probes| !probes.is_empty()) {
+        Some(probes) => probes
+            .names()
+            .map(|name| format!("{provider}{name}"))
+            .collect::<Vec<_>>()
+            .join(","),
+        None => provider,
+    }
+}
+
+/// Attaches a 128-bit trace ID to the task currently being polled.
+///
+/// This is intended to correlate DTrace task traces with distributed tracing
+/// systems that use 128-bit trace IDs, such as OpenTelemetry. The trace ID is
+/// emitted as two 64-bit arguments on the `tokio*:::task-spawn` and
+/// `tokio*:::task-poll-start` probes: `arg4` is the high 64 bits of the trace
+/// ID (`(trace_id >> 64) as u64`), and `arg5` is the low 64 bits
+/// (`trace_id as u64`). Tasks without a trace ID emit `0` for both arguments.
+///
+/// Tasks spawned by a task inherit the trace ID that their parent had at the
+/// time they were spawned. Changing a task's trace ID does not change the trace
+/// IDs of any children it has already spawned.
+///
+/// A trace ID is only attached if this function is called from within a task
+/// running on a runtime with `tokio-dtrace`'s hooks registered (including the
+/// [`on_before_task_poll`](hooks::on_before_task_poll) and
+/// [`on_after_task_poll`](hooks::on_after_task_poll) hooks). Otherwise, this
+/// function does nothing.
+pub fn set_trace_id(trace_id: u128) {
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    if let Some(id) = current_task::id() {
+        current_task::set_trace_id(trace_id);
+        task_table::TASKS.with_polling(id, |task| task.trace_id = trace_id);
+    }
+    #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+    let _ = trace_id;
+}
+
+/// Sets a deadline for the task currently being polled, relative to when it
+/// was spawned.
+///
+/// When a task with a deadline terminates after being alive for longer than
+/// its deadline, the `tokio*:::task-deadline-missed` probe fires. `arg1` is
+/// the [task `Id`](tokio::task::Id) of the task, and `arg2` is the number of
+/// nanoseconds by which its lifetime exceeded the deadline. Tasks without a
+/// deadline never fire the probe. Calling this function again replaces the
+/// task's deadline; the deadline is always measured from when the task was
+/// spawned, not from when this function was called.
+///
+/// The deadline is only checked when the task terminates: there is no timer.
+/// A task which is still running long after its deadline has passed will not
+/// be reported until it terminates, and a task which never terminates will
+/// never be reported.
+///
+/// As with [`set_trace_id`], this function does nothing unless it is called
+/// from within a task running on a runtime with `tokio-dtrace`'s hooks
+/// registered.
+pub fn set_task_deadline(deadline: Duration) {
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    if let Some(id) = current_task::id() {
+        task_table::TASKS.with_polling(id, |task| task.deadline = Some(deadline));
+    }
+    #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+    let _ = deadline;
+}
+
+/// Errors returned by [`register_hooks`], [`register_hooks_with`], and
+/// [`register_hooks_without_probe_registration`].
 #[derive(Debug, thiserror::Error)]
 pub enum RegistrationError {
     /// `tokio-dtrace` hooks cannot be registered, as [Tokio's unstable
@@ -227,29 +1138,76 @@ pub enum RegistrationError {
     #[error(transparent)]
     InvalidCasts(#[from] InvalidCasts),
 
-    /// Probes could not be registered with DTrace.
+    /// Probes could not be registered with DTrace. This variant only exists
+    /// when the `usdt` feature is enabled, on targets which `usdt` supports.
+    #[cfg(all(feature = "usdt", any(unix, windows)))]
     #[error(transparent)]
     DTrace(#[from] usdt::Error),
+
+    /// A [`TaskStore`] was set using [`Config::task_store`], but the hooks had
+    /// already chosen a different store, either because a different store was
+    /// configured earlier or because a task had already been tracked using
+    /// the default store. The store cannot be changed once it has been
+    /// chosen. This variant only exists when the `probes-task` feature is
+    /// enabled and `tokio_unstable` is set.
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    #[error("tokio-dtrace's task store has already been chosen, and cannot be changed")]
+    TaskStoreAlreadyChosen,
 }
 
 /// Errors returned by [`check_casts`].
+///
+/// The error describes which type's layout no longer matches the one
+/// `tokio-dtrace` assumes, and how, so that it can be reported against the
+/// Tokio release which changed it.
 #[derive(Debug, thiserror::Error)]
 #[error(
     "\
-tokio-dtrace: POTENTIALLY UNSOUND CAST DETECTED!\n \
-  size_of::<tokio::task::Id>() = {id_size}\n       \
-       size_of::<NonZeroU64>() = {nonzero_u64_size}\n \
- align_of::<tokio::task::Id>() = {id_align}\n      \
-      align_of::<NonZeroU64>() = {nonzero_u64_align}\n\
+tokio-dtrace: POTENTIALLY UNSOUND CAST DETECTED!\n  \
+  size_of::<{type_name}>() = {id_size} (expected {nonzero_u64_size})\n  \
+  align_of::<{type_name}>() = {id_align} (expected {nonzero_u64_align})\n\
+The layout of `{type_name}` is not supported by tokio-dtrace {version}. \
+Please report this, along with the version of Tokio in use (as shown by \
+`cargo tree -i tokio`).\
 "
 )]
 pub struct InvalidCasts {
+    type_name: &'static str,
+    version: &'static str,
     id_size: usize,
     nonzero_u64_size: usize,
     id_align: usize,
     nonzero_u64_align: usize,
 }
 
+impl InvalidCasts {
+    /// Returns the name of the type whose layout is not supported.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns the type's actual size, in bytes.
+    pub fn actual_size(&self) -> usize {
+        self.id_size
+    }
+
+    /// Returns the size, in bytes, `tokio-dtrace` requires the type to have.
+    pub fn expected_size(&self) -> usize {
+        self.nonzero_u64_size
+    }
+
+    /// Returns the type's actual alignment, in bytes.
+    pub fn actual_align(&self) -> usize {
+        self.id_align
+    }
+
+    /// Returns the alignment, in bytes, `tokio-dtrace` requires the type to
+    /// have.
+    pub fn expected_align(&self) -> usize {
+        self.nonzero_u64_align
+    }
+}
+
 /// Checks that unsafe casts performed by `tokio-dtrace` are valid.
 ///
 /// `tokio-dtrace` relies on the ability to cast a [`tokio::task::Id`] to a
@@ -273,6 +1231,8 @@ pub fn check_casts() -> Result<(), InvalidCasts> {
 
     if id_size != nonzero_u64_size || id_align != nonzero_u64_align {
         Err(InvalidCasts {
+            type_name: std::any::type_name::<tokio::task::Id>(),
+            version: env!("CARGO_PKG_VERSION"),
             id_size,
             nonzero_u64_size,
             id_align,
@@ -313,7 +1273,8 @@ pub fn check_casts() -> Result<(), InvalidCasts> {
 /// [`on_task_spawn`](tokio::runtime::Builder::on_task_spawn) hook, I can write
 /// a wrapper function like this:
 ///
-/// ```rust
+#[cfg_attr(all(feature = "usdt", any(unix, windows)), doc = "```")]
+#[cfg_attr(not(all(feature = "usdt", any(unix, windows))), doc = "```ignore")]
 /// use tokio::runtime::TaskMeta;
 /// # fn other_on_task_spawn_thing(meta: &TaskMeta<'_>) {};
 ///
@@ -329,19 +1290,12 @@ pub fn check_casts() -> Result<(), InvalidCasts> {
 ///     tokio_dtrace::check_casts()?;
 ///     usdt::register_probes()?;
 ///
-///     // Construct a new Tokio runtime builder.
-///     let rt = tokio::runtime::Builder::new_multi_thread()
-///         // Register the `on_task_spawn` hook defined above.
+///     // Construct a new Tokio runtime builder, and install all of
+///     // `tokio-dtrace`'s hooks.
+///     let mut builder = tokio::runtime::Builder::new_multi_thread();
+///     let rt = tokio_dtrace::hooks::install_all(&mut builder)
+///         // Replace the `on_task_spawn` hook with the one defined above.
 ///         .on_task_spawn(on_task_spawn)
-///         // Register `tokio-dtrace`'s other hooks.
-///         .on_before_task_poll(tokio_dtrace::hooks::on_before_task_poll)
-///         .on_after_task_poll(tokio_dtrace::hooks::on_after_task_poll)
-///         .on_task_terminate(tokio_dtrace::hooks::on_task_terminate)
-///         .on_thread_start(tokio_dtrace::hooks::on_thread_start)
-///         .on_thread_stop(tokio_dtrace::hooks::on_thread_stop)
-///         .on_thread_park(tokio_dtrace::hooks::on_thread_park)
-///         .on_thread_unpark(tokio_dtrace::hooks::on_thread_unpark)
-///         .on_thread_stop(tokio_dtrace::hooks::on_thread_stop)
 ///         // Enable other Tokio runtime features, and configure other builder
 ///         // settings as needed...
 ///         .enable_all()
@@ -401,88 +1355,615 @@ pub fn check_casts() -> Result<(), InvalidCasts> {
 /// }
 /// ```
 ///
+/// Wrapper functions like these can instead be generated by a
+/// [`HookSet`](hooks::HookSet), which runs closures before or after each of
+/// `tokio-dtrace`'s hooks, and installs them all at once:
+///
+#[cfg_attr(all(feature = "usdt", any(unix, windows)), doc = "```")]
+#[cfg_attr(not(all(feature = "usdt", any(unix, windows))), doc = "```ignore")]
+/// use tokio_dtrace::hooks::{HookSet, Order};
+/// # fn other_on_task_spawn_thing(meta: &tokio::runtime::TaskMeta<'_>) {};
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     tokio_dtrace::check_casts()?;
+///     usdt::register_probes()?;
+///
+///     let mut builder = tokio::runtime::Builder::new_multi_thread();
+///     HookSet::new()
+///         .on_task_spawn(Order::After, other_on_task_spawn_thing)
+///         .install(&mut builder);
+///     let rt = builder.enable_all().build()?;
+///
+///     rt.block_on(async {
+///         // Your application code here
+///     });
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// ## Requirements for Custom Integrations
+///
+/// Applications which wrap Tokio's scheduling, or which call these hooks from
+/// their own hook functions, may rely on the following guarantees about how
+/// the task hooks use their [`TaskMeta`](tokio::runtime::TaskMeta) argument:
+///
+/// - Only [`TaskMeta::id`] and [`TaskMeta::spawned_at`] are called. No other
+///   part of the `TaskMeta` is accessed.
+/// - The task [`Id`](tokio::task::Id) returned by [`TaskMeta::id`] is
+///   converted to an integer by reinterpreting it as a [`NonZeroU64`], which
+///   is Tokio's current representation. This is the only assumption made about
+///   Tokio's internals, and it is verified by [`check_casts`].
+/// - Task IDs are only used to identify tasks in probe arguments and in
+///   `tokio-dtrace`'s per-task bookkeeping; they are never dereferenced. Any
+///   ID that is unique among the live tasks is sufficient.
+/// - The hooks tolerate being called out of the usual order. For example, if
+///   `on_task_spawn` was never called for a task (because the task was
+///   spawned before the hooks were registered, or because an integration does
+///   not forward that hook), the other hooks still fire their probes, but
+///   report information derived from the spawn (such as the `task-pickup`
+///   probe and the task's trace ID) as missing.
+///
+/// Note that `TaskMeta` can currently only be constructed by Tokio itself.
+///
+/// [`TaskMeta::id`]: tokio::runtime::TaskMeta::id
+/// [`TaskMeta::spawned_at`]: tokio::runtime::TaskMeta::spawned_at
 /// [`tokio_dtrace::register_hooks`]: crate::register_hooks
 #[cfg(tokio_unstable)]
-pub mod hooks {
-    use super::*;
-    use tokio::runtime::TaskMeta;
+pub mod hooks;
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_task_spawn`].
-    pub fn on_task_spawn(meta: &TaskMeta<'_>) {
-        probes::task__spawn!(|| unpack_meta(meta));
+#[cfg(all(
+    feature = "usdt",
+    any(unix, windows),
+    not(all(feature = "sdt", target_os = "linux"))
+))]
+#[usdt::provider(provider = "tokio")]
+#[allow(non_snake_case)]
+mod probes {
+    fn task__spawn(task_id: u64, file: &str, line: u32, col: u32, trace_hi: u64, trace_lo: u64) {}
+    fn task__poll__start(
+        task_id: u64,
+        file: &str,
+        line: u32,
+        col: u32,
+        trace_hi: u64,
+        trace_lo: u64,
+    ) {
+    }
+    fn task__poll__end(
+        task_id: u64,
+        file: &str,
+        line: u32,
+        col: u32,
+        budget_exhausted: u8,
+        poll_nanos: u64,
+    ) {
+    }
+    fn task__terminate(task_id: u64, file: &str, line: u32, col: u32, reschedules: u64, flags: u8) {
+    }
+    fn task__spawn__source(runtime: u64, task_id: u64, spawn_source: u8) {}
+    fn task__kind(runtime: u64, task_id: u64, kind: u8) {}
+    fn task__spawn__queue__depth(runtime: u64, task_id: u64, queue_depth: u64) {}
+    fn task__spawn__scope(runtime: u64, task_id: u64, scope_id: u32) {}
+    fn task__spawn__runtime(runtime: u64, task_id: u64) {}
+    fn task__spawn__parent(runtime: u64, task_id: u64, parent_id: u64) {}
+    fn scope__enter(task_id: u64, scope_id: u32, name: &str) {}
+    fn task__name(runtime: u64, task_id: u64, name: &str) {}
+    fn task__poll__span(runtime: u64, task_id: u64, span_id: u64) {}
+    fn task__table__contended(wait_nanos: u64) {}
+    fn task__poll__after__complete(runtime: u64, task_id: u64) {}
+    fn task__panic(runtime: u64, task_id: u64, file: &str, line: u32, col: u32) {}
+    fn task__deadline__missed(runtime: u64, task_id: u64, over_by_nanos: u64) {}
+    fn task__finalize__latency(runtime: u64, task_id: u64, nanos: u64) {}
+    fn probe__dropped(probe: u32, count: u64) {}
+    fn task__pickup(
+        runtime: u64,
+        task_id: u64,
+        spawner_worker: u64,
+        poller_worker: u64,
+        queue_nanos: u64,
+    ) {
     }
+    fn task__blocking__suspected(runtime: u64, task_id: u64, poll_nanos: u64) {}
+    fn task__poll__slow(
+        runtime: u64,
+        task_id: u64,
+        poll_nanos: u64,
+        file: &str,
+        line: u32,
+        col: u32,
+    ) {
+    }
+    fn task__changepoint(runtime: u64, task_id: u64, kind: u8, poll_nanos: u64) {}
+    fn task__wake(runtime: u64, task_id: u64, worker: u64, file: &str, line: u32, col: u32) {}
+    fn task__sched__latency(runtime: u64, task_id: u64, latency_nanos: u64) {}
+    fn blocking__task__spawn(id: u64, file: &str, line: u32, col: u32) {}
+    fn blocking__task__start(id: u64, worker: u64, queue_nanos: u64) {}
+    fn blocking__task__end(id: u64, run_nanos: u64) {}
+    fn mutex__acquire__start(lock_id: u64, task_id: u64) {}
+    fn mutex__acquire__end(lock_id: u64, task_id: u64, wait_nanos: u64) {}
+    fn mutex__release(lock_id: u64, task_id: u64, held_nanos: u64) {}
+    fn span__enter(span_id: u64, name: &str, target: &str, task_id: u64) {}
+    fn span__exit(span_id: u64, name: &str, target: &str, task_id: u64) {}
+    fn event(name: &str, target: &str, level: u8, task_id: u64) {}
+
+    fn worker__steal__event(thief: u64, victim: u64, count: u64, interval_nanos: u64) {}
+    fn worker__poll__utilization(worker: u64, permille: u64, interval_nanos: u64) {}
+    fn hook__overhead(hook_id: u8, total_nanos: u64, calls: u64) {}
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_before_task_poll`].
-    pub fn on_before_task_poll(meta: &TaskMeta<'_>) {
-        probes::task__poll__start!(|| unpack_meta(meta));
+    fn runtime__heartbeat(seq: u64, epoch_base_nanos: u64, uptime_nanos: u64) {}
+    fn runtime__shutdown() {}
+    fn runtime__rates(spawned: u64, terminated: u64, interval_nanos: u64) {}
+    fn runtime__alive__delta(current: u64, delta: i64, interval_nanos: u64) {}
+    fn runtime__workers__changed(old: u64, new: u64, kind: u8) {}
+    fn runtime__metrics(
+        workers: u64,
+        alive_tasks: u64,
+        global_queue_depth: u64,
+        local_queue_depth: u64,
+        polls: u64,
+        interval_nanos: u64,
+    ) {
+    }
+    fn location__cumulative(
+        index: u64,
+        total_poll_nanos: u64,
+        poll_count: u64,
+        file: &str,
+        line: u32,
+        col: u32,
+    ) {
     }
+    fn runtime__idle() {}
+    fn runtime__active() {}
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_after_task_poll`].
-    pub fn on_after_task_poll(meta: &TaskMeta<'_>) {
-        probes::task__poll__end!(|| unpack_meta(meta));
+    fn worker__thread__start(runtime: u64, thread_name: &str, worker: u64, pool: u8) {}
+    fn worker__thread__stop(runtime: u64, thread_name: &str, worker: u64, pool: u8) {}
+    fn worker__thread__park(runtime: u64, thread_name: &str, worker: u64) {}
+    fn worker__thread__unpark(runtime: u64, thread_name: &str, worker: u64) {}
+    fn worker__thread__abnormal__stop(runtime: u64, worker: u64) {}
+}
+
+/// Fires the `tokio` provider's probes as SystemTap-style SDT probes on Linux,
+/// with a macro for each probe which takes the same argument closure as the
+/// USDT provider's. See the `sdt` module.
+#[cfg(all(feature = "sdt", target_os = "linux"))]
+// As with the provider's macros, which probes are used depends on the features
+// enabled.
+#[allow(unused_imports, unused_macros)]
+mod probes {
+    macro_rules! task__spawn {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__spawn, $args, [0 1 2 3 4 5])
+        };
     }
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_task_terminate`].
-    pub fn on_task_terminate(meta: &TaskMeta<'_>) {
-        probes::task__terminate!(|| unpack_meta(meta));
+    macro_rules! task__poll__start {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__poll__start, $args, [0 1 2 3 4 5])
+        };
     }
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_thread_start`].
-    pub fn on_thread_start() {
-        probes::worker__thread__start!(|| ());
+    macro_rules! task__poll__end {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__poll__end, $args, [0 1 2 3 4 5])
+        };
     }
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_thread_stop`].
-    pub fn on_thread_stop() {
-        probes::worker__thread__stop!(|| ());
+    macro_rules! task__terminate {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__terminate, $args, [0 1 2 3 4 5])
+        };
     }
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_thread_park`].
-    pub fn on_thread_park() {
-        probes::worker__thread__park!(|| ());
+    macro_rules! task__spawn__source {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__spawn__source, $args, [0 1 2])
+        };
+    }
+    macro_rules! task__kind {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__kind, $args, [0 1 2])
+        };
     }
 
-    /// Hook function to be used in [`tokio::runtime::Builder::on_thread_unpark`].
-    pub fn on_thread_unpark() {
-        probes::worker__thread__unpark!(|| ());
+    macro_rules! task__spawn__queue__depth {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__spawn__queue__depth, $args, [0 1 2])
+        };
     }
 
-    #[inline]
-    fn unpack_meta(meta: &TaskMeta<'_>) -> (u64, String, u32, u32) {
-        let id = id_to_u64(meta.id());
-        let location = meta.spawned_at();
-        let file = location.file().to_string();
-        let line = location.line();
-        let col = location.column();
-        (id, file, line, col)
+    macro_rules! task__spawn__runtime {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__spawn__runtime, $args, [0 1])
+        };
     }
 
-    #[inline]
-    fn id_to_u64(id: tokio::task::Id) -> u64 {
-        unsafe {
-            // SAFETY: Based on training and experience, I know that a
-            // `tokio::task::Id` is represented as a single `NonZeroU64`.
-            union TrustMeOnThis {
-                id: tokio::task::Id,
-                int: NonZeroU64,
-            }
-            TrustMeOnThis { id }.int.get()
-        }
+    macro_rules! task__spawn__parent {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__spawn__parent, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! task__spawn__scope {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__spawn__scope, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! task__name {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__name, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! scope__enter {
+        ($args:expr) => {
+            crate::sdt::sdt!(scope__enter, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! task__poll__span {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__poll__span, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! task__table__contended {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__table__contended, $args, [0])
+        };
+    }
+
+    macro_rules! task__poll__after__complete {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__poll__after__complete, $args, [0 1])
+        };
+    }
+
+    macro_rules! task__panic {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__panic, $args, [0 1 2 3 4])
+        };
+    }
+
+    macro_rules! task__deadline__missed {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__deadline__missed, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! task__finalize__latency {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__finalize__latency, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! probe__dropped {
+        ($args:expr) => {
+            crate::sdt::sdt!(probe__dropped, $args, [0 1])
+        };
+    }
+
+    macro_rules! task__pickup {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__pickup, $args, [0 1 2 3 4])
+        };
+    }
+
+    macro_rules! task__blocking__suspected {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__blocking__suspected, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! task__poll__slow {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__poll__slow, $args, [0 1 2 3 4 5])
+        };
+    }
+
+    macro_rules! task__changepoint {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__changepoint, $args, [0 1 2 3])
+        };
+    }
+
+    macro_rules! blocking__task__spawn {
+        ($args:expr) => {
+            crate::sdt::sdt!(blocking__task__spawn, $args, [0 1 2 3])
+        };
+    }
+
+    macro_rules! blocking__task__start {
+        ($args:expr) => {
+            crate::sdt::sdt!(blocking__task__start, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! blocking__task__end {
+        ($args:expr) => {
+            crate::sdt::sdt!(blocking__task__end, $args, [0 1])
+        };
+    }
+
+    macro_rules! mutex__acquire__start {
+        ($args:expr) => {
+            crate::sdt::sdt!(mutex__acquire__start, $args, [0 1])
+        };
+    }
+
+    macro_rules! mutex__acquire__end {
+        ($args:expr) => {
+            crate::sdt::sdt!(mutex__acquire__end, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! mutex__release {
+        ($args:expr) => {
+            crate::sdt::sdt!(mutex__release, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! task__wake {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__wake, $args, [0 1 2 3 4 5])
+        };
+    }
+
+    macro_rules! task__sched__latency {
+        ($args:expr) => {
+            crate::sdt::sdt!(task__sched__latency, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! span__enter {
+        ($args:expr) => {
+            crate::sdt::sdt!(span__enter, $args, [0 1 2 3])
+        };
+    }
+
+    macro_rules! span__exit {
+        ($args:expr) => {
+            crate::sdt::sdt!(span__exit, $args, [0 1 2 3])
+        };
+    }
+
+    macro_rules! event {
+        ($args:expr) => {
+            crate::sdt::sdt!(event, $args, [0 1 2 3])
+        };
+    }
+
+    macro_rules! worker__steal__event {
+        ($args:expr) => {
+            crate::sdt::sdt!(worker__steal__event, $args, [0 1 2 3])
+        };
+    }
+
+    macro_rules! worker__poll__utilization {
+        ($args:expr) => {
+            crate::sdt::sdt!(worker__poll__utilization, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! hook__overhead {
+        ($args:expr) => {
+            crate::sdt::sdt!(hook__overhead, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! runtime__heartbeat {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__heartbeat, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! runtime__shutdown {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__shutdown, $args)
+        };
+    }
+
+    macro_rules! runtime__rates {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__rates, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! runtime__alive__delta {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__alive__delta, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! runtime__workers__changed {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__workers__changed, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! runtime__metrics {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__metrics, $args, [0 1 2 3 4 5])
+        };
+    }
+
+    macro_rules! location__cumulative {
+        ($args:expr) => {
+            crate::sdt::sdt!(location__cumulative, $args, [0 1 2 3 4 5])
+        };
+    }
+
+    macro_rules! runtime__idle {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__idle, $args)
+        };
+    }
+
+    macro_rules! runtime__active {
+        ($args:expr) => {
+            crate::sdt::sdt!(runtime__active, $args)
+        };
+    }
+
+    macro_rules! worker__thread__start {
+        ($args:expr) => {
+            crate::sdt::sdt!(worker__thread__start, $args, [0 1 2 3])
+        };
     }
+
+    macro_rules! worker__thread__stop {
+        ($args:expr) => {
+            crate::sdt::sdt!(worker__thread__stop, $args, [0 1 2 3])
+        };
+    }
+
+    macro_rules! worker__thread__park {
+        ($args:expr) => {
+            crate::sdt::sdt!(worker__thread__park, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! worker__thread__unpark {
+        ($args:expr) => {
+            crate::sdt::sdt!(worker__thread__unpark, $args, [0 1 2])
+        };
+    }
+
+    macro_rules! worker__thread__abnormal__stop {
+        ($args:expr) => {
+            crate::sdt::sdt!(worker__thread__abnormal__stop, $args, [0 1])
+        };
+    }
+
+    pub(crate) use blocking__task__end;
+    pub(crate) use blocking__task__spawn;
+    pub(crate) use blocking__task__start;
+    pub(crate) use event;
+    pub(crate) use mutex__acquire__end;
+    pub(crate) use mutex__acquire__start;
+    pub(crate) use mutex__release;
+    pub(crate) use probe__dropped;
+    pub(crate) use scope__enter;
+    pub(crate) use span__enter;
+    pub(crate) use span__exit;
+    pub(crate) use task__blocking__suspected;
+    pub(crate) use task__changepoint;
+    pub(crate) use task__deadline__missed;
+    pub(crate) use task__finalize__latency;
+    pub(crate) use task__kind;
+    pub(crate) use task__name;
+    pub(crate) use task__panic;
+    pub(crate) use task__pickup;
+    pub(crate) use task__poll__after__complete;
+    pub(crate) use task__poll__end;
+    pub(crate) use task__poll__slow;
+    pub(crate) use task__poll__span;
+    pub(crate) use task__poll__start;
+    pub(crate) use task__sched__latency;
+    pub(crate) use task__spawn;
+    pub(crate) use task__spawn__parent;
+    pub(crate) use task__spawn__queue__depth;
+    pub(crate) use task__spawn__runtime;
+    pub(crate) use task__spawn__scope;
+    pub(crate) use task__spawn__source;
+    pub(crate) use task__table__contended;
+    pub(crate) use task__terminate;
+    pub(crate) use task__wake;
+
+    pub(crate) use hook__overhead;
+    pub(crate) use worker__poll__utilization;
+    pub(crate) use worker__steal__event;
+
+    pub(crate) use location__cumulative;
+    pub(crate) use runtime__active;
+    pub(crate) use runtime__alive__delta;
+    pub(crate) use runtime__heartbeat;
+    pub(crate) use runtime__idle;
+    pub(crate) use runtime__metrics;
+    pub(crate) use runtime__rates;
+    pub(crate) use runtime__shutdown;
+    pub(crate) use runtime__workers__changed;
+
+    pub(crate) use worker__thread__abnormal__stop;
+    pub(crate) use worker__thread__park;
+    pub(crate) use worker__thread__start;
+    pub(crate) use worker__thread__stop;
+    pub(crate) use worker__thread__unpark;
 }
 
-#[usdt::provider(provider = "tokio")]
-#[allow(non_snake_case)]
+/// Stands in for the USDT provider when no probe backend is enabled, or the
+/// target is not supported by any of them, with a macro for each probe which
+/// discards its arguments without evaluating them.
+#[cfg(not(any(
+    all(feature = "usdt", any(unix, windows)),
+    all(feature = "sdt", target_os = "linux")
+)))]
+// As with the provider's macros, which probes are used depends on the features
+// enabled.
+#[allow(unused_imports)]
 mod probes {
-    fn task__spawn(task_id: u64, file: String, line: u32, col: u32) {}
-    fn task__poll__start(task_id: u64, file: String, line: u32, col: u32) {}
-    fn task__poll__end(task_id: u64, file: String, line: u32, col: u32) {}
-    fn task__terminate(task_id: u64, file: String, line: u32, col: u32) {}
-
-    fn worker__thread__start() {}
-    fn worker__thread__stop() {}
-    fn worker__thread__park() {}
-    fn worker__thread__unpark() {}
+    macro_rules! disabled {
+        ($args:expr) => {{
+            let _ = $args;
+        }};
+    }
+
+    pub(crate) use disabled as task__spawn;
+    pub(crate) use disabled as task__poll__start;
+    pub(crate) use disabled as task__poll__end;
+    pub(crate) use disabled as task__terminate;
+    pub(crate) use disabled as task__spawn__source;
+    pub(crate) use disabled as task__kind;
+    pub(crate) use disabled as task__spawn__queue__depth;
+    pub(crate) use disabled as task__spawn__scope;
+    pub(crate) use disabled as task__spawn__runtime;
+    pub(crate) use disabled as task__spawn__parent;
+    pub(crate) use disabled as scope__enter;
+    pub(crate) use disabled as task__name;
+    pub(crate) use disabled as task__poll__span;
+    pub(crate) use disabled as task__table__contended;
+    pub(crate) use disabled as task__poll__after__complete;
+    pub(crate) use disabled as task__panic;
+    pub(crate) use disabled as task__deadline__missed;
+    pub(crate) use disabled as task__finalize__latency;
+    pub(crate) use disabled as probe__dropped;
+    pub(crate) use disabled as task__pickup;
+    pub(crate) use disabled as task__blocking__suspected;
+    pub(crate) use disabled as task__poll__slow;
+    pub(crate) use disabled as task__changepoint;
+    pub(crate) use disabled as task__wake;
+    pub(crate) use disabled as task__sched__latency;
+    pub(crate) use disabled as blocking__task__spawn;
+    pub(crate) use disabled as blocking__task__start;
+    pub(crate) use disabled as blocking__task__end;
+    pub(crate) use disabled as mutex__acquire__start;
+    pub(crate) use disabled as mutex__acquire__end;
+    pub(crate) use disabled as mutex__release;
+    pub(crate) use disabled as span__enter;
+    pub(crate) use disabled as span__exit;
+    pub(crate) use disabled as event;
+
+    pub(crate) use disabled as worker__steal__event;
+    pub(crate) use disabled as worker__poll__utilization;
+    pub(crate) use disabled as hook__overhead;
+
+    pub(crate) use disabled as runtime__heartbeat;
+    pub(crate) use disabled as runtime__shutdown;
+    pub(crate) use disabled as runtime__rates;
+    pub(crate) use disabled as runtime__alive__delta;
+    pub(crate) use disabled as runtime__metrics;
+    pub(crate) use disabled as runtime__workers__changed;
+    pub(crate) use disabled as location__cumulative;
+    pub(crate) use disabled as runtime__idle;
+    pub(crate) use disabled as runtime__active;
+
+    pub(crate) use disabled as worker__thread__start;
+    pub(crate) use disabled as worker__thread__stop;
+    pub(crate) use disabled as worker__thread__park;
+    pub(crate) use disabled as worker__thread__unpark;
+    pub(crate) use disabled as worker__thread__abnormal__stop;
 }
 
 #[cfg(test)]
@@ -491,4 +1972,271 @@ mod tests {
     fn casts_are_valid() {
         crate::check_casts().unwrap();
     }
+
+    #[test]
+    fn invalid_casts_name_the_type() {
+        let error = crate::InvalidCasts {
+            type_name: "tokio::task::Id",
+            version: "1.2.3",
+            id_size: 16,
+            nonzero_u64_size: 8,
+            id_align: 8,
+            nonzero_u64_align: 8,
+        };
+        let message = error.to_string();
+        assert!(message.contains("size_of::<tokio::task::Id>() = 16 (expected 8)"));
+        assert!(message.contains("tokio-dtrace 1.2.3"));
+        assert_eq!(error.type_name(), "tokio::task::Id");
+        assert_eq!((error.actual_size(), error.expected_size()), (16, 8));
+    }
+
+    #[test]
+    fn dtrace_match_formats_descriptions() {
+        use crate::{ProbeMask, dtrace_match_for};
+
+        assert_eq!(dtrace_match_for(12345, None), "tokio12345:::");
+        assert_eq!(
+            dtrace_match_for(12345, Some(ProbeMask::empty())),
+            "tokio12345:::"
+        );
+        assert_eq!(
+            dtrace_match_for(1, Some(ProbeMask::TASK_SPAWN)),
+            "tokio1:::task-spawn"
+        );
+        assert_eq!(
+            dtrace_match_for(1, Some(ProbeMask::TASK_TERMINATE | ProbeMask::TASK_SPAWN)),
+            "tokio1:::task-spawn,tokio1:::task-terminate"
+        );
+        assert_eq!(
+            crate::dtrace_match(None),
+            format!("tokio{}:::", std::process::id())
+        );
+    }
+
+    /// The `tokio` provider's probes, in the order in which they are defined,
+    /// with the D type and name of each of their arguments.
+    const PROBE_SCHEMA: &str = "\
+task-spawn(uint64_t task_id, char *file, uint32_t line, uint32_t col, uint64_t trace_hi, uint64_t trace_lo)
+task-poll-start(uint64_t task_id, char *file, uint32_t line, uint32_t col, uint64_t trace_hi, uint64_t trace_lo)
+task-poll-end(uint64_t task_id, char *file, uint32_t line, uint32_t col, uint8_t budget_exhausted, uint64_t poll_nanos)
+task-terminate(uint64_t task_id, char *file, uint32_t line, uint32_t col, uint64_t reschedules, uint8_t flags)
+task-spawn-source(uint64_t runtime, uint64_t task_id, uint8_t spawn_source)
+task-kind(uint64_t runtime, uint64_t task_id, uint8_t kind)
+task-spawn-queue-depth(uint64_t runtime, uint64_t task_id, uint64_t queue_depth)
+task-spawn-scope(uint64_t runtime, uint64_t task_id, uint32_t scope_id)
+task-spawn-runtime(uint64_t runtime, uint64_t task_id)
+task-spawn-parent(uint64_t runtime, uint64_t task_id, uint64_t parent_id)
+scope-enter(uint64_t task_id, uint32_t scope_id, char *name)
+task-name(uint64_t runtime, uint64_t task_id, char *name)
+task-poll-span(uint64_t runtime, uint64_t task_id, uint64_t span_id)
+task-table-contended(uint64_t wait_nanos)
+task-poll-after-complete(uint64_t runtime, uint64_t task_id)
+task-panic(uint64_t runtime, uint64_t task_id, char *file, uint32_t line, uint32_t col)
+task-deadline-missed(uint64_t runtime, uint64_t task_id, uint64_t over_by_nanos)
+task-finalize-latency(uint64_t runtime, uint64_t task_id, uint64_t nanos)
+probe-dropped(uint32_t probe, uint64_t count)
+task-pickup(uint64_t runtime, uint64_t task_id, uint64_t spawner_worker, uint64_t poller_worker, uint64_t queue_nanos)
+task-blocking-suspected(uint64_t runtime, uint64_t task_id, uint64_t poll_nanos)
+task-poll-slow(uint64_t runtime, uint64_t task_id, uint64_t poll_nanos, char *file, uint32_t line, uint32_t col)
+task-changepoint(uint64_t runtime, uint64_t task_id, uint8_t kind, uint64_t poll_nanos)
+task-wake(uint64_t runtime, uint64_t task_id, uint64_t worker, char *file, uint32_t line, uint32_t col)
+task-sched-latency(uint64_t runtime, uint64_t task_id, uint64_t latency_nanos)
+blocking-task-spawn(uint64_t id, char *file, uint32_t line, uint32_t col)
+blocking-task-start(uint64_t id, uint64_t worker, uint64_t queue_nanos)
+blocking-task-end(uint64_t id, uint64_t run_nanos)
+mutex-acquire-start(uint64_t lock_id, uint64_t task_id)
+mutex-acquire-end(uint64_t lock_id, uint64_t task_id, uint64_t wait_nanos)
+mutex-release(uint64_t lock_id, uint64_t task_id, uint64_t held_nanos)
+span-enter(uint64_t span_id, char *name, char *target, uint64_t task_id)
+span-exit(uint64_t span_id, char *name, char *target, uint64_t task_id)
+event(char *name, char *target, uint8_t level, uint64_t task_id)
+worker-steal-event(uint64_t thief, uint64_t victim, uint64_t count, uint64_t interval_nanos)
+worker-poll-utilization(uint64_t worker, uint64_t permille, uint64_t interval_nanos)
+hook-overhead(uint8_t hook_id, uint64_t total_nanos, uint64_t calls)
+runtime-heartbeat(uint64_t seq, uint64_t epoch_base_nanos, uint64_t uptime_nanos)
+runtime-shutdown()
+runtime-rates(uint64_t spawned, uint64_t terminated, uint64_t interval_nanos)
+runtime-alive-delta(uint64_t current, int64_t delta, uint64_t interval_nanos)
+runtime-workers-changed(uint64_t old, uint64_t new, uint8_t kind)
+runtime-metrics(uint64_t workers, uint64_t alive_tasks, uint64_t global_queue_depth, uint64_t local_queue_depth, uint64_t polls, uint64_t interval_nanos)
+location-cumulative(uint64_t index, uint64_t total_poll_nanos, uint64_t poll_count, char *file, uint32_t line, uint32_t col)
+runtime-idle()
+runtime-active()
+worker-thread-start(uint64_t runtime, char *thread_name, uint64_t worker, uint8_t pool)
+worker-thread-stop(uint64_t runtime, char *thread_name, uint64_t worker, uint8_t pool)
+worker-thread-park(uint64_t runtime, char *thread_name, uint64_t worker)
+worker-thread-unpark(uint64_t runtime, char *thread_name, uint64_t worker)
+worker-thread-abnormal-stop(uint64_t runtime, uint64_t worker)
+";
+
+    /// Returns the body of the `tokio` provider's `probes` module.
+    fn provider_module() -> &'static str {
+        let source = include_str!("lib.rs");
+        let source = &source[source
+            .find("#[usdt::provider(provider = \"tokio\")]")
+            .unwrap()..];
+        let body = &source[source.find("mod probes {").unwrap() + "mod probes {".len()..];
+        let mut depth = 1;
+        let end = body
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .unwrap()
+            .0;
+        &body[..end]
+    }
+
+    /// Returns the D type of a probe argument of the given Rust type, as
+    /// `usdt` converts it.
+    fn d_type(ty: &str) -> &'static str {
+        match ty {
+            "u8" => "uint8_t",
+            "u32" => "uint32_t",
+            "u64" => "uint64_t",
+            "i64" => "int64_t",
+            "&str" | "String" => "char *",
+            _ => panic!("unexpected probe argument type `{ty}`"),
+        }
+    }
+
+    /// Enumerates the probes of the `tokio` provider, as its `usdt` attribute
+    /// reads them, formatted as in [`PROBE_SCHEMA`].
+    fn provider_schema() -> String {
+        provider_module()
+            .split("fn ")
+            .skip(1)
+            .map(|probe| {
+                let (name, rest) = probe.split_once('(').unwrap();
+                let (args, _) = rest.split_once(')').unwrap();
+                let args = args
+                    .split(',')
+                    .filter_map(|arg| arg.split_once(':'))
+                    .map(|(name, ty)| {
+                        let ty = d_type(ty.trim());
+                        let sep = if ty.ends_with('*') { "" } else { " " };
+                        format!("{ty}{sep}{}", name.trim())
+                    })
+                    .collect::<Vec<_>>();
+                format!("{}({})\n", name.trim().replace("__", "-"), args.join(", "))
+            })
+            .collect()
+    }
+
+    /// Pins the name, order, and type of every probe's arguments.
+    ///
+    /// `usdt` does not provide a way to read back the probes it registers,
+    /// and registers none on targets without DTrace, so this enumerates the
+    /// probes from the provider's definition, as the `usdt` attribute does
+    /// when it generates them. Unlike type-checking the probes' macros, this
+    /// runs whichever features and backend are enabled.
+    #[test]
+    fn probe_schema() {
+        assert_eq!(provider_schema(), PROBE_SCHEMA);
+    }
+
+    /// Checks that the SDT backend fires each of the provider's probes with
+    /// all of its arguments, and that every probe has a stub for when no
+    /// backend is enabled.
+    #[test]
+    fn probe_backends_match_the_provider() {
+        let source = include_str!("lib.rs");
+        for probe in provider_schema().lines() {
+            let (name, args) = probe.split_once('(').unwrap();
+            let ident = name.replace('-', "__");
+            let args = args.trim_end_matches(')');
+            let arity = if args.is_empty() {
+                0
+            } else {
+                args.split(", ").count()
+            };
+            let indices = (0..arity).map(|i| i.to_string()).collect::<Vec<_>>();
+            let expected = if arity == 0 {
+                format!("crate::sdt::sdt!({ident}, $args)")
+            } else {
+                format!("crate::sdt::sdt!({ident}, $args, [{}])", indices.join(" "))
+            };
+            assert!(
+                source.contains(&expected),
+                "`{name}` is not fired as `{expected}`"
+            );
+            assert!(
+                source.contains(&format!("pub(crate) use disabled as {ident};")),
+                "`{name}` has no disabled stub"
+            );
+        }
+    }
+
+    #[test]
+    fn unstable_features_are_required() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let error = crate::register_hooks_impl(&mut builder, crate::Config::default(), true, false)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            crate::RegistrationError::UnstableFeaturesRequired
+        ));
+        assert_eq!(
+            error.to_string(),
+            "tokio-dtrace requires `RUSTFLAGS=\"--cfg tokio_unstable\"`"
+        );
+    }
+
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    #[test]
+    fn trace_id_is_inherited() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let trace_id = rt.block_on(async {
+            tokio::spawn(async {
+                crate::set_trace_id(0x0123_4567_89ab_cdef_fedc_ba98_7654_3210);
+                tokio::spawn(async { crate::current_task::trace_id() })
+                    .await
+                    .unwrap()
+            })
+            .await
+            .unwrap()
+        });
+        assert_eq!(trace_id, 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210);
+    }
+
+    /// `TaskMeta` cannot be constructed outside of Tokio, so this simulates an
+    /// integration which forwards only some of the task hooks.
+    #[cfg(tokio_unstable)]
+    #[test]
+    fn hooks_tolerate_missing_spawn_hook() {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .on_before_task_poll(crate::hooks::on_before_task_poll)
+            .on_after_task_poll(crate::hooks::on_after_task_poll)
+            .on_task_terminate(crate::hooks::on_task_terminate)
+            .build()
+            .unwrap();
+
+        let sum = rt.block_on(async {
+            let tasks: Vec<_> = (0..8u64)
+                .map(|i| {
+                    tokio::spawn(async move {
+                        tokio::task::yield_now().await;
+                        i
+                    })
+                })
+                .collect();
+            let mut sum = 0;
+            for task in tasks {
+                sum += task.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(sum, (0..8u64).sum::<u64>());
+    }
 }
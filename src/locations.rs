@@ -0,0 +1,151 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Cumulative poll time per spawn location, reported by the
+//! [metrics sampler](crate::metrics).
+//!
+//! When enabled by [`Config::location_stats`](crate::Config::location_stats),
+//! each spawn location is interned the first time a task is spawned there,
+//! assigning it an index and a set of counters which live for the rest of the
+//! process. Each task refers to its location's counters, so recording a poll
+//! only costs two atomic additions, with no lookup.
+use std::collections::HashMap;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+
+/// The counters for a single spawn location.
+#[derive(Debug)]
+pub(crate) struct LocationStats {
+    index: u64,
+    location: &'static Location<'static>,
+    poll_nanos: AtomicU64,
+    polls: AtomicU64,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Interned locations, keyed by their address.
+    by_address: HashMap<usize, &'static LocationStats>,
+    /// Interned locations, in the order in which they were interned.
+    all: Vec<&'static LocationStats>,
+}
+
+static REGISTRY: LazyLock<RwLock<Registry>> = LazyLock::new(Default::default);
+
+/// Returns the counters for `location`, interning it if it has not been seen
+/// before.
+pub(crate) fn intern(location: &'static Location<'static>) -> &'static LocationStats {
+    let key = std::ptr::from_ref(location) as usize;
+    let interned = REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .by_address
+        .get(&key)
+        .copied();
+    interned.unwrap_or_else(|| {
+        let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+        let Registry { by_address, all } = &mut *registry;
+        by_address.entry(key).or_insert_with(|| {
+            // Interned locations are never freed, but there are only as many
+            // as there are places in the program which spawn tasks.
+            let stats = Box::leak(Box::new(LocationStats {
+                index: all.len() as u64,
+                location,
+                poll_nanos: AtomicU64::new(0),
+                polls: AtomicU64::new(0),
+            }));
+            all.push(stats);
+            stats
+        })
+    })
+}
+
+impl LocationStats {
+    /// Records a completed poll of a task spawned at this location.
+    #[inline]
+    pub(crate) fn record(&self, poll_nanos: u64) {
+        self.poll_nanos.fetch_add(poll_nanos, Ordering::Relaxed);
+        self.polls.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Each location's poll count as of the previous call to
+/// [`Cumulative::report`].
+pub(crate) struct Cumulative {
+    reported: Vec<u64>,
+}
+
+impl Cumulative {
+    pub(crate) fn new() -> Self {
+        Self {
+            reported: Vec::new(),
+        }
+    }
+
+    /// Fires the `location-cumulative` probe for each location which has been
+    /// polled since the previous call.
+    pub(crate) fn report(&mut self) {
+        let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+        for stats in self.active(&registry.all) {
+            crate::probes::location__cumulative!(|| {
+                let location = stats.location;
+                (
+                    stats.index,
+                    stats.poll_nanos.load(Ordering::Relaxed),
+                    stats.polls.load(Ordering::Relaxed),
+                    crate::config::probe_path(location.file()),
+                    location.line(),
+                    location.column(),
+                )
+            });
+        }
+    }
+
+    /// Returns the locations in `all` which have been polled since the
+    /// previous call.
+    fn active<'a>(
+        &'a mut self,
+        all: &'a [&'static LocationStats],
+    ) -> impl Iterator<Item = &'static LocationStats> + 'a {
+        self.reported.resize(all.len(), 0);
+        all.iter()
+            .zip(self.reported.iter_mut())
+            .filter_map(|(stats, reported)| {
+                let polls = stats.polls.load(Ordering::Relaxed);
+                let active = polls != *reported;
+                *reported = polls;
+                active.then_some(*stats)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_active_locations_are_reported() {
+        let first = intern(Location::caller());
+        let second = intern(Location::caller());
+        assert_ne!(first.index, second.index);
+        assert_eq!(intern(first.location).index, first.index);
+
+        // Other tests may intern locations of their own, so only the locations
+        // interned here are considered.
+        let all = [first, second];
+        let mut cumulative = Cumulative::new();
+        first.record(10);
+        let active = |cumulative: &mut Cumulative| {
+            cumulative
+                .active(&all)
+                .map(|stats| stats.index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(active(&mut cumulative), [first.index]);
+        assert_eq!(active(&mut cumulative), [0u64; 0]);
+        second.record(5);
+        second.record(5);
+        assert_eq!(active(&mut cumulative), [second.index]);
+        assert_eq!(second.poll_nanos.load(Ordering::Relaxed), 10);
+    }
+}
@@ -0,0 +1,608 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Probes derived from periodically sampling Tokio's [`RuntimeMetrics`].
+//!
+//! Some information about the runtime's behavior, such as how often worker
+//! threads steal work from each other, is not observable from the runtime
+//! hooks, but is tracked by Tokio's [runtime metrics][`RuntimeMetrics`]. The
+//! [`spawn_sampler`] function spawns a task which periodically samples these
+//! metrics and fires probes describing them.
+//!
+//! Because metrics are *sampled*, the probes fired by the sampler describe
+//! what happened over each sampling interval, rather than individual events.
+//! For example, the `worker-steal-event` probe reports the number of tasks a
+//! worker stole since the previous sample, not each individual steal.
+//!
+//! The sampler is a task like any other, so when the runtime is saturated, it
+//! may run later than scheduled, and the time between samples can be much
+//! longer than the nominal interval. Each probe which describes an interval
+//! therefore also reports the *actual* time since the previous sample, which
+//! should be used, rather than the nominal interval, to compute accurate rates
+//! under load.
+//!
+//! The sampler is a task like any other, so it stops if its runtime shuts
+//! down. [`try_spawn_sampler`] and [`SamplerGuard::check`] report this, and
+//! other failures, as a [`MetricsError`].
+#[cfg(feature = "probes-metrics")]
+use crate::worker::NO_WORKER;
+use std::any::Any;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+#[cfg(feature = "probes-metrics")]
+use std::time::Instant;
+use tokio::runtime::Handle;
+#[cfg(feature = "probes-metrics")]
+use tokio::runtime::RuntimeMetrics;
+use tokio::task::JoinHandle;
+
+/// Spawns a task on the runtime referenced by `handle` which samples that
+/// runtime's metrics every `interval` and fires the corresponding probes.
+///
+/// The sampler task stops when the runtime shuts down, or when the returned
+/// [`SamplerGuard`] is dropped, whichever comes first.
+///
+/// The sampler currently fires the following probes:
+///
+/// - **`tokio*:::worker-steal-event`**: fired for each worker which stole
+///   tasks from another worker since the previous sample. `arg0` is the
+///   stealing worker's index (as used by [`RuntimeMetrics`]), `arg2` is the
+///   number of tasks it stole since the previous sample, and `arg3` is the
+///   actual time since the previous sample, in nanoseconds.
+///
+///   `arg1` is intended to identify the worker the tasks were stolen from.
+///   Tokio's metrics do not currently record this, so it is always
+///   `0xffffffffffffffff` (`u64::MAX`).
+///
+/// - **`tokio*:::worker-poll-utilization`**: only fired when the
+///   `probes-task` feature is also enabled. Fired for each worker on every
+///   sample, with the worker's index (as used by [`RuntimeMetrics`]) as
+///   `arg0`, and the fraction of the time since the previous sample which the
+///   worker spent polling tasks, in thousandths, as `arg1`. `arg2` is the
+///   actual time since the previous sample, in nanoseconds.
+///
+///   This is the worker's *effective* utilization: only time spent inside
+///   task polls counts, as measured by the poll hooks. It differs from the
+///   park-based view given by the `worker-thread-park` and
+///   `worker-thread-unpark` probes (and by Tokio's
+///   [`RuntimeMetrics::worker_total_busy_duration`]), in which a worker is
+///   busy whenever it is not parked, including while it is running the I/O
+///   and timer drivers, stealing, or spinning looking for work. A worker
+///   which unparks constantly but has little work to do is "busy" by that
+///   measure, while its utilization stays low; a worker close to 1000 is
+///   genuinely saturated with task work.
+///
+///   A poll's duration is counted when the poll ends, so a long poll is
+///   attributed entirely to the interval in which it ended; values which
+///   would exceed 1000 as a result are reported as 1000. A worker is not
+///   reported for the first interval in which it polls a task, or if its
+///   thread changes between samples. On a current-thread runtime, the single
+///   worker is whichever thread is currently running
+///   [`Runtime::block_on`](tokio::runtime::Runtime::block_on), so it is only
+///   reported while the same thread keeps doing so.
+///
+/// - **`tokio*:::runtime-rates`**: only fired when the `probes-task` feature
+///   is also enabled. Fired on every sample, with the number of tasks spawned
+///   as `arg0`, and the number of tasks terminated as `arg1`, since the
+///   previous sample. `arg2` is the actual time since the previous sample, in
+///   nanoseconds. The counts include tasks on *every* runtime with
+///   `tokio-dtrace`'s hooks registered, not only the sampled one.
+///
+///   Counting spawns and terminations in the hooks costs far less than firing
+///   a probe for each of them, so this probe allows a consumer which only
+///   needs aggregate throughput, such as a dashboard, to leave the
+///   `task-spawn` and `task-terminate` probes disabled.
+///
+/// - **`tokio*:::runtime-alive-delta`**: fired on every sample. `arg0` is the
+///   number of tasks alive on the sampled runtime, as reported by
+///   [`RuntimeMetrics::num_alive_tasks`], and `arg1` is the (signed) change
+///   in that number since the previous sample: positive when the number of
+///   alive tasks grew, and negative when it shrank. `arg2` is the actual time
+///   since the previous sample, in nanoseconds. A delta which stays
+///   positive over many samples suggests that tasks are being leaked, while
+///   a delta which oscillates around zero indicates healthy churn.
+///
+/// - **`tokio*:::runtime-workers-changed`**: fired when the number of threads
+///   in one of the sampled runtime's thread pools has changed since the
+///   previous sample. `arg0` is the previous number of threads, and `arg1` is
+///   the new number. `arg2` identifies the pool: `0` for the runtime's worker
+///   threads, as reported by [`RuntimeMetrics::num_workers`], `1` for its
+///   blocking thread pool, as reported by
+///   [`RuntimeMetrics::num_blocking_threads`], and `2` for the blocking pool's
+///   idle threads, as reported by
+///   [`RuntimeMetrics::num_idle_blocking_threads`]. Only the net change
+///   between samples is observed, so a pool which grows and shrinks back
+///   within a single interval does not fire the probe.
+///
+///   Tokio does not currently resize a runtime's worker pool, so in practice
+///   this probe reports the blocking pool, which grows as blocking tasks are
+///   spawned and shrinks as idle threads time out. Per-worker aggregations
+///   made over an interval in which a pool was resized should be interpreted
+///   with care.
+///
+/// - **`tokio*:::runtime-metrics`**: fired on every sample, with a snapshot
+///   of the sampled runtime's scheduler state. `arg0` is the number of worker
+///   threads, `arg1` the number of alive tasks, `arg2` the depth of the
+///   global queue, as reported by [`RuntimeMetrics::global_queue_depth`], and
+///   `arg3` the total depth of the workers' local queues, as reported by
+///   [`RuntimeMetrics::worker_local_queue_depth`]. `arg4` is the number of
+///   task polls completed by all of the workers since the previous sample,
+///   and `arg5` is the actual time since the previous sample, in nanoseconds.
+///
+///   The queue depths are instantaneous, and may be inconsistent with each
+///   other, as the workers continue to run while they are read. Queue depths
+///   which stay high across many samples indicate that tasks are being
+///   spawned or woken faster than the workers can poll them.
+///
+/// - **`tokio*:::location-cumulative`**: only fired when the `probes-task`
+///   feature is also enabled, and per-location statistics are enabled using
+///   [`Config::location_stats`](crate::Config::location_stats). Fired for
+///   each spawn location at which a task has completed a poll since the
+///   previous sample. `arg0` is an index identifying the location, which is
+///   assigned the first time a task is spawned there and does not change for
+///   the lifetime of the process. `arg1` is the total time spent polling tasks
+///   spawned at the location, in nanoseconds, and `arg2` is the total number
+///   of polls; both are cumulative since the location was first seen, so the
+///   time spent over an interval is the difference between two samples. `arg3`,
+///   `arg4`, and `arg5` are the location's file, line, and column. Like
+///   `runtime-rates`, this includes tasks on every runtime with
+///   `tokio-dtrace`'s hooks registered.
+///
+///   A program with many spawn locations may fire this probe many times per
+///   sample. Locations with no polls since the previous sample are skipped,
+///   so only the locations which are actually doing work cost anything. The
+///   totals are updated when a poll completes, so a single long poll is only
+///   counted once it has ended.
+///
+/// - **`tokio*:::probe-dropped`**: fired for each probe which has had fires
+///   dropped by its [rate limit](crate::Config::rate_limit) since the previous
+///   sample. `arg0` is the probe's bit, as in [`ProbeMask`](crate::ProbeMask),
+///   and `arg1` is the number of fires dropped.
+///
+/// - **`tokio*:::hook-overhead`**: only fired when the `debug-overhead`
+///   feature is enabled. Fired for each of `tokio-dtrace`'s runtime hooks
+///   which has been called, with the hook's identifier as `arg0`, the total
+///   time spent executing it, in nanoseconds, as `arg1`, and the total number
+///   of calls to it as `arg2`. Both totals count from the start of the
+///   process, and are approximate. The hook identifiers are: `0`:
+///   `on_task_spawn`, `1`: `on_before_task_poll`, `2`: `on_after_task_poll`,
+///   `3`: `on_task_terminate`, `4`: `on_thread_start`, `5`:
+///   `on_thread_stop`, `6`: `on_thread_park`, and `7`: `on_thread_unpark`.
+///
+/// The sampler is also responsible for detecting when a DTrace consumer
+/// attaches or detaches, and calling the callback set by
+/// [`Config::on_consumer_change`](crate::Config::on_consumer_change).
+///
+/// # Errors
+///
+/// This function does not report errors: if the sampler cannot be started, or
+/// stops unexpectedly, no probes are fired. Use [`try_spawn_sampler`] to
+/// detect these failures.
+///
+/// The sampler uses [`tokio::time`], so the runtime must have been built
+/// with the time driver enabled (e.g. using
+/// [`Builder::enable_time`](tokio::runtime::Builder::enable_time)).
+/// Otherwise, the sampler task will panic.
+///
+/// If the `probes-metrics` feature is disabled, this function does nothing.
+pub fn spawn_sampler(handle: &Handle, interval: Duration) -> SamplerGuard {
+    try_spawn_sampler(handle, interval).unwrap_or(SamplerGuard {
+        task: None,
+        error: None,
+    })
+}
+
+/// Spawns a metrics sampler task, as [`spawn_sampler`] does, returning an
+/// error if it could not be started.
+///
+/// Failures which occur after the sampler has started, such as the runtime
+/// shutting down, are reported by [`SamplerGuard::check`].
+///
+/// # Errors
+///
+/// - [`MetricsError::ZeroInterval`] if `interval` is zero.
+/// - [`MetricsError::RuntimeShutdown`] if the runtime referenced by `handle`
+///   has already shut down.
+/// - [`MetricsError::SamplerPanicked`] if the sampler task panicked
+///   immediately after being spawned. This is not guaranteed to be detected
+///   here, as the sampler may not have been polled yet.
+///
+/// If the `probes-metrics` feature is disabled, this function does nothing,
+/// and returns `Ok` unless `interval` is zero.
+pub fn try_spawn_sampler(
+    handle: &Handle,
+    interval: Duration,
+) -> Result<SamplerGuard, MetricsError> {
+    if interval.is_zero() {
+        return Err(MetricsError::ZeroInterval);
+    }
+    #[cfg(feature = "probes-metrics")]
+    {
+        let mut sampler = Sampler::new(handle.metrics());
+        let task = handle.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                sampler.sample();
+            }
+        });
+        let mut guard = SamplerGuard {
+            task: Some(task),
+            error: None,
+        };
+        guard.check()?;
+        Ok(guard)
+    }
+    #[cfg(not(feature = "probes-metrics"))]
+    {
+        let _ = handle;
+        Ok(SamplerGuard {
+            task: None,
+            error: None,
+        })
+    }
+}
+
+/// Stops a sampler task spawned by [`spawn_sampler`] when dropped.
+#[derive(Debug)]
+#[must_use = "the sampler stops when the guard is dropped"]
+pub struct SamplerGuard {
+    task: Option<JoinHandle<()>>,
+    /// Why the sampler stopped, once that has been observed by
+    /// [`SamplerGuard::check`].
+    error: Option<MetricsError>,
+}
+
+impl SamplerGuard {
+    /// Returns an error if the sampler task has stopped.
+    ///
+    /// The sampler only stops on its own if its runtime shuts down
+    /// ([`MetricsError::RuntimeShutdown`]), or if it panics
+    /// ([`MetricsError::SamplerPanicked`]), so this may be called periodically
+    /// to detect that the sampler's probes are no longer firing. Once the
+    /// sampler has stopped, every subsequent call returns the same error.
+    ///
+    /// If the `probes-metrics` feature is disabled, this always returns `Ok`.
+    pub fn check(&mut self) -> Result<(), MetricsError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        let Some(task) = self.task.as_mut().filter(|task| task.is_finished()) else {
+            return Ok(());
+        };
+        // The task has finished, so polling it will not register the waker.
+        let mut cx = Context::from_waker(Waker::noop());
+        let error = match Pin::new(task).poll(&mut cx) {
+            Poll::Ready(Err(error)) if error.is_panic() => {
+                MetricsError::SamplerPanicked(panic_message(error.into_panic()))
+            }
+            _ => MetricsError::RuntimeShutdown,
+        };
+        self.task = None;
+        self.error = Some(error.clone());
+        Err(error)
+    }
+}
+
+impl Drop for SamplerGuard {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Errors returned by [`try_spawn_sampler`] and [`SamplerGuard::check`].
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum MetricsError {
+    /// The sampling interval passed to [`try_spawn_sampler`] was zero.
+    #[error("the metrics sampling interval must be non-zero")]
+    ZeroInterval,
+
+    /// The runtime which the sampler was spawned on has shut down. This is
+    /// returned by [`try_spawn_sampler`] if the runtime had already shut down
+    /// when it was called, and by [`SamplerGuard::check`] if it shut down
+    /// while the sampler was running.
+    #[error("the runtime running the metrics sampler has shut down")]
+    RuntimeShutdown,
+
+    /// The sampler task panicked, with the given message. The most likely
+    /// cause is that the runtime was built without the time driver enabled.
+    #[error("the metrics sampler panicked: {0}")]
+    SamplerPanicked(String),
+}
+
+/// Returns the message of a panic, if it has one.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or_else(
+            || "<non-string panic payload>".to_string(),
+            |s| s.to_string(),
+        ),
+    }
+}
+
+#[cfg(feature = "probes-metrics")]
+struct Sampler {
+    metrics: RuntimeMetrics,
+    /// Each worker's steal count as of the previous sample.
+    steal_counts: Vec<u64>,
+    /// The number of alive tasks as of the previous sample.
+    alive_tasks: usize,
+    /// The number of worker threads as of the previous sample.
+    workers: usize,
+    /// The number of blocking threads as of the previous sample.
+    blocking_threads: usize,
+    /// The number of idle blocking threads as of the previous sample.
+    idle_blocking_threads: usize,
+    /// The total number of polls by all workers as of the previous sample.
+    polls: u64,
+    /// When the previous sample was taken.
+    sampled_at: Instant,
+    #[cfg(feature = "probes-task")]
+    rates: crate::rates::Rates,
+    #[cfg(feature = "probes-task")]
+    locations: crate::locations::Cumulative,
+    /// Each worker's thread, and that thread's total poll time, as of the
+    /// previous sample.
+    #[cfg(feature = "probes-task")]
+    poll_nanos: Vec<Option<(std::thread::ThreadId, u64)>>,
+}
+
+#[cfg(feature = "probes-metrics")]
+impl Sampler {
+    fn new(metrics: RuntimeMetrics) -> Self {
+        let steal_counts = (0..metrics.num_workers())
+            .map(|worker| metrics.worker_steal_count(worker))
+            .collect();
+        let alive_tasks = metrics.num_alive_tasks();
+        let workers = metrics.num_workers();
+        let blocking_threads = metrics.num_blocking_threads();
+        let idle_blocking_threads = metrics.num_idle_blocking_threads();
+        let polls = total_polls(&metrics);
+        Self {
+            metrics,
+            steal_counts,
+            alive_tasks,
+            workers,
+            blocking_threads,
+            idle_blocking_threads,
+            polls,
+            sampled_at: Instant::now(),
+            #[cfg(feature = "probes-task")]
+            rates: crate::rates::Rates::new(),
+            #[cfg(feature = "probes-task")]
+            locations: crate::locations::Cumulative::new(),
+            #[cfg(feature = "probes-task")]
+            poll_nanos: Vec::new(),
+        }
+    }
+
+    fn sample(&mut self) {
+        crate::consumer::poll();
+        let now = Instant::now();
+        let interval_nanos = crate::config::nanos(now.saturating_duration_since(self.sampled_at));
+        self.sampled_at = now;
+        self.sample_pools();
+        self.sample_steals(interval_nanos);
+        #[cfg(feature = "probes-task")]
+        self.sample_utilization(interval_nanos);
+        self.sample_alive_tasks(interval_nanos);
+        self.sample_snapshot(interval_nanos);
+        #[cfg(feature = "probes-task")]
+        self.rates.report();
+        #[cfg(feature = "probes-task")]
+        self.locations.report();
+        crate::rate_limit::report();
+        #[cfg(feature = "debug-overhead")]
+        crate::overhead::report();
+    }
+
+    fn sample_alive_tasks(&mut self, interval_nanos: u64) {
+        let alive = self.metrics.num_alive_tasks();
+        let delta = alive as i64 - self.alive_tasks as i64;
+        self.alive_tasks = alive;
+        crate::probes::runtime__alive__delta!(|| (alive as u64, delta, interval_nanos));
+    }
+
+    fn sample_snapshot(&mut self, interval_nanos: u64) {
+        let polls = total_polls(&self.metrics);
+        let polled = polls.saturating_sub(std::mem::replace(&mut self.polls, polls));
+        crate::probes::runtime__metrics!(|| {
+            let local_queue_depth: usize = (0..self.workers)
+                .map(|worker| self.metrics.worker_local_queue_depth(worker))
+                .sum();
+            (
+                self.workers as u64,
+                self.alive_tasks as u64,
+                self.metrics.global_queue_depth() as u64,
+                local_queue_depth as u64,
+                polled,
+                interval_nanos,
+            )
+        });
+    }
+
+    fn sample_pools(&mut self) {
+        /// The `kind` argument of `runtime-workers-changed` for each pool.
+        const WORKERS: u8 = 0;
+        const BLOCKING: u8 = 1;
+        const IDLE_BLOCKING: u8 = 2;
+
+        let workers = self.metrics.num_workers();
+        if workers != self.workers {
+            let old = std::mem::replace(&mut self.workers, workers);
+            crate::probes::runtime__workers__changed!(|| (old as u64, workers as u64, WORKERS));
+            // Keep tracking steals for the workers which remain.
+            self.steal_counts.truncate(workers);
+            self.steal_counts.extend(
+                (self.steal_counts.len()..workers)
+                    .map(|worker| self.metrics.worker_steal_count(worker)),
+            );
+        }
+        let blocking = self.metrics.num_blocking_threads();
+        if blocking != self.blocking_threads {
+            let old = std::mem::replace(&mut self.blocking_threads, blocking);
+            crate::probes::runtime__workers__changed!(|| (old as u64, blocking as u64, BLOCKING));
+        }
+        let idle = self.metrics.num_idle_blocking_threads();
+        if idle != self.idle_blocking_threads {
+            let old = std::mem::replace(&mut self.idle_blocking_threads, idle);
+            crate::probes::runtime__workers__changed!(|| (old as u64, idle as u64, IDLE_BLOCKING));
+        }
+    }
+
+    #[cfg(feature = "probes-task")]
+    fn sample_utilization(&mut self, interval_nanos: u64) {
+        self.poll_nanos.resize(self.workers, None);
+        for (worker, prev) in self.poll_nanos.iter_mut().enumerate() {
+            let current = self.metrics.worker_thread_id(worker).and_then(|thread| {
+                crate::utilization::poll_nanos(thread).map(|poll_nanos| (thread, poll_nanos))
+            });
+            // The first interval in which a worker is seen polling on a
+            // thread has no starting point, so it is not reported.
+            if let (Some((prev_thread, start)), Some((thread, end))) = (*prev, current)
+                && prev_thread == thread
+            {
+                crate::probes::worker__poll__utilization!(|| {
+                    let permille = crate::utilization::permille(start, end, interval_nanos);
+                    (worker as u64, permille, interval_nanos)
+                });
+            }
+            *prev = current;
+        }
+    }
+
+    fn sample_steals(&mut self, interval_nanos: u64) {
+        for (worker, prev) in self.steal_counts.iter_mut().enumerate() {
+            let count = self.metrics.worker_steal_count(worker);
+            let stolen = count.saturating_sub(*prev);
+            *prev = count;
+            if stolen > 0 && crate::config::tier_enabled(crate::Tier::Verbose) {
+                crate::probes::worker__steal__event!(|| {
+                    (worker as u64, NO_WORKER, stolen, interval_nanos)
+                });
+            }
+        }
+    }
+}
+
+/// Returns the total number of polls by all of the runtime's workers.
+#[cfg(feature = "probes-metrics")]
+fn total_polls(metrics: &RuntimeMetrics) -> u64 {
+    (0..metrics.num_workers())
+        .map(|worker| metrics.worker_poll_count(worker))
+        .sum()
+}
+
+#[cfg(all(test, feature = "probes-metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampler_stops_when_guard_dropped() {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_time()
+            .build()
+            .unwrap();
+        let guard = spawn_sampler(rt.handle(), Duration::from_millis(1));
+        let task = guard.task.as_ref().unwrap().abort_handle();
+
+        rt.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+        assert!(!task.is_finished());
+
+        drop(guard);
+        rt.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+        assert!(task.is_finished());
+    }
+
+    #[test]
+    fn sampler_tracks_blocking_pool() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut sampler = Sampler::new(rt.metrics());
+        assert_eq!(sampler.blocking_threads, 0);
+
+        rt.block_on(rt.spawn_blocking(|| ())).unwrap();
+        sampler.sample_pools();
+        assert_eq!(sampler.blocking_threads, 1);
+        assert_eq!(sampler.workers, 1);
+    }
+
+    #[test]
+    fn sampler_tracks_polls() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut sampler = Sampler::new(rt.metrics());
+        assert_eq!(sampler.polls, 0);
+
+        rt.block_on(async {
+            for _ in 0..3 {
+                tokio::spawn(async {}).await.unwrap();
+            }
+        });
+        sampler.sample_snapshot(1);
+        assert!(sampler.polls > 0);
+    }
+
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    #[test]
+    fn sampler_tracks_worker_poll_time() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut sampler = Sampler::new(rt.metrics());
+        let busy = || tokio::spawn(async { std::thread::sleep(Duration::from_millis(5)) });
+
+        rt.block_on(async {
+            busy().await.unwrap();
+            sampler.sample_utilization(1);
+            let (thread, start) = sampler.poll_nanos[0].unwrap();
+            assert_eq!(thread, std::thread::current().id());
+
+            busy().await.unwrap();
+            sampler.sample_utilization(1);
+            let (_, end) = sampler.poll_nanos[0].unwrap();
+            assert!(end - start >= 5_000_000, "{start} -> {end}");
+        });
+    }
+
+    #[test]
+    fn sampler_errors() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let handle = rt.handle().clone();
+        assert!(matches!(
+            try_spawn_sampler(&handle, Duration::ZERO),
+            Err(MetricsError::ZeroInterval)
+        ));
+
+        // The runtime was built without the time driver.
+        let mut guard = try_spawn_sampler(&handle, Duration::from_millis(1)).unwrap();
+        rt.block_on(tokio::task::yield_now());
+        assert!(matches!(
+            guard.check(),
+            Err(MetricsError::SamplerPanicked(_))
+        ));
+        assert!(matches!(
+            guard.check(),
+            Err(MetricsError::SamplerPanicked(_))
+        ));
+
+        drop(rt);
+        assert!(matches!(
+            try_spawn_sampler(&handle, Duration::from_millis(1)),
+            Err(MetricsError::RuntimeShutdown)
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Reporting of task and worker activity using the [`metrics`] crate.
+//!
+//! Registering a metric with its labels costs a lookup in the recorder and an
+//! allocation for the labels, so each spawn location's metrics are registered
+//! once, the first time a task is spawned there, and interned for the rest of
+//! the process, as the [location statistics](crate::locations) are. Each task
+//! refers to its location's metrics, so recording a spawn or a poll costs no
+//! lookup. As a result, metrics are only reported to the recorder which was
+//! installed when each location was first seen.
+use ::metrics::{Counter, Histogram, Label};
+use std::collections::HashMap;
+use std::panic::Location;
+use std::sync::{LazyLock, RwLock};
+
+/// The metrics for a single spawn location.
+#[derive(Debug)]
+pub(crate) struct LocationMetrics {
+    spawned: Counter,
+    poll_duration: Histogram,
+}
+
+/// Interned locations' metrics, keyed by the locations' addresses.
+static REGISTRY: LazyLock<RwLock<HashMap<usize, &'static LocationMetrics>>> =
+    LazyLock::new(Default::default);
+
+/// Returns the metrics for `location`, registering them if it has not been
+/// seen before.
+fn intern(location: &'static Location<'static>) -> &'static LocationMetrics {
+    let key = std::ptr::from_ref(location) as usize;
+    let interned = REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&key)
+        .copied();
+    interned.unwrap_or_else(|| {
+        let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+        registry.entry(key).or_insert_with(|| {
+            let labels = [
+                Label::new(
+                    "file",
+                    crate::config::probe_path(location.file()).into_owned(),
+                ),
+                Label::new("line", location.line().to_string()),
+            ];
+            // As with the location statistics, interned metrics are never
+            // freed, but there are only as many as there are places in the
+            // program which spawn tasks.
+            Box::leak(Box::new(LocationMetrics {
+                spawned: ::metrics::counter!("tokio.task.spawned", labels.to_vec()),
+                poll_duration: ::metrics::histogram!("tokio.task.poll_duration", labels.to_vec()),
+            }))
+        })
+    })
+}
+
+/// Records that a task was spawned at `location`, and returns the metrics its
+/// polls should be recorded in.
+pub(crate) fn spawned(location: &'static Location<'static>) -> &'static LocationMetrics {
+    let metrics = intern(location);
+    metrics.spawned.increment(1);
+    metrics
+}
+
+/// Records a poll which took `poll_nanos` nanoseconds of a task whose metrics
+/// were returned by [`spawned`].
+pub(crate) fn polled(metrics: &LocationMetrics, poll_nanos: u64) {
+    metrics.poll_duration.record(poll_nanos as f64 / 1e9);
+}
+
+/// Records that a worker thread parked.
+pub(crate) fn parked() {
+    ::metrics::counter!("tokio.worker.parks").increment(1);
+}
@@ -0,0 +1,182 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Reporting of task lifetimes as OpenTelemetry spans, and of task activity
+//! as OpenTelemetry metrics.
+//!
+//! When the `otel` feature is enabled, the task hooks start a span, using the
+//! tracer provider installed with [`opentelemetry::global`], when each task is
+//! first polled. The span's start time is backdated to when the task was
+//! spawned, and it is ended when the task terminates. The span is held in the
+//! task table alongside the rest of the task's state, behind a lock of its
+//! own, so that recording a poll in it does not hold up other tasks' hooks.
+//!
+//! The hooks also record measurements using instruments created from the
+//! global meter provider. The instruments are created the first time a
+//! measurement is recorded, and keep using the meter provider which was
+//! installed then.
+use opentelemetry::global::{self, BoxedSpan};
+use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
+use opentelemetry::trace::{Span, Tracer};
+use opentelemetry::{Context, KeyValue};
+use std::panic::Location;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime};
+
+/// The name of the tracer used to create task spans, and of the meter used
+/// to create the metric instruments.
+const SCOPE_NAME: &str = "tokio-dtrace";
+
+/// The name of each task span.
+const SPAN_NAME: &str = "tokio.task";
+
+/// A task's span, which is started when the task is first polled.
+///
+/// This is kept in the task's state, and shared with the hooks, so that they
+/// can record the task's polls in its span without holding the lock on the
+/// task table.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TaskSpan(Arc<Mutex<Option<BoxedSpan>>>);
+
+impl TaskSpan {
+    fn lock(&self) -> MutexGuard<'_, Option<BoxedSpan>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records that a task's poll has started, first starting its span if
+    /// this is its first poll.
+    pub(crate) fn poll_start(
+        &self,
+        id: u64,
+        location: &'static Location<'static>,
+        spawned_at: Instant,
+    ) {
+        let mut span = self.lock();
+        let span = span.get_or_insert_with(|| start(id, location, spawned_at));
+        if span.is_recording() {
+            span.add_event("poll-start", Vec::new());
+        }
+    }
+
+    /// Records that a task's poll has ended, after `poll_nanos` nanoseconds.
+    pub(crate) fn poll_end(&self, poll_nanos: u64) {
+        if let Some(span) = &mut *self.lock() {
+            poll_end(span, poll_nanos);
+        }
+    }
+
+    /// Ends the span of a task which has terminated, first recording the end
+    /// of the poll it terminated during, if any, which took `poll_nanos`
+    /// nanoseconds.
+    pub(crate) fn end(&self, poll_nanos: Option<u64>) {
+        if let Some(span) = &mut *self.lock() {
+            if let Some(poll_nanos) = poll_nanos {
+                poll_end(span, poll_nanos);
+            }
+            span.end();
+        }
+    }
+}
+
+/// Starts the span for a task which is about to be polled for the first time.
+///
+/// Task spans are always root spans: the OpenTelemetry context which is
+/// current on a worker thread when a task is first polled is unrelated to
+/// that task.
+fn start(id: u64, location: &'static Location<'static>, spawned_at: Instant) -> BoxedSpan {
+    let tracer = global::tracer(SCOPE_NAME);
+    let now = SystemTime::now();
+    let start_time = now.checked_sub(spawned_at.elapsed()).unwrap_or(now);
+    tracer
+        .span_builder(SPAN_NAME)
+        .with_start_time(start_time)
+        .with_attributes([
+            KeyValue::new("tokio.task.id", id as i64),
+            KeyValue::new("code.file.path", location.file()),
+            KeyValue::new("code.line.number", i64::from(location.line())),
+            KeyValue::new("code.column.number", i64::from(location.column())),
+        ])
+        .start_with_context(&tracer, &Context::new())
+}
+
+/// Records that a task's poll has ended, after `poll_nanos` nanoseconds.
+fn poll_end(span: &mut BoxedSpan, poll_nanos: u64) {
+    if span.is_recording() {
+        let nanos = i64::try_from(poll_nanos).unwrap_or(i64::MAX);
+        span.add_event("poll-end", vec![KeyValue::new("tokio.poll.nanos", nanos)]);
+    }
+}
+
+/// The metric instruments updated by the hooks.
+struct Instruments {
+    /// The duration of each task poll, in seconds.
+    poll_duration: Histogram<f64>,
+    /// The number of tasks which have been spawned and have not terminated.
+    active_tasks: UpDownCounter<i64>,
+    /// The number of tasks spawned.
+    spawned_tasks: Counter<u64>,
+}
+
+static INSTRUMENTS: LazyLock<Instruments> = LazyLock::new(|| {
+    let meter = global::meter(SCOPE_NAME);
+    Instruments {
+        poll_duration: meter
+            .f64_histogram("tokio.task.poll.duration")
+            .with_description("The duration of each poll of a Tokio task.")
+            .with_unit("s")
+            .build(),
+        active_tasks: meter
+            .i64_up_down_counter("tokio.task.active")
+            .with_description("The number of Tokio tasks which have not terminated.")
+            .with_unit("{task}")
+            .build(),
+        spawned_tasks: meter
+            .u64_counter("tokio.task.spawned")
+            .with_description("The number of Tokio tasks spawned.")
+            .with_unit("{task}")
+            .build(),
+    }
+});
+
+/// Records that a task has been spawned.
+pub(crate) fn spawned() {
+    INSTRUMENTS.spawned_tasks.add(1, &[]);
+}
+
+/// Records that a task has become active: that is, it has been spawned, and
+/// its state is being tracked, so that [`terminated`] will be called for it
+/// when its [`Active`] is dropped.
+fn active() {
+    INSTRUMENTS.active_tasks.add(1, &[]);
+}
+
+/// Records that a task which was recorded by [`active`] has terminated.
+fn terminated() {
+    INSTRUMENTS.active_tasks.add(-1, &[]);
+}
+
+/// Counts a task as [`active`] until it is dropped, along with the task's
+/// state.
+#[derive(Debug, Default)]
+pub(crate) struct Active(bool);
+
+impl Active {
+    pub(crate) fn new() -> Self {
+        active();
+        Self(true)
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        if self.0 {
+            terminated();
+        }
+    }
+}
+
+/// Records a poll which took `poll_nanos` nanoseconds.
+pub(crate) fn polled(poll_nanos: u64) {
+    INSTRUMENTS
+        .poll_duration
+        .record(poll_nanos as f64 / 1e9, &[]);
+}
@@ -0,0 +1,183 @@
+// Copyright 2025 Oxide Computer Company
+
+//! How tasks terminated, as reported in the `task-terminate` probe's flags.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Why a task terminated, encoded in bits 3 and 4 of the `task-terminate`
+/// probe's flags.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Reason {
+    /// The hooks alone could not tell how the task terminated: it completed,
+    /// panicked, or was aborted while being polled, and was not wrapped using
+    /// [`trace_outcome`].
+    Unknown = 0,
+    /// The task's future returned [`Poll::Ready`].
+    Completed = 1,
+    /// The task was aborted, or its runtime shut down, before it completed.
+    Cancelled = 2,
+    /// A panic unwound out of the task's poll.
+    Panicked = 3,
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl Reason {
+    /// Returns the bits of the `task-terminate` probe's flags encoding this
+    /// reason.
+    pub(crate) const fn flags(self) -> u8 {
+        (self as u8) << 3
+    }
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+thread_local! {
+    /// The reason the task with the given ID is about to terminate on this
+    /// thread, taken by the `on_task_terminate` hook.
+    ///
+    /// Tokio drops a task's future, or stores its output, on the same thread
+    /// and immediately before calling the terminate hook, so the reason only
+    /// needs to outlive that gap.
+    static PENDING: std::cell::Cell<Option<(u64, Reason)>> = const { std::cell::Cell::new(None) };
+}
+
+/// Records that the task with the given ID is about to terminate for
+/// `reason`.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn record(id: u64, reason: Reason) {
+    PENDING.set(Some((id, reason)));
+}
+
+/// Takes the reason recorded for the task with the given ID, if there is one.
+/// A reason recorded for any other task is discarded.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn take(id: u64) -> Option<Reason> {
+    PENDING
+        .take()
+        .and_then(|(pending, reason)| (pending == id).then_some(reason))
+}
+
+/// Wraps `future` so that the `tokio*:::task-terminate` probe reports exactly
+/// how the task running it terminated.
+///
+/// Tokio's terminate hook does not say whether a task completed, was
+/// cancelled, or panicked, so `tokio-dtrace` infers what it can: a task which
+/// terminates outside of a poll was cancelled (such as by its runtime shutting
+/// down). A task which terminates during a poll may have completed, panicked,
+/// or been aborted while it was being polled, which the hooks cannot tell
+/// apart, so its reason is reported as unknown. In particular, the
+/// [panic hook](crate::Config::panic_hook) does not mark a task as panicked,
+/// since the task may catch the panic, using
+/// [`catch_unwind`](std::panic::catch_unwind), and then complete.
+///
+/// The returned future records whether it completed, was dropped while a
+/// panic unwound out of its poll, or was dropped otherwise, so a task running
+/// it always reports one of the other reasons.
+///
+/// The reason is encoded in bits 3 and 4 of the probe's `arg5`, and may be
+/// extracted with `(arg5 >> 3) & 3`: `0` if it is unknown, `1` if the task
+/// completed, `2` if it was cancelled, and `3` if it panicked. Tasks spawned
+/// using [`spawn_named`](crate::spawn_named) are wrapped automatically.
+///
+/// Like [`trace_wakes`](crate::trace_wakes), the future must be wrapped where
+/// it is spawned, so that it is dropped with its task:
+///
+/// ```rust
+/// # async fn handle_connection() {}
+/// # async fn example() {
+/// tokio::spawn(tokio_dtrace::trace_outcome(handle_connection()));
+/// # }
+/// ```
+///
+/// The reason is only recorded when the `probes-task` feature is enabled;
+/// otherwise, the returned future behaves exactly like `future`.
+pub fn trace_outcome<F: Future>(future: F) -> TraceOutcome<F> {
+    TraceOutcome {
+        future,
+        completed: false,
+    }
+}
+
+/// A future which records how its task terminated, for the
+/// `tokio*:::task-terminate` probe.
+///
+/// Returned by [`trace_outcome`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct TraceOutcome<F> {
+    future: F,
+    /// Whether `future` has returned [`Poll::Ready`].
+    completed: bool,
+}
+
+impl<F: Future> Future for TraceOutcome<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: `future` is structurally pinned: it is never moved out of
+        // `self`, including by `drop`, and `TraceOutcome` is only `Unpin` if
+        // `F` is. `completed` is not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let output = future.poll(cx);
+        if output.is_ready() {
+            this.completed = true;
+            #[cfg(all(tokio_unstable, feature = "probes-task"))]
+            record_current(Reason::Completed);
+        }
+        output
+    }
+}
+
+impl<F> Drop for TraceOutcome<F> {
+    fn drop(&mut self) {
+        if !self.completed {
+            // Tokio drops the future of a task which panics while unwinding
+            // out of its poll.
+            #[cfg(all(tokio_unstable, feature = "probes-task"))]
+            record_current(if std::thread::panicking() {
+                Reason::Panicked
+            } else {
+                Reason::Cancelled
+            });
+        }
+    }
+}
+
+/// Records `reason` for the task whose future is being polled or dropped on
+/// this thread, if there is one.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+fn record_current(reason: Reason) {
+    // Tokio sets the current task ID while a task's future is polled and
+    // while it is dropped, including when its runtime shuts down.
+    if let Some(id) = tokio::task::try_id() {
+        record(crate::hooks::id_to_u64(id), reason);
+    }
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    use super::Reason;
+
+    #[test]
+    fn reasons_are_recorded() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let id = |handle: &tokio::task::JoinHandle<()>| crate::hooks::id_to_u64(handle.id());
+
+        // No hooks are registered, so nothing takes the reasons.
+        let completed = rt.spawn(super::trace_outcome(async {}));
+        let completed_id = id(&completed);
+        rt.block_on(completed).unwrap();
+        assert_eq!(super::take(completed_id), Some(Reason::Completed));
+
+        let cancelled = rt.spawn(super::trace_outcome(std::future::pending::<()>()));
+        let cancelled_id = id(&cancelled);
+        drop(rt);
+        assert_eq!(super::take(cancelled_id), Some(Reason::Cancelled));
+        assert_eq!(super::take(cancelled_id), None);
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Measurement of the time spent executing `tokio-dtrace`'s own hooks.
+//!
+//! When the `debug-overhead` feature is enabled, each runtime hook measures
+//! how long it takes to run, and accumulates the total time and number of
+//! calls into per-hook counters. The [metrics sampler](crate::metrics) reports
+//! these counters using the `hook-overhead` probe.
+//!
+//! Counters are sharded by worker index, so that workers rarely contend with
+//! each other when recording. Measurements are approximate: they include
+//! most, but not all, of the cost of the measurement itself, and do not
+//! include the cost of Tokio calling the hook.
+use crate::worker;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// The number of independently-updated sets of counters.
+const SHARDS: usize = 8;
+
+/// Identifies a runtime hook. The discriminant is passed as `arg0` of the
+/// `hook-overhead` probe.
+///
+/// The hooks of a disabled probe family never record their overhead.
+#[derive(Copy, Clone, Debug)]
+#[repr(u8)]
+#[cfg_attr(
+    not(all(feature = "probes-task", feature = "probes-worker")),
+    allow(dead_code)
+)]
+pub(crate) enum Hook {
+    TaskSpawn = 0,
+    BeforeTaskPoll = 1,
+    AfterTaskPoll = 2,
+    TaskTerminate = 3,
+    ThreadStart = 4,
+    ThreadStop = 5,
+    ThreadPark = 6,
+    ThreadUnpark = 7,
+}
+
+const HOOKS: usize = 8;
+
+#[repr(align(64))]
+struct Counters {
+    nanos: [AtomicU64; HOOKS],
+    calls: [AtomicU64; HOOKS],
+}
+
+static COUNTERS: [Counters; SHARDS] = [const {
+    Counters {
+        nanos: [const { AtomicU64::new(0) }; HOOKS],
+        calls: [const { AtomicU64::new(0) }; HOOKS],
+    }
+}; SHARDS];
+
+/// Measures the time until it is dropped, and records it against a hook.
+#[must_use]
+pub(crate) struct Timer {
+    hook: Hook,
+    start: Instant,
+}
+
+impl Timer {
+    #[inline]
+    pub(crate) fn start(hook: Hook) -> Self {
+        Self {
+            hook,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    #[inline]
+    fn drop(&mut self) {
+        let nanos = crate::config::nanos(self.start.elapsed());
+        let shard = &COUNTERS[worker::current() as usize % SHARDS];
+        let hook = self.hook as usize;
+        shard.nanos[hook].fetch_add(nanos, Ordering::Relaxed);
+        shard.calls[hook].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Fires the `hook-overhead` probe for each hook which has been called, with
+/// the total time spent in that hook and the number of calls to it since the
+/// process started.
+pub(crate) fn report() {
+    if !crate::config::tier_enabled(crate::Tier::Verbose) {
+        return;
+    }
+    for hook in 0..HOOKS {
+        let (nanos, calls) = totals(hook);
+        if calls > 0 {
+            crate::probes::hook__overhead!(|| (hook as u8, nanos, calls));
+        }
+    }
+}
+
+fn totals(hook: usize) -> (u64, u64) {
+    COUNTERS.iter().fold((0, 0), |(nanos, calls), shard| {
+        (
+            nanos + shard.nanos[hook].load(Ordering::Relaxed),
+            calls + shard.calls[hook].load(Ordering::Relaxed),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_records_calls() {
+        let (_, before) = totals(Hook::ThreadUnpark as usize);
+        drop(Timer::start(Hook::ThreadUnpark));
+        drop(Timer::start(Hook::ThreadUnpark));
+        let (_, after) = totals(Hook::ThreadUnpark as usize);
+        assert!(after >= before + 2);
+    }
+}
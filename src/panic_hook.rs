@@ -0,0 +1,45 @@
+// Copyright 2025 Oxide Computer Company
+
+//! A panic hook which fires the `task-panic` probe.
+//!
+//! When a task panics, the panic unwinds out of its poll, and Tokio catches
+//! it. The panic hook runs at the moment of the panic, before unwinding
+//! begins, while the thread-local state recorded by `on_before_task_poll` still
+//! identifies the task being polled.
+use crate::config::{self, Tier};
+use crate::{current_task, probes};
+use std::sync::Once;
+use std::sync::atomic::Ordering;
+
+static INSTALL: Once = Once::new();
+
+/// Installs the panic hook, if it has not already been installed.
+///
+/// The previously installed panic hook is called after the probe fires.
+pub(crate) fn install() {
+    INSTALL.call_once(|| {
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // The hook is never removed, so it must check whether the hooks
+            // are still enabled, and whether the probe's tier is.
+            if crate::ENABLED.load(Ordering::Relaxed)
+                && config::tier_enabled(Tier::Minimal)
+                && let Some((id, location)) = current_task::id_and_location()
+            {
+                // The task may yet catch the panic and complete, so this
+                // does not record that it panicked: only the terminate path
+                // can tell, for tasks wrapped using `trace_outcome`.
+                fire!(probes::task__panic, || {
+                    (
+                        current_task::runtime(),
+                        config::probe_id(id),
+                        config::probe_path(location.file()),
+                        location.line(),
+                        location.column(),
+                    )
+                });
+            }
+            prev(info);
+        }));
+    });
+}
@@ -0,0 +1,289 @@
+// Copyright 2025 Oxide Computer Company
+
+//! In-process poll latency and task lifetime percentiles.
+//!
+//! When the `poll-percentiles` feature is enabled, the duration of every task
+//! poll, and the lifetime of every task, is recorded in a histogram, which can
+//! be queried using [`poll_latency_percentiles`] and
+//! [`task_lifetime_percentiles`]. Unlike the DTrace probes, this recording
+//! happens regardless of whether a DTrace consumer is attached, adding a small
+//! cost to every poll.
+//!
+//! Each histogram is an array of atomic counters, one per range of durations,
+//! so that recording a duration is a single atomic increment which never
+//! blocks, however many workers record at once. As in an [HDR histogram], the
+//! ranges grow with the durations they contain, so that every range is
+//! narrower than [`POLL_HISTOGRAM_SIGFIG`] significant digits of the durations
+//! within it, and the histograms have a fixed size of a few tens of kilobytes.
+//!
+//! [HDR histogram]: https://hdrhistogram.github.io/HdrHistogram/
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The largest poll duration, in nanoseconds, that the poll latency histogram
+/// can distinguish (60 seconds).
+///
+/// Polls longer than this are recorded as taking exactly this long.
+pub const POLL_HISTOGRAM_MAX_NANOS: u64 = 60_000_000_000;
+
+/// The longest task lifetime, in nanoseconds, that the task lifetime
+/// histogram can distinguish (one hour).
+///
+/// Tasks which live longer than this are recorded as living exactly this
+/// long.
+pub const LIFETIME_HISTOGRAM_MAX_NANOS: u64 = 3_600_000_000_000;
+
+/// The number of significant decimal digits to which poll durations and task
+/// lifetimes are recorded in their histograms.
+///
+/// Two significant digits means that any recorded duration is accurate to
+/// within 1% of its true value. The smallest distinguishable duration is one
+/// nanosecond.
+pub const POLL_HISTOGRAM_SIGFIG: u8 = 2;
+
+/// The number of bits of each duration which select its bucket.
+///
+/// Durations shorter than `1 << SUB_BUCKET_BITS` nanoseconds each have a
+/// bucket of their own. Each longer power of two is divided into
+/// [`HALF_SUB_BUCKETS`] equal buckets, each less than 1% as wide as the
+/// durations it contains, meeting [`POLL_HISTOGRAM_SIGFIG`].
+const SUB_BUCKET_BITS: u32 = 8;
+
+/// The number of buckets each power of two longer than `1 << SUB_BUCKET_BITS`
+/// nanoseconds is divided into.
+const HALF_SUB_BUCKETS: usize = 1 << (SUB_BUCKET_BITS - 1);
+
+static POLLS: Histogram<{ bucket(POLL_HISTOGRAM_MAX_NANOS) + 1 }> =
+    Histogram::new(POLL_HISTOGRAM_MAX_NANOS);
+
+static LIFETIMES: Histogram<{ bucket(LIFETIME_HISTOGRAM_MAX_NANOS) + 1 }> =
+    Histogram::new(LIFETIME_HISTOGRAM_MAX_NANOS);
+
+/// A histogram of durations, counting those recorded in each of `N` buckets.
+struct Histogram<const N: usize> {
+    max_nanos: u64,
+    buckets: [AtomicU64; N],
+    /// The longest duration recorded.
+    max: AtomicU64,
+}
+
+/// Percentiles of the durations recorded so far, such as those of all task
+/// polls.
+///
+/// Returned by [`poll_latency_percentiles`] and [`task_lifetime_percentiles`].
+/// The [`Display`](fmt::Display) implementation formats the percentiles on a
+/// single line, for logging.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Percentiles {
+    /// The number of durations recorded.
+    pub count: u64,
+    /// The median duration.
+    pub p50: Duration,
+    /// The 99th percentile duration.
+    pub p99: Duration,
+    /// The 99.9th percentile duration.
+    pub p999: Duration,
+    /// The longest recorded duration.
+    pub max: Duration,
+}
+
+/// Returns percentiles of the durations of all task polls recorded since the
+/// process started, across all runtimes with `tokio-dtrace`'s hooks
+/// registered.
+///
+/// Durations are accurate to within the precision described by
+/// [`POLL_HISTOGRAM_SIGFIG`], and are capped at [`POLL_HISTOGRAM_MAX_NANOS`].
+/// If no polls have been recorded, all fields of the returned [`Percentiles`]
+/// are zero.
+pub fn poll_latency_percentiles() -> Percentiles {
+    POLLS.percentiles()
+}
+
+/// Returns percentiles of the lifetimes of all tasks which have terminated
+/// since the process started, across all runtimes with `tokio-dtrace`'s hooks
+/// registered.
+///
+/// A task's lifetime is the time from when it was spawned to when it
+/// terminated. Tasks which were spawned before the hooks were registered, and
+/// blocking tasks, are not recorded. Lifetimes are accurate to within the
+/// precision described by [`POLL_HISTOGRAM_SIGFIG`], and are capped at
+/// [`LIFETIME_HISTOGRAM_MAX_NANOS`]. If no tasks have terminated, all fields of
+/// the returned [`Percentiles`] are zero.
+///
+/// Together with [`poll_latency_percentiles`], and the spawn and terminate
+/// counts returned by [`take_counters`](crate::take_counters), this allows a
+/// program to report how its tasks behave where DTrace cannot be attached:
+///
+/// ```rust
+/// let counters = tokio_dtrace::take_counters();
+/// println!("spawned {} tasks", counters.spawns);
+/// println!("poll latency: {}", tokio_dtrace::poll_latency_percentiles());
+/// println!("task lifetime: {}", tokio_dtrace::task_lifetime_percentiles());
+/// ```
+pub fn task_lifetime_percentiles() -> Percentiles {
+    LIFETIMES.percentiles()
+}
+
+/// Records a poll of the given duration.
+#[cfg_attr(not(tokio_unstable), allow(dead_code))]
+pub(crate) fn record(duration: Duration) {
+    POLLS.record(duration);
+}
+
+/// Records the lifetime of a task which terminated.
+#[cfg_attr(not(tokio_unstable), allow(dead_code))]
+pub(crate) fn record_lifetime(lifetime: Duration) {
+    LIFETIMES.record(lifetime);
+}
+
+/// Returns the index of the bucket in which a duration of `nanos` nanoseconds
+/// is counted.
+const fn bucket(nanos: u64) -> usize {
+    let bits = u64::BITS - nanos.leading_zeros();
+    if bits <= SUB_BUCKET_BITS {
+        return nanos as usize;
+    }
+    // The duration's top `SUB_BUCKET_BITS` bits, whose highest bit is always
+    // set, select one of `HALF_SUB_BUCKETS` buckets for its power of two.
+    let shift = bits - SUB_BUCKET_BITS;
+    shift as usize * HALF_SUB_BUCKETS + (nanos >> shift) as usize
+}
+
+/// Returns the longest duration, in nanoseconds, counted in the bucket with
+/// the given index.
+const fn highest_in(bucket: usize) -> u64 {
+    if bucket < 2 * HALF_SUB_BUCKETS {
+        return bucket as u64;
+    }
+    let shift = (bucket / HALF_SUB_BUCKETS - 1) as u32;
+    let top = (bucket % HALF_SUB_BUCKETS + HALF_SUB_BUCKETS) as u64;
+    ((top + 1) << shift) - 1
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new(max_nanos: u64) -> Self {
+        Self {
+            max_nanos,
+            buckets: [const { AtomicU64::new(0) }; N],
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos())
+            .unwrap_or(u64::MAX)
+            .min(self.max_nanos);
+        self.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn percentiles(&self) -> Percentiles {
+        // Durations may be recorded while the buckets are read, so the counts
+        // are copied first, and the percentiles are those of the copy.
+        let counts: [u64; N] =
+            std::array::from_fn(|bucket| self.buckets[bucket].load(Ordering::Relaxed));
+        let count = counts.iter().sum();
+        if count == 0 {
+            return Percentiles::default();
+        }
+        let max = self.max.load(Ordering::Relaxed);
+        let at_quantile = |quantile: f64| {
+            // The rank of the duration at the quantile, counting from 1.
+            let rank = ((quantile * count as f64).ceil() as u64).clamp(1, count);
+            let mut seen = 0;
+            let bucket = counts
+                .iter()
+                .position(|&n| {
+                    seen += n;
+                    seen >= rank
+                })
+                .unwrap_or(N - 1);
+            Duration::from_nanos(highest_in(bucket).min(max))
+        };
+        Percentiles {
+            count,
+            p50: at_quantile(0.5),
+            p99: at_quantile(0.99),
+            p999: at_quantile(0.999),
+            max: Duration::from_nanos(max),
+        }
+    }
+}
+
+impl fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            count,
+            p50,
+            p99,
+            p999,
+            max,
+        } = self;
+        write!(
+            f,
+            "count={count} p50={p50:?} p99={p99:?} p99.9={p999:?} max={max:?}"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_within_one_percent() {
+        let durations = (0..100_000).chain((17..42).map(|shift| (1 << shift) - 1));
+        for nanos in durations {
+            let highest = highest_in(bucket(nanos));
+            assert!(nanos <= highest, "{nanos} > {highest}");
+            assert!((highest - nanos) * 100 <= nanos, "{nanos} {highest}");
+        }
+        // Buckets are contiguous, so every index is used.
+        for bucket in 0..bucket(LIFETIME_HISTOGRAM_MAX_NANOS) {
+            assert_eq!(super::bucket(highest_in(bucket) + 1), bucket + 1);
+        }
+    }
+
+    #[test]
+    fn percentiles_are_exact_for_short_durations() {
+        let histogram = Histogram::<{ bucket(1_000) + 1 }>::new(1_000);
+        assert_eq!(histogram.percentiles(), Percentiles::default());
+        for nanos in 1..=200 {
+            histogram.record(Duration::from_nanos(nanos));
+        }
+        histogram.record(Duration::from_secs(1));
+        let percentiles = histogram.percentiles();
+        assert_eq!(percentiles.count, 201);
+        assert_eq!(percentiles.p50, Duration::from_nanos(101));
+        assert_eq!(percentiles.p99, Duration::from_nanos(199));
+        // Durations past the maximum are capped at it.
+        assert_eq!(percentiles.max, Duration::from_nanos(1_000));
+    }
+
+    #[cfg(tokio_unstable)]
+    #[test]
+    fn polls_and_lifetimes_are_recorded() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        let before = poll_latency_percentiles().count;
+        let lifetimes_before = task_lifetime_percentiles().count;
+
+        rt.block_on(async {
+            for _ in 0..10 {
+                tokio::spawn(async {}).await.unwrap();
+            }
+        });
+
+        let percentiles = poll_latency_percentiles();
+        assert!(percentiles.count >= before + 10);
+        assert!(percentiles.p50 <= percentiles.p99);
+        assert!(percentiles.p99 <= percentiles.p999);
+        assert!(percentiles.p999 <= percentiles.max);
+        assert!(task_lifetime_percentiles().count >= lifetimes_before + 10);
+    }
+}
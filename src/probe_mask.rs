@@ -0,0 +1,134 @@
+// Copyright 2025 Oxide Computer Company
+
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+/// A set of `tokio-dtrace`'s probes, represented as a bitset.
+///
+/// Returned by [`enabled_probes`](crate::enabled_probes). The bit assigned to
+/// each probe is part of `tokio-dtrace`'s stable API, and will not change, so
+/// the value returned by [`ProbeMask::bits`] may be exposed directly by (for
+/// example) a status endpoint:
+///
+/// | Bit | Probe                  |
+/// |-----|------------------------|
+/// | 0   | `task-spawn`           |
+/// | 1   | `task-poll-start`      |
+/// | 2   | `task-poll-end`        |
+/// | 3   | `task-terminate`       |
+/// | 4   | `worker-thread-start`  |
+/// | 5   | `worker-thread-stop`   |
+/// | 6   | `worker-thread-park`   |
+/// | 7   | `worker-thread-unpark` |
+///
+/// Other bits are reserved for probes which may be added in the future.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ProbeMask(u32);
+
+impl ProbeMask {
+    /// The `task-spawn` probe.
+    pub const TASK_SPAWN: Self = Self(1 << 0);
+    /// The `task-poll-start` probe.
+    pub const TASK_POLL_START: Self = Self(1 << 1);
+    /// The `task-poll-end` probe.
+    pub const TASK_POLL_END: Self = Self(1 << 2);
+    /// The `task-terminate` probe.
+    pub const TASK_TERMINATE: Self = Self(1 << 3);
+    /// The `worker-thread-start` probe.
+    pub const WORKER_THREAD_START: Self = Self(1 << 4);
+    /// The `worker-thread-stop` probe.
+    pub const WORKER_THREAD_STOP: Self = Self(1 << 5);
+    /// The `worker-thread-park` probe.
+    pub const WORKER_THREAD_PARK: Self = Self(1 << 6);
+    /// The `worker-thread-unpark` probe.
+    pub const WORKER_THREAD_UNPARK: Self = Self(1 << 7);
+
+    /// The number of probes which have a bit assigned.
+    pub(crate) const COUNT: usize = Self::NAMES.len();
+
+    const NAMES: [(Self, &'static str); 8] = [
+        (Self::TASK_SPAWN, "task-spawn"),
+        (Self::TASK_POLL_START, "task-poll-start"),
+        (Self::TASK_POLL_END, "task-poll-end"),
+        (Self::TASK_TERMINATE, "task-terminate"),
+        (Self::WORKER_THREAD_START, "worker-thread-start"),
+        (Self::WORKER_THREAD_STOP, "worker-thread-stop"),
+        (Self::WORKER_THREAD_PARK, "worker-thread-park"),
+        (Self::WORKER_THREAD_UNPARK, "worker-thread-unpark"),
+    ];
+
+    /// Returns a mask containing no probes.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns a mask from its bit representation.
+    ///
+    /// Bits which do not correspond to a known probe are retained.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the bit representation of this mask.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if this mask contains no probes.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns `true` if this mask contains every probe in `other`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns an iterator over the names of the probes in this mask.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .into_iter()
+            .filter(move |&(probe, _)| self.contains(probe))
+            .map(|(_, name)| name)
+    }
+}
+
+impl BitOr for ProbeMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for ProbeMask {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Debug for ProbeMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ProbeMask(")?;
+        f.debug_set().entries(self.names()).finish()?;
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_and_names() {
+        let mask = ProbeMask::TASK_SPAWN | ProbeMask::WORKER_THREAD_PARK;
+        assert_eq!(mask.bits(), 0b100_0001);
+        assert!(mask.contains(ProbeMask::TASK_SPAWN));
+        assert!(!mask.contains(ProbeMask::TASK_SPAWN | ProbeMask::TASK_POLL_END));
+        assert_eq!(
+            mask.names().collect::<Vec<_>>(),
+            ["task-spawn", "worker-thread-park"]
+        );
+        assert!(ProbeMask::empty().is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Per-probe rate limiting, configured by
+//! [`Config::rate_limit`](crate::Config::rate_limit).
+//!
+//! Each probe in [`ProbeMask`] has its own limiter, implementing a token
+//! bucket which holds up to one second's worth of fires, using the generic
+//! cell rate algorithm: rather than counting tokens, the limiter stores the
+//! time at which the bucket will next be empty, which can be updated with a
+//! single atomic compare-and-swap.
+use crate::ProbeMask;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+struct Limiter {
+    /// The time between fires at the limited rate, in nanoseconds, or 0 if
+    /// the probe is not limited.
+    interval_nanos: AtomicU64,
+    /// The time at which the bucket will be empty, in nanoseconds since
+    /// [`EPOCH`].
+    empty_at: AtomicU64,
+    /// The number of fires dropped since the previous call to [`report`].
+    dropped: AtomicU64,
+}
+
+static LIMITERS: [Limiter; ProbeMask::COUNT] = [const {
+    Limiter {
+        interval_nanos: AtomicU64::new(0),
+        empty_at: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+    }
+}; ProbeMask::COUNT];
+
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Sets the rate limit of each probe, in fires per second, indexed by the
+/// probe's bit in [`ProbeMask`]. A limit of 0 means that the probe is not
+/// limited.
+pub(crate) fn configure(limits: &[u32; ProbeMask::COUNT]) {
+    for (limiter, &per_sec) in LIMITERS.iter().zip(limits) {
+        let interval_nanos = match per_sec {
+            0 => 0,
+            per_sec => NANOS_PER_SEC / u64::from(per_sec),
+        };
+        limiter
+            .interval_nanos
+            .store(interval_nanos, Ordering::Relaxed);
+    }
+}
+
+/// Returns whether `probe` may fire now, consuming a token if so.
+///
+/// A dropped fire is only counted if the probe was recently
+/// [enabled](crate::enabled_probes), so that calls made while no consumer is
+/// attached are not reported as dropped.
+#[cfg_attr(
+    not(any(feature = "probes-task", feature = "probes-worker")),
+    allow(dead_code)
+)]
+#[inline]
+pub(crate) fn allow(probe: ProbeMask) -> bool {
+    let limiter = &LIMITERS[probe.bits().trailing_zeros() as usize];
+    let interval_nanos = limiter.interval_nanos.load(Ordering::Relaxed);
+    if interval_nanos == 0 {
+        return true;
+    }
+    let now = crate::config::nanos(EPOCH.elapsed());
+    let allowed = limiter.try_acquire(now, interval_nanos);
+    if !allowed && crate::consumer::enabled().contains(probe) {
+        limiter.dropped.fetch_add(1, Ordering::Relaxed);
+        crate::counters::dropped();
+    }
+    allowed
+}
+
+/// Fires the `probe-dropped` probe for each probe which has had fires dropped
+/// since the previous call.
+#[cfg_attr(not(feature = "probes-metrics"), allow(dead_code))]
+pub(crate) fn report() {
+    for (bit, limiter) in LIMITERS.iter().enumerate() {
+        let dropped = limiter.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            crate::probes::probe__dropped!(|| (1u32 << bit, dropped));
+        }
+    }
+}
+
+impl Limiter {
+    fn try_acquire(&self, now: u64, interval_nanos: u64) -> bool {
+        // A full bucket allows a burst of one second's worth of fires.
+        let tolerance = NANOS_PER_SEC.saturating_sub(interval_nanos);
+        self.empty_at
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |empty_at| {
+                let empty_at = empty_at.max(now);
+                (empty_at - now <= tolerance).then_some(empty_at + interval_nanos)
+            })
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_bursts_then_limits() {
+        let limiter = Limiter {
+            interval_nanos: AtomicU64::new(0),
+            empty_at: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        };
+        // Four fires per second.
+        let interval = NANOS_PER_SEC / 4;
+        let start = 10 * NANOS_PER_SEC;
+
+        let burst = (0..10)
+            .filter(|_| limiter.try_acquire(start, interval))
+            .count();
+        assert_eq!(burst, 4);
+
+        // One token is added every interval.
+        assert!(!limiter.try_acquire(start + interval - 1, interval));
+        assert!(limiter.try_acquire(start + interval, interval));
+        assert!(!limiter.try_acquire(start + interval, interval));
+
+        // After a second of idleness, the bucket is full again.
+        let later = start + 3 * NANOS_PER_SEC;
+        let burst = (0..10)
+            .filter(|_| limiter.try_acquire(later, interval))
+            .count();
+        assert_eq!(burst, 4);
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Counting of task spawns and terminations, reported as rates by the
+//! [metrics sampler](crate::metrics).
+//!
+//! Counters are sharded by task ID, so that workers spawning or terminating
+//! tasks concurrently rarely contend with each other.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// The number of independently-updated sets of counters.
+const SHARDS: usize = 8;
+
+#[repr(align(64))]
+struct Counters {
+    spawned: AtomicU64,
+    terminated: AtomicU64,
+}
+
+static COUNTERS: [Counters; SHARDS] = [const {
+    Counters {
+        spawned: AtomicU64::new(0),
+        terminated: AtomicU64::new(0),
+    }
+}; SHARDS];
+
+/// Records that the task with the given ID was spawned.
+#[inline]
+pub(crate) fn spawned(id: u64) {
+    COUNTERS[id as usize % SHARDS]
+        .spawned
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records that the task with the given ID terminated.
+#[inline]
+pub(crate) fn terminated(id: u64) {
+    COUNTERS[id as usize % SHARDS]
+        .terminated
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the total number of tasks spawned and terminated since the process
+/// started.
+fn totals() -> (u64, u64) {
+    COUNTERS
+        .iter()
+        .fold((0, 0), |(spawned, terminated), shard| {
+            (
+                spawned + shard.spawned.load(Ordering::Relaxed),
+                terminated + shard.terminated.load(Ordering::Relaxed),
+            )
+        })
+}
+
+/// The totals as of the previous call to [`Rates::report`].
+pub(crate) struct Rates {
+    spawned: u64,
+    terminated: u64,
+    sampled_at: Instant,
+}
+
+impl Rates {
+    pub(crate) fn new() -> Self {
+        let (spawned, terminated) = totals();
+        Self {
+            spawned,
+            terminated,
+            sampled_at: Instant::now(),
+        }
+    }
+
+    /// Fires the `runtime-rates` probe with the number of tasks spawned and
+    /// terminated since the previous call.
+    pub(crate) fn report(&mut self) {
+        let (spawned, terminated, interval_nanos) = self.update(totals(), Instant::now());
+        crate::probes::runtime__rates!(|| (spawned, terminated, interval_nanos));
+    }
+
+    /// Records new totals, returning the number of tasks spawned and
+    /// terminated, and the time elapsed in nanoseconds, since the previous
+    /// totals.
+    fn update(&mut self, (spawned, terminated): (u64, u64), now: Instant) -> (u64, u64, u64) {
+        let delta = (
+            spawned.saturating_sub(self.spawned),
+            terminated.saturating_sub(self.terminated),
+            crate::config::nanos(now.saturating_duration_since(self.sampled_at)),
+        );
+        self.spawned = spawned;
+        self.terminated = terminated;
+        self.sampled_at = now;
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn update_reports_deltas() {
+        let start = Instant::now();
+        let mut rates = Rates {
+            spawned: 10,
+            terminated: 4,
+            sampled_at: start,
+        };
+        let now = start + Duration::from_millis(100);
+        assert_eq!(rates.update((15, 10), now), (5, 6, 100_000_000));
+        assert_eq!(rates.update((15, 12), now), (0, 2, 0));
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2025 Oxide Computer Company
+
+//! A bounded, lock-free, multi-producer single-consumer ring buffer.
+//!
+//! This is Dmitry Vyukov's bounded queue, restricted to a single consumer.
+//! Each slot carries a sequence number which records whether it is ready to
+//! be written for a given lap of the ring, or ready to be read. Producers
+//! claim a position using a compare-and-swap on the tail, so a push never
+//! waits for the consumer, or for another producer to finish writing; when the
+//! ring is full, the value is dropped instead. A producer whose
+//! compare-and-swap fails retries at the position claimed by the winner, so
+//! pushes are lock-free but not wait-free.
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+pub(crate) struct Ring<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    /// The position of the next value to be read. Only modified by the
+    /// consumer.
+    head: AtomicUsize,
+    /// The position of the next value to be written.
+    tail: AtomicUsize,
+    /// The number of values dropped because the ring was full.
+    dropped: AtomicU64,
+}
+
+struct Slot<T> {
+    /// `pos` when the slot is ready to be written at position `pos`, and
+    /// `pos + 1` once it has been written and is ready to be read.
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: Each slot's value is only accessed by the single thread which
+// claimed it, as coordinated by the slot's sequence number, so the ring may be
+// shared between threads if its values may be sent between them.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    /// Returns a ring which holds at least `capacity` values. The capacity is
+    /// rounded up to a power of two, and is at least 2.
+    pub(crate) fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        Self {
+            slots: (0..capacity)
+                .map(|pos| Slot {
+                    seq: AtomicUsize::new(pos),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+            mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Pushes `value` onto the ring, returning `false` and counting it as
+    /// dropped if the ring is full.
+    pub(crate) fn push(&self, value: T) -> bool {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match seq.wrapping_sub(pos) as isize {
+                0 => match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: Claiming `pos` gives this thread exclusive
+                        // access to the slot until its sequence is updated.
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return true;
+                    }
+                    Err(current) => pos = current,
+                },
+                // The slot still holds the value from the previous lap, which
+                // the consumer has not yet read.
+                diff if diff < 0 => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                // Another producer claimed this position.
+                _ => pos = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Pops the oldest value from the ring, if there is one.
+    ///
+    /// # Safety
+    ///
+    /// This must not be called concurrently from more than one thread.
+    pub(crate) unsafe fn pop(&self) -> Option<T> {
+        let pos = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[pos & self.mask];
+        if slot.seq.load(Ordering::Acquire) != pos.wrapping_add(1) {
+            return None;
+        }
+        // SAFETY: The slot has been written, and the caller guarantees that
+        // no other thread is reading from the ring.
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        slot.seq
+            .store(pos.wrapping_add(self.mask + 1), Ordering::Release);
+        self.head.store(pos.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Returns the position after the last value claimed by a producer.
+    pub(crate) fn tail(&self) -> usize {
+        self.tail.load(Ordering::Acquire)
+    }
+
+    /// Returns whether the consumer has popped every value up to `pos`, as
+    /// returned by [`tail`](Self::tail).
+    pub(crate) fn popped_to(&self, pos: usize) -> bool {
+        self.head.load(Ordering::Acquire).wrapping_sub(pos) as isize >= 0
+    }
+
+    /// Returns the number of values dropped because the ring was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        // SAFETY: The ring is exclusively borrowed.
+        while unsafe { self.pop() }.is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn drops_when_full() {
+        let ring = Ring::new(3);
+        assert_eq!(ring.capacity(), 4);
+        for i in 0..6 {
+            ring.push(i);
+        }
+        assert_eq!(ring.dropped(), 2);
+
+        // SAFETY: The ring is only used by this thread.
+        let popped: Vec<_> = std::iter::from_fn(|| unsafe { ring.pop() }).collect();
+        assert_eq!(popped, [0, 1, 2, 3]);
+        assert!(ring.push(6));
+        let tail = ring.tail();
+        assert!(!ring.popped_to(tail));
+        assert_eq!(unsafe { ring.pop() }, Some(6));
+        assert!(ring.popped_to(tail));
+    }
+
+    #[test]
+    fn concurrent_producers() {
+        const PRODUCERS: usize = 4;
+        const PER_PRODUCER: usize = 10_000;
+
+        let ring = Arc::new(Ring::new(64));
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let ring = ring.clone();
+                std::thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        ring.push((producer, i));
+                    }
+                })
+            })
+            .collect();
+
+        // Values from each producer are received in the order it pushed them.
+        let mut last = [None; PRODUCERS];
+        let mut received = 0;
+        loop {
+            let done = producers.iter().all(|producer| producer.is_finished());
+            // SAFETY: This is the only thread which pops from the ring.
+            while let Some((producer, i)) = unsafe { ring.pop() } {
+                assert!(last[producer] < Some(i));
+                last[producer] = Some(i);
+                received += 1;
+            }
+            if done {
+                break;
+            }
+            std::thread::yield_now();
+        }
+        assert_eq!(
+            received as u64 + ring.dropped(),
+            (PRODUCERS * PER_PRODUCER) as u64
+        );
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Logical scopes, which tag the tasks spawned within them.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+use crate::{
+    config::{self, Tier},
+    current_task, probes,
+    task_table::TASKS,
+};
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+use std::collections::HashMap;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+use std::sync::{LazyLock, RwLock, atomic::Ordering};
+
+/// The ID interned for each scope name. IDs are allocated from 1, as 0 means
+/// that a task is not in a scope.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+static SCOPE_IDS: LazyLock<RwLock<HashMap<&'static str, u32>>> = LazyLock::new(Default::default);
+
+/// Enters a scope named `name` on the task currently being polled, until the
+/// returned guard is dropped.
+///
+/// Scopes group tasks by the logical operation which spawned them, rather than
+/// by where they were spawned. While a scope is active on a task, every task it
+/// spawns inherits the scope, and every task *those* tasks spawn inherits it in
+/// turn, so the tasks spawned to carry out one operation share a scope:
+///
+/// ```rust
+/// async fn checkout() {
+///     let _scope = tokio_dtrace::scope("checkout");
+///     // Both of these tasks, and any tasks they spawn, are in the `checkout`
+///     // scope.
+///     let a = tokio::spawn(async { /* ... */ });
+///     let b = tokio::spawn(async { /* ... */ });
+///     let _ = tokio::join!(a, b);
+/// }
+/// ```
+///
+/// The scope belongs to the *task*, not to the thread, so it stays active
+/// across `.await` points, even if the task resumes on a different worker
+/// thread. When scopes are nested, the innermost one applies, and dropping its
+/// guard restores the scope that was active when it was entered; guards
+/// should therefore be dropped in the reverse order to which they were
+/// created. A child task's scope is fixed when it is spawned: leaving the
+/// scope, or entering another one, in the parent does not change the scope of
+/// children it has already spawned.
+///
+/// Each name is interned to a `u32` scope ID, which is never reused. Two
+/// probes report scopes:
+///
+/// - `tokio*:::scope-enter` fires when a scope is entered. `arg0` is the [task
+///   `Id`](tokio::task::Id) of the task entering it, `arg1` is the scope ID,
+///   and `arg2` is the scope's name. This associates each scope ID with its
+///   name.
+/// - `tokio*:::task-spawn-scope` fires immediately after `task-spawn` for a
+///   task spawned in a scope. `arg1` is the task `Id` of the spawned task, and
+///   `arg2` is its scope ID. Tasks which are not in a scope do not fire this
+///   probe.
+///
+/// Scopes are tracked by the runtime hooks, so, as with [`set_trace_id`], this
+/// function does nothing unless it is called from within a task running on a
+/// runtime with `tokio-dtrace`'s hooks registered, and the `probes-task`
+/// feature enabled.
+///
+/// [`set_trace_id`]: crate::set_trace_id
+pub fn scope(name: &'static str) -> ScopeGuard {
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    if let Some(task) = current_task::id() {
+        let scope = intern(name);
+        let previous = current_task::set_scope(scope);
+        TASKS.with_polling(task, |state| state.scope = scope);
+        // The scope is tracked regardless, so that tasks spawned within it are
+        // tagged if the hooks are enabled later.
+        if crate::ENABLED.load(Ordering::Relaxed) && config::tier_enabled(Tier::Minimal) {
+            fire!(probes::scope__enter, || (
+                config::probe_id(task),
+                scope,
+                name
+            ));
+        }
+        return ScopeGuard {
+            entered: Some((task, previous)),
+        };
+    }
+    #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+    let _ = name;
+    ScopeGuard {
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        entered: None,
+    }
+}
+
+/// Leaves the scope entered by [`scope`] when dropped.
+#[must_use = "the scope is left as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct ScopeGuard {
+    /// The task which entered the scope, and the scope it was previously in.
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    entered: Option<(u64, u32)>,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        // A guard which has been moved to another task, or which is dropped
+        // outside of a poll, cannot change the scope of the task which
+        // created it.
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        if let Some((task, previous)) = self.entered
+            && current_task::id() == Some(task)
+        {
+            current_task::set_scope(previous);
+            TASKS.with_polling(task, |state| state.scope = previous);
+        }
+    }
+}
+
+/// Returns the ID of the scope named `name`, interning it if necessary.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+fn intern(name: &'static str) -> u32 {
+    if let Some(&id) = SCOPE_IDS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+    {
+        return id;
+    }
+    let mut ids = SCOPE_IDS.write().unwrap_or_else(|e| e.into_inner());
+    let next = ids.len() as u32 + 1;
+    *ids.entry(name).or_insert(next)
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_interned() {
+        let a = intern("scope::tests::a");
+        let b = intern("scope::tests::b");
+        assert_ne!(a, 0);
+        assert_ne!(a, b);
+        assert_eq!(intern("scope::tests::a"), a);
+    }
+
+    #[test]
+    fn scopes_are_inherited_and_nest() {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.worker_threads(2);
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        let child_scope = || async { tokio::spawn(async { current_task::scope() }).await.unwrap() };
+
+        let scopes = rt.block_on(async move {
+            tokio::spawn(async move {
+                let outer = crate::scope("scope::tests::outer");
+                // The scope survives the task being rescheduled.
+                tokio::task::yield_now().await;
+                let in_outer = child_scope().await;
+                let inner = crate::scope("scope::tests::inner");
+                let in_inner = child_scope().await;
+                drop(inner);
+                let after_inner = child_scope().await;
+                drop(outer);
+                let after_outer = child_scope().await;
+                (in_outer, in_inner, after_inner, after_outer)
+            })
+            .await
+            .unwrap()
+        });
+        let outer = intern("scope::tests::outer");
+        let inner = intern("scope::tests::inner");
+        assert_eq!(scopes, (outer, inner, outer, 0));
+    }
+}
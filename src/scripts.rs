@@ -0,0 +1,124 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Ready-made D scripts for `tokio-dtrace`'s probes.
+//!
+//! Each script is a `&'static str` which names `tokio-dtrace`'s provider as
+//! `tokio$1`, like the scripts in the repository's `examples` directory, so it
+//! may be written to a file and run as-is, passing the PID of the process to
+//! trace as the script's first argument:
+//!
+//! ```console
+//! $ pfexec dtrace -s poll-latency.d <PID>
+//! ```
+//!
+//! Tools which run DTrace themselves can instead use [`instantiate`] to fill
+//! in the PID, and optionally a different provider name, before passing the
+//! script to `dtrace -n` or a DTrace library. [`ALL`] lists every script by
+//! name, for tools which offer a menu of them. The `tokio-dtrace-gen`
+//! program, which is built when the `script-gen` feature is enabled, does
+//! this from the command line:
+//!
+//! ```console
+//! $ tokio-dtrace-gen poll-latency <PID> > poll-latency.d
+//! $ pfexec dtrace -s poll-latency.d
+//! ```
+//!
+//! The scripts aggregate over the whole run, printing their aggregations when
+//! DTrace exits, except for [`SPAWN_RATE`], which prints every second.
+
+/// Poll durations, in nanoseconds, for each spawn location, as a
+/// power-of-two histogram.
+///
+/// Polls whose duration is unknown are skipped. Uses `task-poll-end`, which
+/// belongs to the [`Normal`](crate::Tier::Normal) tier.
+pub const POLL_LATENCY: &str = r#"tokio$1:::task-poll-end
+/arg5 != 0xffffffffffffffff/
+{
+    @["poll duration (ns)", copyinstr(arg1), arg2, arg3] = quantize(arg5);
+}
+"#;
+
+/// The number of tasks spawned at each spawn location, printed and reset
+/// every second.
+pub const SPAWN_RATE: &str = r#"#pragma D option quiet
+
+tokio$1:::task-spawn
+{
+    @spawns[copyinstr(arg1), arg2, arg3] = count();
+}
+
+profile:::tick-1sec
+{
+    printf("%Y\n", walltimestamp);
+    printa("  %s:%d:%d %@d\n", @spawns);
+    trunc(@spawns);
+}
+"#;
+
+/// The lifetime of each task, from its spawn to its termination, in
+/// nanoseconds, for each spawn location, as a power-of-two histogram.
+///
+/// Tasks spawned before the script started are not counted.
+pub const TASK_LIFETIME: &str = r#"tokio$1:::task-spawn
+{
+    spawned[arg0] = timestamp;
+}
+
+tokio$1:::task-terminate
+/spawned[arg0]/
+{
+    @["task lifetime (ns)", copyinstr(arg1), arg2, arg3] =
+        quantize(timestamp - spawned[arg0]);
+    spawned[arg0] = 0;
+}
+"#;
+
+/// The longest poll, and the number of polls, exceeding the
+/// [slow poll threshold](crate::Config::slow_poll_threshold) at each spawn
+/// location, with the user stack of the thread at the end of each.
+pub const BLOCKING_POLLS: &str = r"tokio$1:::task-poll-slow
+{
+    @longest[copyinstr(arg3), arg4, arg5] = max(arg2);
+    @count[copyinstr(arg3), arg4, arg5] = count();
+    @stacks[ustack()] = count();
+}
+";
+
+/// Every script in this module, keyed by a short name.
+pub const ALL: &[(&str, &str)] = &[
+    ("poll-latency", POLL_LATENCY),
+    ("spawn-rate", SPAWN_RATE),
+    ("task-lifetime", TASK_LIFETIME),
+    ("blocking-polls", BLOCKING_POLLS),
+];
+
+/// Returns `script` with its probes bound to the `provider` provider in the
+/// process with the given PID.
+///
+/// Each `tokio$1` in the script is replaced by `provider` followed by `pid`.
+/// `provider` is normally `"tokio"`; other values allow a script to be used
+/// with a provider which fires the same probes under a different name.
+///
+/// # Examples
+///
+/// ```rust
+/// use tokio_dtrace::scripts;
+///
+/// let script = scripts::instantiate(scripts::POLL_LATENCY, "tokio", std::process::id());
+/// assert!(script.starts_with(&format!("tokio{}:::task-poll-end", std::process::id())));
+/// ```
+pub fn instantiate(script: &str, provider: &str, pid: u32) -> String {
+    script.replace("tokio$1", &format!("{provider}{pid}"))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn instantiate_replaces_every_provider() {
+        for (name, script) in super::ALL {
+            let script = super::instantiate(script, "tokio", 1234);
+            assert!(!script.contains('$'), "{name}: {script}");
+            assert!(script.contains("tokio1234:::"), "{name}: {script}");
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Support for firing `tokio-dtrace`'s probes as SystemTap-style SDT probes on
+//! Linux, using the [`probe`] crate.
+//!
+//! SDT probes are recorded in an ELF note section of the binary, where they
+//! can be found by `bpftrace`, `perf`, and SystemTap, without registering them
+//! at runtime. Their arguments must be integers or pointers, so each argument
+//! of a `tokio-dtrace` probe is converted to a [`Held`] argument, which holds
+//! a NUL-terminated copy of any string for as long as the probe needs it.
+//! The arguments are held in an array on the stack, so firing a probe does
+//! not allocate.
+use std::borrow::Cow;
+
+/// The longest string argument passed to an SDT probe, in bytes, which
+/// matches DTrace's default `strsize` and
+/// [`Config::DEFAULT_MAX_PATH_LEN`](crate::Config::DEFAULT_MAX_PATH_LEN).
+/// Longer strings are truncated.
+pub(crate) const MAX_STR_LEN: usize = 256;
+
+/// An argument to an SDT probe.
+pub(crate) enum Held {
+    Int(u64),
+    /// A string, followed by at least one NUL byte.
+    Str([u8; MAX_STR_LEN + 1]),
+}
+
+impl Held {
+    /// Returns the argument's value, as passed to the probe: the integer
+    /// itself, or a pointer to the string.
+    #[inline]
+    pub(crate) fn value(&self) -> u64 {
+        match self {
+            Self::Int(value) => *value,
+            Self::Str(bytes) => bytes.as_ptr() as u64,
+        }
+    }
+}
+
+/// Converts a single probe argument to a [`Held`] argument.
+pub(crate) trait Arg {
+    fn hold(self) -> Held;
+}
+
+macro_rules! int_args {
+    ($($ty:ty),*) => {
+        $(
+            impl Arg for $ty {
+                #[inline]
+                fn hold(self) -> Held {
+                    // Signed integers are passed as their two's complement
+                    // representation, which scripts may cast back.
+                    Held::Int(self as u64)
+                }
+            }
+        )*
+    };
+}
+
+int_args!(u8, u32, u64, i64);
+
+impl Arg for &str {
+    fn hold(self) -> Held {
+        // Strings containing NUL bytes are truncated at the first one, as a
+        // consumer reading them would do, and strings which are too long are
+        // truncated at the last character which fits.
+        let string = self.split('\0').next().unwrap_or_default();
+        let mut len = string.len().min(MAX_STR_LEN);
+        while !string.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; MAX_STR_LEN + 1];
+        bytes[..len].copy_from_slice(&string.as_bytes()[..len]);
+        Held::Str(bytes)
+    }
+}
+
+impl Arg for String {
+    fn hold(self) -> Held {
+        self.as_str().hold()
+    }
+}
+
+impl Arg for Cow<'_, str> {
+    fn hold(self) -> Held {
+        (&*self).hold()
+    }
+}
+
+/// Converts the value returned by a probe's argument closure to an array of
+/// [`Held`] arguments.
+pub(crate) trait Args {
+    type Held: AsRef<[Held]>;
+
+    fn hold(self) -> Self::Held;
+}
+
+impl Args for u64 {
+    type Held = [Held; 1];
+
+    fn hold(self) -> [Held; 1] {
+        [Arg::hold(self)]
+    }
+}
+
+macro_rules! tuple_args {
+    ($($len:literal: ($($ty:ident),+)),*) => {
+        $(
+            impl<$($ty: Arg),+> Args for ($($ty,)+) {
+                type Held = [Held; $len];
+
+                #[allow(non_snake_case)]
+                fn hold(self) -> [Held; $len] {
+                    let ($($ty,)+) = self;
+                    [$($ty.hold()),+]
+                }
+            }
+        )*
+    };
+}
+
+tuple_args!(
+    2: (A, B),
+    3: (A, B, C),
+    4: (A, B, C, D),
+    5: (A, B, C, D, E),
+    6: (A, B, C, D, E, F)
+);
+
+/// Fires the SDT probe `tokio:$name`, with the arguments returned by the
+/// closure `$args`, which is only called if the probe is enabled.
+///
+/// `$index` lists the index of each argument. Each argument expression passed
+/// to [`probe::probe_lazy`] is only evaluated once the probe is known to be
+/// enabled, so the first one to be evaluated calls the closure.
+macro_rules! sdt {
+    ($name:ident, $args:expr) => {{
+        let _ = $args;
+        ::probe::probe!(tokio, $name);
+    }};
+    ($name:ident, $args:expr, [$($index:tt)+]) => {{
+        let args = $args;
+        let mut held = None;
+        ::probe::probe_lazy!(
+            tokio,
+            $name,
+            $(
+                held.get_or_insert_with(|| crate::sdt::Args::hold(args())).as_ref()[$index].value()
+            ),+
+        );
+    }};
+}
+
+pub(crate) use sdt;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    /// Returns the string held by `held`, as a consumer would read it.
+    fn read(held: &Held) -> &str {
+        match held {
+            Held::Str(bytes) => CStr::from_bytes_until_nul(bytes).unwrap().to_str().unwrap(),
+            Held::Int(_) => panic!("not a string"),
+        }
+    }
+
+    #[test]
+    fn strings_are_nul_terminated() {
+        assert_eq!(read(&"src/main.rs".hold()), "src/main.rs");
+        assert_eq!(read(&String::new().hold()), "");
+        assert_eq!(read(&Cow::Borrowed("a").hold()), "a");
+    }
+
+    #[test]
+    fn strings_are_truncated_at_interior_nuls() {
+        assert_eq!(read(&"before\0after".hold()), "before");
+        assert_eq!(read(&"\0".hold()), "");
+    }
+
+    #[test]
+    fn long_strings_are_truncated_to_a_character() {
+        let long = "x".repeat(MAX_STR_LEN + 10);
+        assert_eq!(read(&long.as_str().hold()), &long[..MAX_STR_LEN]);
+        // A two-byte character straddling the limit is dropped whole.
+        let straddling = format!("{}é", "x".repeat(MAX_STR_LEN - 1));
+        assert_eq!(
+            read(&straddling.as_str().hold()),
+            &straddling[..MAX_STR_LEN - 1]
+        );
+    }
+
+    #[test]
+    fn integers_are_passed_as_bits() {
+        assert_eq!(Arg::hold(7u8).value(), 7);
+        assert_eq!(Arg::hold(u32::MAX).value(), u64::from(u32::MAX));
+        assert_eq!(Arg::hold(-1i64).value(), u64::MAX);
+    }
+
+    #[test]
+    fn the_most_arguments_are_held_in_order() {
+        let held = (1u64, "two", 3u32, String::from("four"), 5u8, -6i64).hold();
+        assert_eq!(held.len(), 6);
+        assert_eq!(held[0].value(), 1);
+        assert_eq!(read(&held[1]), "two");
+        assert_eq!(held[2].value(), 3);
+        assert_eq!(read(&held[3]), "four");
+        assert_eq!(held[4].value(), 5);
+        assert_eq!(held[5].value(), -6i64 as u64);
+        assert_eq!(Args::hold(9u64)[0].value(), 9);
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Probes with serialized, structured arguments.
+//!
+//! When the `serialized-probes` feature is enabled, each of the task probes is
+//! accompanied by a variant which takes a single structured argument, rather
+//! than positional arguments. These probes are provided by a separate
+//! `tokio_json${PID}` provider, and have a `-json` suffix:
+//!
+//! - `tokio_json*:::task-spawn-json`
+//! - `tokio_json*:::task-poll-start-json`
+//! - `tokio_json*:::task-poll-end-json`
+//! - `tokio_json*:::task-terminate-json`
+//!
+//! Each fires immediately after the corresponding positional probe, with a
+//! [`TaskRecord`] serialized as JSON as `arg0`. Fields may be extracted in D
+//! using the `json` subroutine:
+//!
+//! ```d
+//! tokio_json*:::task-spawn-json
+//! {
+//!     this->json = copyinstr(arg0);
+//!     printf("task %s spawned at %s:%s\n",
+//!         json(this->json, "id"),
+//!         json(this->json, "file"),
+//!         json(this->json, "line"));
+//! }
+//! ```
+//!
+//! New fields may be added to [`TaskRecord`] in future releases without
+//! changing the probes' signatures, so scripts which extract fields by name
+//! will continue to work. The positional probes remain the default, and their
+//! argument order is unaffected by this feature.
+//!
+//! ## Performance
+//!
+//! Serializing a record allocates a [`String`] and formats every field as
+//! JSON, which is considerably more expensive than passing positional
+//! arguments. As with all probes, this work is only done while DTrace has the
+//! probe enabled. However, since the task probes fire on every spawn and poll,
+//! enabling the serialized probes on a busy runtime adds noticeable overhead to
+//! the hot path, and the positional probes should be preferred when
+//! performance matters.
+use serde::Serialize;
+
+/// The structured argument to the serialized task probes.
+#[derive(Copy, Clone, Debug, Serialize)]
+#[non_exhaustive]
+pub struct TaskRecord {
+    /// The task's [`tokio::task::Id`], as an integer.
+    pub id: u64,
+    /// The source file in which the task was spawned.
+    pub file: &'static str,
+    /// The line number at which the task was spawned.
+    pub line: u32,
+    /// The column at which the task was spawned.
+    pub col: u32,
+    /// The high 64 bits of the task's [trace ID](crate::set_trace_id).
+    pub trace_hi: u64,
+    /// The low 64 bits of the task's [trace ID](crate::set_trace_id).
+    pub trace_lo: u64,
+}
+
+#[cfg(tokio_unstable)]
+impl TaskRecord {
+    pub(crate) fn new(meta: &tokio::runtime::TaskMeta<'_>, trace_id: u128) -> Self {
+        let location = meta.spawned_at();
+        Self {
+            id: crate::hooks::id_to_u64(meta.id()),
+            file: location.file(),
+            line: location.line(),
+            col: location.column(),
+            trace_hi: (trace_id >> 64) as u64,
+            trace_lo: trace_id as u64,
+        }
+    }
+}
+
+#[cfg(any(unix, windows))]
+#[usdt::provider(provider = "tokio_json")]
+#[allow(non_snake_case)]
+pub(crate) mod probes {
+    fn task__spawn__json(task: crate::serialized::TaskRecord) {}
+    fn task__poll__start__json(task: crate::serialized::TaskRecord) {}
+    fn task__poll__end__json(task: crate::serialized::TaskRecord) {}
+    fn task__terminate__json(task: crate::serialized::TaskRecord) {}
+}
+
+/// Stands in for the provider on targets which `usdt` does not support.
+#[cfg(not(any(unix, windows)))]
+#[allow(unused_imports)]
+pub(crate) mod probes {
+    macro_rules! disabled {
+        ($args:expr) => {{
+            let _ = $args;
+        }};
+    }
+
+    pub(crate) use disabled as task__spawn__json;
+    pub(crate) use disabled as task__poll__start__json;
+    pub(crate) use disabled as task__poll__end__json;
+    pub(crate) use disabled as task__terminate__json;
+}
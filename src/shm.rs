@@ -0,0 +1,196 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Task counters mirrored into shared memory, for post-mortem inspection.
+//!
+//! DTrace can only report what happens while a consumer is attached. When a
+//! runtime crashes without one, [`map`] allows a few key counters to be
+//! recovered afterwards: it maps a file into the process's memory, and the
+//! runtime hooks then update the counters in the mapping as tasks are spawned
+//! and terminate. Because the mapping is shared with the file, the last values
+//! written remain in the file after the process exits, however it exits, and
+//! can be read by another process using [`read`].
+//!
+//! ## Layout
+//!
+//! The file contains [`SIZE`] bytes: six 64-bit unsigned integers in the
+//! native byte order of the process that wrote them, at the following byte
+//! offsets:
+//!
+//! | Offset | Field        | Description                                     |
+//! |--------|--------------|-------------------------------------------------|
+//! | 0      | `magic`      | Always [`MAGIC`].                               |
+//! | 8      | `version`    | The layout version; currently [`VERSION`].      |
+//! | 16     | `pid`        | The ID of the process which mapped the file.    |
+//! | 24     | `spawned`    | The number of tasks spawned.                    |
+//! | 32     | `terminated` | The number of tasks which terminated.           |
+//! | 40     | `leaked`     | The number of tasks discarded by [`teardown`].  |
+//!
+//! The difference between `spawned` and `terminated` is the number of tasks
+//! which were alive when the counters were last written. `leaked` counts tasks
+//! which were still alive when [`teardown`] was called, such as tasks which
+//! were still running when their runtime was dropped.
+//!
+//! The counters are best-effort. Each is updated independently, with relaxed
+//! atomic operations, so a reader may observe a spawn whose termination has
+//! already been counted, and the values in the file are only as current as the
+//! last write which the operating system flushed to it. In particular, if the
+//! whole machine (rather than just the process) crashes, recent updates may be
+//! lost.
+//!
+//! [`teardown`]: crate::teardown
+use memmap2::MmapMut;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// The value of the first field of the shared counters.
+pub const MAGIC: u64 = u64::from_be_bytes(*b"TOKDTRSC");
+
+/// The current version of the shared counters' layout.
+pub const VERSION: u64 = 1;
+
+/// The size of the shared counters, in bytes.
+pub const SIZE: usize = size_of::<Counters>();
+
+#[repr(C)]
+struct Counters {
+    magic: AtomicU64,
+    version: AtomicU64,
+    pid: AtomicU64,
+    spawned: AtomicU64,
+    terminated: AtomicU64,
+    leaked: AtomicU64,
+}
+
+static MAPPING: OnceLock<MmapMut> = OnceLock::new();
+
+/// Held by [`map`] from checking whether the counters are mapped until they
+/// are, so that a concurrent call cannot truncate the file of a mapping it is
+/// about to lose the race to set.
+static MAP_LOCK: Mutex<()> = Mutex::new(());
+
+/// The values of the shared counters, as returned by [`read`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// The ID of the process which mapped the counters.
+    pub pid: u64,
+    /// The number of tasks spawned.
+    pub spawned: u64,
+    /// The number of tasks which terminated.
+    pub terminated: u64,
+    /// The number of tasks discarded by [`teardown`](crate::teardown).
+    pub leaked: u64,
+}
+
+/// Creates the file at `path`, and mirrors `tokio-dtrace`'s task counters into
+/// it for the rest of the process's lifetime.
+///
+/// If the file already exists, it is truncated and overwritten, so a process
+/// which is restarted using the same path replaces the previous process's
+/// counters: a post-mortem tool should copy the file before the process is
+/// restarted. Only counts from after this function is called are recorded.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or mapped, or with
+/// [`io::ErrorKind::AlreadyExists`] if the counters have already been mapped
+/// by an earlier call.
+pub fn map(path: impl AsRef<Path>) -> io::Result<()> {
+    let _guard = MAP_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if MAPPING.get().is_some() {
+        return Err(already_mapped());
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.set_len(SIZE as u64)?;
+    // SAFETY: `tokio-dtrace` only accesses the mapping through atomics. The
+    // file was created for this purpose; other processes should only read it.
+    let mapping = unsafe { MmapMut::map_mut(&file)? };
+    let counters = counters_in(&mapping);
+    counters.magic.store(MAGIC, Ordering::Relaxed);
+    counters.version.store(VERSION, Ordering::Relaxed);
+    counters
+        .pid
+        .store(u64::from(std::process::id()), Ordering::Relaxed);
+    MAPPING.set(mapping).map_err(|_| already_mapped())
+}
+
+/// Reads the shared counters from the file at `path`, which was written by
+/// [`map`] in this or another process.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, or with
+/// [`io::ErrorKind::InvalidData`] if it does not contain shared counters with
+/// the current [`VERSION`] of the layout.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Snapshot> {
+    let bytes = std::fs::read(path)?;
+    let word = |i: usize| {
+        bytes
+            .get(i * 8..(i + 1) * 8)
+            .map(|word| u64::from_ne_bytes(word.try_into().unwrap()))
+    };
+    if word(0) != Some(MAGIC) || word(1) != Some(VERSION) || bytes.len() != SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a tokio-dtrace shared counters file",
+        ));
+    }
+    Ok(Snapshot {
+        pid: word(2).unwrap_or(0),
+        spawned: word(3).unwrap_or(0),
+        terminated: word(4).unwrap_or(0),
+        leaked: word(5).unwrap_or(0),
+    })
+}
+
+/// Records that a task was spawned.
+#[cfg_attr(not(tokio_unstable), allow(dead_code))]
+#[inline]
+pub(crate) fn spawned() {
+    if let Some(counters) = counters() {
+        counters.spawned.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records that a task terminated.
+#[cfg_attr(not(tokio_unstable), allow(dead_code))]
+#[inline]
+pub(crate) fn terminated() {
+    if let Some(counters) = counters() {
+        counters.terminated.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records that `count` tasks were discarded without terminating.
+#[cfg_attr(not(tokio_unstable), allow(dead_code))]
+pub(crate) fn leaked(count: usize) {
+    if let Some(counters) = counters() {
+        counters.leaked.fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+fn counters() -> Option<&'static Counters> {
+    MAPPING.get().map(counters_in)
+}
+
+fn counters_in(mapping: &MmapMut) -> &Counters {
+    // SAFETY: The mapping is page-aligned and `SIZE` bytes long, and
+    // `Counters` consists only of atomics, for which any bit pattern is valid
+    // and which may be mutated through a shared reference.
+    unsafe { &*mapping.as_ptr().cast::<Counters>() }
+}
+
+fn already_mapped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "shared counters are already mapped",
+    )
+}
@@ -0,0 +1,69 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Macros for declaring named spawn sites, with a probe for each.
+
+/// Declares a USDT provider with a zero-argument probe for each of a set of
+/// named spawn sites.
+///
+/// The task probes identify where a task was spawned by its file, line, and
+/// column, which DTrace scripts must match against as strings. For an
+/// application with a handful of hot spawn sites, it can be more convenient
+/// to give each site its own probe, so that a DTrace script can select the
+/// spawns from a site by probe name alone. Probe names must be known at
+/// compile time, so each site is declared, by name, using this macro:
+///
+/// ```rust,ignore
+/// tokio_dtrace::named_spawn_sites!(tokio_sites: db_pool, http_accept);
+/// ```
+///
+/// This declares a module named `tokio_sites`, defining a USDT provider of the
+/// same name, with the probes `db_pool` and `http_accept`. Tasks are then
+/// spawned at a site using [`spawn_at_site!`](crate::spawn_at_site), which
+/// fires the site's probe immediately before spawning the task:
+///
+/// ```rust,ignore
+/// let handle = tokio_dtrace::spawn_at_site!(tokio_sites::db_pool, async {
+///     // ...
+/// });
+/// ```
+///
+/// The spawns from that site can then be traced using the probe
+/// `tokio_sites*:::db_pool`. The task probes still fire for these tasks as
+/// usual, so the site's probe is immediately followed, on the same thread, by
+/// the `tokio*:::task-spawn` probe for the new task.
+///
+/// ## Registration
+///
+/// The provider is defined in the crate which invokes this macro, using the
+/// [`usdt`] crate's `provider` attribute, so that crate must depend on `usdt`
+/// itself. Like any USDT probes, the site probes must be registered with
+/// DTrace before they can be used. [`register_hooks`](crate::register_hooks)
+/// and [`register_hooks_with`](crate::register_hooks_with) register every
+/// provider in the program, including these; a program which registers
+/// `tokio-dtrace`'s hooks using
+/// [`register_hooks_without_probe_registration`](crate::register_hooks_without_probe_registration)
+/// must call [`usdt::register_probes`] itself.
+#[macro_export]
+macro_rules! named_spawn_sites {
+    ($provider:ident: $($site:ident),+ $(,)?) => {
+        #[usdt::provider]
+        mod $provider {
+            $(fn $site() {})+
+        }
+    };
+}
+
+/// Spawns a task at a named spawn site declared using
+/// [`named_spawn_sites!`](crate::named_spawn_sites), firing the site's probe.
+///
+/// The first argument is the path of the site's probe, which is the name of
+/// the provider module followed by the name of the site, and the second is the
+/// future to spawn. The future is spawned using [`tokio::spawn`], and its
+/// [`JoinHandle`](tokio::task::JoinHandle) is returned.
+#[macro_export]
+macro_rules! spawn_at_site {
+    ($($site:ident)::+, $future:expr $(,)?) => {{
+        $($site)::+!(|| ());
+        $crate::__private::tokio::spawn($future)
+    }};
+}
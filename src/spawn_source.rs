@@ -0,0 +1,118 @@
+// Copyright 2025 Oxide Computer Company
+
+/// Describes where a task was spawned from.
+///
+/// This is emitted as `arg2` of the `tokio*:::task-spawn-source` probe, as the
+/// integer value of the variant. These values are stable, and will not change
+/// in future releases, although new variants may be added.
+///
+/// The source of a spawn is inferred from the context in which the
+/// `on_task_spawn` hook runs, so it cannot distinguish between every way a
+/// task may be spawned. In particular, tasks spawned using
+/// [`tokio::spawn`], [`Handle::spawn`](tokio::runtime::Handle::spawn), and
+/// [`JoinSet::spawn`](tokio::task::JoinSet::spawn) are indistinguishable when
+/// spawned from the same context.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum SpawnSource {
+    /// The source of the spawn could not be determined (`0`).
+    Unknown = 0,
+    /// The task was spawned from within another task, while that task was
+    /// being polled by a runtime with `tokio-dtrace`'s hooks registered (`1`).
+    ///
+    /// This classification is reliable, provided that the `on_before_task_poll`
+    /// and `on_after_task_poll` hooks are registered.
+    Task = 1,
+    /// The task was spawned from a thread started by a runtime with
+    /// `tokio-dtrace`'s hooks registered, but not from within a task (`2`).
+    ///
+    /// This typically means the task was spawned from a blocking task, such as
+    /// a closure passed to [`tokio::task::spawn_blocking`].
+    RuntimeThread = 2,
+    /// The task was spawned from a thread which does not belong to a runtime
+    /// with `tokio-dtrace`'s hooks registered (`3`).
+    ///
+    /// For example, this includes tasks spawned by a future passed to
+    /// [`Runtime::block_on`](tokio::runtime::Runtime::block_on) on a
+    /// multi-threaded runtime, and tasks spawned using a
+    /// [`Handle`](tokio::runtime::Handle) from a thread that is not part of
+    /// the runtime.
+    External = 3,
+}
+
+impl From<SpawnSource> for u8 {
+    fn from(source: SpawnSource) -> Self {
+        source as u8
+    }
+}
+
+impl TryFrom<u8> for SpawnSource {
+    type Error = u8;
+
+    /// Converts the integer value emitted by the `task-spawn-source` probe
+    /// back into a [`SpawnSource`], returning the value as an error if it is
+    /// not recognized.
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            0 => Ok(Self::Unknown),
+            1 => Ok(Self::Task),
+            2 => Ok(Self::RuntimeThread),
+            3 => Ok(Self::External),
+            _ => Err(value),
+        }
+    }
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl SpawnSource {
+    /// Determines the source of a task being spawned on the current thread.
+    pub(crate) fn current() -> Self {
+        if crate::current_task::id().is_some() {
+            Self::Task
+        } else if crate::worker::current() != crate::worker::NO_WORKER {
+            Self::RuntimeThread
+        } else {
+            Self::External
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    #[test]
+    fn classifies_spawn_context() {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .worker_threads(1)
+            .build()
+            .unwrap();
+
+        rt.block_on(async {
+            assert_eq!(SpawnSource::current(), SpawnSource::External);
+
+            let in_task = tokio::spawn(async { SpawnSource::current() });
+            assert_eq!(in_task.await.unwrap(), SpawnSource::Task);
+
+            let in_blocking = tokio::task::spawn_blocking(SpawnSource::current);
+            assert_eq!(in_blocking.await.unwrap(), SpawnSource::RuntimeThread);
+        });
+    }
+
+    #[test]
+    fn u8_round_trip() {
+        for source in [
+            SpawnSource::Unknown,
+            SpawnSource::Task,
+            SpawnSource::RuntimeThread,
+            SpawnSource::External,
+        ] {
+            assert_eq!(SpawnSource::try_from(u8::from(source)), Ok(source));
+        }
+        assert_eq!(SpawnSource::try_from(4), Err(4));
+    }
+}
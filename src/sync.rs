@@ -0,0 +1,281 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Instrumented synchronization primitives.
+//!
+//! Time a task spends waiting for a lock does not appear in any of the task
+//! probes: the task is simply not polled until the lock is released. The
+//! types in this module wrap Tokio's, firing probes as they are acquired and
+//! released, so that contention can be measured and attributed to tasks.
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+use {
+    crate::{clock, config, current_task, probes},
+    std::time::{Duration, Instant},
+};
+
+pub use tokio::sync::TryLockError;
+
+/// The identifier of the next [`Mutex`] to be created.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An asynchronous mutex which fires probes as it is locked and unlocked.
+///
+/// This wraps [`tokio::sync::Mutex`], and behaves exactly like it, but also
+/// fires the following probes. `arg0` of each is an identifier assigned by
+/// `tokio-dtrace` to each mutex when it is created, starting from 1, and
+/// `arg1` is the [task `Id`](tokio::task::Id) of the task acquiring or
+/// releasing the lock, or `0` if it is not being acquired or released from
+/// within a task.
+///
+/// - `tokio*:::mutex-acquire-start`, when a task begins waiting for the lock.
+/// - `tokio*:::mutex-acquire-end`, when it acquires the lock. `arg2` is the
+///   time spent waiting, in nanoseconds. If the [`lock`](Self::lock) future
+///   is dropped before the lock is acquired, this does not fire.
+/// - `tokio*:::mutex-release`, when the [`MutexGuard`] is dropped. `arg2` is
+///   the time the lock was held, in nanoseconds.
+///
+/// For example, to find the most contended locks:
+/// `tokio*:::mutex-acquire-end { @[arg0] = sum(arg2); }`.
+///
+/// The probes belong to the [`Normal`](crate::Tier::Normal) tier. They only
+/// fire when the `probes-task` feature is enabled and the hooks are
+/// registered; otherwise, the wrapper only adds the identifier.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use tokio_dtrace::sync::Mutex;
+///
+/// # async fn example() {
+/// let count = Arc::new(Mutex::new(0));
+/// for _ in 0..3 {
+///     let count = count.clone();
+///     tokio::spawn(async move {
+///         *count.lock().await += 1;
+///     });
+/// }
+/// # }
+/// ```
+pub struct Mutex<T: ?Sized> {
+    id: u64,
+    inner: tokio::sync::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Creates a new unlocked mutex containing `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            inner: tokio::sync::Mutex::new(value),
+        }
+    }
+
+    /// Consumes the mutex, returning the value it contains.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Returns the identifier passed to this mutex's probes as `arg0`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Locks the mutex, waiting until it is available, as
+    /// [`tokio::sync::Mutex::lock`] does.
+    ///
+    /// Fires `mutex-acquire-start` when called, and `mutex-acquire-end` once
+    /// the lock is acquired.
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        let started_at = {
+            self.acquire_started();
+            clock::now()
+        };
+        let guard = self.inner.lock().await;
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        {
+            let acquired_at = clock::now();
+            let wait = acquired_at.saturating_duration_since(started_at);
+            self.acquired(guard, acquired_at, wait)
+        }
+        #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+        {
+            MutexGuard { guard }
+        }
+    }
+
+    /// Attempts to lock the mutex without waiting, as
+    /// [`tokio::sync::Mutex::try_lock`] does.
+    ///
+    /// If the lock is acquired, fires `mutex-acquire-start` and then
+    /// `mutex-acquire-end`, with a wait time of zero.
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, TryLockError> {
+        let guard = self.inner.try_lock()?;
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        {
+            // The lock was not waited for, so the wait is reported as exactly
+            // zero, rather than as the time between two reads of the clock.
+            self.acquire_started();
+            Ok(self.acquired(guard, clock::now(), Duration::ZERO))
+        }
+        #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+        {
+            Ok(MutexGuard { guard })
+        }
+    }
+
+    /// Returns a mutable reference to the value, which requires no locking
+    /// since the mutex is borrowed mutably. Fires no probes.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Fires `mutex-acquire-start`.
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    fn acquire_started(&self) {
+        if enabled() {
+            fire!(probes::mutex__acquire__start, || (self.id, task_id()));
+        }
+    }
+
+    /// Fires `mutex-acquire-end` for a lock acquired at `acquired_at`, after
+    /// waiting for `wait`, and wraps its guard.
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    fn acquired<'a>(
+        &self,
+        guard: tokio::sync::MutexGuard<'a, T>,
+        acquired_at: Instant,
+        wait: Duration,
+    ) -> MutexGuard<'a, T> {
+        if enabled() {
+            fire!(probes::mutex__acquire__end, || {
+                (self.id, task_id(), config::nanos(wait))
+            });
+        }
+        MutexGuard {
+            guard,
+            id: self.id,
+            acquired_at,
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex")
+            .field("id", &self.id)
+            .field("inner", &&self.inner)
+            .finish()
+    }
+}
+
+/// A guard which holds the lock of a [`Mutex`], and fires
+/// `tokio*:::mutex-release` when dropped.
+///
+/// Returned by [`Mutex::lock`] and [`Mutex::try_lock`].
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MutexGuard<'a, T: ?Sized> {
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    id: u64,
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    acquired_at: Instant,
+    guard: tokio::sync::MutexGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.guard, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.guard, f)
+    }
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // The lock itself is released when `guard` is dropped, just after
+        // this.
+        if enabled() {
+            fire!(probes::mutex__release, || {
+                let held_nanos = clock::now().saturating_duration_since(self.acquired_at);
+                (self.id, task_id(), config::nanos(held_nanos))
+            });
+        }
+    }
+}
+
+/// Returns `true` if the mutex probes should fire.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+fn enabled() -> bool {
+    crate::ENABLED.load(Ordering::Relaxed) && config::tier_enabled(config::Tier::Normal)
+}
+
+/// Returns the ID of the current task, as passed to the probes.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+fn task_id() -> u64 {
+    current_task::id().map_or(0, config::probe_id)
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    use super::Mutex;
+
+    #[test]
+    fn mutexes_lock() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mutex = std::sync::Arc::new(Mutex::new(0u32));
+        let task = mutex.clone();
+        rt.block_on(async move {
+            tokio::spawn(async move {
+                let mut guard = task.lock().await;
+                *guard += 1;
+                assert!(task.try_lock().is_err());
+            })
+            .await
+            .unwrap()
+        });
+        *mutex.try_lock().unwrap() += 1;
+        assert_ne!(mutex.id(), Mutex::new(()).id());
+        assert_eq!(std::sync::Arc::into_inner(mutex).unwrap().into_inner(), 2);
+    }
+}
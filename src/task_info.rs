@@ -0,0 +1,93 @@
+// Copyright 2025 Oxide Computer Company
+
+use std::fmt;
+use std::time::Instant;
+
+/// Describes a task spawned on a runtime with `tokio-dtrace`'s hooks
+/// registered.
+///
+/// This type is shared by all of `tokio-dtrace`'s APIs which report
+/// information about individual tasks, so that each field has the same meaning
+/// everywhere it appears.
+///
+/// Task information is collected by the task hooks, so it is only available
+/// when the `probes-task` feature is enabled. Within a `TaskInfo`:
+///
+/// - `id`, `file`, `line`, `col`, and `spawned_at` are always populated.
+/// - `poll_count` and `total_poll_nanos` describe the polls of the task that
+///   have *completed*: a poll which is in progress when the `TaskInfo` is
+///   created is counted in `poll_count`, but not in `total_poll_nanos`. Both
+///   are zero if the task has never been polled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TaskInfo {
+    /// The task's [`tokio::task::Id`], as an integer.
+    pub id: u64,
+    /// The source file in which the task was spawned.
+    pub file: &'static str,
+    /// The line number at which the task was spawned.
+    pub line: u32,
+    /// The column at which the task was spawned.
+    pub col: u32,
+    /// When the task was spawned.
+    pub spawned_at: Instant,
+    /// The number of times the task has been polled.
+    pub poll_count: u64,
+    /// The total time spent polling the task, in nanoseconds.
+    pub total_poll_nanos: u64,
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl TaskInfo {
+    #[allow(dead_code)]
+    pub(crate) fn new(id: u64, task: &crate::task_table::TaskState) -> Self {
+        Self {
+            id,
+            file: task.location.file(),
+            line: task.location.line(),
+            col: task.location.column(),
+            spawned_at: task.spawned_at,
+            poll_count: task.poll_count,
+            total_poll_nanos: task.total_poll_nanos,
+        }
+    }
+}
+
+impl fmt::Display for TaskInfo {
+    /// Formats the task as, for example:
+    ///
+    /// ```text
+    /// task 42 (src/main.rs:10:5): 3 polls, 1500ns
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "task {} ({}:{}:{}): {} polls, {}ns",
+            self.id, self.file, self.line, self.col, self.poll_count, self.total_poll_nanos,
+        )
+    }
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    use super::*;
+    use crate::task_table::TaskState;
+
+    #[test]
+    fn display() {
+        let location = std::panic::Location::caller();
+        let mut task = TaskState::new(location, 0, 0);
+        task.poll_count = 3;
+        task.total_poll_nanos = 1500;
+        let info = TaskInfo::new(42, &task);
+        assert_eq!(
+            info.to_string(),
+            format!(
+                "task 42 ({}:{}:{}): 3 polls, 1500ns",
+                file!(),
+                location.line(),
+                location.column()
+            ),
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2025 Oxide Computer Company
+
+//! The kinds of tasks reported by the `task-kind` probe.
+use std::future::Future;
+use tokio::task::JoinHandle;
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+use {
+    crate::config::{self, Tier},
+    crate::{probes, worker},
+    std::cell::Cell,
+    std::sync::atomic::Ordering,
+};
+
+/// Describes what kind of task a task is.
+///
+/// This is emitted as `arg2` of the `tokio*:::task-kind` probe, as the integer
+/// value of the variant. These values are stable, and will not change in
+/// future releases, although new variants may be added.
+///
+/// Tokio only calls its spawn hook for asynchronous tasks spawned on a
+/// runtime, so `task-kind` only fires for the other kinds of task when they
+/// are spawned using `tokio-dtrace`'s wrappers: [`spawn_blocking`] for
+/// blocking tasks, and [`spawn_local`] for local tasks.
+///
+/// [`spawn_blocking`]: crate::spawn_blocking
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum TaskKind {
+    /// An asynchronous task spawned on a runtime, such as by [`tokio::spawn`]
+    /// (`0`).
+    Async = 0,
+    /// A closure run on a runtime's blocking thread pool (`1`).
+    Blocking = 1,
+    /// A future spawned onto the current thread's
+    /// [`LocalSet`](tokio::task::LocalSet) or local runtime (`2`).
+    Local = 2,
+}
+
+impl From<TaskKind> for u8 {
+    fn from(kind: TaskKind) -> Self {
+        kind as u8
+    }
+}
+
+impl TryFrom<u8> for TaskKind {
+    type Error = u8;
+
+    /// Converts the integer value emitted by the `task-kind` probe back into a
+    /// [`TaskKind`], returning the value as an error if it is not recognized.
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            0 => Ok(Self::Async),
+            1 => Ok(Self::Blocking),
+            2 => Ok(Self::Local),
+            _ => Err(value),
+        }
+    }
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+thread_local! {
+    /// The kind of the task being spawned by [`spawn_local`] on this thread,
+    /// taken by the `on_task_spawn` hook.
+    static PENDING: Cell<Option<TaskKind>> = const { Cell::new(None) };
+}
+
+/// Spawns a `!Send` future on the current thread's
+/// [`LocalSet`](tokio::task::LocalSet) or local runtime, as
+/// [`tokio::task::spawn_local`] does, reporting it as a
+/// [`Local`](TaskKind::Local) task.
+///
+/// A `LocalSet` does not call the runtime's hooks, so its tasks fire no task
+/// probes other than `task-kind`, which fires when this returns. A task
+/// spawned on a local runtime fires the usual task probes, with `task-kind`
+/// firing immediately after `task-spawn`. The kind is only reported when the
+/// `probes-task` feature is enabled and the hooks are registered; otherwise,
+/// this behaves exactly like `tokio::task::spawn_local`.
+///
+/// # Panics
+///
+/// Panics if called from outside a `LocalSet` or local runtime, as
+/// [`tokio::task::spawn_local`] does.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::rc::Rc;
+///
+/// # async fn example() {
+/// let local = tokio::task::LocalSet::new();
+/// local
+///     .run_until(async {
+///         let shared = Rc::new(1);
+///         tokio_dtrace::spawn_local(async move { *shared + 1 })
+///             .await
+///             .unwrap()
+///     })
+///     .await;
+/// # }
+/// ```
+#[track_caller]
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    {
+        PENDING.set(Some(TaskKind::Local));
+        let handle = tokio::task::spawn_local(future);
+        // If the kind was not taken, the spawn hook was not called, as is the
+        // case for every task spawned on a `LocalSet`.
+        if PENDING.take().is_some() {
+            spawned(handle.id(), TaskKind::Local);
+        }
+        handle
+    }
+    #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+    {
+        tokio::task::spawn_local(future)
+    }
+}
+
+/// Takes the kind of the task being spawned on this thread, which is
+/// [`Async`](TaskKind::Async) unless it is being spawned by [`spawn_local`].
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn take_pending() -> TaskKind {
+    PENDING.take().unwrap_or(TaskKind::Async)
+}
+
+/// Fires `task-kind` for a task whose spawn the hooks did not see.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn spawned(id: tokio::task::Id, kind: TaskKind) {
+    if crate::ENABLED.load(Ordering::Relaxed) && config::tier_enabled(Tier::Minimal) {
+        fire!(probes::task__kind, || {
+            let id = config::probe_id(crate::hooks::id_to_u64(id));
+            (worker::runtime(), id, u8::from(kind))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TaskKind;
+
+    #[test]
+    fn kinds_round_trip() {
+        for kind in [TaskKind::Async, TaskKind::Blocking, TaskKind::Local] {
+            assert_eq!(TaskKind::try_from(u8::from(kind)), Ok(kind));
+        }
+        assert_eq!(TaskKind::try_from(3), Err(3));
+    }
+}
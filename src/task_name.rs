@@ -0,0 +1,100 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Human-readable task names.
+use std::future::Future;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+thread_local! {
+    /// The name of the task being spawned by [`spawn_named`] on this thread,
+    /// taken by the `on_task_spawn` hook.
+    static PENDING: std::cell::RefCell<Option<Arc<str>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Spawns a new task named `name`, as [`tokio::spawn`] does.
+///
+/// Task IDs and spawn locations do not always identify a task: in a large
+/// program, many tasks may be spawned from the same generic helper, and a
+/// long-lived server's tasks are more easily recognized by what they are for,
+/// such as `"conn 10.0.0.1:443"`. The name is stored with `tokio-dtrace`'s
+/// state for the task, and reported by the `tokio*:::task-name` probe, which
+/// fires immediately after each of `task-spawn`, `task-poll-start`, and
+/// `task-terminate` for a named task. `arg0` is the ID of the task's runtime,
+/// `arg1` is the [task `Id`](tokio::task::Id), and `arg2` is the name. The name is a separate
+/// probe, rather than an argument to the others, because USDT probes may take
+/// at most six arguments.
+///
+/// The task's spawn location is that of the call to `spawn_named`, and its
+/// future is wrapped using [`trace_outcome`](crate::trace_outcome), so that
+/// `task-terminate` reports how it terminated. Naming a task costs an atomic
+/// reference count update each time it is polled, while its `task-poll-start`
+/// probe is enabled. Names are only recorded for tasks spawned on a runtime
+/// with `tokio-dtrace`'s hooks registered, and when the `probes-task` feature
+/// is enabled; otherwise, this behaves exactly like `tokio::spawn`.
+///
+/// # Panics
+///
+/// Panics if called from outside a Tokio runtime, as [`tokio::spawn`] does.
+///
+/// # Examples
+///
+/// ```rust
+/// # async fn handle(_: u32) {}
+/// # async fn example() {
+/// for conn in 0..3 {
+///     tokio_dtrace::spawn_named(format!("conn {conn}"), handle(conn));
+/// }
+/// # }
+/// ```
+#[track_caller]
+pub fn spawn_named<F>(name: impl Into<Arc<str>>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    {
+        PENDING.set(Some(name.into()));
+        let handle = tokio::spawn(crate::trace_outcome(future));
+        // If the hooks are not registered, the name was not taken.
+        PENDING.take();
+        handle
+    }
+    #[cfg(not(all(tokio_unstable, feature = "probes-task")))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Takes the name of the task being spawned on this thread, if it is being
+/// spawned by [`spawn_named`].
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+pub(crate) fn take_pending() -> Option<Arc<str>> {
+    PENDING.take()
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    #[test]
+    fn names_are_stored() {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = crate::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let name = rt.block_on(async {
+            super::spawn_named("worker", async {
+                let id = crate::current_task::id().unwrap();
+                crate::task_table::TASKS.with_polling(id, |task| task.name.clone())
+            })
+            .await
+            .unwrap()
+        });
+        assert_eq!(name.flatten().as_deref(), Some("worker"));
+        // The name was taken when the task was spawned.
+        assert_eq!(super::take_pending(), None);
+    }
+}
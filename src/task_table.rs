@@ -0,0 +1,605 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Per-task bookkeeping shared between runtime hooks.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::Location;
+#[cfg(feature = "debug-contention")]
+use std::sync::TryLockError;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{RegistrationError, TaskInfo, config};
+
+/// The state of every live task spawned on a runtime with `tokio-dtrace`'s
+/// hooks registered, kept in the configured [`TaskStore`].
+pub(crate) static TASKS: Tasks = Tasks;
+
+/// The default [`TaskStore`], created the first time a task is tracked if no
+/// other store was chosen.
+///
+/// The number of shards is set by [`Config::task_table_shards`]. Task IDs are
+/// allocated sequentially, so tasks spawned around the same time are spread
+/// evenly across the shards.
+///
+/// [`Config::task_table_shards`]: crate::Config::task_table_shards
+static DEFAULT_STORE: OnceLock<TaskTable<TaskState>> = OnceLock::new();
+
+/// The store in use, chosen by [`set_store`] or the first time a task is
+/// tracked.
+static STORE: OnceLock<&'static dyn TaskStore> = OnceLock::new();
+
+thread_local! {
+    /// The state of each task being polled on this thread, taken from a store
+    /// other than the default one, which the hooks hold from the start of its
+    /// poll until the end, so that the task's own calls (such as
+    /// [`crate::set_trace_id`]) can update it without calling the store.
+    ///
+    /// A task may poll other tasks within its own poll, such as by driving a
+    /// [`LocalSet`](tokio::task::LocalSet), so the innermost poll is last.
+    static POLLING: RefCell<Vec<(u64, TaskState)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Storage for `tokio-dtrace`'s per-task state, which may be replaced using
+/// [`Config::task_store`](crate::Config::task_store).
+///
+/// The runtime hooks keep a [`TaskState`] for each live task, keyed by its
+/// [task ID](crate::hooks#requirements-for-custom-integrations), and call the
+/// store at each point in the task's lifecycle:
+///
+/// 1. [`on_spawn`](Self::on_spawn) passes the store the state of a newly
+///    spawned task.
+/// 2. [`on_poll_start`](Self::on_poll_start) takes the task's state back from
+///    the store as a poll of the task starts, and
+///    [`on_poll_end`](Self::on_poll_end) returns it, updated, once the poll
+///    ends. While the task is being polled, its state is held by the hooks
+///    rather than the store.
+/// 3. [`on_terminate`](Self::on_terminate) removes the task's state when it
+///    terminates, returning it as a [`TaskSummary`].
+///
+/// By default, this state is kept in a map which is sharded into
+/// independently-locked parts (see
+/// [`Config::task_table_shards`](crate::Config::task_table_shards)), which
+/// suits most workloads. The hooks update the default map's state in place,
+/// rather than taking each polled task's state out of it. Implementing this trait allows a different structure
+/// to be used instead, such as a lock-free map, or a bounded one which evicts
+/// the oldest tasks when full. A store which never stores anything is also
+/// valid: the hooks treat every task as one which was spawned before they were
+/// registered, and report the information derived from its state as missing.
+///
+/// The contents of a [`TaskState`] are private to `tokio-dtrace`: the store
+/// only decides where it is kept.
+///
+/// # Thread Safety
+///
+/// The store's methods are called by the runtime hooks on every worker thread
+/// of every runtime with the hooks registered, so they are called
+/// concurrently, for different task IDs. A task is only polled on one thread
+/// at a time, so the calls for a single task ID are made in the order above,
+/// but successive calls may be made from different threads. Each method must
+/// complete its operation atomically with respect to the others. Methods are
+/// called on the hot path of every spawn and poll, so they should not block
+/// for long.
+pub trait TaskStore: Send + Sync + fmt::Debug {
+    /// Stores the state of a newly spawned task, replacing any state already
+    /// stored for its ID.
+    fn on_spawn(&self, id: u64, state: TaskState);
+
+    /// Removes and returns the state of the task with the given ID, if it is
+    /// stored, as a poll of the task starts.
+    fn on_poll_start(&self, id: u64) -> Option<TaskState>;
+
+    /// Stores the state of the task with the given ID once its poll has
+    /// ended. This is only called with state returned by
+    /// [`on_poll_start`](Self::on_poll_start).
+    fn on_poll_end(&self, id: u64, state: TaskState);
+
+    /// Removes the state of the task with the given ID as it terminates,
+    /// returning it as a [`TaskSummary`] if it is stored.
+    ///
+    /// A task which completes terminates during its final poll, while its
+    /// state is held by the hooks, so this is called, and may return `None`,
+    /// even for a task whose state was stored.
+    fn on_terminate(&self, id: u64) -> Option<TaskSummary>;
+
+    /// Removes and returns the state of every stored task. This is called by
+    /// [`teardown`](crate::teardown).
+    fn drain(&self) -> Vec<(u64, TaskState)>;
+}
+
+/// Set in the `task-terminate` probe's flags if the task was never polled.
+pub(crate) const TERMINATE_NEVER_POLLED: u8 = 1 << 0;
+
+/// Set in the `task-terminate` probe's flags if the task completed during its
+/// first poll.
+pub(crate) const TERMINATE_SINGLE_POLL: u8 = 1 << 1;
+
+/// Set in the `task-terminate` probe's flags if the task was not seen being
+/// spawned, so that the other flags could not be determined.
+pub(crate) const TERMINATE_UNTRACKED: u8 = 1 << 2;
+
+/// Provides access to the [`TaskStore`] in use.
+pub(crate) struct Tasks;
+
+/// A map of task IDs to per-task state, sharded to reduce lock contention
+/// between worker threads.
+pub(crate) struct TaskTable<T> {
+    shards: Box<[Mutex<HashMap<u64, T>>]>,
+}
+
+/// The state `tokio-dtrace` tracks for each live task, kept in a
+/// [`TaskStore`].
+///
+/// The contents of this type are private to `tokio-dtrace`, and may change
+/// between releases.
+#[derive(Debug)]
+pub struct TaskState {
+    /// When the task was spawned.
+    pub(crate) spawned_at: Instant,
+    /// Where the task was spawned.
+    pub(crate) location: &'static Location<'static>,
+    /// The worker index of the thread that spawned the task.
+    pub(crate) spawner_worker: u64,
+    /// The number of times the task has been polled.
+    pub(crate) poll_count: u64,
+    /// The total time spent polling the task, in nanoseconds.
+    pub(crate) total_poll_nanos: u64,
+    /// When the task's most recent completed poll ended.
+    pub(crate) last_poll_end: Option<Instant>,
+    /// Whether the task's most recent completed poll was slow, as used by
+    /// [`Config::changepoint_mode`](crate::Config::changepoint_mode).
+    pub(crate) last_poll_slow: bool,
+    /// The task's trace ID, set by [`crate::set_trace_id`].
+    pub(crate) trace_id: u128,
+    /// The ID of the task's scope, entered using [`crate::scope`], or 0 if it
+    /// is not in one.
+    pub(crate) scope: u32,
+    /// The task's deadline, relative to when it was spawned, set by
+    /// [`crate::set_task_deadline`].
+    pub(crate) deadline: Option<Duration>,
+    /// The task's name, if it was spawned by [`crate::spawn_named`].
+    pub(crate) name: Option<Arc<str>>,
+    /// The counters for the task's spawn location, if
+    /// [`Config::location_stats`](crate::Config::location_stats) is enabled.
+    #[cfg(feature = "probes-metrics")]
+    pub(crate) location_stats: Option<&'static crate::locations::LocationStats>,
+    /// The metrics for the task's spawn location, which its polls are
+    /// recorded in, when the `metrics` feature is enabled.
+    #[cfg(feature = "metrics")]
+    pub(crate) location_metrics: Option<&'static crate::metrics_rs::LocationMetrics>,
+    /// The task's OpenTelemetry span, started when it is first polled.
+    #[cfg(feature = "otel")]
+    pub(crate) span: crate::otel::TaskSpan,
+    /// Counts the task in the `tokio.task.active` OpenTelemetry instrument
+    /// until its state is dropped, whether because it terminated or because
+    /// the store discarded it.
+    #[cfg(feature = "otel")]
+    #[allow(dead_code)]
+    pub(crate) otel_active: crate::otel::Active,
+}
+
+/// The state of a task which has terminated, returned by
+/// [`TaskStore::on_terminate`].
+#[derive(Debug)]
+pub struct TaskSummary {
+    id: u64,
+    state: TaskState,
+}
+
+/// A change in a task's behavior, reported by the `task-changepoint` probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Changepoint {
+    /// The task completed its first poll.
+    FirstPoll = 0,
+    /// The task completed a slow poll, after a fast one.
+    Slow = 1,
+    /// The task completed a fast poll, after a slow one.
+    Fast = 2,
+}
+
+/// Makes `store` the [`TaskStore`] used by the hooks, failing if a different
+/// store has already been chosen, by an earlier call or because a task has
+/// already been tracked using the default store.
+pub(crate) fn set_store(store: &'static dyn TaskStore) -> Result<(), RegistrationError> {
+    let chosen = *STORE.get_or_init(|| store);
+    if std::ptr::addr_eq(chosen, store) {
+        Ok(())
+    } else {
+        Err(RegistrationError::TaskStoreAlreadyChosen)
+    }
+}
+
+impl Tasks {
+    fn store(&self) -> &'static dyn TaskStore {
+        *STORE.get_or_init(|| {
+            DEFAULT_STORE.get_or_init(|| TaskTable::new(config::task_table_shards()))
+        })
+    }
+
+    /// Returns the default store, if it is the store in use.
+    fn default_store(&self) -> Option<&'static TaskTable<TaskState>> {
+        let store = self.store();
+        DEFAULT_STORE
+            .get()
+            .filter(|table| std::ptr::addr_eq(store, *table))
+    }
+
+    pub(crate) fn insert(&self, id: u64, state: TaskState) {
+        self.store().on_spawn(id, state);
+    }
+
+    /// Calls `f` with the state of the task with the given ID as its poll
+    /// starts, if it is present, taking the state from the store until the
+    /// poll ends unless the store is the default one.
+    pub(crate) fn poll_start<R>(&self, id: u64, f: impl FnOnce(&mut TaskState) -> R) -> Option<R> {
+        if let Some(table) = self.default_store() {
+            return table.with(id, f);
+        }
+        let mut state = self.store().on_poll_start(id)?;
+        let result = f(&mut state);
+        POLLING.with_borrow_mut(|polling| polling.push((id, state)));
+        Some(result)
+    }
+
+    /// Calls `f` with the state of the task with the given ID, which is being
+    /// polled on this thread, if it is present.
+    pub(crate) fn with_polling<R>(
+        &self,
+        id: u64,
+        f: impl FnOnce(&mut TaskState) -> R,
+    ) -> Option<R> {
+        if let Some(table) = self.default_store() {
+            return table.with(id, f);
+        }
+        POLLING.with_borrow_mut(|polling| {
+            let (_, state) = polling.iter_mut().rfind(|(polling, _)| *polling == id)?;
+            Some(f(state))
+        })
+    }
+
+    /// Calls `f` with the state of the task with the given ID as its poll
+    /// ends, if it is present, and returns the state to the store.
+    pub(crate) fn poll_end<R>(&self, id: u64, f: impl FnOnce(&mut TaskState) -> R) -> Option<R> {
+        if let Some(table) = self.default_store() {
+            return table.with(id, f);
+        }
+        let mut ended = POLLING.with_borrow_mut(|polling| {
+            let index = polling.iter().rposition(|(polling, _)| *polling == id)?;
+            Some(polling.split_off(index))
+        })?;
+        // Polls nested within this one which did not end, such as ones which
+        // unwound through the hooks, leave their tasks' state behind, so it
+        // is returned too.
+        for (id, state) in ended.drain(1..) {
+            self.store().on_poll_end(id, state);
+        }
+        let (_, mut state) = ended.pop()?;
+        let result = f(&mut state);
+        self.store().on_poll_end(id, state);
+        Some(result)
+    }
+
+    /// Removes and returns the state of the task with the given ID as it
+    /// terminates, from this thread if it terminated during its poll, or
+    /// otherwise from the store.
+    pub(crate) fn terminate(&self, id: u64) -> Option<TaskState> {
+        let polling = POLLING.with_borrow_mut(|polling| {
+            let index = polling.iter().rposition(|(polling, _)| *polling == id)?;
+            Some(polling.remove(index).1)
+        });
+        let stored = self.store().on_terminate(id).map(|summary| summary.state);
+        stored.or(polling)
+    }
+
+    /// Removes every task from the store, returning how many were removed.
+    pub(crate) fn clear(&self) -> usize {
+        self.drain().len()
+    }
+
+    /// Removes and returns every task in the store.
+    pub(crate) fn drain(&self) -> Vec<(u64, TaskState)> {
+        self.store().drain()
+    }
+}
+
+impl TaskStore for TaskTable<TaskState> {
+    fn on_spawn(&self, id: u64, state: TaskState) {
+        self.insert(id, state);
+    }
+
+    fn on_poll_start(&self, id: u64) -> Option<TaskState> {
+        self.remove(id)
+    }
+
+    fn on_poll_end(&self, id: u64, state: TaskState) {
+        self.insert(id, state);
+    }
+
+    fn on_terminate(&self, id: u64) -> Option<TaskSummary> {
+        self.remove(id).map(|state| TaskSummary::new(id, state))
+    }
+
+    fn drain(&self) -> Vec<(u64, TaskState)> {
+        TaskTable::drain(self)
+    }
+}
+
+impl TaskSummary {
+    /// Returns the summary of the task with the given ID, which has
+    /// terminated with the given state.
+    pub fn new(id: u64, state: TaskState) -> Self {
+        Self { id, state }
+    }
+
+    /// Returns information about the task, such as where it was spawned and
+    /// how many times it was polled.
+    pub fn info(&self) -> TaskInfo {
+        TaskInfo::new(self.id, &self.state)
+    }
+}
+
+impl<T> fmt::Debug for TaskTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskTable")
+            .field("shards", &self.shards.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> TaskTable<T> {
+    pub(crate) fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    pub(crate) fn insert(&self, id: u64, state: T) {
+        self.shard(id).insert(id, state);
+    }
+
+    /// Calls `f` with the state for the task with the given ID, if it is
+    /// present in the table.
+    pub(crate) fn with<R>(&self, id: u64, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.shard(id).get_mut(&id).map(f)
+    }
+
+    pub(crate) fn remove(&self, id: u64) -> Option<T> {
+        self.shard(id).remove(&id)
+    }
+
+    /// Removes every entry for which `f` returns `false`.
+    #[cfg_attr(not(feature = "debug-completion"), allow(dead_code))]
+    pub(crate) fn retain(&self, mut f: impl FnMut(u64, &mut T) -> bool) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            shard.retain(|&id, state| f(id, state));
+        }
+    }
+
+    /// Removes every entry from the table, returning how many were removed.
+    #[cfg_attr(not(any(test, feature = "debug-completion")), allow(dead_code))]
+    pub(crate) fn clear(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+                let len = shard.len();
+                shard.clear();
+                len
+            })
+            .sum()
+    }
+
+    /// Removes and returns every entry in the table.
+    pub(crate) fn drain(&self) -> Vec<(u64, T)> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+                shard.drain().collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn shard(&self, id: u64) -> MutexGuard<'_, HashMap<u64, T>> {
+        let shard = &self.shards[id as usize % self.shards.len()];
+        // A panic while holding the lock cannot leave a map in an invalid
+        // state, so a poisoned shard is safe to keep using.
+        #[cfg(feature = "debug-contention")]
+        match shard.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => lock_contended(shard),
+        }
+        #[cfg(not(feature = "debug-contention"))]
+        shard.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Locks a shard which is already locked by another thread, firing the
+/// `task-table-contended` probe if waiting for the lock took longer than the
+/// configured threshold.
+#[cfg(feature = "debug-contention")]
+#[cold]
+fn lock_contended<T>(shard: &Mutex<T>) -> MutexGuard<'_, T> {
+    let start = Instant::now();
+    let guard = shard.lock().unwrap_or_else(|e| e.into_inner());
+    let nanos = config::nanos(start.elapsed());
+    if nanos > config::contention_threshold_nanos() && config::tier_enabled(crate::Tier::Verbose) {
+        crate::probes::task__table__contended!(|| nanos);
+    }
+    guard
+}
+
+impl TaskState {
+    pub(crate) fn new(
+        location: &'static Location<'static>,
+        spawner_worker: u64,
+        trace_id: u128,
+    ) -> Self {
+        Self {
+            spawned_at: crate::clock::now(),
+            location,
+            spawner_worker,
+            poll_count: 0,
+            total_poll_nanos: 0,
+            last_poll_end: None,
+            last_poll_slow: false,
+            trace_id,
+            scope: 0,
+            deadline: None,
+            name: None,
+            #[cfg(feature = "probes-metrics")]
+            location_stats: config::location_stats().then(|| crate::locations::intern(location)),
+            #[cfg(feature = "metrics")]
+            location_metrics: None,
+            #[cfg(feature = "otel")]
+            span: Default::default(),
+            #[cfg(feature = "otel")]
+            otel_active: Default::default(),
+        }
+    }
+
+    /// The number of times the task has been rescheduled: every poll after
+    /// the first, including one that is in progress.
+    pub(crate) fn reschedules(&self) -> u64 {
+        self.poll_count.saturating_sub(1)
+    }
+
+    /// Records whether the task's poll which just completed was slow,
+    /// returning the transition it represents, if any, as reported by the
+    /// `task-changepoint` probe.
+    pub(crate) fn changepoint(&mut self, slow: bool) -> Option<Changepoint> {
+        let was_slow = std::mem::replace(&mut self.last_poll_slow, slow);
+        match (self.poll_count, was_slow, slow) {
+            (1, _, _) => Some(Changepoint::FirstPoll),
+            (_, false, true) => Some(Changepoint::Slow),
+            (_, true, false) => Some(Changepoint::Fast),
+            _ => None,
+        }
+    }
+
+    /// Returns the flags reported by the `task-terminate` probe for the task,
+    /// given whether it terminated by completing during a poll.
+    pub(crate) fn terminate_flags(&self, completed: bool) -> u8 {
+        match self.poll_count {
+            0 => TERMINATE_NEVER_POLLED,
+            1 if completed => TERMINATE_SINGLE_POLL,
+            _ => 0,
+        }
+    }
+
+    /// If the task has completed a poll, returns the time from the end of its
+    /// most recent poll to `now`.
+    pub(crate) fn since_last_poll(&self, now: Instant) -> Option<Duration> {
+        Some(now.saturating_duration_since(self.last_poll_end?))
+    }
+
+    /// If the task has a deadline, and has been alive for longer than it at
+    /// `now`, returns by how much the deadline was missed.
+    pub(crate) fn deadline_missed_by(&self, now: Instant) -> Option<Duration> {
+        let lifetime = now.saturating_duration_since(self.spawned_at);
+        lifetime
+            .checked_sub(self.deadline?)
+            .filter(|over| !over.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHARDS: usize = 4;
+
+    #[test]
+    fn insert_with_remove() {
+        let table = TaskTable::new(SHARDS);
+        table.insert(1, TaskState::new(Location::caller(), 0, 0));
+        table.insert(1 + SHARDS as u64, TaskState::new(Location::caller(), 1, 0));
+
+        assert_eq!(table.with(1, |task| task.spawner_worker), Some(0));
+        assert_eq!(
+            table.with(1 + SHARDS as u64, |task| task.spawner_worker),
+            Some(1)
+        );
+        assert!(table.with(2, |task| task.spawner_worker).is_none());
+
+        assert!(table.remove(1).is_some());
+        assert!(table.remove(1).is_none());
+        assert!(table.with(1, |task| task.poll_count).is_none());
+
+        table.insert(2, TaskState::new(Location::caller(), 2, 0));
+        let mut drained: Vec<_> = table.drain().into_iter().map(|(id, _)| id).collect();
+        drained.sort();
+        assert_eq!(drained, [2, 1 + SHARDS as u64]);
+        assert_eq!(table.clear(), 0);
+    }
+
+    #[test]
+    fn since_last_poll() {
+        let mut task = TaskState::new(Location::caller(), 0, 0);
+        let now = task.spawned_at + Duration::from_millis(15);
+        assert_eq!(task.since_last_poll(now), None);
+
+        task.last_poll_end = Some(task.spawned_at + Duration::from_millis(10));
+        assert_eq!(task.since_last_poll(now), Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn deadline_missed_by() {
+        let mut task = TaskState::new(Location::caller(), 0, 0);
+        let late = task.spawned_at + Duration::from_millis(15);
+        assert_eq!(task.deadline_missed_by(late), None);
+
+        task.deadline = Some(Duration::from_millis(10));
+        assert_eq!(
+            task.deadline_missed_by(late),
+            Some(Duration::from_millis(5))
+        );
+        assert_eq!(
+            task.deadline_missed_by(task.spawned_at + Duration::from_millis(10)),
+            None
+        );
+        assert_eq!(task.deadline_missed_by(task.spawned_at), None);
+    }
+
+    #[test]
+    fn reschedules_exclude_first_poll() {
+        let mut task = TaskState::new(Location::caller(), 0, 0);
+        assert_eq!(task.reschedules(), 0);
+        task.poll_count = 1;
+        assert_eq!(task.reschedules(), 0);
+        task.poll_count = 4;
+        assert_eq!(task.reschedules(), 3);
+    }
+
+    #[test]
+    fn terminate_flags() {
+        let mut task = TaskState::new(Location::caller(), 0, 0);
+        assert_eq!(task.terminate_flags(false), TERMINATE_NEVER_POLLED);
+        task.poll_count = 1;
+        assert_eq!(task.terminate_flags(true), TERMINATE_SINGLE_POLL);
+        // A task which returned pending from its only poll, and was then
+        // cancelled, was not fire-and-forget.
+        assert_eq!(task.terminate_flags(false), 0);
+        task.poll_count = 2;
+        assert_eq!(task.terminate_flags(true), 0);
+    }
+
+    #[test]
+    fn changepoints_are_transitions() {
+        let mut task = TaskState::new(Location::caller(), 0, 0);
+        let mut poll = |slow| {
+            task.poll_count += 1;
+            task.changepoint(slow)
+        };
+        assert_eq!(poll(false), Some(Changepoint::FirstPoll));
+        assert_eq!(poll(false), None);
+        assert_eq!(poll(true), Some(Changepoint::Slow));
+        assert_eq!(poll(true), None);
+        assert_eq!(poll(false), Some(Changepoint::Fast));
+        assert_eq!(poll(false), None);
+    }
+}
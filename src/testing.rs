@@ -0,0 +1,217 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Helpers for testing code which uses `tokio-dtrace`, without DTrace.
+//!
+//! Most of the work done by `tokio-dtrace`'s hooks, such as mapping task IDs
+//! and recording which probes are [enabled](crate::enabled_probes), happens
+//! while constructing the arguments of a probe, which USDT only does when a
+//! DTrace consumer has enabled it. [`force_enabled`] makes the hooks behave as
+//! if every probe were enabled, so that this logic can be exercised by tests
+//! and demonstrations without a live consumer. [`capture`] does the same, and
+//! also returns the probes which fired, with their arguments, so that tests
+//! can check what a DTrace consumer would have seen.
+//!
+//! This module is only available with the `testing` feature flag, which is
+//! intended for the crate's own tests, and for testing integrations with it
+//! (such as a [consumer change callback](crate::Config::on_consumer_change)).
+//! It should not be enabled in production builds.
+use std::borrow::Cow;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of calls to [`force_enabled`] in progress.
+static FORCED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of calls to [`capture`] in progress.
+static CAPTURING: AtomicUsize = AtomicUsize::new(0);
+
+/// The probes fired since the outermost call to [`capture`] in progress began.
+static FIRED: Mutex<Vec<Fired>> = Mutex::new(Vec::new());
+
+/// A probe fired while [`capture`] was in progress.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fired {
+    /// The probe's name, as DTrace spells it; for example, `task-spawn`.
+    pub probe: String,
+    /// The probe's arguments, from `arg0` onwards.
+    pub args: Vec<Arg>,
+}
+
+/// An argument of a [`Fired`] probe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Arg {
+    /// An integer argument. Signed integers are recorded as the bits of their
+    /// two's complement representation, as DTrace receives them.
+    Int(u64),
+    /// A string argument.
+    Str(String),
+}
+
+macro_rules! int_args {
+    ($($ty:ty),+) => {$(
+        impl From<$ty> for Arg {
+            fn from(value: $ty) -> Self {
+                Self::Int(value as u64)
+            }
+        }
+    )+};
+}
+
+int_args!(u8, u32, u64, i64);
+
+impl From<&str> for Arg {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_string())
+    }
+}
+
+impl From<String> for Arg {
+    fn from(value: String) -> Self {
+        Self::Str(value)
+    }
+}
+
+impl From<Cow<'_, str>> for Arg {
+    fn from(value: Cow<'_, str>) -> Self {
+        Self::Str(value.into_owned())
+    }
+}
+
+/// The value returned by a probe's argument closure, which may be a single
+/// integer, or a tuple of arguments.
+pub(crate) trait Args {
+    fn into_args(self) -> Vec<Arg>;
+}
+
+impl Args for u64 {
+    fn into_args(self) -> Vec<Arg> {
+        vec![Arg::Int(self)]
+    }
+}
+
+macro_rules! tuple_args {
+    ($($name:ident)*) => {
+        impl<$($name: Into<Arg>),*> Args for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Arg> {
+                let ($($name,)*) = self;
+                vec![$($name.into()),*]
+            }
+        }
+    };
+}
+
+tuple_args!();
+tuple_args!(A);
+tuple_args!(A B);
+tuple_args!(A B C);
+tuple_args!(A B C D);
+tuple_args!(A B C D E);
+tuple_args!(A B C D E F);
+
+/// Calls `f`, with the probes fired by `tokio-dtrace`'s runtime hooks behaving
+/// as if DTrace had enabled them, and returns its result.
+///
+/// While `f` runs, the hooks construct the arguments of every probe they
+/// fire, and record the probe as enabled, regardless of whether a DTrace
+/// consumer is attached. This applies to hooks called on *any* thread, such as
+/// the worker threads of a runtime used by `f`, not only to the thread calling
+/// `f`. It does not cause the probes to be fired if DTrace has not enabled
+/// them, and does not apply to probes fired outside the runtime hooks and
+/// `tokio-dtrace`'s instrumented types, such as those fired by the
+/// [metrics sampler](crate::metrics).
+///
+/// Calls may be nested, and probes are forced until the outermost call
+/// returns, or unwinds.
+pub fn force_enabled<R>(f: impl FnOnce() -> R) -> R {
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            FORCED.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    FORCED.fetch_add(1, Ordering::Relaxed);
+    let _guard = Guard;
+    f()
+}
+
+/// Calls `f` as [`force_enabled`] does, and returns its result along with the
+/// probes fired while it ran, in the order in which they fired.
+///
+/// Probes are captured from every thread, so if other code is firing probes
+/// concurrently, such as another test in the same binary, its probes are
+/// captured too; tests should pick out those for the tasks, threads, or
+/// objects they created. As with [`force_enabled`], probes fired outside the
+/// runtime hooks and `tokio-dtrace`'s instrumented types, such as those fired
+/// by the [metrics sampler](crate::metrics), are not captured.
+///
+/// # Examples
+///
+/// ```rust
+/// use tokio_dtrace::testing::{self, Arg};
+///
+/// let (_, fired) = testing::capture(|| {
+///     // Fire some probes
+/// });
+/// for probe in fired.iter().filter(|probe| probe.probe == "task-spawn") {
+///     assert!(matches!(probe.args[0], Arg::Int(_)));
+/// }
+/// ```
+pub fn capture<R>(f: impl FnOnce() -> R) -> (R, Vec<Fired>) {
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let mut fired = FIRED.lock().unwrap_or_else(|e| e.into_inner());
+            if CAPTURING.fetch_sub(1, Ordering::Relaxed) == 1 {
+                fired.clear();
+            }
+        }
+    }
+
+    let start = {
+        let fired = FIRED.lock().unwrap_or_else(|e| e.into_inner());
+        CAPTURING.fetch_add(1, Ordering::Relaxed);
+        fired.len()
+    };
+    let _guard = Guard;
+    let result = force_enabled(f);
+    let fired = FIRED.lock().unwrap_or_else(|e| e.into_inner())[start..].to_vec();
+    (result, fired)
+}
+
+/// Records that `probe`, as named by the path passed to `fire!`, fired with
+/// `args`, if a call to [`capture`] is in progress.
+#[cfg_attr(
+    not(all(
+        tokio_unstable,
+        any(feature = "probes-task", feature = "probes-worker")
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn record(probe: &str, args: impl Args) {
+    if CAPTURING.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let name = probe.rsplit("::").next().unwrap_or(probe).trim();
+    let fired = Fired {
+        probe: name.replace("__", "-"),
+        args: args.into_args(),
+    };
+    FIRED.lock().unwrap_or_else(|e| e.into_inner()).push(fired);
+}
+
+/// Returns whether a call to [`force_enabled`] is in progress.
+#[cfg_attr(
+    not(all(
+        tokio_unstable,
+        any(feature = "probes-task", feature = "probes-worker")
+    )),
+    allow(dead_code)
+)]
+#[inline]
+pub(crate) fn forced() -> bool {
+    FORCED.load(Ordering::Relaxed) != 0
+}
@@ -0,0 +1,153 @@
+// Copyright 2025 Oxide Computer Company
+
+//! A [`tracing_subscriber::Layer`] which fires probes for `tracing` spans and
+//! events.
+//!
+//! Applications which are already instrumented with [`tracing`] can add a
+//! [`DtraceLayer`] to their subscriber, so that their spans and events can be
+//! traced alongside the runtime probes, in a single D script. The layer fires
+//! the following probes:
+//!
+//! - `tokio*:::span-enter` and `tokio*:::span-exit` when a span is entered
+//!   and exited. `arg0` is the span's ID, `arg1` and `arg2` are its name and
+//!   target, and `arg3` is the [task `Id`](tokio::task::Id) of the task being
+//!   polled on the current thread, or 0 if the span was entered outside of a
+//!   task.
+//! - `tokio*:::event` when an event is recorded. `arg0` and `arg1` are the
+//!   event's name and target, `arg2` is its level (1 for `ERROR`, 2 for
+//!   `WARN`, 3 for `INFO`, 4 for `DEBUG`, and 5 for `TRACE`), and `arg3` is
+//!   the task `Id`, as for the span probes.
+//!
+//! The span IDs are the same IDs reported by `task-poll-span`, so a script can
+//! join a task's polls with the spans it enters:
+//!
+//! ```d
+//! tokio*:::span-enter
+//! /arg3 != 0/
+//! {
+//!     printf("task %d entered %s\n", arg3, copyinstr(arg1));
+//! }
+//! ```
+//!
+//! The task `Id` is only known for tasks running on a runtime with
+//! `tokio-dtrace`'s hooks registered. Like the poll probes, these probes belong
+//! to the [`Normal`](crate::Tier::Normal) tier, and their arguments are only
+//! gathered when DTrace has enabled them, and they stop firing once
+//! [`teardown`](crate::teardown) has disabled the runtime hooks. The layer does
+//! not filter spans or events itself; use a
+//! [filter](tracing_subscriber::layer::Filter) to limit which ones it
+//! observes.
+//!
+//! ```rust
+//! use tracing_subscriber::prelude::*;
+//!
+//! tracing_subscriber::registry()
+//!     .with(tokio_dtrace::tracing::DtraceLayer::new())
+//!     .init();
+//! ```
+use crate::probes;
+use ::tracing::span::Id;
+use ::tracing::{Event, Level, Subscriber};
+#[cfg(tokio_unstable)]
+use std::sync::atomic::Ordering;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// A [`Layer`] which fires `tokio-dtrace`'s `span-enter`, `span-exit`, and
+/// `event` probes. See [the module documentation](self) for details.
+#[derive(Clone, Debug, Default)]
+pub struct DtraceLayer {
+    _priv: (),
+}
+
+impl DtraceLayer {
+    /// Returns a new `DtraceLayer`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> Layer<S> for DtraceLayer
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if !enabled() {
+            return;
+        }
+        fire!(probes::span__enter, || {
+            let (name, target) = name_and_target(id, &ctx);
+            (id.into_u64(), name, target, task_id())
+        });
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if !enabled() {
+            return;
+        }
+        fire!(probes::span__exit, || {
+            let (name, target) = name_and_target(id, &ctx);
+            (id.into_u64(), name, target, task_id())
+        });
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if !enabled() {
+            return;
+        }
+        fire!(probes::event, || {
+            let metadata = event.metadata();
+            (
+                metadata.name(),
+                metadata.target(),
+                level(metadata.level()),
+                task_id(),
+            )
+        });
+    }
+}
+
+/// Returns the name and target of the span `id`, or empty strings if it has
+/// been closed.
+fn name_and_target<S>(id: &Id, ctx: &Context<'_, S>) -> (&'static str, &'static str)
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    ctx.metadata(id)
+        .map_or(("", ""), |metadata| (metadata.name(), metadata.target()))
+}
+
+/// Returns the value of `level` passed to the `event` probe.
+fn level(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 1,
+        Level::WARN => 2,
+        Level::INFO => 3,
+        Level::DEBUG => 4,
+        Level::TRACE => 5,
+    }
+}
+
+/// Returns the task `Id` passed to the probes, or 0 if no task is being polled
+/// on this thread.
+fn task_id() -> u64 {
+    #[cfg(tokio_unstable)]
+    {
+        crate::current_task::id().map_or(0, crate::config::probe_id)
+    }
+    #[cfg(not(tokio_unstable))]
+    {
+        0
+    }
+}
+
+/// Returns `true` if the span and event probes should fire, which they do not
+/// once [`teardown`](crate::teardown) has disabled the hooks.
+#[inline]
+fn enabled() -> bool {
+    #[cfg(tokio_unstable)]
+    if !crate::ENABLED.load(Ordering::Relaxed) {
+        return false;
+    }
+    crate::config::tier_enabled(crate::Tier::Normal)
+}
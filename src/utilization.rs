@@ -0,0 +1,114 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Per-worker poll time, reported as utilization by the
+//! [metrics sampler](crate::metrics).
+//!
+//! The `on_after_task_poll` hook adds the duration of each poll to a counter
+//! owned by the thread which ran it. Each counter is registered by the ID of
+//! its thread, so that the sampler can find the counter for each of a
+//! runtime's workers using [`RuntimeMetrics::worker_thread_id`]. A counter is
+//! only written by its own thread, so recording a poll costs a thread-local
+//! access and an unsynchronized add, with no read-modify-write atomics.
+//!
+//! [`RuntimeMetrics::worker_thread_id`]: tokio::runtime::RuntimeMetrics::worker_thread_id
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread::ThreadId;
+
+static REGISTRY: LazyLock<Mutex<HashMap<ThreadId, Arc<AtomicU64>>>> =
+    LazyLock::new(Default::default);
+
+thread_local! {
+    static POLL_NANOS: Registration = Registration::new();
+}
+
+/// The current thread's total poll time, which is registered for as long as
+/// the thread is alive.
+struct Registration {
+    thread: ThreadId,
+    poll_nanos: Arc<AtomicU64>,
+}
+
+impl Registration {
+    fn new() -> Self {
+        let thread = std::thread::current().id();
+        let poll_nanos = Arc::new(AtomicU64::new(0));
+        REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(thread, poll_nanos.clone());
+        Self { thread, poll_nanos }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.thread);
+    }
+}
+
+/// Records a completed poll on the current thread.
+#[inline]
+pub(crate) fn record(poll_nanos: u64) {
+    // This fails only while the thread's thread-locals are being destroyed.
+    let _ = POLL_NANOS.try_with(|registration| {
+        let total = &registration.poll_nanos;
+        total.store(
+            total.load(Ordering::Relaxed).wrapping_add(poll_nanos),
+            Ordering::Relaxed,
+        );
+    });
+}
+
+/// Returns the total time spent polling tasks on `thread`, in nanoseconds, or
+/// `None` if it has not polled any tasks.
+pub(crate) fn poll_nanos(thread: ThreadId) -> Option<u64> {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&thread)
+        .map(|poll_nanos| poll_nanos.load(Ordering::Relaxed))
+}
+
+/// Returns the fraction of `interval_nanos` spent polling, in thousandths,
+/// given the poll time at the start and end of the interval.
+pub(crate) fn permille(start: u64, end: u64, interval_nanos: u64) -> u64 {
+    if interval_nanos == 0 {
+        return 0;
+    }
+    let busy = u128::from(end.wrapping_sub(start));
+    (busy * 1000 / u128::from(interval_nanos)).min(1000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_time_is_per_thread() {
+        let here = std::thread::current().id();
+        record(10);
+        record(5);
+        assert_eq!(poll_nanos(here), Some(15));
+
+        let there = std::thread::spawn(|| {
+            record(7);
+            std::thread::current().id()
+        });
+        let there = there.join().unwrap();
+        // The other thread's registration is removed when it exits.
+        assert_eq!(poll_nanos(there), None);
+        assert_eq!(poll_nanos(here), Some(15));
+    }
+
+    #[test]
+    fn permille_is_clamped() {
+        assert_eq!(permille(100, 600, 1000), 500);
+        assert_eq!(permille(0, 2000, 1000), 1000);
+        assert_eq!(permille(0, 10, 0), 0);
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Instrumented wakers, which fire a probe when a task is woken.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+use {
+    crate::{clock, config, current_task, hooks, probes, worker},
+    std::panic::Location,
+    std::sync::Arc,
+    std::sync::atomic::{AtomicU64, Ordering},
+    std::task::{Wake, Waker},
+    std::time::{Duration, Instant},
+};
+
+/// Wraps `future` so that the `tokio*:::task-wake` probe fires each time the
+/// task polling it is woken by it.
+///
+/// Tokio's runtime hooks report when a task is polled, but not when it is
+/// *woken*: a task which is ready to run, but waiting for a worker to poll it,
+/// is indistinguishable from one which is waiting for I/O. Tokio does not
+/// provide a hook for wakes, so instead, the returned future passes the
+/// future it wraps a waker which fires the probe before waking the task. The
+/// probe's arguments are:
+///
+/// - `arg0`: the ID of the task's runtime, as reported by `task-spawn-runtime`.
+/// - `arg1`: the [task `Id`](tokio::task::Id) of the task being woken.
+/// - `arg2`: the worker index of the thread waking it, or
+///   `0xffffffffffffffff` (`u64::MAX`) if it is woken from outside the runtime,
+///   such as by a thread performing blocking work.
+/// - `arg3`, `arg4`, and `arg5`: the file, line, and column at which the task
+///   being woken was spawned.
+///
+/// Wrapping a task's future also enables the `tokio*:::task-sched-latency`
+/// probe for it, which fires just after `task-poll-start` for each poll which
+/// follows a wake. `arg1` is the task's ID, and `arg2` is the time since the
+/// task was woken, in nanoseconds: how long it waited in a run queue before a
+/// worker polled it. If the task was woken more than once before it was
+/// polled, the time is measured from the first wake.
+///
+/// Only wakes using wakers obtained from the wrapped future's [`Context`] are
+/// observed, so the future must be wrapped where it is spawned (or at the
+/// outermost point at which its wakes are of interest):
+///
+/// ```rust
+/// # async fn handle_connection() {}
+/// # async fn example() {
+/// tokio::spawn(tokio_dtrace::trace_wakes(handle_connection()));
+/// # }
+/// ```
+///
+/// The waker is only replaced while the wrapped future is polled as part of a
+/// task on a runtime with `tokio-dtrace`'s hooks registered, and the
+/// `probes-task` feature is enabled; otherwise, the future is polled with its
+/// original waker, and the probe never fires. Creating the waker allocates
+/// once per task, and again if the task's own waker changes, which Tokio's
+/// wakers do not. Like the poll probes, the probe belongs to the
+/// [`Normal`](crate::Tier::Normal) tier.
+pub fn trace_wakes<F: Future>(future: F) -> TraceWakes<F> {
+    TraceWakes {
+        future,
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        waker: None,
+    }
+}
+
+/// A future which fires the `tokio*:::task-wake` probe when its task is
+/// woken.
+///
+/// Returned by [`trace_wakes`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct TraceWakes<F> {
+    future: F,
+    /// The waker passed to `future`, and the waker of the task it wraps.
+    #[cfg(all(tokio_unstable, feature = "probes-task"))]
+    waker: Option<(Arc<TaskWaker>, Waker)>,
+}
+
+impl<F: Future> Future for TraceWakes<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: `future` is structurally pinned: it is never moved out of
+        // `self`, and `TraceWakes` is only `Unpin` if `F` is. The waker is not
+        // pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        #[cfg(all(tokio_unstable, feature = "probes-task"))]
+        if let Some((task, location)) = current_task::id_and_location() {
+            let current = this.waker.as_ref().filter(|(waker, _)| waker.task == task);
+            if let Some((waker, _)) = current {
+                waker.polled();
+            }
+            let reuse = current.is_some_and(|(waker, _)| waker.inner.will_wake(cx.waker()));
+            if !reuse {
+                let waker = Arc::new(TaskWaker {
+                    task,
+                    runtime: current_task::runtime(),
+                    location,
+                    created_at: clock::now(),
+                    woken_at: AtomicU64::new(NOT_WOKEN),
+                    inner: cx.waker().clone(),
+                });
+                this.waker = Some((waker.clone(), Waker::from(waker)));
+            }
+            if let Some((_, waker)) = &this.waker {
+                return future.poll(&mut Context::from_waker(waker));
+            }
+        }
+        future.poll(cx)
+    }
+}
+
+/// The value of [`TaskWaker::woken_at`] while the task has not been woken
+/// since it was last polled.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+const NOT_WOKEN: u64 = u64::MAX;
+
+/// Fires `task-wake`, and then wakes the task.
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+#[derive(Debug)]
+struct TaskWaker {
+    task: u64,
+    /// The ID of the task's runtime, as reported by the hooks polling it.
+    runtime: u64,
+    location: &'static Location<'static>,
+    /// When the waker was created, from which `woken_at` is measured.
+    created_at: Instant,
+    /// When the task was first woken since it was last polled, in nanoseconds
+    /// since `created_at`, or [`NOT_WOKEN`].
+    ///
+    /// This is kept in the waker, rather than the task table, so that waking
+    /// the task does not take the table's lock.
+    woken_at: AtomicU64,
+    inner: Waker,
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl TaskWaker {
+    fn fire(&self) {
+        if !crate::ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        // Only the first wake since the task was last polled is recorded, as
+        // that is when it became ready to run.
+        if self.woken_at.load(Ordering::Relaxed) == NOT_WOKEN {
+            let since = clock::now().saturating_duration_since(self.created_at);
+            let since = config::nanos(since).min(NOT_WOKEN - 1);
+            let _ = self.woken_at.compare_exchange(
+                NOT_WOKEN,
+                since,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        if !config::tier_enabled(config::Tier::Normal) {
+            return;
+        }
+        fire!(probes::task__wake, || {
+            (
+                self.runtime,
+                config::probe_id(self.task),
+                worker::current(),
+                config::probe_path(self.location.file()),
+                self.location.line(),
+                self.location.column(),
+            )
+        });
+    }
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl TaskWaker {
+    /// Fires `task-sched-latency` as the task is polled, if it was woken since
+    /// it was last polled.
+    fn polled(&self) {
+        let woken_at = self.woken_at.swap(NOT_WOKEN, Ordering::Relaxed);
+        if woken_at == NOT_WOKEN
+            || !hooks::poll_traced()
+            || !config::tier_enabled(config::Tier::Normal)
+        {
+            return;
+        }
+        fire!(probes::task__sched__latency, || {
+            let woken_at = self.created_at + Duration::from_nanos(woken_at);
+            let latency = clock::now().saturating_duration_since(woken_at);
+            (
+                self.runtime,
+                config::probe_id(self.task),
+                config::nanos(latency),
+            )
+        });
+    }
+}
+
+#[cfg(all(tokio_unstable, feature = "probes-task"))]
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.fire();
+        match Arc::try_unwrap(self) {
+            Ok(waker) => waker.inner.wake(),
+            Err(waker) => waker.inner.wake_by_ref(),
+        }
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.fire();
+        self.inner.wake_by_ref();
+    }
+}
+
+#[cfg(all(test, tokio_unstable, feature = "probes-task"))]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn wakes_reach_the_task() {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        let rt = crate::register_hooks(builder.worker_threads(2).enable_time())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let value = rt.block_on(async {
+            tokio::spawn(super::trace_wakes(async {
+                // Woken by the timer driver, and then by the task itself.
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                tokio::task::yield_now().await;
+                7
+            }))
+            .await
+            .unwrap()
+        });
+        assert_eq!(value, 7);
+    }
+}
@@ -0,0 +1,404 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Identification of Tokio runtime threads.
+//!
+//! Tokio does not currently expose an identifier for the worker thread on
+//! which a hook is running, so `tokio-dtrace` assigns each runtime thread an
+//! index when its `on_thread_start` hook fires, in the order in which threads
+//! are started. Alternatively, if
+//! [`WorkerIndexSource::Native`](crate::WorkerIndexSource::Native) is
+//! configured, the index Tokio itself uses for the current worker is found
+//! using the runtime's metrics.
+//!
+//! The thread's name is also recorded when it starts, so that the worker
+//! probes can identify which runtime a thread belongs to. Tokio names each of
+//! a runtime's threads using the name configured by
+//! [`Builder::thread_name`](tokio::runtime::Builder::thread_name) (by default,
+//! `tokio-runtime-worker`).
+//!
+//! Every task and thread probe also reports the ID `tokio-dtrace` assigned to
+//! the runtime it belongs to. The hooks installed on a builder by
+//! [`HookSet::install`](crate::hooks::HookSet::install) know which runtime
+//! they were installed on, and each runtime thread records the ID when it
+//! starts. Elsewhere, such as in the individual hook functions called from a
+//! thread blocked in [`Runtime::block_on`](tokio::runtime::Runtime::block_on),
+//! the runtime is found from the current [`Handle`], and Tokio's own
+//! [runtime `Id`](tokio::runtime::Id) is mapped to an ID assigned on first
+//! sight.
+#[cfg(feature = "probes-worker")]
+use crate::probes;
+use std::cell::Cell;
+#[cfg(feature = "probes-worker")]
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+use tokio::runtime::Handle;
+#[cfg(feature = "probes-worker")]
+use tokio::runtime::RuntimeFlavor;
+
+/// The worker index reported for threads that were not started by a Tokio
+/// runtime with `tokio-dtrace`'s hooks registered.
+pub(crate) const NO_WORKER: u64 = u64::MAX;
+
+static NEXT_INDEX: AtomicU64 = AtomicU64::new(0);
+
+/// The runtime ID reported for threads and tasks whose runtime is unknown.
+pub(crate) const NO_RUNTIME: u64 = 0;
+
+/// The ID assigned to the next runtime builder on which the hooks are
+/// installed, or to the next runtime found without one.
+static NEXT_RUNTIME: AtomicU64 = AtomicU64::new(1);
+
+/// The ID assigned to each runtime, by Tokio's ID for it.
+static RUNTIME_IDS: LazyLock<RwLock<HashMap<tokio::runtime::Id, u64>>> =
+    LazyLock::new(Default::default);
+
+thread_local! {
+    static INDEX: Cell<u64> = const { Cell::new(NO_WORKER) };
+    /// The ID of the runtime which started the current thread.
+    static RUNTIME: Cell<u64> = const { Cell::new(NO_RUNTIME) };
+    /// The runtime most recently found from the current thread's [`Handle`],
+    /// as Tokio's ID and the ID assigned to it.
+    static HANDLE_RUNTIME: Cell<Option<(tokio::runtime::Id, u64)>> = const { Cell::new(None) };
+    /// The current thread's native worker index, once it has been found.
+    #[cfg(any(feature = "probes-task", feature = "debug-overhead"))]
+    static NATIVE_INDEX: Cell<u64> = const { Cell::new(NO_WORKER) };
+}
+
+#[cfg(feature = "probes-worker")]
+thread_local! {
+    static NAME: RefCell<String> = const { RefCell::new(String::new()) };
+    /// Detects the current thread exiting without `on_thread_stop` being
+    /// called.
+    static LIFETIME: RefCell<Option<Lifetime>> = const { RefCell::new(None) };
+}
+
+/// The `pool` argument of `worker-thread-start` and `worker-thread-stop` for a
+/// thread which runs one of its runtime's workers.
+#[cfg(feature = "probes-worker")]
+pub(crate) const POOL_WORKER: u8 = 0;
+
+/// The `pool` argument of `worker-thread-start` and `worker-thread-stop` for a
+/// thread which only runs blocking tasks.
+#[cfg(feature = "probes-worker")]
+pub(crate) const POOL_BLOCKING: u8 = 1;
+
+/// Fires the `worker-thread-abnormal-stop` probe when dropped, unless the
+/// thread was stopped normally.
+///
+/// This is dropped when the thread's thread-local storage is destroyed, as the
+/// thread exits, so it only fires if the thread exits without
+/// [`stopped`] being called; for instance, if a panic unwinds out of the
+/// runtime's worker loop, skipping Tokio's call to `on_thread_stop`.
+#[cfg(feature = "probes-worker")]
+struct Lifetime {
+    runtime: u64,
+    index: u64,
+}
+
+#[cfg(feature = "probes-worker")]
+impl Drop for Lifetime {
+    fn drop(&mut self) {
+        // If the hooks have been disabled, `on_thread_stop` returned early
+        // without calling `stopped`.
+        if crate::ENABLED.load(Ordering::Relaxed) {
+            fire!(probes::worker__thread__abnormal__stop, || {
+                (self.runtime, self.index)
+            });
+        }
+    }
+}
+
+/// Assigns the current thread, started by the runtime with the given ID, a
+/// new worker index, returning it, and records the thread's name.
+pub(crate) fn register_current(runtime: u64) -> u64 {
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+    INDEX.set(index);
+    #[cfg(any(feature = "probes-task", feature = "debug-overhead"))]
+    NATIVE_INDEX.set(NO_WORKER);
+    #[cfg(feature = "probes-worker")]
+    {
+        NAME.with_borrow_mut(|name| {
+            name.clear();
+            name.push_str(std::thread::current().name().unwrap_or_default());
+        });
+        if let Some(lifetime) = LIFETIME.replace(Some(Lifetime { runtime, index })) {
+            // The thread was already registered, and has been registered again
+            // (e.g. by a second runtime's hooks) without stopping.
+            std::mem::forget(lifetime);
+        }
+    }
+    #[cfg(not(feature = "probes-worker"))]
+    let _ = runtime;
+    index
+}
+
+/// Assigns a new runtime ID, for a builder on which the hooks are being
+/// installed.
+pub(crate) fn next_runtime() -> u64 {
+    NEXT_RUNTIME.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The ID of the runtime built by a builder on which the hooks were installed,
+/// shared by the hooks [`HookSet::install`](crate::hooks::HookSet::install)
+/// installs.
+#[derive(Debug)]
+pub(crate) struct RuntimeId {
+    id: AtomicU64,
+    /// Whether Tokio's ID for the runtime has been mapped to `id`.
+    mapped: AtomicBool,
+}
+
+impl RuntimeId {
+    /// Assigns a new runtime ID.
+    pub(crate) fn new() -> Self {
+        Self {
+            id: AtomicU64::new(next_runtime()),
+            mapped: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the runtime's ID.
+    #[inline]
+    pub(crate) fn get(&self) -> u64 {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    /// Returns the runtime's ID, from a hook which Tokio only calls within the
+    /// runtime's context, such as when starting one of its threads or polling
+    /// one of its tasks.
+    ///
+    /// The first such call maps Tokio's ID for the runtime to this one, so that
+    /// the individual hook functions report the same ID for the runtime. If
+    /// they have already seen it, and assigned it an ID of their own, that ID
+    /// is adopted instead.
+    #[inline]
+    pub(crate) fn entered(&self) -> u64 {
+        if !self.mapped.load(Ordering::Relaxed) {
+            self.map();
+        }
+        self.get()
+    }
+
+    #[cold]
+    fn map(&self) {
+        let Ok(handle) = Handle::try_current() else {
+            return;
+        };
+        let mut ids = RUNTIME_IDS.write().unwrap_or_else(|e| e.into_inner());
+        let id = *ids.entry(handle.id()).or_insert_with(|| self.get());
+        self.id.store(id, Ordering::Relaxed);
+        self.mapped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Records that the current thread was started by the runtime with the given
+/// ID.
+pub(crate) fn set_runtime(runtime: u64) {
+    RUNTIME.set(runtime);
+}
+
+/// Returns the ID of the runtime the current thread belongs to.
+///
+/// This is the runtime which started the current thread, if it is a runtime
+/// thread. Otherwise, it is the runtime whose context the thread has entered,
+/// such as by calling [`Runtime::block_on`](tokio::runtime::Runtime::block_on),
+/// or, outside of any runtime, the only runtime in the process, if it has only
+/// one. Failing that, the runtime is unknown, and [`NO_RUNTIME`] is returned.
+#[inline]
+pub(crate) fn runtime() -> u64 {
+    match RUNTIME.get() {
+        NO_RUNTIME => handle_runtime(),
+        runtime => runtime,
+    }
+}
+
+#[cold]
+fn handle_runtime() -> u64 {
+    let Ok(handle) = Handle::try_current() else {
+        // IDs are allocated from 1, so if the next is 2, only one runtime has
+        // been seen.
+        return match NEXT_RUNTIME.load(Ordering::Relaxed) {
+            2 => 1,
+            _ => NO_RUNTIME,
+        };
+    };
+    let id = handle.id();
+    if let Some((cached, runtime)) = HANDLE_RUNTIME.get()
+        && cached == id
+    {
+        return runtime;
+    }
+    let known = RUNTIME_IDS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&id)
+        .copied();
+    let runtime = known.unwrap_or_else(|| {
+        let mut ids = RUNTIME_IDS.write().unwrap_or_else(|e| e.into_inner());
+        *ids.entry(id).or_insert_with(next_runtime)
+    });
+    HANDLE_RUNTIME.set(Some((id, runtime)));
+    runtime
+}
+
+/// Records that the current thread is stopping normally.
+#[cfg(feature = "probes-worker")]
+pub(crate) fn stopped() {
+    if let Some(lifetime) = LIFETIME.take() {
+        std::mem::forget(lifetime);
+    }
+}
+
+/// Returns which of its runtime's thread pools the current thread belongs to,
+/// as passed to the `worker-thread-start` probe if it is `starting`, or to
+/// `worker-thread-stop` if not.
+///
+/// Tokio starts every runtime thread in its blocking pool, including the
+/// threads which run a multi-threaded runtime's workers, without telling the
+/// hooks which is which. Each worker records its thread's ID in the runtime's
+/// metrics once it begins running, so a stopping thread ran a worker if one
+/// recorded it. That happens after the start hook, so a starting thread is
+/// assumed to be about to run a worker while any of the runtime's workers has
+/// yet to record a thread. This misreports a blocking thread which starts
+/// while the runtime's workers are still starting, and a thread which takes
+/// over a worker from a task calling
+/// [`block_in_place`](tokio::task::block_in_place) is reported as a blocking
+/// thread when it starts.
+#[cfg(feature = "probes-worker")]
+pub(crate) fn pool(starting: bool) -> u8 {
+    let Ok(handle) = Handle::try_current() else {
+        return POOL_BLOCKING;
+    };
+    if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+        return POOL_BLOCKING;
+    }
+    let metrics = handle.metrics();
+    let current = std::thread::current().id();
+    let worker = (0..metrics.num_workers()).any(|worker| {
+        let thread = metrics.worker_thread_id(worker);
+        if starting {
+            thread.is_none()
+        } else {
+            thread == Some(current)
+        }
+    });
+    if worker { POOL_WORKER } else { POOL_BLOCKING }
+}
+
+/// Resets worker index assignment, so that the next thread to start is
+/// assigned index 0.
+pub(crate) fn reset() {
+    NEXT_INDEX.store(0, Ordering::Relaxed);
+}
+
+/// Returns the current thread's worker index, from the configured
+/// [`WorkerIndexSource`](crate::WorkerIndexSource), or [`NO_WORKER`] if the
+/// current thread is not a runtime thread.
+#[cfg(any(feature = "probes-task", feature = "debug-overhead"))]
+#[inline]
+pub(crate) fn current() -> u64 {
+    if crate::config::native_worker_indices() {
+        native()
+    } else {
+        INDEX.get()
+    }
+}
+
+/// Returns the index Tokio uses for the current worker, or [`NO_WORKER`] if
+/// the current thread is not (yet) running one of its runtime's workers.
+#[cfg(any(feature = "probes-task", feature = "debug-overhead"))]
+fn native() -> u64 {
+    let index = NATIVE_INDEX.get();
+    if index != NO_WORKER {
+        return index;
+    }
+    // Tokio records a worker's thread ID when the worker begins running, so
+    // the lookup is retried until it succeeds, rather than caching a miss.
+    let Ok(handle) = tokio::runtime::Handle::try_current() else {
+        return NO_WORKER;
+    };
+    let metrics = handle.metrics();
+    let thread = std::thread::current().id();
+    let Some(index) =
+        (0..metrics.num_workers()).find(|&worker| metrics.worker_thread_id(worker) == Some(thread))
+    else {
+        return NO_WORKER;
+    };
+    let index = index as u64;
+    NATIVE_INDEX.set(index);
+    index
+}
+
+/// Calls `f` with the runtime ID, name, and synthetic worker index of the
+/// current thread, as passed to the `worker-thread-*` probes.
+///
+/// The name is the one the thread had when it was registered, or an empty
+/// string if it was unnamed or is not a runtime thread. It is borrowed, rather
+/// than cloned, as the park and unpark probes fire very frequently.
+///
+/// The synthetic index is used regardless of the configured
+/// [`WorkerIndexSource`](crate::WorkerIndexSource), as Tokio's native index is
+/// not yet known when a thread starts, and the probes for a thread must all
+/// report the same identifier.
+#[cfg(feature = "probes-worker")]
+pub(crate) fn with_identity<R>(f: impl FnOnce(u64, &str, u64) -> R) -> R {
+    NAME.with_borrow(|name| f(RUNTIME.get(), name, INDEX.get()))
+}
+
+#[cfg(all(
+    test,
+    any(
+        feature = "probes-task",
+        feature = "probes-worker",
+        feature = "debug-overhead"
+    )
+))]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "probes-task", feature = "debug-overhead"))]
+    #[test]
+    fn native_index_is_found_on_workers() {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+        let index = rt.block_on(async { tokio::spawn(async { native() }).await.unwrap() });
+        assert!(index < 2);
+        assert_eq!(native(), NO_WORKER);
+    }
+
+    #[cfg(feature = "probes-worker")]
+    #[test]
+    fn thread_name_is_recorded() {
+        let name = std::thread::Builder::new()
+            .name("tokio-dtrace-test".to_string())
+            .spawn(|| {
+                register_current(NO_RUNTIME);
+                with_identity(|_, name, _| name.to_string())
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(name, "tokio-dtrace-test");
+        assert_eq!(with_identity(|_, name, _| name.to_string()), "");
+    }
+
+    #[cfg(feature = "probes-worker")]
+    #[test]
+    fn identity_is_stable() {
+        let (index, identities) = std::thread::spawn(|| {
+            set_runtime(7);
+            let index = register_current(7);
+            let identity =
+                || with_identity(|runtime, name, index| (runtime, name.to_string(), index));
+            (index, [identity(), identity()])
+        })
+        .join()
+        .unwrap();
+        assert_eq!(identities[0], (7, String::new(), index));
+        assert_eq!(identities[0], identities[1]);
+    }
+}
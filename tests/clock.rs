@@ -0,0 +1,68 @@
+// Copyright 2025 Oxide Computer Company
+
+//! The canonical test of poll timing: with a clock which advances by a fixed
+//! step each time it is read, each poll is measured as lasting exactly one
+//! step.
+//!
+//! The clock is process-global, so this test lives in its own integration
+//! test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tokio_dtrace::testing::{self, Arg};
+
+const STEP: Duration = Duration::from_micros(250);
+const STEP_NANOS: Arg = Arg::Int(STEP.as_nanos() as u64);
+
+/// A clock which advances by [`STEP`] each time it is read.
+#[derive(Debug)]
+struct StepClock {
+    start: Instant,
+    reads: AtomicU32,
+}
+
+impl tokio_dtrace::Clock for StepClock {
+    fn now(&self) -> Instant {
+        self.start + STEP * self.reads.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+static CLOCK: LazyLock<StepClock> = LazyLock::new(|| StepClock {
+    start: Instant::now(),
+    reads: AtomicU32::new(0),
+});
+
+#[test]
+fn poll_duration_uses_clock() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let config = tokio_dtrace::Config::new().clock(&*CLOCK);
+    let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let (id, fired) = testing::capture(|| {
+        rt.block_on(async {
+            let handle = tokio::spawn(async {
+                tokio::task::yield_now().await;
+                tokio::task::yield_now().await;
+            });
+            let id = handle.id().to_string().parse::<u64>().unwrap();
+            handle.await.unwrap();
+            id
+        })
+    });
+
+    // task-poll-end reports the poll's duration as arg5. Tokio calls the
+    // terminate hook during the final poll, which reads the clock too, so only
+    // the polls before it are exactly one step long.
+    let polls: Vec<_> = fired
+        .iter()
+        .filter(|fired| fired.probe == "task-poll-end" && fired.args[0] == Arg::Int(id))
+        .map(|fired| fired.args[5].clone())
+        .collect();
+    assert_eq!(polls.len(), 3);
+    assert_eq!(polls[..2], [STEP_NANOS; 2]);
+}
@@ -0,0 +1,32 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `take_counters`.
+//!
+//! The counters are process-global, and count events from every runtime with
+//! hooks registered, so these tests live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "probes-task"))]
+
+use tokio_dtrace::Counters;
+
+#[test]
+fn counters_are_taken() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+    tokio_dtrace::take_counters();
+
+    rt.block_on(async {
+        for _ in 0..3 {
+            tokio::spawn(async {}).await.unwrap();
+        }
+    });
+    let counters = tokio_dtrace::take_counters();
+    assert_eq!(counters.spawns, 3);
+    assert_eq!(counters.terminates, 3);
+    assert_eq!(counters.polls, 3);
+
+    // Taking the counters resets them.
+    assert_eq!(tokio_dtrace::take_counters(), Counters::default());
+}
@@ -0,0 +1,47 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `Config::hook_groups`.
+//!
+//! Which probes have fired is recorded process-wide, so these tests live in
+//! their own integration test binary.
+#![cfg(all(
+    tokio_unstable,
+    feature = "testing",
+    feature = "probes-task",
+    feature = "probes-worker"
+))]
+
+use std::time::Duration;
+use tokio_dtrace::{Config, HookGroups, ProbeMask};
+
+#[test]
+fn unselected_groups_are_not_installed() {
+    tokio_dtrace::testing::force_enabled(|| {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        let config = Config::new().hook_groups(HookGroups::TASK_LIFECYCLE);
+        let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+            .unwrap()
+            .worker_threads(1)
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            tokio::spawn(async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            })
+            .await
+            .unwrap();
+        });
+        rt.shutdown_timeout(Duration::from_secs(10));
+    });
+
+    // Only the spawn and terminate hooks were installed, so none of the poll
+    // or worker thread probes fired.
+    let enabled = tokio_dtrace::enabled_probes();
+    assert_eq!(
+        enabled,
+        ProbeMask::TASK_SPAWN | ProbeMask::TASK_TERMINATE,
+        "{:?}",
+        enabled.names().collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,44 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `hooks::HookSet`.
+//!
+//! Which probes have fired is recorded process-wide, so these tests live in
+//! their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use std::sync::Mutex;
+use tokio_dtrace::ProbeMask;
+use tokio_dtrace::hooks::{HookSet, Order};
+
+/// The hooks which have run, in order, and whether `task-spawn` had fired
+/// when each one ran.
+static RAN: Mutex<Vec<(&str, bool)>> = Mutex::new(Vec::new());
+
+fn record(name: &'static str) {
+    let spawned = tokio_dtrace::enabled_probes().contains(ProbeMask::TASK_SPAWN);
+    RAN.lock().unwrap().push((name, spawned));
+}
+
+#[test]
+fn hooks_run_around_tokio_dtraces() {
+    tokio_dtrace::testing::force_enabled(|| {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        HookSet::new()
+            .on_task_spawn(Order::After, |_| record("after"))
+            .on_task_spawn(Order::Before, |_| record("before 1"))
+            .on_task_spawn(Order::Before, |_| record("before 2"))
+            .install(&mut builder);
+        let rt = builder.build().unwrap();
+        rt.block_on(async {
+            tokio::spawn(async {}).await.unwrap();
+        });
+    });
+
+    // The hooks added before `tokio-dtrace`'s ran in the order they were
+    // added, before `task-spawn` fired, and the hook added after it ran once
+    // it had.
+    assert_eq!(
+        *RAN.lock().unwrap(),
+        [("before 1", false), ("before 2", false), ("after", true)]
+    );
+}
@@ -0,0 +1,40 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `HooksGuard`.
+//!
+//! Dropping a `HooksGuard` resets `tokio-dtrace`'s process-global state, which
+//! would interfere with other tests running concurrently in the same process,
+//! so these tests live in their own integration test binary.
+#![cfg(tokio_unstable)]
+
+use tokio_dtrace::HooksGuard;
+
+#[test]
+fn guard_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<HooksGuard>();
+}
+
+#[test]
+fn build_teardown_cycles() {
+    for _ in 0..3 {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        let (builder, guard) = tokio_dtrace::register_hooks_with_guard(&mut builder).unwrap();
+        let rt = builder.worker_threads(2).build().unwrap();
+
+        let sum = rt.block_on(async {
+            let tasks: Vec<_> = (0..16u64).map(|i| tokio::spawn(async move { i })).collect();
+            let mut sum = 0u64;
+            for task in tasks {
+                sum += task.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(sum, (0..16u64).sum::<u64>());
+
+        drop(rt);
+        // The guard may be dropped on a different thread than the one which
+        // created it.
+        std::thread::spawn(move || drop(guard)).join().unwrap();
+    }
+}
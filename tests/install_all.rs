@@ -0,0 +1,48 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `hooks::install_all`.
+//!
+//! Which probes have fired is recorded process-wide, so these tests live in
+//! their own integration test binary.
+#![cfg(all(
+    tokio_unstable,
+    feature = "testing",
+    feature = "probes-task",
+    feature = "probes-worker"
+))]
+
+use std::time::Duration;
+use tokio_dtrace::ProbeMask;
+
+#[test]
+fn install_all_wires_every_hook() {
+    tokio_dtrace::testing::force_enabled(|| {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        let rt = tokio_dtrace::hooks::install_all(builder.worker_threads(1).enable_time())
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            tokio::spawn(async {
+                // Sleeping parks the worker, and waking unparks it.
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            })
+            .await
+            .unwrap();
+        });
+        // Shutting down stops the worker thread.
+        rt.shutdown_timeout(Duration::from_secs(10));
+    });
+
+    // Every hook fires a probe with a bit in `ProbeMask`, so each bit shows
+    // that the corresponding hook was installed.
+    let enabled = tokio_dtrace::enabled_probes();
+    let all = ProbeMask::TASK_SPAWN
+        | ProbeMask::TASK_POLL_START
+        | ProbeMask::TASK_POLL_END
+        | ProbeMask::TASK_TERMINATE
+        | ProbeMask::WORKER_THREAD_START
+        | ProbeMask::WORKER_THREAD_STOP
+        | ProbeMask::WORKER_THREAD_PARK
+        | ProbeMask::WORKER_THREAD_UNPARK;
+    assert_eq!(enabled, all, "{:?}", enabled.names().collect::<Vec<_>>());
+}
@@ -0,0 +1,52 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the `#[tokio_dtrace::main]` attribute macro.
+#![cfg(feature = "macros")]
+
+#[tokio_dtrace::main(flavor = "current_thread")]
+async fn current_thread() -> std::thread::ThreadId {
+    tokio::spawn(async { std::thread::current().id() })
+        .await
+        .unwrap()
+}
+
+#[tokio_dtrace::main(worker_threads = 2)]
+async fn multi_thread(n: u64) -> Result<u64, tokio::task::JoinError> {
+    tokio::spawn(async move { n * 2 }).await
+}
+
+#[tokio_dtrace::main(flavor = "current_thread", require_hooks = true)]
+async fn hooks_required() -> u64 {
+    tokio::spawn(async { 7 }).await.unwrap()
+}
+
+#[test]
+fn current_thread_runs_on_the_calling_thread() {
+    assert_eq!(current_thread(), std::thread::current().id());
+}
+
+#[test]
+fn multi_thread_passes_arguments_and_returns() {
+    assert_eq!(multi_thread(21).unwrap(), 42);
+}
+
+/// Without Tokio's unstable features the hooks cannot be registered, so the
+/// function runs without them.
+#[cfg(not(tokio_unstable))]
+#[test]
+fn registration_failure_is_not_fatal_by_default() {
+    assert_eq!(current_thread(), std::thread::current().id());
+}
+
+#[cfg(tokio_unstable)]
+#[test]
+fn required_hooks_are_registered() {
+    assert_eq!(hooks_required(), 7);
+}
+
+#[cfg(not(tokio_unstable))]
+#[test]
+#[should_panic(expected = "failed to register tokio-dtrace's hooks")]
+fn registration_failure_is_fatal_if_hooks_are_required() {
+    hooks_required();
+}
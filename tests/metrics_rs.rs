@@ -0,0 +1,54 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the task metrics recorded using the `metrics` crate.
+//!
+//! The recorder is process-global, and each spawn location's metrics are
+//! registered with it once, so these tests live in their own integration test
+//! binary.
+#![cfg(all(tokio_unstable, feature = "metrics"))]
+
+use metrics_util::MetricKind;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+#[test]
+fn task_metrics_are_recorded() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+    // Each of these tasks completes in its first poll, so that poll is only
+    // recorded when the task terminates.
+    rt.block_on(async {
+        for _ in 0..2 {
+            tokio::spawn(async {}).await.unwrap();
+        }
+    });
+
+    let metrics = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .filter(|(key, ..)| {
+            key.key()
+                .labels()
+                .any(|label| label.key() == "file" && label.value() == file!())
+        })
+        .map(|(key, _, _, value)| (key.kind(), key.key().name().to_string(), value))
+        .collect::<Vec<_>>();
+    let spawned = metrics
+        .iter()
+        .find(|(kind, name, _)| *kind == MetricKind::Counter && name == "tokio.task.spawned");
+    assert!(matches!(spawned, Some((_, _, DebugValue::Counter(2)))));
+    let polls = metrics.iter().find(|(kind, name, _)| {
+        *kind == MetricKind::Histogram && name == "tokio.task.poll_duration"
+    });
+    assert!(matches!(
+        polls,
+        Some((_, _, DebugValue::Histogram(polls))) if polls.len() == 2
+    ));
+}
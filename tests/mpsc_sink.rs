@@ -0,0 +1,58 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `Config::mpsc_sink`.
+//!
+//! There is a single ring per process, with a single `Drainer`, so these tests
+//! live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "event-stream", feature = "probes-task"))]
+
+use tokio_dtrace::Config;
+use tokio_dtrace::events::{self, ProbeEvent};
+
+#[test]
+fn drainer_receives_task_events() {
+    assert!(events::drainer().is_none());
+
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let config = Config::new().mpsc_sink(1024);
+    let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut drainer = events::drainer().unwrap();
+    assert!(events::drainer().is_none());
+    assert_eq!(drainer.capacity(), 1024);
+
+    // Drain the ring from a dedicated thread, blocking while it is empty.
+    let id = rt.block_on(async { tokio::spawn(async {}).id() });
+    let id = id.to_string().parse::<u64>().unwrap();
+    let consumer = std::thread::spawn(move || {
+        let mut seen = Vec::new();
+        while seen.last() != Some(&"terminate") {
+            match drainer.blocking_recv() {
+                ProbeEvent::TaskSpawn(task) if task.id == id => seen.push("spawn"),
+                ProbeEvent::TaskTerminate(task) if task.id == id => seen.push("terminate"),
+                _ => {}
+            }
+        }
+        (seen, drainer)
+    });
+    rt.block_on(tokio::task::yield_now());
+    let (seen, mut drainer) = consumer.join().unwrap();
+    assert_eq!(seen, ["spawn", "terminate"]);
+
+    // Once the ring is full, further events are dropped and counted.
+    rt.block_on(async {
+        for _ in 0..1024 {
+            tokio::spawn(async {});
+        }
+    });
+    assert!(drainer.dropped() > 0);
+    let drained = std::iter::from_fn(|| drainer.try_recv()).count();
+    assert_eq!(drained, 1024);
+
+    // The spawned tasks are polled only after `recv` has found the ring
+    // empty, so receiving an event requires the hooks to wake it.
+    let event = rt.block_on(drainer.recv());
+    assert!(matches!(event, ProbeEvent::TaskPollStart(_)), "{event:?}");
+}
@@ -0,0 +1,87 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for tasks polled within the poll of another task on the same thread.
+//!
+//! The task store is chosen once per process, so these tests live in their
+//! own integration test binary.
+#![cfg(all(tokio_unstable, feature = "probes-task"))]
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, mpsc};
+use tokio_dtrace::{Config, TaskInfo, TaskState, TaskStore, TaskSummary};
+
+/// A store which keeps the information of each task which terminates.
+#[derive(Debug, Default)]
+struct SummaryStore {
+    tasks: Mutex<HashMap<u64, TaskState>>,
+    terminated: Mutex<HashMap<u64, TaskInfo>>,
+}
+
+impl TaskStore for SummaryStore {
+    fn on_spawn(&self, id: u64, state: TaskState) {
+        self.tasks.lock().unwrap().insert(id, state);
+    }
+
+    fn on_poll_start(&self, id: u64) -> Option<TaskState> {
+        self.tasks.lock().unwrap().remove(&id)
+    }
+
+    fn on_poll_end(&self, id: u64, state: TaskState) {
+        self.tasks.lock().unwrap().insert(id, state);
+    }
+
+    fn on_terminate(&self, id: u64) -> Option<TaskSummary> {
+        let state = self.tasks.lock().unwrap().remove(&id)?;
+        let summary = TaskSummary::new(id, state);
+        self.terminated.lock().unwrap().insert(id, summary.info());
+        Some(summary)
+    }
+
+    fn drain(&self) -> Vec<(u64, TaskState)> {
+        self.tasks.lock().unwrap().drain().collect()
+    }
+}
+
+#[test]
+fn nested_polls_keep_the_outer_task_state() {
+    static STORE: LazyLock<SummaryStore> = LazyLock::new(Default::default);
+
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.worker_threads(1);
+    let config = Config::new().task_store(&*STORE);
+    let outer_rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let inner_rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let outer = outer_rt.spawn(async move {
+        // The inner runtime polls its task on this thread, within this
+        // task's poll.
+        tokio::task::block_in_place(|| {
+            inner_rt.block_on(async { tokio::spawn(tokio::task::yield_now()).await })
+        })
+        .unwrap();
+        tx.send(inner_rt).unwrap();
+        std::future::pending::<()>().await;
+    });
+    let outer = outer.id().to_string().parse::<u64>().unwrap();
+    drop(rx.recv().unwrap());
+    // The outer task is cancelled when its runtime shuts down, after its poll
+    // has ended, so its state is taken from the store as it terminates.
+    drop(outer_rt);
+
+    let info = STORE.terminated.lock().unwrap()[&outer];
+    assert_eq!(info.poll_count, 1);
+    // The outer task's poll time would not have been recorded if the inner
+    // task's poll had replaced its state.
+    assert!(info.total_poll_nanos > 0);
+    // The inner task terminated during its poll, and its state was not left
+    // behind.
+    assert!(STORE.tasks.lock().unwrap().is_empty());
+}
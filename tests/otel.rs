@@ -0,0 +1,47 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the OpenTelemetry task spans.
+//!
+//! The tracer provider is process-global, so these tests live in their own
+//! integration test binary.
+#![cfg(all(tokio_unstable, feature = "otel"))]
+
+use opentelemetry::Value;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+#[test]
+fn task_lifetime_is_a_span() {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    opentelemetry::global::set_tracer_provider(provider);
+
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+    let line = line!() + 1;
+    rt.block_on(async { tokio::spawn(tokio::task::yield_now()).await.unwrap() });
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let [span] = &spans[..] else {
+        panic!("expected one span, got {spans:?}");
+    };
+    assert_eq!(span.name, "tokio.task");
+    let attribute = |key: &str| {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    };
+    assert_eq!(attribute("code.file.path"), Some(Value::from(file!())));
+    assert_eq!(attribute("code.line.number"), Some(Value::I64(line.into())));
+    let events = span
+        .events
+        .iter()
+        .map(|event| event.name.as_ref())
+        .collect::<Vec<_>>();
+    assert_eq!(events, ["poll-start", "poll-end", "poll-start", "poll-end"]);
+}
@@ -0,0 +1,46 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the OpenTelemetry task metrics.
+//!
+//! The meter provider is process-global, and the hooks' instruments are
+//! created from it once, so these tests live in their own integration test
+//! binary.
+#![cfg(all(tokio_unstable, feature = "otel"))]
+
+use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader, SdkMeterProvider};
+
+#[test]
+fn task_metrics_are_recorded() {
+    let exporter = InMemoryMetricExporter::default();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter.clone()).build())
+        .build();
+    opentelemetry::global::set_meter_provider(provider.clone());
+
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+    rt.block_on(async { tokio::spawn(tokio::task::yield_now()).await.unwrap() });
+
+    provider.force_flush().unwrap();
+    let mut names = exporter
+        .get_finished_metrics()
+        .unwrap()
+        .iter()
+        .flat_map(|resource| resource.scope_metrics())
+        .flat_map(|scope| scope.metrics())
+        .map(|metric| metric.name().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    assert_eq!(
+        names,
+        [
+            "tokio.task.active",
+            "tokio.task.poll.duration",
+            "tokio.task.spawned"
+        ]
+    );
+}
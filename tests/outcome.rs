@@ -0,0 +1,122 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the reason a task terminated, as reported by the
+//! `task-terminate` probe's flags.
+//!
+//! Capturing probes forces them to be enabled process-wide, and the panic hook
+//! is process-global, so these tests live in their own integration test
+//! binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use std::sync::mpsc;
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
+use tokio_dtrace::testing::{self, Arg, Fired};
+
+/// The reasons encoded in bits 3 and 4 of the `task-terminate` probe's flags.
+const UNKNOWN: u64 = 0;
+const COMPLETED: u64 = 1;
+const CANCELLED: u64 = 2;
+const PANICKED: u64 = 3;
+
+fn runtime(builder: &mut Builder) -> Runtime {
+    let config = tokio_dtrace::Config::new().panic_hook(true);
+    tokio_dtrace::register_hooks_with(builder, config)
+        .unwrap()
+        .build()
+        .unwrap()
+}
+
+fn task_id<T>(handle: &JoinHandle<T>) -> Arg {
+    Arg::Int(handle.id().to_string().parse().unwrap())
+}
+
+/// Returns the reason reported by the `task-terminate` probe fired for the
+/// task with the given ID.
+fn reason(fired: &[Fired], id: &Arg) -> u64 {
+    let terminate = fired
+        .iter()
+        .find(|fired| fired.probe == "task-terminate" && fired.args[0] == *id)
+        .expect("task-terminate did not fire");
+    match terminate.args[5] {
+        Arg::Int(flags) => (flags >> 3) & 3,
+        Arg::Str(_) => panic!("the flags are not an integer"),
+    }
+}
+
+/// Spawns a task which catches a panic and then completes, returning the
+/// reason reported for it.
+fn caught_panic_reason(wrap: bool) -> u64 {
+    let rt = runtime(&mut Builder::new_current_thread());
+    let (id, fired) = testing::capture(|| {
+        let future = async {
+            let result = std::panic::catch_unwind(|| panic!("caught"));
+            assert!(result.is_err());
+        };
+        let handle = if wrap {
+            rt.spawn(tokio_dtrace::trace_outcome(future))
+        } else {
+            rt.spawn(future)
+        };
+        let id = task_id(&handle);
+        rt.block_on(handle).unwrap();
+        id
+    });
+    reason(&fired, &id)
+}
+
+#[test]
+fn caught_panic_is_not_reported_as_panicked() {
+    // The hooks alone cannot tell whether the task completed.
+    assert_eq!(caught_panic_reason(false), UNKNOWN);
+    assert_eq!(caught_panic_reason(true), COMPLETED);
+}
+
+#[test]
+fn unwound_panic_is_reported_if_wrapped() {
+    let rt = runtime(&mut Builder::new_current_thread());
+    let (id, fired) = testing::capture(|| {
+        let handle = rt.spawn(tokio_dtrace::trace_outcome(async { panic!("unwound") }));
+        let id = task_id(&handle);
+        assert!(rt.block_on(handle).unwrap_err().is_panic());
+        id
+    });
+    assert_eq!(reason(&fired, &id), PANICKED);
+}
+
+/// Spawns a task which is aborted while it is being polled, returning the
+/// reason reported for it.
+fn aborted_during_poll_reason(wrap: bool) -> u64 {
+    let rt = runtime(Builder::new_multi_thread().worker_threads(1));
+    let (id, fired) = testing::capture(|| {
+        let (polled_tx, polled_rx) = mpsc::channel();
+        let (aborted_tx, aborted_rx) = mpsc::channel::<()>();
+        let future = async move {
+            polled_tx.send(()).unwrap();
+            aborted_rx.recv().unwrap();
+            std::future::pending::<()>().await
+        };
+        let handle = if wrap {
+            rt.spawn(tokio_dtrace::trace_outcome(future))
+        } else {
+            rt.spawn(future)
+        };
+        let id = task_id(&handle);
+        polled_rx.recv().unwrap();
+        handle.abort();
+        aborted_tx.send(()).unwrap();
+        assert!(rt.block_on(handle).unwrap_err().is_cancelled());
+        // The join handle may be woken before the worker thread fires
+        // task-terminate, so wait for the worker thread to stop.
+        rt.shutdown_timeout(Duration::from_secs(10));
+        id
+    });
+    reason(&fired, &id)
+}
+
+#[test]
+fn abort_during_poll_is_only_known_if_wrapped() {
+    assert_eq!(aborted_during_poll_reason(false), UNKNOWN);
+    assert_eq!(aborted_during_poll_reason(true), CANCELLED);
+}
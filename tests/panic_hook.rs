@@ -0,0 +1,29 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the panic hook installed by `tokio-dtrace`.
+//!
+//! Panic hooks are process-global, so these tests live in their own
+//! integration test binary.
+#![cfg(all(tokio_unstable, feature = "probes-task"))]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static PREV_HOOK_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+#[test]
+fn previous_panic_hook_is_called() {
+    std::panic::set_hook(Box::new(|_| {
+        PREV_HOOK_CALLS.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let config = tokio_dtrace::Config::new().panic_hook(true);
+    let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let result = rt.block_on(async { tokio::spawn(async { panic!("oh no") }).await });
+    assert!(result.unwrap_err().is_panic());
+    assert_eq!(PREV_HOOK_CALLS.load(Ordering::SeqCst), 1);
+}
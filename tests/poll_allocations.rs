@@ -0,0 +1,68 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests that encoding a task's spawn location for the per-poll probes does
+//! not allocate.
+//!
+//! Allocations are counted by a global allocator, which counts allocations
+//! made on every thread, so this test lives in its own integration test
+//! binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counts heap allocations.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: forwarded directly to the system allocator.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: forwarded directly to the system allocator.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn polls_do_not_allocate_locations() {
+    const POLLS: u64 = 1000;
+
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    // Forcing the probes makes the hooks encode each poll's arguments,
+    // including the spawn location, as if DTrace had enabled the probes.
+    let allocations = tokio_dtrace::testing::force_enabled(|| {
+        rt.block_on(async {
+            tokio::spawn(async {
+                let start = ALLOCATIONS.load(Ordering::Relaxed);
+                for _ in 0..POLLS {
+                    tokio::task::yield_now().await;
+                }
+                ALLOCATIONS.load(Ordering::Relaxed) - start
+            })
+            .await
+            .unwrap()
+        })
+    });
+
+    // Each poll fires both `task-poll-start` and `task-poll-end`, so copying
+    // the location would allocate at least twice per poll. The runtime itself
+    // may allocate a few times, such as to grow its queues.
+    assert!(
+        allocations < POLLS,
+        "{allocations} allocations in {POLLS} polls"
+    );
+}
@@ -0,0 +1,121 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the runtime ID reported by `task-spawn-runtime` and the other
+//! task companion probes, and as `arg0` of the worker thread probes.
+//!
+//! Capturing probes forces them to be enabled process-wide, so these tests
+//! live in their own integration test binary.
+#![cfg(all(
+    tokio_unstable,
+    feature = "testing",
+    feature = "probes-task",
+    feature = "probes-worker"
+))]
+
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
+use tokio_dtrace::testing::{self, Arg, Fired};
+
+/// The probes which fire for every task, and report its runtime as `arg0` and
+/// its ID as `arg1`.
+const TASK_PROBES: [&str; 3] = ["task-spawn-runtime", "task-spawn-parent", "task-kind"];
+
+fn task_id<T>(handle: &JoinHandle<T>) -> Arg {
+    Arg::Int(handle.id().to_string().parse().unwrap())
+}
+
+/// Spawns a task on `rt`, waits for it, and shuts the runtime down, returning
+/// the task's ID.
+fn run_task(rt: Runtime) -> Arg {
+    let handle = rt.spawn(async {});
+    let id = task_id(&handle);
+    rt.block_on(handle).unwrap();
+    // The join handle may be woken before task-terminate fires.
+    rt.shutdown_timeout(Duration::from_secs(10));
+    id
+}
+
+/// Returns the runtime reported by every task probe fired for the task with
+/// the given ID, asserting that they all agree.
+fn task_runtime(fired: &[Fired], id: &Arg) -> Arg {
+    let runtimes: Vec<_> = TASK_PROBES
+        .iter()
+        .map(|probe| {
+            let fired = fired
+                .iter()
+                .find(|fired| fired.probe == *probe && fired.args[1] == *id)
+                .unwrap_or_else(|| panic!("{probe} did not fire"));
+            fired.args[0].clone()
+        })
+        .collect();
+    assert!(
+        runtimes.iter().all(|runtime| *runtime == runtimes[0]),
+        "{runtimes:?}"
+    );
+    assert_ne!(runtimes[0], Arg::Int(0));
+    runtimes[0].clone()
+}
+
+#[test]
+fn probes_report_their_runtime() {
+    let ((a, b), fired) = testing::capture(|| {
+        let mut builder = Builder::new_multi_thread();
+        builder.worker_threads(1).thread_name("runtime-a");
+        let a = tokio_dtrace::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut builder = Builder::new_current_thread();
+        let b = tokio_dtrace::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        (run_task(a), run_task(b))
+    });
+
+    let a = task_runtime(&fired, &a);
+    let b = task_runtime(&fired, &b);
+    assert_ne!(a, b);
+
+    let threads: Vec<_> = fired
+        .iter()
+        .filter(|fired| {
+            fired.probe.starts_with("worker-thread-")
+                && fired.args.get(1) == Some(&Arg::Str("runtime-a".into()))
+        })
+        .collect();
+    assert!(
+        threads
+            .iter()
+            .any(|fired| fired.probe == "worker-thread-start")
+    );
+    for fired in threads {
+        assert_eq!(fired.args[0], a, "{}", fired.probe);
+    }
+}
+
+#[test]
+fn individual_hooks_report_the_block_on_runtime() {
+    let (id, fired) = testing::capture(|| {
+        let mut builder = Builder::new_current_thread();
+        tokio_dtrace::register_hooks(&mut builder).unwrap();
+        builder
+            .on_task_spawn(tokio_dtrace::hooks::on_task_spawn)
+            .on_before_task_poll(tokio_dtrace::hooks::on_before_task_poll)
+            .on_after_task_poll(tokio_dtrace::hooks::on_after_task_poll)
+            .on_task_terminate(tokio_dtrace::hooks::on_task_terminate);
+        let rt = builder.build().unwrap();
+        // Spawned from the thread blocking on the runtime, which it did not
+        // start.
+        let id = rt.block_on(async {
+            let handle = tokio::spawn(async {});
+            let id = task_id(&handle);
+            handle.await.unwrap();
+            id
+        });
+        rt.shutdown_timeout(Duration::from_secs(10));
+        id
+    });
+    task_runtime(&fired, &id);
+}
@@ -0,0 +1,53 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the shared memory counters.
+//!
+//! The mapping is process-global, so these tests live in their own integration
+//! test binary.
+#![cfg(all(tokio_unstable, unix, feature = "shared-counters"))]
+
+use tokio_dtrace::shm;
+
+#[test]
+fn counters_are_shared() {
+    let path = std::env::temp_dir().join(format!("tokio-dtrace-shm-{}", std::process::id()));
+    // Concurrent calls race to map the same file: exactly one must succeed,
+    // and the others must not truncate the file it mapped.
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..8).map(|_| scope.spawn(|| shm::map(&path))).collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect()
+    });
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    for result in results.iter().filter_map(|result| result.as_ref().err()) {
+        assert_eq!(result.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    builder.enable_time();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        for _ in 0..3 {
+            tokio::spawn(async {}).await.unwrap();
+        }
+        tokio::spawn(std::future::pending::<()>());
+        tokio::task::yield_now().await;
+    });
+    // Tearing down while a task is still alive discards it, which counts as a
+    // leak. Cancelling it when the runtime is dropped is then not counted.
+    tokio_dtrace::teardown();
+    drop(rt);
+
+    let snapshot = shm::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(u64::from(std::process::id()), snapshot.pid);
+    // `block_on` itself is not a task.
+    assert_eq!(snapshot.spawned, 4);
+    assert_eq!(snapshot.terminated, 3);
+    assert_eq!(snapshot.leaked, 1);
+}
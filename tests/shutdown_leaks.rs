@@ -0,0 +1,39 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `Config::on_shutdown_leaks`.
+//!
+//! Teardown resets `tokio-dtrace`'s process-global state, so these tests live
+//! in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "probes-task"))]
+
+use std::sync::Mutex;
+use tokio_dtrace::{Config, TaskInfo};
+
+static LEAKED: Mutex<Vec<TaskInfo>> = Mutex::new(Vec::new());
+
+#[test]
+fn leaked_tasks_are_reported() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let config = Config::new().on_shutdown_leaks(|tasks| LEAKED.lock().unwrap().extend(tasks));
+    let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+    let line = line!() + 3;
+    rt.block_on(async {
+        tokio::spawn(async {}).await.unwrap();
+        tokio::spawn(std::future::pending::<()>());
+        tokio::task::yield_now().await;
+    });
+
+    tokio_dtrace::teardown();
+    drop(rt);
+
+    let leaked = LEAKED.lock().unwrap();
+    let [task] = &leaked[..] else {
+        panic!("expected one leaked task, got {leaked:?}");
+    };
+    assert_eq!(task.file, file!());
+    assert_eq!(task.line, line);
+    assert_eq!(task.poll_count, 1);
+}
@@ -0,0 +1,60 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `events::SinkGuard`.
+//!
+//! There is a single ring per process, with a single `Drainer`, so these tests
+//! live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "event-stream", feature = "probes-task"))]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio_dtrace::Config;
+use tokio_dtrace::events::{self, ProbeEvent};
+
+const TASKS: usize = 100;
+
+#[test]
+fn flush_waits_for_slow_drainer() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let config = Config::new().mpsc_sink(4 * TASKS);
+    let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut drainer = events::drainer().unwrap();
+
+    // A consumer which is slower than the hooks, and which stops as soon as it
+    // is told to and finds the ring empty.
+    let stop = Arc::new(AtomicBool::new(false));
+    let consumer = std::thread::spawn({
+        let stop = stop.clone();
+        move || {
+            let mut terminated = 0;
+            loop {
+                match drainer.try_recv() {
+                    Some(ProbeEvent::TaskTerminate(_)) => terminated += 1,
+                    Some(_) => {}
+                    None if stop.load(Ordering::Acquire) => return terminated,
+                    None => {}
+                }
+                std::thread::sleep(Duration::from_micros(50));
+            }
+        }
+    });
+
+    rt.block_on(async {
+        for _ in 0..TASKS {
+            tokio::spawn(async {});
+        }
+        tokio::task::yield_now().await;
+    });
+    drop(rt);
+
+    events::sink_guard().blocking_flush();
+    stop.store(true, Ordering::Release);
+    assert_eq!(consumer.join().unwrap(), TASKS);
+
+    // With the `Drainer` gone, there is nothing left to wait for.
+    events::sink_guard().blocking_flush();
+}
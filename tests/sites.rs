@@ -0,0 +1,19 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for named spawn sites, which must be declared outside the crate.
+#![cfg(all(feature = "usdt", any(unix, windows)))]
+
+tokio_dtrace::named_spawn_sites!(tokio_test_sites: answer, question);
+
+#[test]
+fn spawn_at_site() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let answer = rt.block_on(async {
+        tokio_dtrace::spawn_at_site!(tokio_test_sites::answer, async { 42 })
+            .await
+            .unwrap()
+    });
+    assert_eq!(answer, 42);
+}
@@ -0,0 +1,69 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the probes which fire for long polls.
+//!
+//! Capturing probes forces them to be enabled process-wide, so these tests
+//! live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use std::time::Duration;
+use tokio_dtrace::testing::{self, Arg, Fired};
+
+/// Spawns a task whose only poll takes about a millisecond, on a runtime
+/// registered with `config`, returning its ID, the line it was spawned on, and
+/// the probes fired for it.
+fn slow_poll(config: tokio_dtrace::Config) -> (Arg, u32, Vec<Fired>) {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+    let ((id, line), fired) = testing::capture(|| {
+        rt.block_on(async {
+            let line = line!() + 1;
+            let handle = tokio::spawn(async { std::thread::sleep(Duration::from_millis(1)) });
+            let id = Arg::Int(handle.id().to_string().parse().unwrap());
+            handle.await.unwrap();
+            (id, line)
+        })
+    });
+    let fired = fired
+        .into_iter()
+        .filter(|fired| fired.args.get(1) == Some(&id))
+        .collect();
+    (id, line, fired)
+}
+
+#[test]
+fn slow_polls_report_their_spawn_location() {
+    let config = tokio_dtrace::Config::new().slow_poll_threshold(Duration::from_micros(100));
+    let (id, line, fired) = slow_poll(config);
+
+    let slow = fired
+        .iter()
+        .find(|fired| fired.probe == "task-poll-slow")
+        .expect("task-poll-slow did not fire");
+    assert_eq!(slow.args[1], id);
+    assert!(matches!(slow.args[2], Arg::Int(nanos) if nanos >= 1_000_000));
+    assert!(matches!(&slow.args[3], Arg::Str(file) if file.ends_with("slow_polls.rs")));
+    assert_eq!(slow.args[4], Arg::Int(line.into()));
+    // The blocking threshold is separate, and was not exceeded.
+    assert!(
+        !fired
+            .iter()
+            .any(|fired| fired.probe == "task-blocking-suspected")
+    );
+}
+
+#[test]
+fn blocking_suspected_reports_the_duration() {
+    let config = tokio_dtrace::Config::new().blocking_threshold(Duration::from_micros(100));
+    let (_, _, fired) = slow_poll(config);
+
+    let blocking = fired
+        .iter()
+        .find(|fired| fired.probe == "task-blocking-suspected")
+        .expect("task-blocking-suspected did not fire");
+    assert_eq!(blocking.args.len(), 3);
+    assert!(matches!(blocking.args[2], Arg::Int(nanos) if nanos >= 1_000_000));
+}
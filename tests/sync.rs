@@ -0,0 +1,99 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the probes fired by the instrumented synchronization primitives.
+//!
+//! Capturing probes forces them to be enabled process-wide, so these tests
+//! live in their own integration test binary.
+#![cfg(all(
+    tokio_unstable,
+    feature = "testing",
+    feature = "sync",
+    feature = "probes-task"
+))]
+
+use std::sync::Arc;
+use tokio_dtrace::sync::Mutex;
+use tokio_dtrace::testing::{self, Arg, Fired};
+
+/// Returns the name and arguments of each of the mutex probes fired for
+/// `mutex`.
+fn mutex_probes(fired: &[Fired], mutex: &Mutex<u32>) -> Vec<(String, Vec<Arg>)> {
+    fired
+        .iter()
+        .filter(|fired| fired.probe.starts_with("mutex-"))
+        .filter(|fired| fired.args[0] == Arg::from(mutex.id()))
+        .map(|fired| (fired.probe.clone(), fired.args.clone()))
+        .collect()
+}
+
+#[test]
+fn mutex_probes_fire_in_order() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let mutex = Arc::new(Mutex::new(0u32));
+    let (task_id, fired) = testing::capture(|| {
+        let mutex = mutex.clone();
+        rt.block_on(async move {
+            tokio::spawn(async move {
+                *mutex.lock().await += 1;
+                tokio::task::id()
+            })
+            .await
+            .unwrap()
+        })
+    });
+    let task_id = Arg::Int(task_id.to_string().parse().unwrap());
+    let id = Arg::from(mutex.id());
+
+    let probes = mutex_probes(&fired, &mutex);
+    let names: Vec<_> = probes.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["mutex-acquire-start", "mutex-acquire-end", "mutex-release"]
+    );
+    assert_eq!(probes[0].1, [id.clone(), task_id.clone()]);
+    for (_, args) in &probes[1..] {
+        assert_eq!(args.len(), 3);
+        assert_eq!(args[..2], [id.clone(), task_id.clone()]);
+    }
+}
+
+#[test]
+fn try_lock_reports_no_wait() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    // The probes only fire once the hooks are registered.
+    let _rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let mutex = Mutex::new(0u32);
+    let ((), fired) = testing::capture(|| {
+        let guard = mutex.try_lock().unwrap();
+        assert!(mutex.try_lock().is_err());
+        drop(guard);
+    });
+    let id = Arg::from(mutex.id());
+
+    let probes = mutex_probes(&fired, &mutex);
+    assert_eq!(
+        probes[..2],
+        [
+            (
+                "mutex-acquire-start".to_string(),
+                vec![id.clone(), Arg::Int(0)]
+            ),
+            (
+                "mutex-acquire-end".to_string(),
+                vec![id.clone(), Arg::Int(0), Arg::Int(0)]
+            ),
+        ]
+    );
+    // A failed attempt fires nothing.
+    assert_eq!(probes.len(), 3);
+    assert_eq!(probes[2].0, "mutex-release");
+}
@@ -0,0 +1,57 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the kind of task reported by the `task-kind` probe.
+//!
+//! Capturing probes forces them to be enabled process-wide, so these tests
+//! live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use tokio::task::{JoinHandle, LocalSet};
+use tokio_dtrace::TaskKind;
+use tokio_dtrace::testing::{self, Arg};
+
+fn task_id<T>(handle: &JoinHandle<T>) -> Arg {
+    Arg::Int(handle.id().to_string().parse().unwrap())
+}
+
+#[test]
+fn each_kind_is_reported() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let (ids, fired) = testing::capture(|| {
+        rt.block_on(async {
+            let spawned = tokio::spawn(async {});
+            let blocking = tokio_dtrace::spawn_blocking(|| {});
+            let local = LocalSet::new();
+            let local = local
+                .run_until(async {
+                    let local = tokio_dtrace::spawn_local(async {});
+                    let id = task_id(&local);
+                    local.await.unwrap();
+                    id
+                })
+                .await;
+            let ids = [
+                (task_id(&spawned), TaskKind::Async),
+                (task_id(&blocking), TaskKind::Blocking),
+                (local, TaskKind::Local),
+            ];
+            spawned.await.unwrap();
+            blocking.await.unwrap();
+            ids
+        })
+    });
+
+    for (id, kind) in ids {
+        let kinds: Vec<_> = fired
+            .iter()
+            .filter(|fired| fired.probe == "task-kind" && fired.args[1] == id)
+            .map(|fired| fired.args[2].clone())
+            .collect();
+        assert_eq!(kinds, [Arg::Int(u8::from(kind).into())], "{kind:?}");
+    }
+}
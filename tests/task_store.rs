@@ -0,0 +1,98 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for `Config::task_store`.
+//!
+//! The task store is chosen once per process, so these tests live in their
+//! own integration test binary.
+#![cfg(all(tokio_unstable, feature = "probes-task"))]
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio_dtrace::{Config, RegistrationError, TaskState, TaskStore, TaskSummary};
+
+/// A store which counts the calls made to it.
+#[derive(Debug, Default)]
+struct CountingStore {
+    tasks: Mutex<HashMap<u64, TaskState>>,
+    spawns: AtomicUsize,
+    polls: AtomicUsize,
+    terminates: AtomicUsize,
+}
+
+impl TaskStore for CountingStore {
+    fn on_spawn(&self, id: u64, state: TaskState) {
+        self.spawns.fetch_add(1, Ordering::Relaxed);
+        self.tasks.lock().unwrap().insert(id, state);
+    }
+
+    fn on_poll_start(&self, id: u64) -> Option<TaskState> {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.tasks.lock().unwrap().remove(&id)
+    }
+
+    fn on_poll_end(&self, id: u64, state: TaskState) {
+        self.tasks.lock().unwrap().insert(id, state);
+    }
+
+    fn on_terminate(&self, id: u64) -> Option<TaskSummary> {
+        self.terminates.fetch_add(1, Ordering::Relaxed);
+        let state = self.tasks.lock().unwrap().remove(&id)?;
+        Some(TaskSummary::new(id, state))
+    }
+
+    fn drain(&self) -> Vec<(u64, TaskState)> {
+        self.tasks.lock().unwrap().drain().collect()
+    }
+}
+
+#[test]
+fn custom_store_is_used_and_cannot_be_replaced() {
+    static STORE: std::sync::LazyLock<CountingStore> = std::sync::LazyLock::new(Default::default);
+    static OTHER: std::sync::LazyLock<CountingStore> = std::sync::LazyLock::new(Default::default);
+
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.worker_threads(2);
+    let config = Config::new().task_store(&*STORE);
+    let rt = tokio_dtrace::register_hooks_with(&mut builder, config)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    rt.block_on(async {
+        let tasks: Vec<_> = (0..8)
+            .map(|_| tokio::spawn(tokio::task::yield_now()))
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+    drop(rt);
+
+    // The `block_on` future is not a spawned task, so only the spawned tasks
+    // are stored. Each is polled twice, and removed when it terminates. Tokio
+    // also calls the terminate hook for each worker thread, which it runs as
+    // a blocking task.
+    assert_eq!(STORE.spawns.load(Ordering::Relaxed), 8);
+    assert_eq!(STORE.polls.load(Ordering::Relaxed), 16);
+    assert_eq!(STORE.terminates.load(Ordering::Relaxed), 8 + 2);
+    assert!(STORE.tasks.lock().unwrap().is_empty());
+
+    // The same store may be configured again, but not a different one.
+    let config = Config::new().task_store(&*STORE);
+    assert!(
+        tokio_dtrace::register_hooks_with(
+            &mut tokio::runtime::Builder::new_current_thread(),
+            config
+        )
+        .is_ok()
+    );
+    let config = Config::new().task_store(&*OTHER);
+    assert!(matches!(
+        tokio_dtrace::register_hooks_with(
+            &mut tokio::runtime::Builder::new_current_thread(),
+            config
+        ),
+        Err(RegistrationError::TaskStoreAlreadyChosen)
+    ));
+}
@@ -0,0 +1,33 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for forcing probes to behave as if enabled.
+//!
+//! Forcing probes is process-global, so these tests live in their own
+//! integration test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use tokio_dtrace::ProbeMask;
+
+#[test]
+fn forced_probes_are_observed() {
+    let spawn_and_poll = || {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        let rt = tokio_dtrace::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        rt.block_on(async { tokio::spawn(async {}).await.unwrap() });
+    };
+
+    spawn_and_poll();
+    assert!(!tokio_dtrace::enabled_probes().contains(ProbeMask::TASK_SPAWN));
+
+    tokio_dtrace::testing::force_enabled(spawn_and_poll);
+    let enabled = tokio_dtrace::enabled_probes();
+    assert!(enabled.contains(
+        ProbeMask::TASK_SPAWN
+            | ProbeMask::TASK_POLL_START
+            | ProbeMask::TASK_POLL_END
+            | ProbeMask::TASK_TERMINATE
+    ));
+}
@@ -0,0 +1,50 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the pool reported by the `worker-thread-start` and
+//! `worker-thread-stop` probes.
+//!
+//! Capturing probes forces them to be enabled process-wide, so these tests
+//! live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-worker"))]
+
+use std::time::Duration;
+use tokio_dtrace::testing::{self, Arg, Fired};
+
+const POOL_WORKER: Arg = Arg::Int(0);
+const POOL_BLOCKING: Arg = Arg::Int(1);
+
+/// Returns the pools reported by the given probe for the test runtime's
+/// threads, workers first.
+fn pools(fired: &[Fired], probe: &str) -> Vec<Arg> {
+    let mut pools: Vec<_> = fired
+        .iter()
+        .filter(|fired| fired.probe == probe && fired.args[1] == Arg::Str("pools".into()))
+        .map(|fired| fired.args[3].clone())
+        .collect();
+    pools.sort_by_key(|pool| *pool != POOL_WORKER);
+    pools
+}
+
+#[test]
+fn threads_report_their_pool() {
+    let ((), fired) = testing::capture(|| {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.worker_threads(2).thread_name("pools");
+        let rt = tokio_dtrace::register_hooks(&mut builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        // Wait for both workers to begin running, so that the blocking thread
+        // cannot be mistaken for one of them as it starts.
+        let metrics = rt.metrics();
+        while (0..2).any(|worker| metrics.worker_thread_id(worker).is_none()) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        rt.block_on(async { tokio::task::spawn_blocking(|| {}).await.unwrap() });
+        rt.shutdown_timeout(Duration::from_secs(10));
+    });
+
+    let expected = [POOL_WORKER, POOL_WORKER, POOL_BLOCKING];
+    assert_eq!(pools(&fired, "worker-thread-start"), expected);
+    assert_eq!(pools(&fired, "worker-thread-stop"), expected);
+}
@@ -0,0 +1,72 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the probes fired by the `tracing` subscriber layer.
+//!
+//! Capturing probes forces them to be enabled process-wide, and
+//! [`teardown`](tokio_dtrace::teardown) disables the hooks process-wide, so
+//! these tests live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "tracing"))]
+
+use tokio_dtrace::testing::{self, Arg, Fired};
+use tokio_dtrace::tracing::DtraceLayer;
+use tracing_subscriber::prelude::*;
+
+const TARGET: &str = "tracing_layer";
+
+/// Returns the arguments of each of the layer's probes fired with `TARGET`.
+fn layer_probes(fired: &[Fired]) -> Vec<(String, Vec<Arg>)> {
+    fired
+        .iter()
+        .filter(|fired| fired.args.contains(&Arg::from(TARGET)))
+        .map(|fired| (fired.probe.clone(), fired.args.clone()))
+        .collect()
+}
+
+/// Enters a span and records a warning in it.
+fn instrumented() {
+    let span = tracing::info_span!(target: TARGET, "work");
+    let _entered = span.enter();
+    tracing::warn!(target: TARGET, "warned");
+}
+
+#[test]
+fn layer_fires_probes_until_teardown() {
+    let _default =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(DtraceLayer::new()));
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let (task_id, fired) = testing::capture(|| {
+        let handle = rt.spawn(async { instrumented() });
+        let task_id = Arg::Int(handle.id().to_string().parse().unwrap());
+        rt.block_on(handle).unwrap();
+        task_id
+    });
+    let probes = layer_probes(&fired);
+    let names: Vec<_> = probes.iter().map(|(probe, _)| probe.as_str()).collect();
+    assert_eq!(names, ["span-enter", "event", "span-exit"]);
+    let (_, enter) = &probes[0];
+    assert_eq!(
+        enter[1..],
+        [Arg::from("work"), Arg::from(TARGET), task_id.clone()]
+    );
+    let (_, event) = &probes[1];
+    // Events are at level 2, `WARN`.
+    assert_eq!(event[1..], [Arg::from(TARGET), Arg::Int(2), task_id]);
+    let (_, exit) = &probes[2];
+    assert_eq!(exit, enter);
+
+    // Outside of a task, the task ID is 0.
+    let (_, fired) = testing::capture(instrumented);
+    let probes = layer_probes(&fired);
+    assert_eq!(probes.len(), 3);
+    assert!(probes.iter().all(|(_, args)| args[3] == Arg::Int(0)));
+
+    drop(rt);
+    tokio_dtrace::teardown();
+    let (_, fired) = testing::capture(instrumented);
+    assert_eq!(layer_probes(&fired), []);
+}
@@ -0,0 +1,48 @@
+// Copyright 2025 Oxide Computer Company
+
+//! Tests for the probes fired by the wakers of futures wrapped using
+//! [`trace_wakes`](tokio_dtrace::trace_wakes).
+//!
+//! Capturing probes forces them to be enabled process-wide, so these tests
+//! live in their own integration test binary.
+#![cfg(all(tokio_unstable, feature = "testing", feature = "probes-task"))]
+
+use tokio_dtrace::testing::{self, Arg, Fired};
+
+/// Returns the arguments of each `probe` fired for the task `id`.
+fn fired_for<'a>(fired: &'a [Fired], probe: &str, id: &Arg) -> Vec<&'a [Arg]> {
+    fired
+        .iter()
+        .filter(|fired| fired.probe == probe && fired.args[1] == *id)
+        .map(|fired| fired.args.as_slice())
+        .collect()
+}
+
+#[test]
+fn wakes_fire_probes() {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    let rt = tokio_dtrace::register_hooks(&mut builder)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let line = line!() + 2;
+    let (id, fired) = testing::capture(|| {
+        let handle = rt.spawn(tokio_dtrace::trace_wakes(async {
+            // Each yield wakes the task, and is followed by another poll.
+            tokio::task::yield_now().await;
+            tokio::task::yield_now().await;
+        }));
+        let id = Arg::Int(handle.id().to_string().parse().unwrap());
+        rt.block_on(handle).unwrap();
+        id
+    });
+
+    let wakes = fired_for(&fired, "task-wake", &id);
+    assert_eq!(wakes.len(), 2);
+    for wake in wakes {
+        assert_eq!(wake[4], Arg::from(line));
+        assert!(matches!(&wake[3], Arg::Str(file) if file.ends_with("wakes.rs")));
+    }
+    assert_eq!(fired_for(&fired, "task-sched-latency", &id).len(), 2);
+}